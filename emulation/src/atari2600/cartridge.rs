@@ -0,0 +1,206 @@
+use crate::common::message::*;
+
+const SLICE_SIZE: usize = 0x0400;
+const BANK_4K: usize = 0x1000;
+const BANK_2K: usize = 0x0800;
+/// 3F carts switch on writes below this address, the TIA mirror no program writes to
+const TIGERVISION_HOTSPOTS: u16 = 0x0040;
+/// Parker Brothers programs switch slices through one of these accesses at startup
+const E0_SIGNATURES: [[u8; 3]; 8] = [
+    [0x8D, 0xE0, 0x1F],
+    [0x8D, 0xE0, 0x5F],
+    [0x8D, 0xE9, 0xFF],
+    [0x0C, 0xE0, 0x1F],
+    [0xAD, 0xE0, 0x1F],
+    [0xAD, 0xE9, 0xFF],
+    [0xAD, 0xED, 0xFF],
+    [0xAD, 0xF3, 0xBF],
+];
+/// `STA $3F`
+const TIGERVISION_SIGNATURE: [u8; 2] = [0x85, 0x3F];
+
+/// Bank switching schemes, named after their hotspot addresses as usual
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BankSwitching {
+    /// 2K or 4K, no switching
+    None,
+    /// Atari 8K, two 4K banks at 0x1FF8..=0x1FF9
+    F8,
+    /// Atari 16K, four 4K banks at 0x1FF6..=0x1FF9
+    F6,
+    /// Atari 32K, eight 4K banks at 0x1FF4..=0x1FFB
+    F4,
+    /// Parker Brothers 8K, three switchable 1K slices and a fixed last one
+    E0,
+    /// Tigervision, a 2K bank selected by writes to 0x00..=0x3F and the last 2K fixed
+    ThreeF,
+}
+
+impl BankSwitching {
+    /// Guessed from the size and the bank switching accesses in the code, like most emulators do
+    pub fn detect(rom: &[u8]) -> Option<BankSwitching> {
+        let contains = |pattern: &[u8]| rom.windows(pattern.len()).filter(|w| *w == pattern).count();
+        let tigervision = contains(&TIGERVISION_SIGNATURE) >= 2;
+        match rom.len() {
+            0x0800 | 0x1000 => Some(BankSwitching::None),
+            0x2000 if E0_SIGNATURES.iter().any(|s| contains(s) > 0) => Some(BankSwitching::E0),
+            len if tigervision && len % BANK_2K == 0 => Some(BankSwitching::ThreeF),
+            0x2000 => Some(BankSwitching::F8),
+            0x4000 => Some(BankSwitching::F6),
+            0x8000 => Some(BankSwitching::F4),
+            _ => None,
+        }
+    }
+
+    /// First hotspot and the number of 4K banks of the Atari schemes
+    fn atari_hotspots(&self) -> Option<(u16, usize)> {
+        match self {
+            BankSwitching::F8 => Some((0x1FF8, 2)),
+            BankSwitching::F6 => Some((0x1FF6, 4)),
+            BankSwitching::F4 => Some((0x1FF4, 8)),
+            _ => None,
+        }
+    }
+}
+
+/// ROM cartridge seen through the 4K window at 0x1000..=0x1FFF
+pub struct Cartridge {
+    rom: Vec<u8>,
+    scheme: BankSwitching,
+    /// ROM offset of each 1K slice of the window
+    slices: [usize; 4],
+}
+
+impl Cartridge {
+    pub fn new(rom: Vec<u8>) -> Result<Self, Box<dyn Msg>> {
+        let scheme = match BankSwitching::detect(&rom) {
+            Some(scheme) => scheme,
+            None => {
+                let err = ErrorMsg::new(ErrorTopicId::Cartridge.into(), ErrorMsgId::InvalidFormat.into())
+                    .add_param(rom.len().to_string());
+                return Err(Box::new(err));
+            }
+        };
+        let mut cartridge = Self {
+            rom,
+            scheme,
+            slices: [0, SLICE_SIZE, 2 * SLICE_SIZE, 3 * SLICE_SIZE],
+        };
+        match scheme {
+            // the reset vector may only be valid in the last bank
+            BankSwitching::F8 | BankSwitching::F6 | BankSwitching::F4 => {
+                cartridge.select_bank_4k(cartridge.rom.len() / BANK_4K - 1)
+            }
+            BankSwitching::E0 => cartridge.slices = [4, 5, 6, 7].map(|s| s * SLICE_SIZE),
+            BankSwitching::ThreeF => cartridge.select_bank_2k(0),
+            BankSwitching::None => {}
+        }
+        Ok(cartridge)
+    }
+
+    pub fn scheme(&self) -> BankSwitching {
+        self.scheme
+    }
+
+    /// Read of the 0x1000..=0x1FFF window, hotspots switch on reads too
+    pub fn read(&mut self, addr: u16) -> u8 {
+        self.hotspot(addr);
+        self.peek(addr)
+    }
+
+    /// Every write of the machine, 3F carts watch the TIA addresses
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if self.scheme == BankSwitching::ThreeF && addr < TIGERVISION_HOTSPOTS {
+            self.select_bank_2k(value as usize);
+        } else if addr & 0x1000 != 0 {
+            self.hotspot(addr);
+        }
+    }
+
+    /// Read without switching banks, for traces and debuggers
+    pub fn peek(&self, addr: u16) -> u8 {
+        let addr = addr as usize & 0x0FFF;
+        let offset = self.slices[addr / SLICE_SIZE] + (addr % SLICE_SIZE);
+        self.rom[offset % self.rom.len()]
+    }
+
+    fn hotspot(&mut self, addr: u16) {
+        let addr = addr & 0x1FFF;
+        if let Some((first, banks)) = self.scheme.atari_hotspots() {
+            if (first..first + banks as u16).contains(&addr) {
+                self.select_bank_4k((addr - first) as usize);
+            }
+        } else if self.scheme == BankSwitching::E0 && (0x1FE0..=0x1FF7).contains(&addr) {
+            let slot = ((addr - 0x1FE0) / 8) as usize;
+            self.slices[slot] = (addr & 0x07) as usize * SLICE_SIZE;
+        }
+    }
+
+    fn select_bank_4k(&mut self, bank: usize) {
+        for (slot, slice) in self.slices.iter_mut().enumerate() {
+            *slice = bank * BANK_4K + slot * SLICE_SIZE;
+        }
+    }
+
+    /// The lower 2K of the window, the upper one always holds the last bank
+    fn select_bank_2k(&mut self, bank: usize) {
+        let banks = (self.rom.len() / BANK_2K).max(1);
+        let (low, last) = ((bank % banks) * BANK_2K, (banks - 1) * BANK_2K);
+        self.slices = [low, low + SLICE_SIZE, last, last + SLICE_SIZE];
+    }
+}
+
+#[cfg(test)]
+mod cartridge_tests {
+
+    use super::*;
+
+    /// Every byte holds the number of its 1K slice
+    fn rom(size: usize) -> Vec<u8> {
+        (0..size).map(|i| (i / SLICE_SIZE) as u8).collect()
+    }
+
+    #[test]
+    fn test_atari_schemes() {
+        let mut cart = Cartridge::new(rom(0x800)).unwrap();
+        assert_eq!(cart.scheme(), BankSwitching::None);
+        assert_eq!(cart.read(0x1800), 0);
+
+        let mut cart = Cartridge::new(rom(0x4000)).unwrap();
+        assert_eq!(cart.scheme(), BankSwitching::F6);
+        assert_eq!(cart.read(0x1000), 12);
+        cart.read(0x1FF7);
+        assert_eq!(cart.read(0x1C00), 7);
+        // the mirror at 0xF000 used by the programs
+        cart.write(0xFFF6, 0);
+        assert_eq!(cart.peek(0xF000), 0);
+
+        assert!(Cartridge::new(rom(0x1234)).is_err());
+    }
+
+    #[test]
+    fn test_e0_and_3f() {
+        let mut data = rom(0x2000);
+        data[0x100..0x103].copy_from_slice(&E0_SIGNATURES[0]);
+        let mut cart = Cartridge::new(data).unwrap();
+        assert_eq!(cart.scheme(), BankSwitching::E0);
+        assert_eq!(cart.read(0x1000), 4);
+        cart.read(0x1FE2);
+        cart.write(0x1FF1, 0);
+        assert_eq!(cart.read(0x1000), 2);
+        assert_eq!(cart.read(0x1800), 1);
+        assert_eq!(cart.read(0x1C00), 7);
+
+        let mut data = rom(0x4000);
+        data[0x10..0x12].copy_from_slice(&TIGERVISION_SIGNATURE);
+        data[0x20..0x22].copy_from_slice(&TIGERVISION_SIGNATURE);
+        let mut cart = Cartridge::new(data).unwrap();
+        assert_eq!(cart.scheme(), BankSwitching::ThreeF);
+        cart.write(0x003F, 3);
+        assert_eq!(cart.read(0x1000), 6);
+        assert_eq!(cart.read(0x1FFF), 15);
+        // a TIA register above the hotspots
+        cart.write(0x0040, 1);
+        assert_eq!(cart.read(0x1000), 6);
+    }
+}
@@ -0,0 +1,4 @@
+pub mod system;
+pub mod cartridge;
+pub mod riot;
+pub mod tia;
\ No newline at end of file
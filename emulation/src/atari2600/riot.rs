@@ -0,0 +1,187 @@
+use crate::common::ram::{AddressMode, Ram};
+
+const RAM_SIZE: usize = 0x80;
+/// Clocks between decrements, selected by the low address bits of the timer write
+const TIMER_INTERVALS: [u32; 4] = [1, 8, 64, 1024];
+const TIMER_FLAG: u8 = 0x80;
+
+/// Port B bits, the two buttons are active low
+pub const SWITCH_RESET: u8 = 0x01;
+pub const SWITCH_SELECT: u8 = 0x02;
+/// Set for colour, cleared for black and white
+pub const SWITCH_COLOR: u8 = 0x08;
+/// Set for the A (pro) position
+pub const SWITCH_LEFT_DIFFICULTY: u8 = 0x40;
+pub const SWITCH_RIGHT_DIFFICULTY: u8 = 0x80;
+
+/// MOS 6532: the 128 bytes of RAM, the interval timer and the joystick and switch ports
+pub struct Riot {
+    ram: Ram,
+    /// Joystick directions, player 0 in the high nibble, a cleared bit is pushed
+    port_a: u8,
+    port_a_out: u8,
+    ddr_a: u8,
+    port_b: u8,
+    port_b_out: u8,
+    ddr_b: u8,
+    timer: u8,
+    interval: u32,
+    /// Interval written by the program, the timer counts every clock after an underflow
+    programmed_interval: u32,
+    prescaler: u32,
+    flags: u8,
+}
+
+impl Default for Riot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Riot {
+    pub fn new() -> Self {
+        Self {
+            ram: Ram::with_mode(RAM_SIZE, AddressMode::Wrap),
+            port_a: 0xFF,
+            port_a_out: 0,
+            ddr_a: 0,
+            port_b: SWITCH_RESET | SWITCH_SELECT | SWITCH_COLOR,
+            port_b_out: 0,
+            ddr_b: 0,
+            timer: 0,
+            interval: TIMER_INTERVALS[3],
+            programmed_interval: TIMER_INTERVALS[3],
+            prescaler: 0,
+            flags: 0,
+        }
+    }
+
+    /// `addr` has A7 set, A9 selects the I/O and timer registers over the RAM
+    pub fn read(&mut self, addr: u16) -> u8 {
+        if addr & 0x0200 == 0 {
+            return self.ram.read_byte(addr as usize).unwrap_or(0);
+        }
+        match (addr & 0x04 != 0, addr & 0x03) {
+            (false, 0) => (self.port_a_out & self.ddr_a) | (self.port_a & !self.ddr_a),
+            (false, 1) => self.ddr_a,
+            (false, 2) => (self.port_b_out & self.ddr_b) | (self.port_b & !self.ddr_b),
+            (false, _) => self.ddr_b,
+            (true, 0 | 2) => {
+                if self.flags & TIMER_FLAG != 0 {
+                    self.flags &= !TIMER_FLAG;
+                    self.interval = self.programmed_interval;
+                }
+                self.timer
+            }
+            (true, _) => self.flags,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        if addr & 0x0200 == 0 {
+            let _ = self.ram.write_byte(addr as usize, value);
+            return;
+        }
+        match (addr & 0x04 != 0, addr & 0x03) {
+            (false, 0) => self.port_a_out = value,
+            (false, 1) => self.ddr_a = value,
+            (false, 2) => self.port_b_out = value,
+            (false, _) => self.ddr_b = value,
+            // TIM1T, TIM8T, TIM64T and T1024T, the others set up the PA7 edge detection
+            (true, select) if addr & 0x10 != 0 => {
+                self.programmed_interval = TIMER_INTERVALS[select as usize];
+                self.interval = self.programmed_interval;
+                self.timer = value;
+                self.prescaler = 0;
+                self.flags &= !TIMER_FLAG;
+            }
+            _ => {}
+        }
+    }
+
+    /// RAM only, the registers have side effects
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        if addr & 0x0200 == 0 {
+            self.ram.read_byte(addr as usize).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Runs `cycles` CPU cycles
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            if self.prescaler > 0 {
+                self.prescaler -= 1;
+                continue;
+            }
+            let (timer, underflow) = self.timer.overflowing_sub(1);
+            self.timer = timer;
+            if underflow {
+                self.flags |= TIMER_FLAG;
+                self.interval = 1;
+            }
+            self.prescaler = self.interval - 1;
+        }
+    }
+
+    /// `mask` of the direction bits in port A
+    pub fn set_joystick(&mut self, mask: u8, pressed: bool) {
+        if pressed {
+            self.port_a &= !mask;
+        } else {
+            self.port_a |= mask;
+        }
+    }
+
+    /// Reset and select are buttons, held while the key is
+    pub fn set_button(&mut self, mask: u8, pressed: bool) {
+        if pressed {
+            self.port_b &= !mask;
+        } else {
+            self.port_b |= mask;
+        }
+    }
+
+    /// The colour and difficulty switches stay where they were flipped
+    pub fn toggle_switch(&mut self, mask: u8) {
+        self.port_b ^= mask;
+    }
+}
+
+#[cfg(test)]
+mod riot_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_timer() {
+        let mut riot = Riot::new();
+        // TIM64T
+        riot.write(0x0296, 2);
+        riot.tick(1);
+        assert_eq!(riot.read(0x0284), 1);
+        riot.tick(64);
+        assert_eq!(riot.read(0x0284), 0);
+        riot.tick(64);
+        assert_eq!(riot.read(0x0285) & TIMER_FLAG, TIMER_FLAG);
+        // counts every clock after the underflow
+        riot.tick(2);
+        assert_eq!(riot.timer, 0xFD);
+        assert_eq!(riot.read(0x0284), 0xFD);
+        assert_eq!(riot.read(0x0285) & TIMER_FLAG, 0);
+    }
+
+    #[test]
+    fn test_ports_and_ram() {
+        let mut riot = Riot::new();
+        riot.write(0x00FF, 0x42);
+        // the stack page mirrors the RAM
+        assert_eq!(riot.read(0x01FF), 0x42);
+        riot.set_joystick(0x10, true);
+        assert_eq!(riot.read(0x0280), 0xEF);
+        riot.set_button(SWITCH_RESET, true);
+        riot.toggle_switch(SWITCH_LEFT_DIFFICULTY);
+        assert_eq!(riot.read(0x0282), SWITCH_SELECT | SWITCH_COLOR | SWITCH_LEFT_DIFFICULTY);
+    }
+}
@@ -0,0 +1,395 @@
+use crate::atari2600::cartridge::Cartridge;
+use crate::atari2600::riot::{self, Riot};
+use crate::atari2600::tia::{self, Tia};
+use crate::common::bus::Bus;
+use crate::common::emulator::*;
+use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::registry::{Capabilities, SystemInfo};
+use crate::common::utils;
+use crate::mos6502::cpu::{Mos6502, Variant};
+
+/// NTSC colour burst divided by 3
+const CLOCK: u64 = 1_193_182;
+const TIA_CLOCKS_PER_CYCLE: u32 = 3;
+/// The 6507 has 13 address lines
+const ADDRESS_MASK: u16 = 0x1FFF;
+/// Joystick keys of one player, player 2 keys start at this offset
+pub const PLAYER_2: u32 = 8;
+/// Port A bits of the joystick directions of player 0, player 1 uses the low nibble
+const JOYSTICK_BITS: [u8; 4] = [0x10, 0x20, 0x40, 0x80];
+
+/// NTSC colours, indexed by the colour registers without their unused bit 0
+const NTSC_PALETTE: [u32; 128] = [
+    0x000000FF, 0x4A4A4AFF, 0x6F6F6FFF, 0x8E8E8EFF, 0xAAAAAAFF, 0xC0C0C0FF, 0xD6D6D6FF, 0xECECECFF,
+    0x484800FF, 0x69690FFF, 0x86861DFF, 0xA2A22AFF, 0xBBBB35FF, 0xD2D240FF, 0xE8E84AFF, 0xFCFC54FF,
+    0x7C2C00FF, 0x904811FF, 0xA26221FF, 0xB47A30FF, 0xC3903DFF, 0xD2A44AFF, 0xDFB755FF, 0xECC860FF,
+    0x901C00FF, 0xA33915FF, 0xB55328FF, 0xC66C3AFF, 0xD5824AFF, 0xE39759FF, 0xF0AA67FF, 0xFCBC74FF,
+    0x940000FF, 0xA71A1AFF, 0xB83232FF, 0xC84848FF, 0xD65C5CFF, 0xE46F6FFF, 0xF08080FF, 0xFC9090FF,
+    0x840064FF, 0x97197AFF, 0xA8308FFF, 0xB846A2FF, 0xC659B3FF, 0xD46CC3FF, 0xE07CD2FF, 0xEC8CE0FF,
+    0x500084FF, 0x68199AFF, 0x7D30ADFF, 0x9246C0FF, 0xA459D0FF, 0xB56CE0FF, 0xC57CEEFF, 0xD48CFCFF,
+    0x140090FF, 0x331AA3FF, 0x4E32B5FF, 0x6848C6FF, 0x7F5CD5FF, 0x956FE3FF, 0xA980F0FF, 0xBC90FCFF,
+    0x000094FF, 0x181AA7FF, 0x2D32B8FF, 0x4248C8FF, 0x545CD6FF, 0x656FE4FF, 0x7580F0FF, 0x8490FCFF,
+    0x001C88FF, 0x183B9DFF, 0x2D57B0FF, 0x4272C2FF, 0x548AD2FF, 0x65A0E1FF, 0x75B5EFFF, 0x84C8FCFF,
+    0x003064FF, 0x185080FF, 0x2D6D98FF, 0x4288B0FF, 0x54A0C5FF, 0x65B7D9FF, 0x75CCEBFF, 0x84E0FCFF,
+    0x004030FF, 0x18624EFF, 0x2D8169FF, 0x429E82FF, 0x54B899FF, 0x65D1AEFF, 0x75E7C2FF, 0x84FCD4FF,
+    0x004400FF, 0x1A661AFF, 0x328432FF, 0x48A048FF, 0x5CBA5CFF, 0x6FD26FFF, 0x80E880FF, 0x90FC90FF,
+    0x143C00FF, 0x355F18FF, 0x527E2DFF, 0x6E9C42FF, 0x87B754FF, 0x9ED065FF, 0xB4E775FF, 0xC8FC84FF,
+    0x303800FF, 0x505916FF, 0x6D762BFF, 0x88923EFF, 0xA0AB4FFF, 0xB7C25FFF, 0xCCD86EFF, 0xE0EC7CFF,
+    0x482C00FF, 0x694D14FF, 0x866A26FF, 0xA28638FF, 0xBB9F47FF, 0xD2B656FF, 0xE8CC63FF, 0xFCE070FF,
+];
+
+/// Everything the 6507 sees on its bus
+struct AtariBus {
+    tia: Tia,
+    riot: Riot,
+    cartridge: Option<Cartridge>,
+    /// TIA writes of the running instruction, they land once the beam has caught up with it
+    tia_writes: Vec<(u16, u8)>,
+    /// Last value on the data bus, the TIA only drives bits 6 and 7
+    open_bus: u8,
+}
+
+impl AtariBus {
+    fn new(cartridge: Option<Cartridge>) -> Self {
+        Self {
+            tia: Tia::new(),
+            riot: Riot::new(),
+            cartridge,
+            tia_writes: Vec::new(),
+            open_bus: 0,
+        }
+    }
+
+    /// A12 selects the cartridge, otherwise A7 the RIOT over the TIA
+    fn read(&mut self, addr: u16) -> u8 {
+        let addr = addr & ADDRESS_MASK;
+        let value = if addr & 0x1000 != 0 {
+            self.cartridge.as_mut().map_or(self.open_bus, |c| c.read(addr))
+        } else if addr & 0x0080 == 0 {
+            self.tia.read(addr) | (self.open_bus & 0x3F)
+        } else {
+            self.riot.read(addr)
+        };
+        self.open_bus = value;
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        let addr = addr & ADDRESS_MASK;
+        self.open_bus = value;
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.write(addr, value);
+        }
+        if addr & 0x1000 != 0 {
+            return;
+        }
+        if addr & 0x0080 == 0 {
+            self.tia_writes.push((addr, value));
+        } else {
+            self.riot.write(addr, value);
+        }
+    }
+
+    /// Runs the TIA and the RIOT for `cycles` CPU cycles
+    fn tick(&mut self, cycles: u32) {
+        self.tia.tick(cycles * TIA_CLOCKS_PER_CYCLE);
+        self.riot.tick(cycles);
+    }
+}
+
+impl Bus for AtariBus {
+    fn read_byte(&mut self, addr: usize) -> Result<u8, Box<dyn Msg>> {
+        Ok(self.read(addr as u16))
+    }
+
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Box<dyn Msg>> {
+        self.write(addr as u16, value);
+        Ok(())
+    }
+
+    /// Cartridge and RAM, reading the registers has side effects
+    fn peek(&self, addr: usize) -> Option<u8> {
+        let addr = addr as u16 & ADDRESS_MASK;
+        if addr & 0x1000 != 0 {
+            self.cartridge.as_ref().map(|c| c.peek(addr))
+        } else if addr & 0x0080 != 0 {
+            self.riot.peek(addr)
+        } else {
+            None
+        }
+    }
+}
+
+/// NTSC Atari 2600 / VCS
+pub struct Atari2600 {
+    cpu: Mos6502,
+    bus: AtariBus,
+    cycle_count: u128,
+}
+
+impl Default for Atari2600 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Atari2600 {
+    pub fn new() -> Self {
+        Atari2600 {
+            cpu: Mos6502::new(Variant::Nmos),
+            bus: AtariBus::new(None),
+            cycle_count: 0,
+        }
+    }
+
+    /// Plain ROM dumps, recognised by extension only
+    pub fn system_info() -> SystemInfo {
+        SystemInfo {
+            name: "Atari 2600",
+            extensions: &["a26"],
+            capabilities: Capabilities {
+                save_states: false,
+                reset: true,
+                sound: true,
+            },
+            factory: || Box::new(Atari2600::new()),
+            sniff: None,
+        }
+    }
+
+    pub fn cpu(&self) -> &Mos6502 {
+        &self.cpu
+    }
+
+    pub fn load_rom(&mut self, file_name: &str) {
+        let rom = match utils::load_rom(file_name) {
+            Ok(rom) => rom,
+            Err(error) => {
+                log::error!(file = file_name; "Cannot load ROM: {}", error);
+                return;
+            }
+        };
+        match Cartridge::new(rom) {
+            Ok(cartridge) => {
+                log::debug!(scheme:? = cartridge.scheme(); "Cartridge inserted");
+                self.insert(cartridge);
+            }
+            Err(err) => log::error!(file = file_name; "{}", err),
+        }
+    }
+
+    /// Powers the console on with `cartridge`
+    fn insert(&mut self, cartridge: Cartridge) {
+        self.cpu = Mos6502::new(Variant::Nmos);
+        self.bus = AtariBus::new(Some(cartridge));
+        self.cycle_count = 0;
+        if let Err(err) = self.cpu.reset(&mut self.bus) {
+            log::error!("{}", err);
+        }
+    }
+}
+
+impl Emulator for Atari2600 {
+    fn video_buffer(&self) -> &[u8] {
+        self.bus.tia.framebuffer().video()
+    }
+
+    fn palette(&self) -> Palette {
+        Palette::new("NTSC", NTSC_PALETTE.to_vec())
+    }
+
+    /// One instruction, then the beam catches up, the TIA writes land and WSYNC stalls the CPU
+    fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
+        if self.bus.cartridge.is_none() {
+            return Ok(CycleResult::default());
+        }
+        let mut cycles = self.cpu.step(&mut self.bus)?;
+        self.bus.tick(cycles);
+        for (addr, value) in self.bus.tia_writes.drain(..) {
+            self.bus.tia.write(addr, value);
+        }
+        if self.bus.tia.take_wsync() {
+            let stall = self.bus.tia.clocks_to_line_end().div_ceil(TIA_CLOCKS_PER_CYCLE);
+            self.bus.tick(stall);
+            self.cpu.cycles += stall as u64;
+            cycles += stall;
+        }
+
+        self.cycle_count += cycles as u128;
+        Ok(CycleResult {
+            video_buff_changed: self.bus.tia.take_frame(),
+            total_cycle_count: self.cycle_count,
+            last_cycle_count: cycles as u128,
+        })
+    }
+
+    /// The TIA follows the clock in `cycle`, frames end at VSYNC
+    fn end_frame(&mut self) {}
+
+    fn process_input(&mut self, key: u32, pressed: bool) {
+        let (player, key) = if key >= PLAYER_2 { (1, key - PLAYER_2) } else { (0, key) };
+        match key {
+            0..=3 => self.bus.riot.set_joystick(JOYSTICK_BITS[key as usize] >> (player * 4), pressed),
+            4 => self.bus.tia.set_button(player, pressed),
+            5 if player == 0 => self.bus.riot.set_button(riot::SWITCH_RESET, pressed),
+            6 if player == 0 => self.bus.riot.set_button(riot::SWITCH_SELECT, pressed),
+            7 if pressed && player == 0 => self.bus.riot.toggle_switch(riot::SWITCH_COLOR),
+            5 if pressed => self.bus.riot.toggle_switch(riot::SWITCH_LEFT_DIFFICULTY),
+            6 if pressed => self.bus.riot.toggle_switch(riot::SWITCH_RIGHT_DIFFICULTY),
+            _ => {}
+        }
+    }
+
+    fn load_rom(&mut self, file_name: &String) {
+        self.load_rom(file_name);
+    }
+
+    /// The height is the part of the last frame the program did not blank
+    fn resolution(&self) -> [u32; 2] {
+        [tia::WIDTH as u32, self.bus.tia.framebuffer().height() as u32]
+    }
+
+    fn cycles_in_sec(&self) -> u64 {
+        CLOCK
+    }
+
+    fn sound_active(&self) -> bool {
+        self.bus.tia.sound_active()
+    }
+
+    /// The console has no reset line, this restarts the program through the reset vector
+    fn reset(&mut self) {
+        if self.bus.cartridge.is_none() {
+            return;
+        }
+        if let Err(err) = self.cpu.reset(&mut self.bus) {
+            log::error!("{}", err);
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        self.cpu = Mos6502::new(Variant::Nmos);
+        self.bus = AtariBus::new(None);
+        self.cycle_count = 0;
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, Box<dyn Msg>> {
+        let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::NotSupported.into());
+        Err(Box::new(err))
+    }
+
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::NotSupported.into());
+        Err(Box::new(err))
+    }
+
+    fn trace(&self) -> Option<String> {
+        Some(self.cpu.trace(&self.bus))
+    }
+
+    fn set_pc(&mut self, pc: u32) -> Result<(), Box<dyn Msg>> {
+        self.cpu.pc = pc as u16;
+        Ok(())
+    }
+}
+
+/// Joystick and console keys, add `PLAYER_2` for the second joystick. With the offset the
+/// console keys flip the difficulty switches instead
+pub enum Atari2600Keys {
+    Up = 0,
+    Down = 1,
+    Left = 2,
+    Right = 3,
+    Fire = 4,
+    /// Game reset, or the left difficulty switch for player 2
+    Reset = 5,
+    /// Game select, or the right difficulty switch for player 2
+    Select = 6,
+    /// Colour / black and white switch
+    TvType = 7,
+}
+
+#[cfg(test)]
+mod atari2600_tests {
+
+    use super::*;
+
+    /// 4K image, `program` at 0xF000 where the vectors point
+    fn with_program(program: &[u8]) -> Atari2600 {
+        let mut rom = vec![0xEAu8; 0x1000];
+        rom[..program.len()].copy_from_slice(program);
+        rom[0xFFC..].copy_from_slice(&[0x00, 0xF0, 0x00, 0xF0]);
+        let mut atari = Atari2600::new();
+        atari.insert(Cartridge::new(rom).unwrap());
+        atari
+    }
+
+    #[test]
+    fn test_kernel_frame() {
+        let program = [
+            // VSYNC on for three lines, the frame starts after it
+            0xA9, 0x02, // LDA #$02
+            0x85, 0x00, // STA VSYNC
+            0x85, 0x02, // STA WSYNC
+            0x85, 0x02, // STA WSYNC
+            0x85, 0x02, // STA WSYNC
+            0xA9, 0x00, // LDA #$00
+            0x85, 0x00, // STA VSYNC
+            0xA9, 0x1E, // LDA #$1E
+            0x85, 0x09, // STA COLUBK
+            // 40 visible lines, then VBLANK
+            0xA2, 0x28, // LDX #$28
+            0x85, 0x02, // STA WSYNC
+            0xCA, // DEX
+            0xD0, 0xFB, // BNE -5
+            0xA9, 0x02, // LDA #$02
+            0x85, 0x01, // STA VBLANK
+            0x85, 0x02, // STA WSYNC
+            0xA9, 0x00, // LDA #$00
+            0x85, 0x01, // STA VBLANK
+            0x4C, 0x00, 0xF0, // JMP $F000
+        ];
+        let mut atari = with_program(&program);
+        let mut frames = 0;
+        let mut cycles = 0;
+        while frames < 2 {
+            let res = atari.cycle().unwrap();
+            cycles += res.last_cycle_count;
+            if res.video_buff_changed {
+                frames += 1;
+                cycles = 0;
+            }
+        }
+        assert_eq!(atari.resolution(), [160, 40]);
+        assert!(atari.video_buffer().iter().all(|p| *p == 0x0F));
+
+        // one more frame, 3 + 40 + 1 lines of 76 cycles
+        loop {
+            let res = atari.cycle().unwrap();
+            cycles += res.last_cycle_count;
+            if res.video_buff_changed {
+                break;
+            }
+        }
+        let lines = cycles / 76;
+        assert!((43..=45).contains(&lines));
+    }
+
+    #[test]
+    fn test_mirrors_and_inputs() {
+        // LDA SWCHA; STA $01FF, the stack page is the RAM; LDA INPT4; STA $81; JMP $F00A
+        let program = [0xAD, 0x80, 0x02, 0x8D, 0xFF, 0x01, 0xA5, 0x0C, 0x85, 0x81, 0x4C, 0x0A, 0xF0];
+        let mut atari = with_program(&program);
+        atari.process_input(Atari2600Keys::Up as u32, true);
+        atari.process_input(Atari2600Keys::Right as u32 + PLAYER_2, true);
+        atari.process_input(Atari2600Keys::Fire as u32, true);
+        for _ in 0..4 {
+            atari.cycle().unwrap();
+        }
+        assert_eq!(atari.bus.peek(0xFF), Some(0xE7));
+        assert_eq!(atari.bus.peek(0x81).map(|v| v & 0x80), Some(0x00));
+        assert_eq!(atari.cpu().pc, 0xF00A);
+        assert!(atari.trace().unwrap().starts_with("F00A  4C 0A F0  JMP $F00A"));
+    }
+}
@@ -0,0 +1,499 @@
+use crate::common::vram::Vram;
+
+pub const WIDTH: usize = 160;
+/// Lines of the usual NTSC picture, shown until the program has drawn a frame
+pub const DEFAULT_HEIGHT: usize = 192;
+/// Colour clocks of a line, the first 68 are the horizontal blank
+pub const CLOCKS_PER_LINE: u32 = 228;
+const HBLANK: u32 = 68;
+/// HMOVE during the horizontal blank hides the first pixels, the "comb" on the left edge
+const HMOVE_BLANK: u32 = 8;
+/// A program that never syncs still gets its frames shown
+const MAX_LINES: usize = 312;
+
+/// Objects in the collision masks
+const P0: u8 = 0x01;
+const P1: u8 = 0x02;
+const M0: u8 = 0x04;
+const M1: u8 = 0x08;
+const BL: u8 = 0x10;
+const PF: u8 = 0x20;
+
+/// Object pairs latched in bit 7 and bit 6 of CXM0P..=CXPPMM
+const COLLISION_PAIRS: [[u8; 2]; 8] = [
+    [M0 | P1, M0 | P0],
+    [M1 | P0, M1 | P1],
+    [P0 | PF, P0 | BL],
+    [P1 | PF, P1 | BL],
+    [M0 | PF, M0 | BL],
+    [M1 | PF, M1 | BL],
+    [BL | PF, 0],
+    [P0 | P1, M0 | M1],
+];
+
+/// Start of each copy drawn by the NUSIZ number/size modes
+const COPIES: [&[u32]; 8] = [&[0], &[0, 16], &[0, 32], &[0, 16, 32], &[0, 64], &[0], &[0, 32, 64], &[0]];
+
+/// Where a RESPx/RESMx/RESBL strobe puts the object, in the blank and in the picture
+const PLAYER_RESET: (u32, u32) = (3, 5);
+const MISSILE_RESET: (u32, u32) = (2, 4);
+
+const CTRLPF_REFLECT: u8 = 0x01;
+const CTRLPF_SCORE: u8 = 0x02;
+const CTRLPF_PRIORITY: u8 = 0x04;
+
+/// Player or missile and ball, everything the TIA moves horizontally
+#[derive(Debug, Clone, Copy, Default)]
+struct Object {
+    /// Pixel the object starts at, 0..WIDTH
+    position: u32,
+    /// HMxx, the high nibble is a signed move to the left
+    motion: u8,
+}
+
+impl Object {
+    fn reset(&mut self, hpos: u32, offsets: (u32, u32)) {
+        self.position = if hpos < HBLANK {
+            offsets.0
+        } else {
+            (hpos - HBLANK + offsets.1) % WIDTH as u32
+        };
+    }
+
+    fn apply_motion(&mut self) {
+        let shift = (self.motion as i8 >> 4) as i32;
+        self.position = (self.position as i32 - shift).rem_euclid(WIDTH as i32) as u32;
+    }
+
+    /// Offset of pixel `x` from the object start, wrapping around the line
+    fn delta(&self, x: u32) -> u32 {
+        (x + WIDTH as u32 - self.position) % WIDTH as u32
+    }
+}
+
+/// Television Interface Adaptor: the objects are drawn while the beam runs, there is no framebuffer
+pub struct Tia {
+    hpos: u32,
+    vsync: bool,
+    vblank: bool,
+    /// A WSYNC strobe halts the CPU until the end of the line
+    wsync: bool,
+    hmove_blank: bool,
+    colors: [u8; 4],
+    ctrlpf: u8,
+    /// The 20 playfield pixels of the left half, bit 0 first
+    playfield: u32,
+    pf: [u8; 3],
+    nusiz: [u8; 2],
+    reflect: [bool; 2],
+    graphics: [u8; 2],
+    /// Copies taken when the other player's graphics are written, shown with VDELPx
+    old_graphics: [u8; 2],
+    vdel_players: [bool; 2],
+    missile_enabled: [bool; 2],
+    missile_locked: [bool; 2],
+    ball_enabled: bool,
+    old_ball_enabled: bool,
+    vdel_ball: bool,
+    players: [Object; 2],
+    missiles: [Object; 2],
+    ball: Object,
+    collisions: [u8; 8],
+    /// Fire buttons, true while pressed
+    buttons: [bool; 2],
+    audio_control: [u8; 2],
+    audio_frequency: [u8; 2],
+    audio_volume: [u8; 2],
+    line: [u8; WIDTH],
+    line_visible: bool,
+    /// Lines since the end of VSYNC and whether the program unblanked them
+    lines: Vec<(bool, [u8; WIDTH])>,
+    framebuffer: Vram,
+    frame_ready: bool,
+}
+
+impl Default for Tia {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tia {
+    pub fn new() -> Self {
+        Self {
+            hpos: 0,
+            vsync: false,
+            vblank: false,
+            wsync: false,
+            hmove_blank: false,
+            colors: [0; 4],
+            ctrlpf: 0,
+            playfield: 0,
+            pf: [0; 3],
+            nusiz: [0; 2],
+            reflect: [false; 2],
+            graphics: [0; 2],
+            old_graphics: [0; 2],
+            vdel_players: [false; 2],
+            missile_enabled: [false; 2],
+            missile_locked: [false; 2],
+            ball_enabled: false,
+            old_ball_enabled: false,
+            vdel_ball: false,
+            players: [Object::default(); 2],
+            missiles: [Object::default(); 2],
+            ball: Object::default(),
+            collisions: [0; 8],
+            buttons: [false; 2],
+            audio_control: [0; 2],
+            audio_frequency: [0; 2],
+            audio_volume: [0; 2],
+            line: [0; WIDTH],
+            line_visible: false,
+            lines: Vec::with_capacity(MAX_LINES),
+            framebuffer: Vram::new(WIDTH, DEFAULT_HEIGHT),
+            frame_ready: false,
+        }
+    }
+
+    /// Last complete frame, its height follows the lines the program unblanks
+    pub fn framebuffer(&self) -> &Vram {
+        &self.framebuffer
+    }
+
+    /// Whether a frame was completed since the last call
+    pub fn take_frame(&mut self) -> bool {
+        std::mem::take(&mut self.frame_ready)
+    }
+
+    pub fn take_wsync(&mut self) -> bool {
+        std::mem::take(&mut self.wsync)
+    }
+
+    /// Colour clocks until the next line starts
+    pub fn clocks_to_line_end(&self) -> u32 {
+        CLOCKS_PER_LINE - self.hpos
+    }
+
+    pub fn set_button(&mut self, player: usize, pressed: bool) {
+        self.buttons[player] = pressed;
+    }
+
+    /// Whether a channel produces a tone, AUDC 0 and 11 hold the output still
+    pub fn sound_active(&self) -> bool {
+        (0..2).any(|ch| {
+            let control = self.audio_control[ch] & 0x0F;
+            self.audio_volume[ch] & 0x0F > 0 && control != 0x00 && control != 0x0B
+        })
+    }
+
+    /// Frequency divider of a channel, kept for the frontends that synthesize the sound
+    pub fn audio_frequency(&self, channel: usize) -> u8 {
+        self.audio_frequency[channel] & 0x1F
+    }
+
+    /// Collision latches and inputs, only bits 6 and 7 are driven
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr & 0x0F {
+            reg @ 0x00..=0x07 => self.collisions[reg as usize],
+            0x0C => if self.buttons[0] { 0x00 } else { 0x80 },
+            0x0D => if self.buttons[1] { 0x00 } else { 0x80 },
+            // paddles are not emulated, their capacitors never charge
+            _ => 0x00,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr & 0x3F {
+            0x00 => {
+                let vsync = value & 0x02 != 0;
+                if vsync && !self.vsync {
+                    self.end_frame();
+                }
+                self.vsync = vsync;
+            }
+            0x01 => self.vblank = value & 0x02 != 0,
+            0x02 => self.wsync = true,
+            0x04 | 0x05 => self.nusiz[(addr & 0x01) as usize] = value,
+            reg @ 0x06..=0x09 => self.colors[(reg - 0x06) as usize] = value,
+            0x0A => self.ctrlpf = value,
+            0x0B | 0x0C => self.reflect[(addr & 0x3F) as usize - 0x0B] = value & 0x08 != 0,
+            reg @ 0x0D..=0x0F => {
+                self.pf[(reg - 0x0D) as usize] = value;
+                self.update_playfield();
+            }
+            0x10 | 0x11 => self.players[(addr & 0x01) as usize].reset(self.hpos, PLAYER_RESET),
+            0x12 | 0x13 => self.missiles[(addr & 0x01) as usize].reset(self.hpos, MISSILE_RESET),
+            0x14 => self.ball.reset(self.hpos, MISSILE_RESET),
+            0x15 | 0x16 => self.audio_control[(addr & 0x3F) as usize - 0x15] = value,
+            0x17 | 0x18 => self.audio_frequency[(addr & 0x3F) as usize - 0x17] = value,
+            0x19 | 0x1A => self.audio_volume[(addr & 0x3F) as usize - 0x19] = value,
+            0x1B => {
+                self.graphics[0] = value;
+                self.old_graphics[1] = self.graphics[1];
+            }
+            0x1C => {
+                self.graphics[1] = value;
+                self.old_graphics[0] = self.graphics[0];
+                self.old_ball_enabled = self.ball_enabled;
+            }
+            0x1D | 0x1E => self.missile_enabled[(addr & 0x3F) as usize - 0x1D] = value & 0x02 != 0,
+            0x1F => self.ball_enabled = value & 0x02 != 0,
+            0x20 | 0x21 => self.players[(addr & 0x01) as usize].motion = value,
+            0x22 | 0x23 => self.missiles[(addr & 0x01) as usize].motion = value,
+            0x24 => self.ball.motion = value,
+            0x25 | 0x26 => self.vdel_players[(addr & 0x3F) as usize - 0x25] = value & 0x01 != 0,
+            0x27 => self.vdel_ball = value & 0x01 != 0,
+            0x28 | 0x29 => {
+                let player = (addr & 0x3F) as usize - 0x28;
+                self.missile_locked[player] = value & 0x02 != 0;
+                self.lock_missile(player);
+            }
+            0x2A => self.hmove(),
+            0x2B => {
+                for object in self.players.iter_mut().chain(self.missiles.iter_mut()) {
+                    object.motion = 0;
+                }
+                self.ball.motion = 0;
+            }
+            0x2C => self.collisions = [0; 8],
+            _ => {}
+        }
+    }
+
+    /// Runs `clocks` colour clocks, three per CPU cycle
+    pub fn tick(&mut self, clocks: u32) {
+        for _ in 0..clocks {
+            if self.hpos >= HBLANK {
+                self.draw_pixel(self.hpos - HBLANK);
+            }
+            self.hpos += 1;
+            if self.hpos == CLOCKS_PER_LINE {
+                self.hpos = 0;
+                self.end_line();
+            }
+        }
+    }
+
+    fn draw_pixel(&mut self, x: u32) {
+        let mut objects = 0;
+        if self.playfield_pixel(x) {
+            objects |= PF;
+        }
+        for player in 0..2 {
+            if self.player_pixel(player, x) {
+                objects |= P0 << player;
+            }
+            if self.missile_pixel(player, x) {
+                objects |= M0 << player;
+            }
+        }
+        let ball_size = 1 << ((self.ctrlpf >> 4) & 0x03);
+        let ball_enabled = if self.vdel_ball { self.old_ball_enabled } else { self.ball_enabled };
+        if ball_enabled && self.ball.delta(x) < ball_size {
+            objects |= BL;
+        }
+        if objects.count_ones() > 1 {
+            for (register, pairs) in self.collisions.iter_mut().zip(COLLISION_PAIRS.iter()) {
+                for (bit, pair) in pairs.iter().enumerate() {
+                    if *pair != 0 && objects & pair == *pair {
+                        *register |= 0x80 >> bit;
+                    }
+                }
+            }
+        }
+
+        if self.vblank || (self.hmove_blank && x < HMOVE_BLANK) {
+            self.line[x as usize] = 0;
+            return;
+        }
+        self.line_visible = true;
+        let [colup0, colup1, colupf, colubk] = self.colors;
+        let playfield_color = match self.ctrlpf & CTRLPF_SCORE != 0 && objects & PF != 0 {
+            true if x < WIDTH as u32 / 2 => colup0,
+            true => colup1,
+            false => colupf,
+        };
+        let color = if self.ctrlpf & CTRLPF_PRIORITY != 0 && objects & (PF | BL) != 0 {
+            playfield_color
+        } else if objects & (P0 | M0) != 0 {
+            colup0
+        } else if objects & (P1 | M1) != 0 {
+            colup1
+        } else if objects & (PF | BL) != 0 {
+            playfield_color
+        } else {
+            colubk
+        };
+        // the lowest bit of a colour register is not wired
+        self.line[x as usize] = color >> 1;
+    }
+
+    fn playfield_pixel(&self, x: u32) -> bool {
+        let mut index = x / 4;
+        if index >= 20 {
+            index = if self.ctrlpf & CTRLPF_REFLECT != 0 { 39 - index } else { index - 20 };
+        }
+        self.playfield & (1 << index) != 0
+    }
+
+    fn player_pixel(&self, player: usize, x: u32) -> bool {
+        let graphics = if self.vdel_players[player] { self.old_graphics[player] } else { self.graphics[player] };
+        if graphics == 0 {
+            return false;
+        }
+        let mode = (self.nusiz[player] & 0x07) as usize;
+        let scale = match mode {
+            5 => 2,
+            7 => 4,
+            _ => 1,
+        };
+        let delta = self.players[player].delta(x);
+        COPIES[mode].iter().any(|start| {
+            if delta < *start || delta >= start + 8 * scale {
+                return false;
+            }
+            let bit = (delta - start) / scale;
+            let bit = if self.reflect[player] { bit } else { 7 - bit };
+            graphics & (1 << bit) != 0
+        })
+    }
+
+    fn missile_pixel(&self, missile: usize, x: u32) -> bool {
+        if !self.missile_enabled[missile] || self.missile_locked[missile] {
+            return false;
+        }
+        let size = 1 << ((self.nusiz[missile] >> 4) & 0x03);
+        let delta = self.missiles[missile].delta(x);
+        COPIES[(self.nusiz[missile] & 0x07) as usize]
+            .iter()
+            .any(|start| delta >= *start && delta < start + size)
+    }
+
+    /// PF0 bits 4..=7, PF1 bits 7..=0 and PF2 bits 0..=7, left to right
+    fn update_playfield(&mut self) {
+        let [pf0, pf1, pf2] = self.pf.map(|pf| pf as u32);
+        self.playfield = (pf0 >> 4) | (pf1.reverse_bits() >> 24) << 4 | pf2 << 12;
+    }
+
+    /// RESMPx keeps the missile hidden and centred on its player
+    fn lock_missile(&mut self, player: usize) {
+        if self.missile_locked[player] {
+            let center = match self.nusiz[player] & 0x07 {
+                5 => 6,
+                7 => 10,
+                _ => 3,
+            };
+            self.missiles[player].position = (self.players[player].position + center) % WIDTH as u32;
+        }
+    }
+
+    fn hmove(&mut self) {
+        for object in self.players.iter_mut().chain(self.missiles.iter_mut()) {
+            object.apply_motion();
+        }
+        self.ball.apply_motion();
+        for player in 0..2 {
+            self.lock_missile(player);
+        }
+        if self.hpos < HBLANK {
+            self.hmove_blank = true;
+        }
+    }
+
+    fn end_line(&mut self) {
+        if !self.vsync {
+            self.lines.push((self.line_visible, self.line));
+        }
+        self.line_visible = false;
+        self.hmove_blank = false;
+        if self.lines.len() >= MAX_LINES {
+            self.end_frame();
+        }
+    }
+
+    /// Crops the lines blanked above and below the picture, the height is whatever is left
+    fn end_frame(&mut self) {
+        let first = self.lines.iter().position(|(visible, _)| *visible);
+        let last = self.lines.iter().rposition(|(visible, _)| *visible);
+        if let (Some(first), Some(last)) = (first, last) {
+            let height = last - first + 1;
+            if self.framebuffer.height() != height {
+                self.framebuffer = Vram::new(WIDTH, height);
+            }
+            let pixels: Vec<u8> = self.lines[first..=last].iter().flat_map(|(_, line)| line.iter().copied()).collect();
+            self.framebuffer.load(&pixels);
+        } else {
+            self.framebuffer.clear();
+        }
+        self.lines.clear();
+        self.frame_ready = true;
+    }
+}
+
+#[cfg(test)]
+mod tia_tests {
+
+    use super::*;
+
+    /// Runs whole lines from the start of a line
+    fn run_lines(tia: &mut Tia, lines: u32) {
+        tia.tick(lines * CLOCKS_PER_LINE);
+    }
+
+    #[test]
+    fn test_playfield_and_height() {
+        let mut tia = Tia::new();
+        tia.write(0x08, 0x1E);
+        tia.write(0x09, 0x80);
+        // the leftmost and the rightmost pixels of the left half
+        tia.write(0x0D, 0x10);
+        tia.write(0x0F, 0x80);
+        tia.write(0x0A, CTRLPF_REFLECT);
+        tia.write(0x01, 0x02);
+        run_lines(&mut tia, 10);
+        tia.write(0x01, 0x00);
+        run_lines(&mut tia, 100);
+        tia.write(0x01, 0x02);
+        run_lines(&mut tia, 5);
+        tia.write(0x00, 0x02);
+        assert!(tia.take_frame());
+        assert_eq!(tia.framebuffer().height(), 100);
+        let pixels = tia.framebuffer().video();
+        assert_eq!(&pixels[..5], &[0x0F, 0x0F, 0x0F, 0x0F, 0x40]);
+        assert_eq!(pixels[75], 0x40);
+        assert!(pixels[76..84].iter().all(|p| *p == 0x0F));
+        assert_eq!(pixels[159], 0x0F);
+    }
+
+    #[test]
+    fn test_players_and_collisions() {
+        let mut tia = Tia::new();
+        // RESP0 in the blank, then 4 pixels left and back with HMOVE
+        tia.write(0x10, 0);
+        tia.players[0].motion = 0x40;
+        tia.write(0x2A, 0);
+        tia.players[0].motion = 0xC0;
+        tia.write(0x2A, 0);
+        assert_eq!(tia.players[0].position, 3);
+        tia.write(0x04, 0x01);
+        tia.write(0x1B, 0x81);
+        tia.write(0x06, 0x44);
+        tia.write(0x0D, 0xF0);
+        run_lines(&mut tia, 1);
+        assert_eq!(tia.line[3], 0x00);
+        run_lines(&mut tia, 1);
+        let line = tia.line;
+        // two close copies, 16 pixels apart and drawn over the playfield
+        assert_eq!(line[3], 0x22);
+        assert_eq!(line[10], 0x22);
+        assert_eq!(line[19], 0x22);
+        assert_eq!(line[11], 0x00);
+        assert_eq!(tia.read(0x02) & 0x80, 0x80);
+        assert_eq!(tia.read(0x07), 0x00);
+        tia.write(0x2C, 0);
+        assert_eq!(tia.read(0x02), 0x00);
+        tia.set_button(0, true);
+        assert_eq!(tia.read(0x0C), 0x00);
+    }
+}
@@ -1,21 +1,53 @@
 
+use crate::chip8::config::{Chip8Config, Profile, BUTTON_NAMES, START_ADDRESS};
+use crate::chip8::megachip::{self, BlendMode, MegaChip, SOUND_HEADER_SIZE};
+use crate::chip8::rom_db::{RomDb, RomInfo};
+use crate::common::bus::{Bus, MappedBus, OpenBus};
 use crate::common::emulator::*;
 use crate::common::input::*;
 use crate::common::message::*;
-use crate::common::ram::Ram;
+use crate::common::palette::{self, Palette};
+use crate::common::registry::{Capabilities, SystemInfo};
 use crate::common::utils;
 use crate::common::vram::Vram;
 
 use rand::thread_rng;
 use rand::Rng;
+use std::ops::RangeInclusive;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const MEMORY_SIZE: usize = 4096;
+/// MEGA-CHIP programs address 16M with the 24-bit I of `01nn nnnn`
+const MEGACHIP_MEMORY_SIZE: usize = 0x1000000;
 const REGISTERS_COUNT: usize = 16;
-const START_ADDRESS: usize = 0x200;
 const STACK_LEVELS: usize = 16;
-const COLOR: u32 = 0x00FF00FF;
 const KEY_COUNT: usize = 16;
+/// Keys of the CHIP-8X second keypad start here
+pub const KEYPAD_2: u32 = 16;
+/// Logical buttons (`BUTTON_NAMES` order) start here, the ROM's key layout picks their CHIP-8 key
+pub const BUTTONS: u32 = 32;
+/// The whole address space is a single RAM region
+const RAM_REGION: &str = "ram";
+/// Addresses past 4K wrap around up to the end of the 16-bit I register range
+const ADDRESS_SPACE: usize = 0x10000;
+/// Save state header, followed by the format version
+const STATE_MAGIC: &[u8; 4] = b"OXC8";
+const STATE_VERSION: u8 = 3;
+
+/// The VP-590 colours the screen in 8x1 pixel zones, `BXY0` sets them in blocks of four rows
+const ZONE_WIDTH: usize = 8;
+const ZONE_COLUMNS: usize = 8;
+const ZONE_ROWS: usize = 32;
+const ZONE_BLOCK_HEIGHT: usize = 4;
+/// Zones are red until the program colours them
+const DEFAULT_ZONE_COLOR: u8 = 1;
+/// `02A0` steps through the first four colours, the zones use the eight after them
+const CHIP8X_BACKGROUNDS: u8 = 4;
+const CHIP8X_COLORS: [u32; 12] = [
+    0x000080FF, 0x000000FF, 0x008000FF, 0x800000FF, // blue, black, green, red
+    0x000000FF, 0xFF0000FF, 0x0000FFFF, 0xFF00FFFF, // black, red, blue, magenta
+    0x00FF00FF, 0xFFFF00FF, 0x00FFFFFF, 0xFFFFFFFF, // green, yellow, cyan, white
+];
 
 const FONTSET_START_ADDRESS: usize = 0x50;
 const FONT_SET: [u8; 80] = [
@@ -38,25 +70,40 @@ const FONT_SET: [u8; 80] = [
 ];
 
 pub struct Chip8 {
-    memory: Ram,
+    memory: MappedBus,
     video_memory: Vram,
     registers: Vec<u8>,
     stack: Vec<u16>,
     pc: u16,
     sp: u8,
-    index: u16,
+    /// 16 bits wide, 24 on MEGA-CHIP
+    index: u32,
     opcode: u16,
     delay_timer: u8,
     sound_timer: u8,
     keypad: Vec<u8>,
+    /// CHIP-8X VP-580 keypad
+    keypad_2: Vec<u8>,
     active: bool,
     cycle_count: u128,
+    /// A sprite was drawn in this frame, used by the vblank quirk
+    drawn_in_frame: bool,
+    config: Chip8Config,
+    profile: Profile,
+    /// CHIP-8X colour of every zone and of the background
+    color_zones: Vec<u8>,
+    background: u8,
+    /// CHIP-8X screen with the colours applied, indices into `CHIP8X_COLORS`
+    color_video: Vram,
+    megachip: MegaChip,
+    rom_db: RomDb,
+    rom_info: Option<RomInfo>,
 }
 
 impl Chip8 {
     pub fn new() -> Self {
         Chip8 {
-            memory: Chip8::init_memory(),
+            memory: Chip8::init_memory(MEMORY_SIZE),
             video_memory: Vram::new(64, 32),
             registers: vec![0u8; REGISTERS_COUNT],
             stack: vec![0u16; STACK_LEVELS],
@@ -67,13 +114,85 @@ impl Chip8 {
             delay_timer: 0,
             sound_timer: 0,
             keypad: vec![0u8; KEY_COUNT],
+            keypad_2: vec![0u8; KEY_COUNT],
             active: false,
             cycle_count: 0,
+            drawn_in_frame: false,
+            config: Chip8Config::default(),
+            profile: Profile::Chip8,
+            color_zones: vec![DEFAULT_ZONE_COLOR; ZONE_COLUMNS * ZONE_ROWS],
+            background: 0,
+            color_video: Vram::new(64, 32),
+            megachip: MegaChip::new(),
+            rom_db: RomDb::default(),
+            rom_info: None,
+        }
+    }
+
+    /// CHIP-8 programs carry no header, they are recognised by the extension only
+    pub fn system_info() -> SystemInfo {
+        SystemInfo {
+            name: "CHIP-8",
+            extensions: &["ch8", "c8", "sc8", "xo8", "c8x", "c8h", "mc8"],
+            capabilities: Capabilities {
+                save_states: true,
+                reset: true,
+                sound: true,
+            },
+            factory: || Box::new(Chip8::new()),
+            sniff: None,
+        }
+    }
+
+    pub fn config(&self) -> &Chip8Config {
+        &self.config
+    }
+
+    /// Also switches the profile and the screen size of the platform
+    pub fn set_config(&mut self, config: Chip8Config) {
+        let [width, height] = config.resolution.map(|size| size as usize);
+        if self.video_memory.width() != width || self.video_memory.height() != height {
+            self.video_memory = Vram::new(width, height);
+            self.color_video = Vram::new(width, height);
+        }
+        self.profile = config.profile();
+        self.config = config;
+        if self.memory.ram(RAM_REGION).map(|ram| ram.size()) != Some(self.memory_size()) {
+            self.memory = Chip8::init_memory(self.memory_size());
+        }
+        self.megachip.reset();
+        self.reset_colors();
+    }
+
+    pub fn rom_db_mut(&mut self) -> &mut RomDb {
+        &mut self.rom_db
+    }
+
+    pub fn rom_info(&self) -> Option<&RomInfo> {
+        self.rom_info.as_ref()
+    }
+
+    fn memory_size(&self) -> usize {
+        match self.profile {
+            Profile::MegaChip => MEGACHIP_MEMORY_SIZE,
+            _ => MEMORY_SIZE,
         }
     }
 
-    fn init_memory() -> Ram {
-        let mut memory = Ram::new(MEMORY_SIZE);
+    /// I wraps around at the end of its range
+    fn address_mask(&self) -> u32 {
+        match self.profile {
+            Profile::MegaChip => 0xFFFFFF,
+            _ => 0xFFFF,
+        }
+    }
+
+    fn init_memory(size: usize) -> MappedBus {
+        let mut memory = MappedBus::new(OpenBus::Error);
+        memory.map_ram(RAM_REGION, 0, size);
+        if size < ADDRESS_SPACE {
+            memory.map_mirror("ram mirror", size, ADDRESS_SPACE - size, 0, size);
+        }
         let mut i = FONTSET_START_ADDRESS;
         for byte in FONT_SET {
             let res = memory.write_byte(i, byte);
@@ -88,6 +207,117 @@ impl Chip8 {
         memory
     }
 
+    /// Soft reset, the program stays in memory
+    fn reset_cpu(&mut self) {
+        self.video_memory.clear();
+        self.registers.fill(0);
+        self.stack.fill(0);
+        self.pc = self.profile.start_address() as u16;
+        self.sp = 0;
+        self.index = 0;
+        self.opcode = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.keypad.fill(0);
+        self.keypad_2.fill(0);
+        self.drawn_in_frame = false;
+        self.megachip.reset();
+        self.reset_colors();
+    }
+
+    fn reset_colors(&mut self) {
+        self.color_zones.fill(DEFAULT_ZONE_COLOR);
+        self.background = 0;
+        self.compose_colors();
+    }
+
+    /// CHIP-8X picture: the zone colour where a pixel is lit, the background elsewhere
+    fn compose_colors(&mut self) {
+        if self.profile != Profile::Chip8X {
+            return;
+        }
+        let width = self.video_memory.width();
+        for addr in 0..self.video_memory.size() {
+            let (x, y) = (addr % width, addr / width);
+            let pixel = if self.video_memory.read_pixel(addr) != palette::BACKGROUND {
+                let zone = (y % ZONE_ROWS) * ZONE_COLUMNS + (x / ZONE_WIDTH) % ZONE_COLUMNS;
+                CHIP8X_BACKGROUNDS + self.color_zones[zone]
+            } else {
+                self.background
+            };
+            self.color_video.write_pixel(addr, pixel);
+        }
+    }
+
+    /// Memory, screen, registers, stack and timers; the config is restored from the ROM.
+    /// The MEGA-CHIP state follows on that platform
+    fn write_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(self.memory_size() + self.video_memory.size() + self.color_zones.len() + 64);
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+        if let Some(ram) = self.memory.ram(RAM_REGION) {
+            state.extend_from_slice(ram.data());
+        }
+        state.extend_from_slice(self.video_memory.video());
+        state.extend_from_slice(&self.registers);
+        for level in &self.stack {
+            state.extend_from_slice(&level.to_be_bytes());
+        }
+        state.extend_from_slice(&self.pc.to_be_bytes());
+        state.extend_from_slice(&self.index.to_be_bytes());
+        state.extend_from_slice(&[self.sp, self.delay_timer, self.sound_timer, self.active as u8]);
+        state.extend_from_slice(&self.color_zones);
+        state.push(self.background);
+        if self.profile == Profile::MegaChip {
+            self.megachip.write_state(&mut state);
+        }
+        state
+    }
+
+    fn read_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        let memory_size = self.memory_size();
+        let vram_size = self.video_memory.size();
+        let zones = self.color_zones.len();
+        let megachip_size = match self.profile {
+            Profile::MegaChip => MegaChip::state_size(),
+            _ => 0,
+        };
+        let expected = STATE_MAGIC.len() + 1 + memory_size + vram_size + REGISTERS_COUNT + STACK_LEVELS * 2
+            + 10 + zones + 1 + megachip_size;
+        if state.len() != expected || &state[..4] != STATE_MAGIC || state[4] != STATE_VERSION {
+            let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::InvalidFormat.into())
+                .add_param(state.len().to_string());
+            return Err(Box::new(err));
+        }
+        let (memory, rest) = state[5..].split_at(memory_size);
+        let (video, rest) = rest.split_at(vram_size);
+        let (registers, rest) = rest.split_at(REGISTERS_COUNT);
+        let (stack, rest) = rest.split_at(STACK_LEVELS * 2);
+        let (rest, megachip) = rest.split_at(rest.len() - megachip_size);
+        if let Some(ram) = self.memory.ram_mut(RAM_REGION) {
+            ram.load(memory)?;
+        }
+        if self.profile == Profile::MegaChip {
+            self.megachip.read_state(megachip)?;
+        }
+        self.video_memory.load(video);
+        self.registers.copy_from_slice(registers);
+        for (level, bytes) in self.stack.iter_mut().zip(stack.chunks_exact(2)) {
+            *level = u16::from_be_bytes([bytes[0], bytes[1]]);
+        }
+        self.pc = u16::from_be_bytes([rest[0], rest[1]]);
+        self.index = u32::from_be_bytes([rest[2], rest[3], rest[4], rest[5]]);
+        self.sp = rest[6];
+        self.delay_timer = rest[7];
+        self.sound_timer = rest[8];
+        self.active = rest[9] != 0;
+        self.color_zones.copy_from_slice(&rest[10..10 + zones]);
+        self.background = rest[10 + zones] % CHIP8X_BACKGROUNDS;
+        self.drawn_in_frame = false;
+        self.compose_colors();
+        Ok(())
+    }
+
     fn decode(opcode: &u16) -> u16 {
         let code = opcode & 0xF000;
         match code >> 12 {
@@ -102,26 +332,69 @@ impl Chip8 {
     }
 
     fn do_cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
-        let opcode = self.memory.read_word(self.pc as usize)?;
-        self.pc += 2;
+        let pc = self.pc;
+        let opcode = match self.memory.read_word_be(pc as usize) {
+            Ok(opcode) => opcode,
+            Err(_) => return Err(self.memory_fault(pc as usize)),
+        };
+        self.pc = pc.wrapping_add(2);
         self.opcode = opcode;
         let res = self.exec_intruction();
-        if self.delay_timer > 0 {
-            self.delay_timer -= 1
-        }
-        if self.sound_timer > 0 {
-            self.sound_timer -= 1
+        match &res {
+            Ok(result) if result.video_buff_changed => self.compose_colors(),
+            // leave PC on the faulting instruction for the debugger
+            Err(_) => self.pc = pc,
+            _ => {}
         }
         res
     }
 
+    /// Guest program error, reported with the address and the opcode of the faulting instruction
+    fn fault(&self, msg_id: ErrorMsgId) -> ErrorMsg {
+        ErrorMsg::new(ErrorTopicId::GuestFault.into(), msg_id.into())
+            .add_param(format!("{:#05X}", self.pc.wrapping_sub(2)))
+            .add_param(format!("{:#06X}", self.opcode))
+    }
+
+    fn memory_fault(&self, addr: usize) -> Box<dyn Msg> {
+        Box::new(self.fault(ErrorMsgId::MemoryFault).add_param(format!("{:#X}", addr)))
+    }
+
+    fn read_memory(&mut self, addr: usize) -> Result<u8, Box<dyn Msg>> {
+        match self.memory.read_byte(addr) {
+            Ok(value) => Ok(value),
+            Err(_) => Err(self.memory_fault(addr)),
+        }
+    }
+
+    fn write_memory(&mut self, addr: usize, value: u8) -> Result<(), Box<dyn Msg>> {
+        match self.memory.write_byte(addr, value) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(self.memory_fault(addr)),
+        }
+    }
+
     pub fn load_rom(&mut self, file_name: &String) {
         match utils::load_rom(file_name) {
-            Err(error) => print!("Cannot load rom {}", error),
+            Err(error) => log::error!(file = file_name.as_str(); "Cannot load ROM: {}", error),
             Ok(result) => {
-                let load_res = self.memory.write_block(START_ADDRESS, result);
+                let mut info = self.rom_db.info(&result);
+                log::debug!(file = file_name.as_str(), sha1 = info.sha1.as_str(), size = result.len(); "ROM identified");
+                let config = match Profile::platform_of_file(file_name) {
+                    Some(platform) if !self.rom_db.contains(&result) => self.rom_db.platform_config(platform),
+                    _ => self.rom_db.config(&result),
+                };
+                info.platform = config.platform.clone();
+                self.rom_info = Some(info);
+                self.set_config(config);
+                self.pc = self.profile.start_address() as u16;
+                let load_address = self.profile.load_address();
+                let load_res = match self.memory.ram_mut(RAM_REGION) {
+                    Some(ram) => ram.write_block(load_address, result),
+                    None => Ok(()),
+                };
                 match load_res {
-                    Err(error) => print!("Cannot write to memory. {}", error),
+                    Err(error) => log::error!(file = file_name.as_str(); "Cannot write ROM to memory: {}", error),
                     _ => self.active = true,
                 }
             }
@@ -133,21 +406,31 @@ impl Chip8 {
         let mut res = CycleResult::default();
         res.total_cycle_count = self.cycle_count;
         res.last_cycle_count = 1;
+        if self.profile == Profile::Chip8X && self.exec_chip8x_instruction()? {
+            res.video_buff_changed = matches!(self.opcode & 0xF000, 0x0000 | 0xB000);
+            return Ok(res);
+        }
+        if self.profile == Profile::MegaChip && self.exec_megachip_instruction()? {
+            res.video_buff_changed = matches!(self.opcode & 0xF000, 0x0000 | 0xD000);
+            return Ok(res);
+        }
         match Chip8::decode(&self.opcode) {
             0x000E => {
-                self.op_00ee();
+                self.op_00ee()?;
                 Ok(res)
             }
             0x1000 => {
                 self.op_1nnn();
                 Ok(res)
             }
+            // 0230 of the hi-res interpreter clears its screen too
             0x0000 => {
                 self.op_00e0();
+                res.video_buff_changed = true;
                 Ok(res)
             }
             0x2000 => {
-                self.op_2nnn();
+                self.op_2nnn()?;
                 Ok(res)
             }
             0x3000 => {
@@ -223,16 +506,15 @@ impl Chip8 {
                 Ok(res)
             }
             0xD000 => {
-                self.op_Dxyn();
-                res.video_buff_changed = true;
+                res.video_buff_changed = self.op_Dxyn()?;
                 Ok(res)
             }
             0xE00E => {
-                self.op_Ex9E();
+                self.op_Ex9E()?;
                 Ok(res)
             }
             0xE001 => {
-                self.op_ExA1();
+                self.op_ExA1()?;
                 Ok(res)
             }
             0xF007 => {
@@ -252,15 +534,15 @@ impl Chip8 {
                 Ok(res)
             }
             0xF033 => {
-                self.op_Fx33();
+                self.op_Fx33()?;
                 Ok(res)
             }
             0xF055 => {
-                self.op_Fx55();
+                self.op_Fx55()?;
                 Ok(res)
             }
             0xF065 => {
-                self.op_Fx65();
+                self.op_Fx65()?;
                 Ok(res)
             }
             0xF00A => {
@@ -271,15 +553,56 @@ impl Chip8 {
                 self.op_Fx1E();
                 Ok(res)
             }
-            y => {
-                let err = ErrorMsg::new(
-                    ErrorTopicId::Emulator.into(),
-                    ErrorMsgId::UnknownInstruction.into(),
-                )
-                .add_param(y.to_string());
-                Err(Box::new(err))
-            }
+            _ => Err(Box::new(self.fault(ErrorMsgId::UnknownInstruction))),
+        }
+    }
+
+    /// CHIP-8X additions, false for the instructions it shares with CHIP-8
+    fn exec_chip8x_instruction(&mut self) -> Result<bool, Box<dyn Msg>> {
+        let opcode = self.opcode;
+        match opcode {
+            0x02A0 => self.op_02a0(),
+            _ if opcode & 0xF00F == 0x5001 => self.op_5xy1(),
+            _ if opcode & 0xF000 == 0xB000 => self.op_Bxyn(),
+            _ if opcode & 0xF0FF == 0xE0F2 => self.op_ExF2()?,
+            _ if opcode & 0xF0FF == 0xE0F5 => self.op_ExF5()?,
+            _ if opcode & 0xF0FF == 0xF0F8 => self.op_FxF8(),
+            _ if opcode & 0xF0FF == 0xF0FB => self.op_FxFB(),
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// MEGA-CHIP additions and the instructions it changes once enabled, false for the others
+    fn exec_megachip_instruction(&mut self) -> Result<bool, Box<dyn Msg>> {
+        let opcode = self.opcode;
+        let enabled = self.megachip.is_enabled();
+        let byte = (opcode & 0x00FF) as u8;
+        match opcode {
+            0x0010 => self.megachip.set_enabled(false),
+            0x0011 => self.megachip.set_enabled(true),
+            0x00E0 if enabled => self.megachip.clear(),
+            0x00FB if enabled => self.megachip.scroll_right(),
+            0x00FC if enabled => self.megachip.scroll_left(),
+            _ if enabled && opcode & 0xFFF0 == 0x00B0 => self.megachip.scroll_up((opcode & 0x000F) as usize),
+            _ if enabled && opcode & 0xFFF0 == 0x00C0 => self.megachip.scroll_down((opcode & 0x000F) as usize),
+            _ if opcode & 0xFF00 == 0x0100 => self.op_01nn()?,
+            _ if opcode & 0xFF00 == 0x0200 => self.op_02nn()?,
+            _ if opcode & 0xFF00 == 0x0300 => self.megachip.set_sprite_width(byte),
+            _ if opcode & 0xFF00 == 0x0400 => self.megachip.set_sprite_height(byte),
+            _ if opcode & 0xFF00 == 0x0500 => self.megachip.set_alpha(byte),
+            _ if opcode & 0xFFF0 == 0x0600 => self.op_060n()?,
+            0x0700 => self.megachip.stop(),
+            _ if opcode & 0xFFF0 == 0x0800 => self.megachip.set_blend(BlendMode::from_nibble(byte & 0x0F)),
+            _ if opcode & 0xFF00 == 0x0900 => self.megachip.set_collision_color(byte),
+            _ if enabled && opcode & 0xF000 == 0xD000 => self.op_Dxyn_megachip()?,
+            _ => return Ok(false),
         }
+        Ok(true)
+    }
+
+    fn read_block(&mut self, addr: usize, len: usize) -> Result<Vec<u8>, Box<dyn Msg>> {
+        (addr..addr + len).map(|addr| self.read_memory(addr)).collect()
     }
 
     fn get_time() -> u128 {
@@ -294,10 +617,49 @@ impl Chip8 {
         self.video_memory.clear();
     }
 
+    //02A0 - CHIP-8X. Step the background colour
+    fn op_02a0(&mut self) {
+        self.background = (self.background + 1) % CHIP8X_BACKGROUNDS;
+    }
+
+    //01nn nnnn - MEGA-CHIP. Set I = nnnnnn, the low 16 bits are the next word
+    fn op_01nn(&mut self) -> Result<(), Box<dyn Msg>> {
+        let low = match self.memory.read_word_be(self.pc as usize) {
+            Ok(word) => word,
+            Err(_) => return Err(self.memory_fault(self.pc as usize)),
+        };
+        self.index = ((self.opcode as u32 & 0x00FF) << 16) | low as u32;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    //02nn - MEGA-CHIP. Load nn ARGB colours from I into the palette
+    fn op_02nn(&mut self) -> Result<(), Box<dyn Msg>> {
+        let count = (self.opcode & 0x00FF) as usize;
+        let colors = self.read_block(self.index as usize, count * 4)?;
+        self.megachip.load_palette(&colors);
+        Ok(())
+    }
+
+    //060n - MEGA-CHIP. Play the sample at I, looping with n = 0
+    fn op_060n(&mut self) -> Result<(), Box<dyn Msg>> {
+        let addr = self.index as usize;
+        let header = self.read_block(addr, SOUND_HEADER_SIZE)?;
+        let rate = u16::from_be_bytes([header[0], header[1]]) as u32;
+        let length = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+        let data = self.read_block(addr + SOUND_HEADER_SIZE, length)?;
+        self.megachip.play(rate, data, self.opcode & 0x000F == 0);
+        Ok(())
+    }
+
     //RET
-    fn op_00ee(&mut self) {
+    fn op_00ee(&mut self) -> Result<(), Box<dyn Msg>> {
+        if self.sp == 0 {
+            return Err(Box::new(self.fault(ErrorMsgId::StackUnderflow)));
+        }
         self.sp -= 1;
         self.pc = self.stack[self.sp as usize];
+        Ok(())
     }
 
     //JP
@@ -306,10 +668,14 @@ impl Chip8 {
     }
 
     //CALL addr
-    fn op_2nnn(&mut self) {
+    fn op_2nnn(&mut self) -> Result<(), Box<dyn Msg>> {
+        if self.sp as usize >= STACK_LEVELS {
+            return Err(Box::new(self.fault(ErrorMsgId::StackOverflow)));
+        }
         self.stack[self.sp as usize] = self.pc;
         self.sp += 1;
         self.pc = self.opcode & 0x0FFF;
+        Ok(())
     }
 
     //SE Vx, byte - skip if equals
@@ -317,7 +683,7 @@ impl Chip8 {
         let vx = (self.opcode & 0x0F00) >> 8;
         let byte = self.opcode & 0x00FF;
         if self.registers[vx as usize] == byte as u8 {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -326,7 +692,7 @@ impl Chip8 {
         let vx = (self.opcode & 0x0F00) >> 8;
         let byte = self.opcode & 0x00FF;
         if self.registers[vx as usize] != byte as u8 {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -335,10 +701,18 @@ impl Chip8 {
         let vx = (self.opcode & 0x0F00) >> 8;
         let vy = (self.opcode & 0x00F0) >> 4;
         if self.registers[vx as usize] == self.registers[vy as usize] {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
+    //5xy1 - CHIP-8X. Add Vy to Vx nibble by nibble, without carries
+    fn op_5xy1(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        let (x, y) = (self.registers[vx], self.registers[vy]);
+        self.registers[vx] = (x & 0xF0).wrapping_add(y & 0xF0) | (x.wrapping_add(y) & 0x0F);
+    }
+
     //LD Vx, byte - set register
     fn op_6xkk(&mut self) {
         let vx = (self.opcode & 0x0F00) >> 8;
@@ -369,6 +743,9 @@ impl Chip8 {
         let vx = (self.opcode & 0x0F00) >> 8;
         let vy = (self.opcode & 0x00F0) >> 4;
         self.registers[vx as usize] |= self.registers[vy as usize];
+        if self.config.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     //AND Vx, Vy
@@ -376,6 +753,9 @@ impl Chip8 {
         let vx = (self.opcode & 0x0F00) >> 8;
         let vy = (self.opcode & 0x00F0) >> 4;
         self.registers[vx as usize] &= self.registers[vy as usize];
+        if self.config.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     //XOR Vx, Vy
@@ -383,6 +763,9 @@ impl Chip8 {
         let vx = (self.opcode & 0x0F00) >> 8;
         let vy = (self.opcode & 0x00F0) >> 4;
         self.registers[vx as usize] ^= self.registers[vy as usize];
+        if self.config.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     //ADD Vx, Vy
@@ -404,17 +787,19 @@ impl Chip8 {
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
         if self.registers[vx] >= self.registers[vy] {
             self.registers[0xF] = 1;
-            self.registers[vx] -= self.registers[vy];
         } else {
             self.registers[0xF] = 0;
-            let res = 0xFF - (self.registers[vy] - self.registers[vx] - 1);
-            self.registers[vx] = res;
         }
+        self.registers[vx] = self.registers[vx].wrapping_sub(self.registers[vy]);
     }
 
     //SHR Vx
     fn op_8xy6(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        if !self.config.quirks.shift {
+            self.registers[vx] = self.registers[vy];
+        }
         self.registers[0xF] = self.registers[vx] & 0x1;
         self.registers[vx] >>= 1;
     }
@@ -428,12 +813,16 @@ impl Chip8 {
         } else {
             self.registers[0xF] = 0;
         }
-        self.registers[vx] = self.registers[vy] - self.registers[vx];
+        self.registers[vx] = self.registers[vy].wrapping_sub(self.registers[vx]);
     }
 
     // SHL Vx {, Vy}
     fn op_8xyE(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        if !self.config.quirks.shift {
+            self.registers[vx] = self.registers[vy];
+        }
         self.registers[0xF] = (self.registers[vx] & 0x80) >> 7;
         self.registers[vx] <<= 1;
     }
@@ -443,20 +832,53 @@ impl Chip8 {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
         if self.registers[vx] != self.registers[vy] {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
     //LD I, addr. Set I = nnn
     fn op_Annn(&mut self) {
         let address = self.opcode & 0x0FFF;
-        self.index = address;
+        self.index = address as u32;
     }
 
-    //Bnnn - JP V0, addr. Jump nnn + V0
+    //Bnnn - JP V0, addr. Jump nnn + V0 (xnn + Vx with jump quirk)
     fn op_Bnnn(&mut self) {
         let address = self.opcode & 0x0FFF;
-        self.pc = self.registers[0] as u16 + address;
+        let vx = if self.config.quirks.jump {
+            ((self.opcode & 0x0F00) >> 8) as usize
+        } else {
+            0
+        };
+        self.pc = self.registers[vx] as u16 + address;
+    }
+
+    //BxyN - CHIP-8X. With N = 0 colour Vy fills the zone columns between the nibbles of Vx
+    //and the blocks of rows between the nibbles of Vx+1. Otherwise colour Vx+1 fills the zones
+    //under an N rows high sprite at Vx, Vy
+    fn op_Bxyn(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        let rows = (self.opcode & 0x000F) as usize;
+        let (x, y, next) = (self.registers[vx], self.registers[vy], self.registers[(vx + 1) & 0xF]);
+        if rows == 0 {
+            let columns = (x & 0x0F) as usize..=(x >> 4) as usize;
+            let top = (next & 0x0F) as usize * ZONE_BLOCK_HEIGHT;
+            let bottom = ((next >> 4) as usize + 1) * ZONE_BLOCK_HEIGHT - 1;
+            self.fill_zones(columns, top..=bottom, y);
+        } else {
+            let (x, y) = (x as usize, y as usize);
+            self.fill_zones(x / ZONE_WIDTH..=(x + 7) / ZONE_WIDTH, y..=y + rows - 1, next);
+        }
+    }
+
+    fn fill_zones(&mut self, columns: RangeInclusive<usize>, rows: RangeInclusive<usize>, color: u8) {
+        for row in rows {
+            for column in columns.clone() {
+                let zone = (row % ZONE_ROWS) * ZONE_COLUMNS + column % ZONE_COLUMNS;
+                self.color_zones[zone] = color & 0x07;
+            }
+        }
     }
 
     //RND Vx, byte. Set Vx = random byte AND kk.
@@ -466,51 +888,117 @@ impl Chip8 {
         self.registers[vx] = Chip8::get_rand() & byte;
     }
 
-    //Dxyn - DRW Vx, Vy, nibble
-    fn op_Dxyn(&mut self) {
+    //Dxyn - DRW Vx, Vy, nibble. Returns false when waiting for vblank
+    fn op_Dxyn(&mut self) -> Result<bool, Box<dyn Msg>> {
+        if self.config.quirks.vblank {
+            if self.drawn_in_frame {
+                self.pc = self.pc.wrapping_sub(2);
+                return Ok(false);
+            }
+            self.drawn_in_frame = true;
+        }
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let vy = ((self.opcode & 0x00F0) >> 4) as usize;
         let height = self.opcode & 0x000F;
+        let width = self.video_memory.width();
+        let screen_height = self.video_memory.height();
 
-        let x_pos = self.registers[vx] % self.video_memory.width() as u8;
-        let y_pos = self.registers[vy] % self.video_memory.height() as u8;
+        let x_pos = self.registers[vx] as usize % width;
+        let y_pos = self.registers[vy] as usize % screen_height;
         self.registers[0xF] = 0;
-        for row in 0..height {
-            let sprite_byte = self
-                .memory
-                .read_byte((self.index + row) as usize)
-                .expect("Cannot read");
+        for row in 0..height as usize {
+            let mut y = y_pos + row;
+            if y >= screen_height {
+                if !self.config.quirks.wrap {
+                    break;
+                }
+                y %= screen_height;
+            }
+            let sprite_byte = self.read_memory(self.index as usize + row)?;
             for col in 0..8 {
+                let mut x = x_pos + col;
+                if x >= width {
+                    if !self.config.quirks.wrap {
+                        break;
+                    }
+                    x %= width;
+                }
                 let sprite_pixel = sprite_byte & (0x80 >> col);
-                let addr = (y_pos as usize + row as usize) * self.video_memory.width()
-                    + (x_pos as usize + col as usize);
+                let addr = y * width + x;
                 let screen_pixel = self.video_memory.read_pixel(addr);
                 if sprite_pixel != 0 {
-                    if screen_pixel == COLOR {
+                    if screen_pixel == palette::FOREGROUND {
                         self.registers[0xF] = 1;
                     }
-                    self.video_memory.write_pixel(addr, screen_pixel ^ COLOR);
+                    self.video_memory.write_pixel(addr, screen_pixel ^ palette::FOREGROUND);
                 }
             }
         }
+        Ok(true)
+    }
+
+    //Dxyn - MEGA-CHIP. Sprite of palette indices at I, its size comes from 03nn and 04nn.
+    //VF is set when it covers the collision colour
+    fn op_Dxyn_megachip(&mut self) -> Result<(), Box<dyn Msg>> {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        let vy = ((self.opcode & 0x00F0) >> 4) as usize;
+        let (width, height) = self.megachip.sprite_size();
+        let pixels = self.read_block(self.index as usize, width * height)?;
+        let (x, y) = (self.registers[vx] as usize % megachip::WIDTH, self.registers[vy] as usize % megachip::HEIGHT);
+        self.registers[0xF] = self.megachip.draw(x, y, &pixels) as u8;
+        Ok(())
+    }
+
+    fn key_state(&self, key: u8) -> Result<u8, Box<dyn Msg>> {
+        self.keypad_state(&self.keypad, key)
+    }
+
+    fn keypad_state(&self, keypad: &[u8], key: u8) -> Result<u8, Box<dyn Msg>> {
+        match keypad.get(key as usize) {
+            Some(state) => Ok(*state),
+            None => {
+                let err = self.fault(ErrorMsgId::InvalidKey).add_param(key.to_string());
+                Err(Box::new(err))
+            }
+        }
     }
 
     //Ex9E - SKP Vx. Skip next instruction if key with the value of Vx is pressed
-    fn op_Ex9E(&mut self) {
+    fn op_Ex9E(&mut self) -> Result<(), Box<dyn Msg>> {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let key = self.registers[vx];
-        if self.keypad[key as usize] != 0 {
-            self.pc += 2;
+        if self.key_state(key)? != 0 {
+            self.pc = self.pc.wrapping_add(2);
         }
+        Ok(())
     }
 
     //ExA1 - SKNP Vx. Skip next instruction if key with the value of Vx is not pressed
-    fn op_ExA1(&mut self) {
+    fn op_ExA1(&mut self) -> Result<(), Box<dyn Msg>> {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let key = self.registers[vx];
-        if self.keypad[key as usize] == 0 {
-            self.pc += 2;
+        if self.key_state(key)? == 0 {
+            self.pc = self.pc.wrapping_add(2);
+        }
+        Ok(())
+    }
+
+    //ExF2 - CHIP-8X. Skip next instruction if key Vx of the second keypad is pressed
+    fn op_ExF2(&mut self) -> Result<(), Box<dyn Msg>> {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        if self.keypad_state(&self.keypad_2, self.registers[vx])? != 0 {
+            self.pc = self.pc.wrapping_add(2);
+        }
+        Ok(())
+    }
+
+    //ExF5 - CHIP-8X. Skip next instruction if key Vx of the second keypad is not pressed
+    fn op_ExF5(&mut self) -> Result<(), Box<dyn Msg>> {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        if self.keypad_state(&self.keypad_2, self.registers[vx])? == 0 {
+            self.pc = self.pc.wrapping_add(2);
         }
+        Ok(())
     }
 
     //Fx07 - LD Vx, DT. Set Vx = delay timer value
@@ -528,7 +1016,7 @@ impl Chip8 {
                 return;
             }
         }
-        self.pc -= 2;
+        self.pc = self.pc.wrapping_sub(2);
     }
 
     //Fx15 - LD DT, Vx. Set delay timer = Vx
@@ -546,49 +1034,86 @@ impl Chip8 {
     //Fx1E - ADD I, Vx. Set I = I + Vx
     fn op_Fx1E(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
-        self.index += self.registers[vx] as u16;
+        self.index = (self.index + self.registers[vx] as u32) & self.address_mask();
     }
 
     //Fx29 - LD F, Vx. Set I = location of sprite for digit Vx
     fn op_Fx29(&mut self) {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let digit = self.registers[vx];
-        self.index = FONTSET_START_ADDRESS as u16 + 5 * digit as u16;
+        self.index = FONTSET_START_ADDRESS as u32 + 5 * digit as u32;
     }
 
     //Fx33 - LD B, Vx. Store BCD representation of Vx in memory locations I, I+1, and I+2
-    fn op_Fx33(&mut self) {
+    fn op_Fx33(&mut self) -> Result<(), Box<dyn Msg>> {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         let mut value = self.registers[vx];
         let addr = self.index as usize;
-        self.memory.write_byte(addr + 2, value % 10).expect("!");
+        self.write_memory(addr + 2, value % 10)?;
         value /= 10;
-        self.memory.write_byte(addr + 1, value % 10).expect("!");
+        self.write_memory(addr + 1, value % 10)?;
         value /= 10;
-        self.memory.write_byte(addr, value % 10).expect("!");
+        self.write_memory(addr, value % 10)
+    }
+
+    //FxF8 - CHIP-8X. Vx to the VP-595 tone generator, the beeper keeps its pitch
+    fn op_FxF8(&mut self) {}
+
+    //FxFB - CHIP-8X. Read the input port into Vx, nothing is connected to it
+    fn op_FxFB(&mut self) {
+        let vx = ((self.opcode & 0x0F00) >> 8) as usize;
+        self.registers[vx] = 0;
     }
 
     //Fx55 - LD [I], Vx. Store registers V0 through Vx in memory starting at location I
-    fn op_Fx55(&mut self) {
+    fn op_Fx55(&mut self) -> Result<(), Box<dyn Msg>> {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         for i in 0..vx + 1 {
             let addr = self.index as usize + i;
-            self.memory.write_byte(addr, self.registers[i]).expect("!");
+            self.write_memory(addr, self.registers[i])?;
         }
+        self.advance_index(vx);
+        Ok(())
     }
 
     //Fx65 - LD Vx, [I]. Read registers V0 through Vx from memory starting at location I
-    fn op_Fx65(&mut self) {
+    fn op_Fx65(&mut self) -> Result<(), Box<dyn Msg>> {
         let vx = ((self.opcode & 0x0F00) >> 8) as usize;
         for i in 0..vx + 1 {
-            self.registers[i] = self.memory.read_byte(self.index as usize + i).expect("!");
+            self.registers[i] = self.read_memory(self.index as usize + i)?;
         }
+        self.advance_index(vx);
+        Ok(())
+    }
+
+    //I after Fx55/Fx65 depends on the memory quirks
+    fn advance_index(&mut self, vx: usize) {
+        if self.config.quirks.memory_leave_i_unchanged {
+            return;
+        }
+        let increment = match self.config.quirks.memory_increment_by_x {
+            true => vx as u32,
+            false => vx as u32 + 1,
+        };
+        self.index = (self.index + increment) & self.address_mask();
     }
 }
 
 impl Emulator for Chip8 {
-    fn video_buffer(&self) -> Vec<u8> {
-        self.video_memory.video_8()
+    fn video_buffer(&self) -> &[u8] {
+        match self.profile {
+            Profile::Chip8X => self.color_video.video(),
+            Profile::MegaChip if self.megachip.is_enabled() => self.megachip.screen().video(),
+            _ => self.video_memory.video(),
+        }
+    }
+
+    fn palette(&self) -> Palette {
+        match self.profile {
+            Profile::Chip8X => Palette::composed("CHIP-8X", CHIP8X_COLORS.to_vec()),
+            Profile::MegaChip if self.megachip.is_enabled() => Palette::true_color(),
+            _ => self.config.palette(),
+        }
     }
 
     fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
@@ -598,8 +1123,35 @@ impl Emulator for Chip8 {
         self.do_cycle()
     }
 
+    fn end_frame(&mut self) {
+        if self.delay_timer > 0 {
+            self.delay_timer -= 1
+        }
+        if self.sound_timer > 0 {
+            self.sound_timer -= 1
+        }
+        self.drawn_in_frame = false;
+        self.megachip.end_frame();
+    }
+
+    /// Keys from `KEYPAD_2` on go to the CHIP-8X second keypad, from `BUTTONS` on through the layout
     fn process_input(&mut self, key: u32, pressed: bool) {
-        self.keypad[key as usize] = pressed as u8;
+        if let Some(button) = key.checked_sub(BUTTONS) {
+            let mapped = BUTTON_NAMES
+                .get(button as usize)
+                .and_then(|name| self.config.keys.get(*name));
+            if let Some(state) = mapped.and_then(|key| self.keypad.get_mut(*key as usize)) {
+                *state = pressed as u8;
+            }
+            return;
+        }
+        let state = match key.checked_sub(KEYPAD_2) {
+            Some(key) => self.keypad_2.get_mut(key as usize),
+            None => self.keypad.get_mut(key as usize),
+        };
+        if let Some(state) = state {
+            *state = pressed as u8;
+        }
     }
 
     fn load_rom(&mut self, file_name: &String) {
@@ -607,11 +1159,41 @@ impl Emulator for Chip8 {
     }
 
     fn resolution(&self) -> [u32; 2] {
-        [64, 32]
+        if self.profile == Profile::MegaChip && self.megachip.is_enabled() {
+            return [megachip::WIDTH as u32, megachip::HEIGHT as u32];
+        }
+        [self.video_memory.width() as u32, self.video_memory.height() as u32]
     }
 
     fn cycles_in_sec(&self) -> u64 {
-        500
+        self.config.cycles_in_sec()
+    }
+
+    fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    fn audio_samples(&self) -> Option<&[i16]> {
+        self.megachip.samples()
+    }
+
+    fn reset(&mut self) {
+        self.reset_cpu();
+    }
+
+    fn power_cycle(&mut self) {
+        self.memory = Chip8::init_memory(self.memory_size());
+        self.reset_cpu();
+        self.cycle_count = 0;
+        self.active = false;
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, Box<dyn Msg>> {
+        Ok(self.write_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        self.read_state(state)
     }
 }
 
@@ -638,6 +1220,7 @@ pub enum Chip8Keys {
 mod Chip8Tests {
 
     use super::*;
+    use crate::chip8::config::{CHIP8X_PLATFORM, DEFAULT_PLATFORM, HIRES_PLATFORM, MEGACHIP_PLATFORM};
 
     #[test]
     fn test_op_5xy0() {
@@ -654,6 +1237,162 @@ mod Chip8Tests {
         assert_eq!(c8.pc, start_addr + 2);
     }
 
+    #[test]
+    fn test_reset_keeps_program() {
+        let mut c8 = Chip8::new();
+        c8.memory.write_byte(START_ADDRESS, 0x12).unwrap();
+        c8.registers[0] = 1;
+        c8.pc = 0x300;
+        c8.reset();
+        assert_eq!(c8.pc, START_ADDRESS as u16);
+        assert_eq!(c8.registers[0], 0);
+        assert_eq!(c8.memory.read_byte(START_ADDRESS).unwrap(), 0x12);
+
+        c8.power_cycle();
+        assert_eq!(c8.memory.read_byte(START_ADDRESS).unwrap(), 0);
+        assert_eq!(c8.memory.read_byte(FONTSET_START_ADDRESS).unwrap(), FONT_SET[0]);
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let mut c8 = Chip8::new();
+        c8.registers[3] = 7;
+        c8.pc = 0x234;
+        c8.video_memory.write_pixel(5, palette::FOREGROUND);
+        let state = c8.write_state();
+
+        let mut restored = Chip8::new();
+        restored.read_state(&state).unwrap();
+        assert_eq!(restored.registers[3], 7);
+        assert_eq!(restored.pc, 0x234);
+        assert_eq!(restored.video_memory.read_pixel(5), palette::FOREGROUND);
+        assert!(restored.read_state(&state[1..]).is_err());
+    }
+
+    #[test]
+    fn test_guest_faults() {
+        let mut c8 = Chip8::new();
+        c8.pc = 0x300;
+        c8.opcode = 0x00EE;
+        let err = c8.exec_intruction().unwrap_err();
+        assert_eq!(err.msg_id(), "StackUnderflow");
+        assert_eq!(err.params().as_ref().unwrap()[0], "0x2FE");
+
+        c8.sp = STACK_LEVELS as u8;
+        c8.opcode = 0x2400;
+        assert_eq!(c8.exec_intruction().unwrap_err().msg_id(), "StackOverflow");
+
+        c8.registers[1] = 0x20;
+        c8.opcode = 0xE19E;
+        assert_eq!(c8.exec_intruction().unwrap_err().msg_id(), "InvalidKey");
+
+        c8.index = 0xFFFF;
+        c8.opcode = 0xF233;
+        assert_eq!(c8.exec_intruction().unwrap_err().msg_id(), "MemoryFault");
+    }
+
+    #[test]
+    fn test_chip8x_colors() {
+        let mut c8 = Chip8::new();
+        c8.set_config(RomDb::bundled().platform_config(CHIP8X_PLATFORM));
+        c8.reset();
+        assert_eq!(c8.pc, 0x300);
+        c8.video_memory.write_pixel(9, palette::FOREGROUND);
+        // background blue to black, zone of pixel 9 (column 1, row 0) to white
+        c8.opcode = 0x02A0;
+        c8.exec_intruction().unwrap();
+        c8.registers[2] = 0x11;
+        c8.registers[3] = 0x00;
+        c8.registers[4] = 7;
+        c8.opcode = 0xB240;
+        c8.exec_intruction().unwrap();
+        c8.compose_colors();
+        assert_eq!(c8.video_buffer()[0], 1);
+        assert_eq!(c8.video_buffer()[9], CHIP8X_BACKGROUNDS + 7);
+        assert_eq!(c8.color_zones[ZONE_COLUMNS * 3 + 1], 7);
+        assert_eq!(c8.color_zones[ZONE_COLUMNS * 4 + 1], DEFAULT_ZONE_COLOR);
+        assert!(c8.palette().is_composed());
+
+        c8.registers[2] = 0x19;
+        c8.registers[3] = 0x28;
+        c8.opcode = 0x5231;
+        c8.exec_intruction().unwrap();
+        assert_eq!(c8.registers[2], 0x31);
+
+        c8.process_input(KEYPAD_2 + 5, true);
+        c8.registers[1] = 5;
+        c8.pc = 0x300;
+        c8.opcode = 0xE1F2;
+        c8.exec_intruction().unwrap();
+        assert_eq!(c8.pc, 0x302);
+    }
+
+    #[test]
+    fn test_key_layout() {
+        let mut c8 = Chip8::new();
+        let db = RomDb::bundled();
+        c8.set_config(db.config(include_bytes!("../../resources/chip8-roms/keypad.ch8")));
+        c8.process_input(BUTTONS + 3, true);
+        assert_eq!(c8.keypad[6], 1);
+        c8.process_input(BUTTONS + 3, false);
+        assert_eq!(c8.keypad[6], 0);
+        // no layout, logical buttons do nothing
+        c8.set_config(db.platform_config(DEFAULT_PLATFORM));
+        c8.process_input(BUTTONS, true);
+        assert!(c8.keypad.iter().all(|key| *key == 0));
+    }
+
+    #[test]
+    fn test_hires_profile() {
+        let mut c8 = Chip8::new();
+        c8.set_config(RomDb::bundled().platform_config(HIRES_PLATFORM));
+        c8.reset();
+        assert_eq!(c8.pc, 0x2C0);
+        assert_eq!(c8.resolution(), [64, 64]);
+        c8.video_memory.write_pixel(64 * 63, palette::FOREGROUND);
+        c8.opcode = 0x0230;
+        assert!(c8.exec_intruction().unwrap().video_buff_changed);
+        assert_eq!(c8.video_memory.read_pixel(64 * 63), palette::BACKGROUND);
+    }
+
+    #[test]
+    fn test_megachip() {
+        let mut c8 = Chip8::new();
+        c8.set_config(RomDb::bundled().platform_config(MEGACHIP_PLATFORM));
+        c8.reset();
+        let program = [
+            0x00, 0x11, // MEGAON
+            0x01, 0x00, 0x03, 0x00, // I = 0x300
+            0x02, 0x01, // one palette entry
+            0x03, 0x01, 0x04, 0x01, // 1x1 sprites
+            0x01, 0x00, 0x03, 0x04, // I = 0x304
+            0xD0, 0x10, // draw at V0, V1
+            0x00, 0xE0, // show the picture
+            0x01, 0x12, 0x34, 0x56, // I = 0x123456
+        ];
+        let data = [0xFF, 0xFF, 0x00, 0x00, 0x01];
+        for (offset, byte) in program.iter().enumerate() {
+            c8.memory.write_byte(START_ADDRESS + offset, *byte).unwrap();
+        }
+        for (offset, byte) in data.iter().enumerate() {
+            c8.memory.write_byte(0x300 + offset, *byte).unwrap();
+        }
+        for _ in 0..9 {
+            c8.do_cycle().unwrap();
+        }
+        assert_eq!(c8.resolution(), [256, 192]);
+        assert!(c8.palette().is_true_color());
+        assert_eq!(c8.video_buffer()[..8], [0xFF, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(c8.index, 0x123456);
+        assert_eq!(c8.pc as usize, START_ADDRESS + program.len());
+
+        let state = c8.write_state();
+        let mut restored = Chip8::new();
+        restored.set_config(RomDb::bundled().platform_config(MEGACHIP_PLATFORM));
+        restored.read_state(&state).unwrap();
+        assert_eq!(restored.video_buffer()[..4], [0xFF, 0x00, 0x00, 0xFF]);
+    }
+
     #[test]
     fn test_op_8xy4() {
         let mut c8 = Chip8::new();
@@ -0,0 +1,136 @@
+use crate::common::palette::Palette;
+use std::collections::HashMap;
+use std::path::Path;
+
+pub const DEFAULT_PLATFORM: &str = "modernChip8";
+pub const CHIP8X_PLATFORM: &str = "chip8x";
+pub const HIRES_PLATFORM: &str = "hiresChip8";
+pub const MEGACHIP_PLATFORM: &str = "megachip8";
+pub const DEFAULT_TICK_RATE: u32 = 12;
+pub const DEFAULT_COLORS: [u32; 2] = [0x000000FF, 0x00FF00FF];
+pub const DEFAULT_RESOLUTION: [u32; 2] = [64, 32];
+pub const START_ADDRESS: usize = 0x200;
+/// Platforms named by a file extension, for programs missing from the database
+pub const EXTENSION_PLATFORMS: [(&str, &str); 3] = [
+    ("c8x", CHIP8X_PLATFORM),
+    ("c8h", HIRES_PLATFORM),
+    ("mc8", MEGACHIP_PLATFORM),
+];
+/// Logical buttons a program database entry can map to CHIP-8 keys, in input order
+pub const BUTTON_NAMES: [&str; 6] = ["up", "down", "left", "right", "a", "b"];
+
+/// Interpreter behind a platform: memory layout and instructions beyond the quirks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Profile {
+    Chip8,
+    /// RCA CHIP-8X, with the VP-590 colour board and the VP-580 second keypad
+    Chip8X,
+    /// Two-page display CHIP-8 for the COSMAC VIP, 64x64
+    HiRes,
+    /// MEGA-CHIP, 256x192 true colour sprites and sampled sound once `0011` switches it on
+    MegaChip,
+}
+
+impl Profile {
+    /// Platform of a file extension like `.c8x`
+    pub fn platform_of_file(file_name: &str) -> Option<&'static str> {
+        let extension = Path::new(file_name).extension()?.to_str()?.to_ascii_lowercase();
+        EXTENSION_PLATFORMS
+            .iter()
+            .find(|(ext, _)| *ext == extension)
+            .map(|(_, platform)| *platform)
+    }
+
+    pub fn from_platform(platform: &str) -> Self {
+        match platform {
+            CHIP8X_PLATFORM => Profile::Chip8X,
+            HIRES_PLATFORM => Profile::HiRes,
+            MEGACHIP_PLATFORM => Profile::MegaChip,
+            _ => Profile::Chip8,
+        }
+    }
+
+    /// Where the program file is loaded, the CHIP-8X interpreter takes a page more
+    pub fn load_address(&self) -> usize {
+        match self {
+            Profile::Chip8X => 0x300,
+            _ => START_ADDRESS,
+        }
+    }
+
+    /// Hi-res programs start with the `1260` jump into the patched interpreter, they run from 0x2C0
+    pub fn start_address(&self) -> usize {
+        match self {
+            Profile::HiRes => 0x2C0,
+            _ => self.load_address(),
+        }
+    }
+}
+
+/// Behaviour differences between CHIP-8 interpreters, named as in the community database
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Quirks {
+    /// 8xy6/8xyE shift Vx in place instead of copying Vy first
+    pub shift: bool,
+    /// Fx55/Fx65 increment I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+    /// Fx55/Fx65 leave I unchanged
+    pub memory_leave_i_unchanged: bool,
+    /// Sprites wrap around the screen edges instead of being clipped
+    pub wrap: bool,
+    /// Bnnn jumps to xnn + Vx instead of nnn + V0
+    pub jump: bool,
+    /// Drawing waits for the vertical blank interrupt
+    pub vblank: bool,
+    /// 8xy1/8xy2/8xy3 reset VF to zero
+    pub logic: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chip8Config {
+    pub platform: String,
+    pub quirks: Quirks,
+    /// Display size at startup, the first one the platform lists
+    pub resolution: [u32; 2],
+    /// Instructions executed per 60Hz frame
+    pub tick_rate: u32,
+    /// RGBA colours, background first
+    pub colors: Vec<u32>,
+    /// Logical buttons from `BUTTON_NAMES` mapped to CHIP-8 keys
+    pub keys: HashMap<String, u8>,
+}
+
+impl Default for Chip8Config {
+    fn default() -> Self {
+        Self {
+            platform: String::from(DEFAULT_PLATFORM),
+            quirks: Quirks::default(),
+            resolution: DEFAULT_RESOLUTION,
+            tick_rate: DEFAULT_TICK_RATE,
+            colors: DEFAULT_COLORS.to_vec(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+impl Chip8Config {
+    pub fn profile(&self) -> Profile {
+        Profile::from_platform(&self.platform)
+    }
+
+    pub fn cycles_in_sec(&self) -> u64 {
+        self.tick_rate as u64 * 60
+    }
+
+    /// Palette from the configured colours, missing XO-CHIP entries taken from the default one
+    pub fn palette(&self) -> Palette {
+        let mut colors = Palette::default().colors().clone();
+        for (i, color) in self.colors.iter().enumerate() {
+            match colors.get_mut(i) {
+                Some(c) => *c = *color,
+                None => colors.push(*color),
+            }
+        }
+        Palette::new("ROM", colors)
+    }
+}
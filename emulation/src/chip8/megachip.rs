@@ -0,0 +1,432 @@
+use crate::common::emulator::{FRAME_RATE, SAMPLE_RATE};
+use crate::common::message::*;
+use crate::common::vram::Vram;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 192;
+/// `060n` sound header: 16-bit sample rate, 24-bit length and a reserved byte
+pub const SOUND_HEADER_SIZE: usize = 6;
+const PALETTE_SIZE: usize = 256;
+/// Sprite width and height registers hold 256 as 0
+const MAX_SPRITE_SIZE: usize = 256;
+/// `00FB`/`00FC` scroll by this many pixels
+const SIDEWAYS_SCROLL: usize = 4;
+const BLACK: u32 = 0x000000FF;
+/// Registers, palette, index map and both pictures, the playing sound is not kept
+const STATE_SIZE: usize = 6 + PALETTE_SIZE * 4 + WIDTH * HEIGHT * 9;
+
+/// How sprite pixels are combined with the picture, set by `080n`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    Alpha25,
+    Alpha50,
+    Alpha75,
+    Add,
+    Multiply,
+}
+
+impl BlendMode {
+    /// Unknown modes draw normally
+    pub fn from_nibble(mode: u8) -> Self {
+        match mode {
+            1 => BlendMode::Alpha25,
+            2 => BlendMode::Alpha50,
+            3 => BlendMode::Alpha75,
+            4 => BlendMode::Add,
+            5 => BlendMode::Multiply,
+            _ => BlendMode::Normal,
+        }
+    }
+
+    fn to_nibble(self) -> u8 {
+        match self {
+            BlendMode::Normal => 0,
+            BlendMode::Alpha25 => 1,
+            BlendMode::Alpha50 => 2,
+            BlendMode::Alpha75 => 3,
+            BlendMode::Add => 4,
+            BlendMode::Multiply => 5,
+        }
+    }
+
+    fn opacity(self) -> f32 {
+        match self {
+            BlendMode::Alpha25 => 0.25,
+            BlendMode::Alpha50 => 0.5,
+            BlendMode::Alpha75 => 0.75,
+            _ => 1.0,
+        }
+    }
+}
+
+/// 8-bit unsigned samples started by `060n`
+struct Sound {
+    data: Vec<u8>,
+    rate: u32,
+    /// In source samples
+    position: f64,
+    looping: bool,
+}
+
+/// MEGA-CHIP display and sound. Sprites are drawn on a canvas that becomes the shown picture when the
+/// program clears the screen for the next frame, as on the original interpreter
+pub struct MegaChip {
+    enabled: bool,
+    /// RGBA, entry 0 is the transparent background
+    palette: Vec<u32>,
+    sprite_width: u8,
+    sprite_height: u8,
+    blend: BlendMode,
+    collision_color: u8,
+    /// Fades the shown picture, 0xFF is fully visible
+    alpha: u8,
+    /// Palette index of every canvas pixel, collisions are checked against it
+    indices: Vram,
+    canvas: Vram,
+    screen: Vram,
+    sound: Option<Sound>,
+    /// PCM of the last frame, empty when nothing played
+    samples: Vec<i16>,
+}
+
+impl Default for MegaChip {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MegaChip {
+    pub fn new() -> Self {
+        let mut megachip = Self {
+            enabled: false,
+            palette: vec![BLACK; PALETTE_SIZE],
+            sprite_width: 0,
+            sprite_height: 0,
+            blend: BlendMode::Normal,
+            collision_color: 0,
+            alpha: 0xFF,
+            indices: Vram::new(WIDTH, HEIGHT),
+            canvas: Vram::true_color(WIDTH, HEIGHT),
+            screen: Vram::true_color(WIDTH, HEIGHT),
+            sound: None,
+            samples: Vec::new(),
+        };
+        megachip.reset();
+        megachip
+    }
+
+    pub fn reset(&mut self) {
+        self.enabled = false;
+        self.palette.fill(BLACK);
+        self.sprite_width = 0;
+        self.sprite_height = 0;
+        self.blend = BlendMode::Normal;
+        self.collision_color = 0;
+        self.alpha = 0xFF;
+        self.indices.clear();
+        MegaChip::fill(&mut self.canvas, BLACK);
+        MegaChip::fill(&mut self.screen, BLACK);
+        self.sound = None;
+        self.samples.clear();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// `0011` and `0010`
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Picture to show, true colour
+    pub fn screen(&self) -> &Vram {
+        &self.screen
+    }
+
+    pub fn samples(&self) -> Option<&[i16]> {
+        match self.samples.is_empty() {
+            true => None,
+            false => Some(&self.samples),
+        }
+    }
+
+    /// `02nn`: ARGB quadruplets into the palette from entry 1 on
+    pub fn load_palette(&mut self, colors: &[u8]) {
+        for (entry, argb) in self.palette[1..].iter_mut().zip(colors.chunks_exact(4)) {
+            *entry = u32::from_be_bytes([argb[1], argb[2], argb[3], argb[0]]);
+        }
+    }
+
+    pub fn set_sprite_width(&mut self, width: u8) {
+        self.sprite_width = width;
+    }
+
+    pub fn set_sprite_height(&mut self, height: u8) {
+        self.sprite_height = height;
+    }
+
+    /// Width and height in pixels, one byte each
+    pub fn sprite_size(&self) -> (usize, usize) {
+        let size = |value: u8| if value == 0 { MAX_SPRITE_SIZE } else { value as usize };
+        (size(self.sprite_width), size(self.sprite_height))
+    }
+
+    pub fn set_alpha(&mut self, alpha: u8) {
+        self.alpha = alpha;
+    }
+
+    pub fn set_blend(&mut self, blend: BlendMode) {
+        self.blend = blend;
+    }
+
+    pub fn set_collision_color(&mut self, index: u8) {
+        self.collision_color = index;
+    }
+
+    /// Sprite of `sprite_size` palette indices, 0 is transparent and the edges clip.
+    /// True when a pixel lands on one drawn with the collision colour
+    pub fn draw(&mut self, x: usize, y: usize, pixels: &[u8]) -> bool {
+        let (width, height) = self.sprite_size();
+        let mut collision = false;
+        for (row, line) in pixels.chunks(width).take(height).enumerate() {
+            if y + row >= HEIGHT {
+                break;
+            }
+            for (col, index) in line.iter().enumerate() {
+                if x + col >= WIDTH {
+                    break;
+                }
+                if *index == 0 {
+                    continue;
+                }
+                let addr = (y + row) * WIDTH + x + col;
+                let under = self.indices.read_pixel(addr);
+                collision |= under != 0 && under == self.collision_color;
+                self.indices.write_pixel(addr, *index);
+                let color = MegaChip::blend(self.canvas.read_color(addr), self.palette[*index as usize], self.blend);
+                self.canvas.write_color(addr, color);
+            }
+        }
+        collision
+    }
+
+    /// `00E0`: the canvas is shown with the screen alpha and cleared for the next picture
+    pub fn clear(&mut self) {
+        let alpha = self.alpha as u32;
+        for addr in 0..self.canvas.size() {
+            let [r, g, b, _] = self.canvas.read_color(addr).to_be_bytes();
+            let fade = |c: u8| (c as u32 * alpha / 0xFF) as u8;
+            self.screen.write_color(addr, u32::from_be_bytes([fade(r), fade(g), fade(b), 0xFF]));
+        }
+        self.indices.clear();
+        MegaChip::fill(&mut self.canvas, BLACK);
+    }
+
+    /// `00Bn`
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll(0, -(lines as isize));
+    }
+
+    /// `00Cn`
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll(0, lines as isize);
+    }
+
+    /// `00FC`
+    pub fn scroll_left(&mut self) {
+        self.scroll(-(SIDEWAYS_SCROLL as isize), 0);
+    }
+
+    /// `00FB`
+    pub fn scroll_right(&mut self) {
+        self.scroll(SIDEWAYS_SCROLL as isize, 0);
+    }
+
+    /// `060n`, `data` follows the header
+    pub fn play(&mut self, rate: u32, data: Vec<u8>, looping: bool) {
+        self.sound = Some(Sound {
+            data,
+            rate,
+            position: 0.0,
+            looping,
+        });
+    }
+
+    /// `0700`
+    pub fn stop(&mut self) {
+        self.sound = None;
+    }
+
+    /// Resamples a frame of the playing sound to `SAMPLE_RATE`
+    pub fn end_frame(&mut self) {
+        self.samples.clear();
+        let sound = match self.sound.as_mut() {
+            Some(sound) => sound,
+            None => return,
+        };
+        let step = sound.rate as f64 / SAMPLE_RATE as f64;
+        for _ in 0..SAMPLE_RATE as u64 / FRAME_RATE {
+            let mut offset = sound.position as usize;
+            if offset >= sound.data.len() {
+                if !sound.looping || sound.data.is_empty() {
+                    self.sound = None;
+                    return;
+                }
+                sound.position = 0.0;
+                offset = 0;
+            }
+            self.samples.push((sound.data[offset] as i16 - 0x80) << 8);
+            sound.position += step;
+        }
+    }
+
+    pub fn write_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.enabled as u8,
+            self.sprite_width,
+            self.sprite_height,
+            self.blend.to_nibble(),
+            self.collision_color,
+            self.alpha,
+        ]);
+        for color in &self.palette {
+            state.extend_from_slice(&color.to_be_bytes());
+        }
+        state.extend_from_slice(self.indices.video());
+        state.extend_from_slice(self.canvas.video());
+        state.extend_from_slice(self.screen.video());
+    }
+
+    pub fn read_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        if state.len() != STATE_SIZE {
+            let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::InvalidFormat.into())
+                .add_param(state.len().to_string());
+            return Err(Box::new(err));
+        }
+        let (registers, rest) = state.split_at(6);
+        let (palette, rest) = rest.split_at(PALETTE_SIZE * 4);
+        let (indices, rest) = rest.split_at(WIDTH * HEIGHT);
+        let (canvas, screen) = rest.split_at(WIDTH * HEIGHT * 4);
+        self.enabled = registers[0] != 0;
+        self.sprite_width = registers[1];
+        self.sprite_height = registers[2];
+        self.blend = BlendMode::from_nibble(registers[3]);
+        self.collision_color = registers[4];
+        self.alpha = registers[5];
+        for (entry, bytes) in self.palette.iter_mut().zip(palette.chunks_exact(4)) {
+            *entry = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        self.indices.load(indices);
+        self.canvas.load(canvas);
+        self.screen.load(screen);
+        self.sound = None;
+        self.samples.clear();
+        Ok(())
+    }
+
+    pub fn state_size() -> usize {
+        STATE_SIZE
+    }
+
+    /// Sprite colour over the picture, the palette alpha scaled by the blend mode sets the opacity
+    fn blend(dst: u32, src: u32, mode: BlendMode) -> u32 {
+        let (dst, src) = (dst.to_be_bytes(), src.to_be_bytes());
+        let opacity = src[3] as f32 / 255.0 * mode.opacity();
+        let mut out = [0u8, 0, 0, 0xFF];
+        for i in 0..3 {
+            let (d, s) = (dst[i] as f32, src[i] as f32);
+            let target = match mode {
+                BlendMode::Add => (d + s).min(255.0),
+                BlendMode::Multiply => d * s / 255.0,
+                _ => s,
+            };
+            out[i] = (d + (target - d) * opacity).round() as u8;
+        }
+        u32::from_be_bytes(out)
+    }
+
+    /// Moves the canvas, uncovered pixels are cleared
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        let indices: Vec<u8> = self.indices.video().clone();
+        let canvas: Vec<u32> = (0..self.canvas.size()).map(|addr| self.canvas.read_color(addr)).collect();
+        for y in 0..HEIGHT as isize {
+            for x in 0..WIDTH as isize {
+                let (src_x, src_y) = (x - dx, y - dy);
+                let addr = (y * WIDTH as isize + x) as usize;
+                let inside = (0..WIDTH as isize).contains(&src_x) && (0..HEIGHT as isize).contains(&src_y);
+                let (index, color) = match inside {
+                    true => {
+                        let src = (src_y * WIDTH as isize + src_x) as usize;
+                        (indices[src], canvas[src])
+                    }
+                    false => (0, BLACK),
+                };
+                self.indices.write_pixel(addr, index);
+                self.canvas.write_color(addr, color);
+            }
+        }
+    }
+
+    fn fill(vram: &mut Vram, color: u32) {
+        for addr in 0..vram.size() {
+            vram.write_color(addr, color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod megachip_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_draw_and_blend() {
+        let mut mc = MegaChip::new();
+        // red opaque, green at half alpha
+        mc.load_palette(&[0xFF, 0xFF, 0x00, 0x00, 0x80, 0x00, 0xFF, 0x00]);
+        mc.set_sprite_width(2);
+        mc.set_sprite_height(1);
+        assert!(!mc.draw(10, 0, &[1, 0]));
+        mc.set_collision_color(1);
+        mc.set_blend(BlendMode::Add);
+        assert!(mc.draw(10, 0, &[2, 2]));
+        assert_eq!(mc.canvas.read_color(10), 0xFF8000FF);
+        assert_eq!(mc.canvas.read_color(11), 0x008000FF);
+        // shown only after the clear, faded by the screen alpha
+        assert_eq!(mc.screen().read_color(10), BLACK);
+        mc.set_alpha(0x80);
+        mc.clear();
+        assert_eq!(mc.screen().read_color(10), 0x804000FF);
+        assert_eq!(mc.canvas.read_color(10), BLACK);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut mc = MegaChip::new();
+        mc.load_palette(&[0xFF, 0xFF, 0xFF, 0xFF]);
+        mc.set_sprite_width(1);
+        mc.set_sprite_height(1);
+        mc.draw(0, 0, &[1]);
+        mc.scroll_down(2);
+        mc.scroll_right();
+        assert_eq!(mc.indices.read_pixel(2 * WIDTH + 4), 1);
+        assert_eq!(mc.canvas.read_color(2 * WIDTH + 4), 0xFFFFFFFF);
+        assert_eq!(mc.indices.read_pixel(0), 0);
+    }
+
+    #[test]
+    fn test_sound() {
+        let mut mc = MegaChip::new();
+        mc.play(SAMPLE_RATE, vec![0x80, 0xFF, 0x00], false);
+        mc.end_frame();
+        assert_eq!(mc.samples(), Some(&[0, 0x7F00, -0x8000][..]));
+        mc.end_frame();
+        assert_eq!(mc.samples(), None);
+
+        mc.play(SAMPLE_RATE / 2, vec![0x00], true);
+        mc.end_frame();
+        assert_eq!(mc.samples().unwrap().len(), (SAMPLE_RATE as u64 / FRAME_RATE) as usize);
+    }
+}
@@ -1 +1,4 @@
-pub mod chip8;
\ No newline at end of file
+pub mod chip8;
+pub mod config;
+pub mod rom_db;
+pub mod megachip;
\ No newline at end of file
@@ -0,0 +1,328 @@
+use crate::chip8::config::*;
+use crate::common::message::*;
+
+use serde_json::Value;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+const BUNDLED_PLATFORMS: &str = include_str!("../../resources/chip8-db/platforms.json");
+const BUNDLED_PROGRAMS: &str = include_str!("../../resources/chip8-db/programs.json");
+
+/// Local file with entries in the programs.json format, applied on top of the bundled database
+pub const OVERRIDES_FILE: &str = "chip8-overrides.json";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub sha1: String,
+    pub title: Option<String>,
+    pub authors: Vec<String>,
+    pub platform: String,
+}
+
+struct Platform {
+    quirks: Quirks,
+    resolution: [u32; 2],
+    tick_rate: u32,
+}
+
+struct RomRecord {
+    title: Option<String>,
+    authors: Vec<String>,
+    platforms: Vec<String>,
+    quirky_platforms: HashMap<String, Value>,
+    tick_rate: Option<u32>,
+    colors: Option<Vec<u32>>,
+    keys: HashMap<String, u8>,
+}
+
+/// ROM database in the CHIP-8 community program database format, keyed by SHA-1
+pub struct RomDb {
+    platforms: HashMap<String, Platform>,
+    roms: HashMap<String, RomRecord>,
+}
+
+impl Default for RomDb {
+    fn default() -> Self {
+        let mut db = Self::bundled();
+        if Path::new(OVERRIDES_FILE).exists() {
+            if let Err(err) = db.load_overrides(OVERRIDES_FILE) {
+                log::warn!(file = OVERRIDES_FILE; "Cannot load ROM overrides: {}", err);
+            }
+        }
+        db
+    }
+}
+
+impl RomDb {
+    pub fn bundled() -> Self {
+        let mut db = Self {
+            platforms: HashMap::new(),
+            roms: HashMap::new(),
+        };
+        db.add_platforms(BUNDLED_PLATFORMS)
+            .expect("Cannot parse bundled platforms");
+        db.add_programs(BUNDLED_PROGRAMS)
+            .expect("Cannot parse bundled programs");
+        db
+    }
+
+    pub fn hash(rom: &[u8]) -> String {
+        Sha1::digest(rom)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    pub fn load_overrides(&mut self, file_name: &str) -> Result<(), Box<dyn Msg>> {
+        let text = fs::read_to_string(file_name).map_err(|e| {
+            let err = ErrorMsg::new(ErrorTopicId::RomDb.into(), ErrorMsgId::RomFileNotFound.into())
+                .add_param(String::from(file_name))
+                .set_source(Box::new(e));
+            Box::new(err) as Box<dyn Msg>
+        })?;
+        self.add_programs(&text)
+    }
+
+    pub fn add_platforms(&mut self, json: &str) -> Result<(), Box<dyn Msg>> {
+        let value = RomDb::parse(json)?;
+        for platform in value.as_array().into_iter().flatten() {
+            let id = match platform["id"].as_str() {
+                Some(id) => id,
+                None => continue,
+            };
+            let quirks = RomDb::read_quirks(&platform["quirks"], Quirks::default());
+            let tick_rate = platform["defaultTickrate"]
+                .as_u64()
+                .map(|t| t as u32)
+                .unwrap_or(DEFAULT_TICK_RATE);
+            let resolution = platform["displayResolutions"][0]
+                .as_str()
+                .and_then(RomDb::parse_resolution)
+                .unwrap_or(DEFAULT_RESOLUTION);
+            self.platforms
+                .insert(String::from(id), Platform { quirks, resolution, tick_rate });
+        }
+        Ok(())
+    }
+
+    pub fn add_programs(&mut self, json: &str) -> Result<(), Box<dyn Msg>> {
+        let value = RomDb::parse(json)?;
+        for program in value.as_array().into_iter().flatten() {
+            let title = program["title"].as_str().map(String::from);
+            let authors: Vec<String> = program["authors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|a| a.as_str().map(String::from))
+                .collect();
+            for (sha1, rom) in program["roms"].as_object().into_iter().flatten() {
+                let record = RomRecord {
+                    title: title.clone(),
+                    authors: authors.clone(),
+                    platforms: rom["platforms"]
+                        .as_array()
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|p| p.as_str().map(String::from))
+                        .collect(),
+                    quirky_platforms: rom["quirkyPlatforms"]
+                        .as_object()
+                        .map(|q| q.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+                        .unwrap_or_default(),
+                    tick_rate: rom["tickrate"].as_u64().map(|t| t as u32),
+                    colors: rom["colors"]["pixels"].as_array().map(|pixels| {
+                        pixels
+                            .iter()
+                            .filter_map(|c| c.as_str().and_then(RomDb::parse_color))
+                            .collect()
+                    }),
+                    keys: rom["keys"]
+                        .as_object()
+                        .map(|keys| {
+                            keys.iter()
+                                .filter_map(|(k, v)| v.as_u64().map(|key| (k.clone(), key as u8)))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                };
+                self.roms.insert(sha1.to_lowercase(), record);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, rom: &[u8]) -> bool {
+        self.roms.contains_key(&RomDb::hash(rom))
+    }
+
+    pub fn info(&self, rom: &[u8]) -> RomInfo {
+        let sha1 = RomDb::hash(rom);
+        match self.roms.get(&sha1) {
+            Some(record) => RomInfo {
+                title: record.title.clone(),
+                authors: record.authors.clone(),
+                platform: self.platform_of(record),
+                sha1,
+            },
+            None => RomInfo {
+                sha1,
+                title: None,
+                authors: Vec::new(),
+                platform: String::from(DEFAULT_PLATFORM),
+            },
+        }
+    }
+
+    /// Configuration for the ROM, defaults for unknown ones
+    pub fn config(&self, rom: &[u8]) -> Chip8Config {
+        let record = match self.roms.get(&RomDb::hash(rom)) {
+            Some(r) => r,
+            None => return self.platform_config(DEFAULT_PLATFORM),
+        };
+        let platform_id = self.platform_of(record);
+        let mut config = self.platform_config(&platform_id);
+        if let Some(quirks) = record.quirky_platforms.get(&platform_id) {
+            config.quirks = RomDb::read_quirks(quirks, config.quirks);
+        }
+        if let Some(tick_rate) = record.tick_rate {
+            config.tick_rate = tick_rate;
+        }
+        if let Some(colors) = &record.colors {
+            if colors.len() >= 2 {
+                config.colors = colors.clone();
+            }
+        }
+        config.keys = record.keys.clone();
+        config
+    }
+
+    pub fn platform_config(&self, platform_id: &str) -> Chip8Config {
+        let mut config = Chip8Config::default();
+        if let Some(platform) = self.platforms.get(platform_id) {
+            config.platform = String::from(platform_id);
+            config.quirks = platform.quirks;
+            config.resolution = platform.resolution;
+            config.tick_rate = platform.tick_rate;
+        }
+        config
+    }
+
+    fn platform_of(&self, record: &RomRecord) -> String {
+        record
+            .platforms
+            .iter()
+            .find(|p| self.platforms.contains_key(*p))
+            .cloned()
+            .unwrap_or_else(|| String::from(DEFAULT_PLATFORM))
+    }
+
+    fn parse(json: &str) -> Result<Value, Box<dyn Msg>> {
+        serde_json::from_str(json).map_err(|e| {
+            let err = ErrorMsg::new(ErrorTopicId::RomDb.into(), ErrorMsgId::InvalidFormat.into())
+                .add_param(e.line().to_string())
+                .set_source(Box::new(e));
+            Box::new(err) as Box<dyn Msg>
+        })
+    }
+
+    fn read_quirks(value: &Value, base: Quirks) -> Quirks {
+        let flag = |name: &str, default: bool| value[name].as_bool().unwrap_or(default);
+        Quirks {
+            shift: flag("shift", base.shift),
+            memory_increment_by_x: flag("memoryIncrementByX", base.memory_increment_by_x),
+            memory_leave_i_unchanged: flag("memoryLeaveIUnchanged", base.memory_leave_i_unchanged),
+            wrap: flag("wrap", base.wrap),
+            jump: flag("jump", base.jump),
+            vblank: flag("vblank", base.vblank),
+            logic: flag("logic", base.logic),
+        }
+    }
+
+    /// "64x32" to width and height
+    fn parse_resolution(resolution: &str) -> Option<[u32; 2]> {
+        let (width, height) = resolution.split_once('x')?;
+        Some([width.parse().ok()?, height.parse().ok()?])
+    }
+
+    /// "#rrggbb" to RGBA
+    fn parse_color(color: &str) -> Option<u32> {
+        let hex = color.trim_start_matches('#');
+        if hex.len() != 6 {
+            return None;
+        }
+        u32::from_str_radix(hex, 16).ok().map(|rgb| (rgb << 8) | 0xFF)
+    }
+}
+
+#[cfg(test)]
+mod rom_db_tests {
+
+    use super::*;
+
+    const PROGRAMS: &str = r##"[{
+        "title": "Test program",
+        "authors": ["Someone"],
+        "roms": {
+            "a9993e364706816aba3e25717850c26c9cd0d89d": {
+                "platforms": ["superchip"],
+                "tickrate": 20,
+                "colors": { "pixels": ["#000000", "#ff8000"] },
+                "keys": { "up": 5 },
+                "quirkyPlatforms": { "superchip": { "wrap": true } }
+            }
+        }
+    }]"##;
+
+    #[test]
+    fn test_known_rom() {
+        let mut db = RomDb::bundled();
+        db.add_programs(PROGRAMS).unwrap();
+        let rom = b"abc";
+        let config = db.config(rom);
+        assert_eq!(config.platform, "superchip");
+        assert_eq!(config.tick_rate, 20);
+        assert!(config.quirks.shift);
+        assert!(config.quirks.wrap);
+        assert_eq!(config.colors, vec![0x000000FF, 0xFF8000FF]);
+        assert_eq!(config.keys.get("up"), Some(&5));
+        assert_eq!(db.info(rom).title.as_deref(), Some("Test program"));
+    }
+
+    #[test]
+    fn test_bundled_rom() {
+        let db = RomDb::bundled();
+        let rom = include_bytes!("../../resources/chip8-roms/box.ch8");
+        let info = db.info(rom);
+        assert_eq!(info.sha1, "f591262678fe50c28c850fd0873028ee62b98505");
+        assert_eq!(info.title.as_deref(), Some("Box"));
+        assert_eq!(info.platform, "originalChip8");
+        let config = db.config(rom);
+        assert_eq!(config.tick_rate, 15);
+        assert!(config.quirks.vblank);
+    }
+
+    #[test]
+    fn test_unknown_rom() {
+        let db = RomDb::bundled();
+        let config = db.config(b"unknown");
+        assert_eq!(config.platform, DEFAULT_PLATFORM);
+        assert_eq!(config.quirks, Quirks::default());
+        assert!(db.info(b"unknown").title.is_none());
+        assert!(!db.contains(b"unknown"));
+    }
+
+    #[test]
+    fn test_platform_profiles() {
+        let db = RomDb::bundled();
+        let config = db.platform_config(HIRES_PLATFORM);
+        assert_eq!(config.resolution, [64, 64]);
+        assert_eq!(config.profile(), Profile::HiRes);
+        assert!(config.quirks.vblank);
+        let config = db.platform_config(CHIP8X_PLATFORM);
+        assert_eq!(config.resolution, [64, 32]);
+        assert_eq!(config.profile().load_address(), 0x300);
+        assert_eq!(db.platform_config("superchip").resolution, [64, 32]);
+    }
+}
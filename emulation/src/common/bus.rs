@@ -0,0 +1,328 @@
+use crate::common::message::*;
+use crate::common::ram::Ram;
+
+/// Mirrors resolving into other mirrors are followed this many times at most
+const MAX_MIRROR_DEPTH: usize = 4;
+
+/// Memory as the CPU sees it, reads take `&mut self` as MMIO reads may have side effects
+pub trait Bus {
+    fn read_byte(&mut self, addr: usize) -> Result<u8, Box<dyn Msg>>;
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Box<dyn Msg>>;
+    /// Value at `addr` without side effects or hooks, for debuggers
+    fn peek(&self, addr: usize) -> Option<u8>;
+
+    fn read_word_be(&mut self, addr: usize) -> Result<u16, Box<dyn Msg>> {
+        let high = self.read_byte(addr)?;
+        let low = self.read_byte(addr + 1)?;
+        Ok(u16::from_be_bytes([high, low]))
+    }
+
+    fn read_word_le(&mut self, addr: usize) -> Result<u16, Box<dyn Msg>> {
+        let low = self.read_byte(addr)?;
+        let high = self.read_byte(addr + 1)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn write_word_be(&mut self, addr: usize, value: u16) -> Result<(), Box<dyn Msg>> {
+        let [high, low] = value.to_be_bytes();
+        self.write_byte(addr, high)?;
+        self.write_byte(addr + 1, low)
+    }
+
+    fn write_word_le(&mut self, addr: usize, value: u16) -> Result<(), Box<dyn Msg>> {
+        let [low, high] = value.to_le_bytes();
+        self.write_byte(addr, low)?;
+        self.write_byte(addr + 1, high)
+    }
+}
+
+/// Memory mapped device, offsets are relative to the start of its region
+pub trait Mmio: Send {
+    fn read(&mut self, offset: usize) -> u8;
+    fn write(&mut self, offset: usize, value: u8);
+    /// Register value without side effects, `None` when reading has side effects
+    fn peek(&self, _offset: usize) -> Option<u8> {
+        None
+    }
+}
+
+/// Read/write observer, e.g. a debugger watchpoint or a cheat
+pub trait BusHook: Send {
+    /// Value the CPU gets, a cheat may replace it
+    fn on_read(&mut self, _addr: usize, value: u8) -> u8 {
+        value
+    }
+
+    /// Value to store, `None` drops the write
+    fn on_write(&mut self, _addr: usize, value: u8) -> Option<u8> {
+        Some(value)
+    }
+}
+
+/// What unmapped addresses return
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenBus {
+    /// Accesses fail with `OutOfBounds`
+    Error,
+    /// Reads return the value, writes are ignored
+    Value(u8),
+    /// Reads return the last byte that was on the bus, writes are ignored
+    LastValue,
+}
+
+pub enum RegionKind {
+    Ram(Ram),
+    /// Writes are ignored
+    Rom(Ram),
+    /// Maps the region onto `size` bytes starting at `target`, repeating every `size` bytes
+    Mirror { target: usize, size: usize },
+    Mmio(Box<dyn Mmio>),
+}
+
+pub struct Region {
+    name: &'static str,
+    start: usize,
+    end: usize,
+    kind: RegionKind,
+}
+
+impl Region {
+    pub fn name(&self) -> &'static str { self.name }
+
+    pub fn start(&self) -> usize { self.start }
+
+    /// Exclusive end address
+    pub fn end(&self) -> usize { self.end }
+
+    pub fn kind(&self) -> &RegionKind { &self.kind }
+
+    fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+}
+
+pub type HookId = usize;
+
+/// `Bus` built from mapped regions, the first region containing an address wins
+pub struct MappedBus {
+    regions: Vec<Region>,
+    open_bus: OpenBus,
+    last_value: u8,
+    hooks: Vec<(HookId, Box<dyn BusHook>)>,
+    next_hook_id: HookId,
+}
+
+impl MappedBus {
+    pub fn new(open_bus: OpenBus) -> Self {
+        Self {
+            regions: Vec::new(),
+            open_bus,
+            last_value: 0,
+            hooks: Vec::new(),
+            next_hook_id: 0,
+        }
+    }
+
+    pub fn map_ram(&mut self, name: &'static str, start: usize, size: usize) {
+        self.map(name, start, size, RegionKind::Ram(Ram::new(size)));
+    }
+
+    pub fn map_rom(&mut self, name: &'static str, start: usize, data: Vec<u8>) {
+        let mut rom = Ram::new(data.len());
+        let size = data.len();
+        // cannot fail, the storage has the size of the data
+        let _ = rom.write_block(0, data);
+        self.map(name, start, size, RegionKind::Rom(rom));
+    }
+
+    pub fn map_mirror(&mut self, name: &'static str, start: usize, size: usize, target: usize, target_size: usize) {
+        let kind = RegionKind::Mirror { target, size: target_size.max(1) };
+        self.map(name, start, size, kind);
+    }
+
+    pub fn map_mmio(&mut self, name: &'static str, start: usize, size: usize, device: Box<dyn Mmio>) {
+        self.map(name, start, size, RegionKind::Mmio(device));
+    }
+
+    pub fn map(&mut self, name: &'static str, start: usize, size: usize, kind: RegionKind) {
+        self.regions.push(Region { name, start, end: start + size, kind });
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn open_bus(&self) -> OpenBus {
+        self.open_bus
+    }
+
+    pub fn set_open_bus(&mut self, open_bus: OpenBus) {
+        self.open_bus = open_bus;
+    }
+
+    /// Storage of a RAM or ROM region, used to load programs and save states
+    pub fn ram(&self, name: &str) -> Option<&Ram> {
+        self.regions.iter().find(|r| r.name == name).and_then(|r| match &r.kind {
+            RegionKind::Ram(ram) | RegionKind::Rom(ram) => Some(ram),
+            _ => None,
+        })
+    }
+
+    pub fn ram_mut(&mut self, name: &str) -> Option<&mut Ram> {
+        self.regions.iter_mut().find(|r| r.name == name).and_then(|r| match &mut r.kind {
+            RegionKind::Ram(ram) | RegionKind::Rom(ram) => Some(ram),
+            _ => None,
+        })
+    }
+
+    pub fn add_hook(&mut self, hook: Box<dyn BusHook>) -> HookId {
+        let id = self.next_hook_id;
+        self.next_hook_id += 1;
+        self.hooks.push((id, hook));
+        id
+    }
+
+    pub fn remove_hook(&mut self, id: HookId) -> Option<Box<dyn BusHook>> {
+        let pos = self.hooks.iter().position(|(hook_id, _)| *hook_id == id)?;
+        Some(self.hooks.remove(pos).1)
+    }
+
+    /// Region index and offset inside it, following mirrors
+    fn resolve(&self, addr: usize) -> Option<(usize, usize)> {
+        let mut addr = addr;
+        for _ in 0..=MAX_MIRROR_DEPTH {
+            let index = self.regions.iter().position(|r| r.contains(addr))?;
+            let region = &self.regions[index];
+            match region.kind {
+                RegionKind::Mirror { target, size } => addr = target + (addr - region.start) % size,
+                _ => return Some((index, addr - region.start)),
+            }
+        }
+        None
+    }
+
+    fn unmapped(&self, topic: ErrorTopicId, addr: usize) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(topic.into(), ErrorMsgId::OutOfBounds.into())
+            .add_param(addr.to_string())
+            .add_param(self.regions.iter().map(|r| r.end).max().unwrap_or(0).to_string())
+            .add_param(String::from("1"));
+        Box::new(err)
+    }
+}
+
+impl Bus for MappedBus {
+    fn read_byte(&mut self, addr: usize) -> Result<u8, Box<dyn Msg>> {
+        let mut value = match self.resolve(addr) {
+            Some((index, offset)) => match &mut self.regions[index].kind {
+                RegionKind::Ram(ram) | RegionKind::Rom(ram) => ram.read_byte(offset)?,
+                RegionKind::Mmio(device) => device.read(offset),
+                RegionKind::Mirror { .. } => unreachable!("mirrors are resolved"),
+            },
+            None => match self.open_bus {
+                OpenBus::Error => return Err(self.unmapped(ErrorTopicId::RamRead, addr)),
+                OpenBus::Value(value) => value,
+                OpenBus::LastValue => self.last_value,
+            },
+        };
+        for (_, hook) in self.hooks.iter_mut() {
+            value = hook.on_read(addr, value);
+        }
+        self.last_value = value;
+        Ok(value)
+    }
+
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Box<dyn Msg>> {
+        let mut value = value;
+        for (_, hook) in self.hooks.iter_mut() {
+            match hook.on_write(addr, value) {
+                Some(v) => value = v,
+                None => return Ok(()),
+            }
+        }
+        self.last_value = value;
+        match self.resolve(addr) {
+            Some((index, offset)) => match &mut self.regions[index].kind {
+                RegionKind::Ram(ram) => ram.write_byte(offset, value),
+                RegionKind::Rom(_) => Ok(()),
+                RegionKind::Mmio(device) => {
+                    device.write(offset, value);
+                    Ok(())
+                }
+                RegionKind::Mirror { .. } => unreachable!("mirrors are resolved"),
+            },
+            None => match self.open_bus {
+                OpenBus::Error => Err(self.unmapped(ErrorTopicId::RamWrite, addr)),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn peek(&self, addr: usize) -> Option<u8> {
+        let (index, offset) = self.resolve(addr)?;
+        match &self.regions[index].kind {
+            RegionKind::Ram(ram) | RegionKind::Rom(ram) => ram.read_byte(offset).ok(),
+            RegionKind::Mmio(device) => device.peek(offset),
+            RegionKind::Mirror { .. } => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod bus_tests {
+
+    use super::*;
+
+    struct Latch(u8);
+
+    impl Mmio for Latch {
+        fn read(&mut self, _offset: usize) -> u8 {
+            let value = self.0;
+            self.0 = 0;
+            value
+        }
+
+        fn write(&mut self, _offset: usize, value: u8) {
+            self.0 = value;
+        }
+    }
+
+    struct Freeze(usize, u8);
+
+    impl BusHook for Freeze {
+        fn on_read(&mut self, addr: usize, value: u8) -> u8 {
+            if addr == self.0 { self.1 } else { value }
+        }
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut bus = MappedBus::new(OpenBus::LastValue);
+        bus.map_ram("ram", 0x0000, 0x0800);
+        bus.map_mirror("ram mirror", 0x0800, 0x1800, 0x0000, 0x0800);
+        bus.map_mmio("latch", 0x2000, 1, Box::new(Latch(0)));
+        bus.map_rom("rom", 0x8000, vec![0xAB, 0xCD]);
+
+        bus.write_byte(0x1801, 0x42).unwrap();
+        assert_eq!(bus.read_byte(0x0001).unwrap(), 0x42);
+        bus.write_byte(0x8000, 0x00).unwrap();
+        assert_eq!(bus.read_word_be(0x8000).unwrap(), 0xABCD);
+        assert_eq!(bus.read_byte(0x4000).unwrap(), 0xCD);
+
+        bus.write_byte(0x2000, 7).unwrap();
+        assert_eq!(bus.peek(0x2000), None);
+        assert_eq!(bus.read_byte(0x2000).unwrap(), 7);
+        assert_eq!(bus.read_byte(0x2000).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_hooks_and_open_bus() {
+        let mut bus = MappedBus::new(OpenBus::Error);
+        bus.map_ram("ram", 0, 16);
+        assert!(bus.read_byte(16).is_err());
+        let id = bus.add_hook(Box::new(Freeze(3, 99)));
+        assert_eq!(bus.read_byte(3).unwrap(), 99);
+        assert_eq!(bus.peek(3), Some(0));
+        bus.remove_hook(id);
+        assert_eq!(bus.read_byte(3).unwrap(), 0);
+    }
+}
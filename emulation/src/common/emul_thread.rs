@@ -0,0 +1,511 @@
+use crate::common::emulator::{EmulMgr, FRAME_RATE};
+use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::record::{FrameSound, RecordFormat, Recorder};
+use crate::common::registry::SystemInfo;
+use crate::common::scheduler::Scheduler;
+use crate::common::triple_buffer::{self, Reader, Writer};
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Sample chunks waiting to be drained, a chunk holds the frames of one loop turn
+const SAMPLE_QUEUE_LENGTH: usize = FRAME_RATE as usize;
+
+/// Requests from the UI, applied by the emulation thread in order
+pub enum Command {
+    Input { key: u32, pressed: bool },
+    SetPause(bool),
+    Reset,
+    PowerCycle,
+    LoadRom(String),
+    /// Save state slot next to the ROM
+    SaveState(u8),
+    LoadState(u8),
+    SetSpeed(f64),
+    SetTurbo(bool),
+    AdvanceFrame,
+    Debug(DebugCommand),
+    /// Records every emulated frame with its sound until `StopRecording`
+    StartRecording { file_name: String, format: RecordFormat, palette: Palette, scale: u32 },
+    StopRecording,
+    Quit,
+}
+
+pub enum DebugCommand {
+    /// Executes a single instruction while paused
+    Step,
+}
+
+/// Notifications from the emulation thread
+pub enum Event {
+    Error(Box<dyn Msg>),
+    StateSaved(String),
+    StateLoaded(String),
+    /// The recording is finished, the audio file is set when the sound went to a separate file
+    RecordingSaved { file_name: String, audio_file: Option<String> },
+}
+
+/// Everything the UI needs to present a frame, published after every emulated frame
+#[derive(Clone, Default)]
+pub struct Frame {
+    pub pixels: Vec<u8>,
+    pub resolution: [u32; 2],
+    pub palette: Palette,
+    pub version: u32,
+    pub frame_version: u64,
+    pub rom_name: Option<String>,
+    /// Registry name of the running core
+    pub system: Option<&'static str>,
+    pub sound_active: bool,
+    pub paused: bool,
+    pub recording: bool,
+}
+
+/// UI side of the emulation thread, owns the `EmulMgr` until dropped
+pub struct EmulThread {
+    commands: Sender<Command>,
+    events: Receiver<Event>,
+    frames: Reader<Frame>,
+    /// PCM of every emulated frame, frames are dropped by the triple buffer but samples are not
+    samples: Receiver<Vec<i16>>,
+    /// Copy of the registry, systems are only registered before the thread starts
+    systems: Vec<SystemInfo>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl EmulThread {
+    pub fn spawn(emul: EmulMgr) -> Self {
+        let (command_tx, command_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let (sample_tx, sample_rx) = mpsc::sync_channel(SAMPLE_QUEUE_LENGTH);
+        let (mut writer, mut frames) = triple_buffer::triple_buffer(Frame::default());
+        publish(&emul, &mut writer, false);
+        frames.update();
+        let systems = emul.registry().systems().to_vec();
+        let handle = thread::Builder::new()
+            .name(String::from("emulation"))
+            .spawn(move || run(emul, command_rx, event_tx, writer, sample_tx))
+            .expect("Cannot start the emulation thread");
+        Self {
+            commands: command_tx,
+            events: event_rx,
+            frames,
+            samples: sample_rx,
+            systems,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        // the thread only stops on `Quit`, a failed send means it is already gone
+        let _ = self.commands.send(command);
+    }
+
+    pub fn poll_event(&self) -> Option<Event> {
+        self.events.try_recv().ok()
+    }
+
+    /// Takes the latest published frame, returns whether there is a new one
+    pub fn update(&mut self) -> bool {
+        self.frames.update()
+    }
+
+    pub fn frame(&self) -> &Frame {
+        self.frames.read()
+    }
+
+    pub fn systems(&self) -> &[SystemInfo] {
+        &self.systems
+    }
+
+    pub fn system(&self) -> Option<&SystemInfo> {
+        let name = self.frame().system?;
+        self.systems.iter().find(|s| s.name == name)
+    }
+
+    pub fn rom_name(&self) -> Option<String> {
+        self.frame().rom_name.clone()
+    }
+
+    pub fn version(&self) -> u32 {
+        self.frame().version
+    }
+
+    /// Changes whenever the framebuffer content may have changed
+    pub fn frame_version(&self) -> u64 {
+        self.frame().frame_version
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.frame().paused
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.frame().sound_active
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.frame().recording
+    }
+
+    /// Samples of all frames emulated since the last call, in order
+    pub fn drain_samples(&self) -> Vec<i16> {
+        self.samples.try_iter().flatten().collect()
+    }
+
+    pub fn video_buffer(&self) -> Result<&[u8], Box<dyn Msg>> {
+        let frame = self.frame();
+        if frame.pixels.is_empty() {
+            return Err(not_init_error());
+        }
+        Ok(&frame.pixels)
+    }
+
+    pub fn resolution(&self) -> Result<[u32; 2], Box<dyn Msg>> {
+        let frame = self.frame();
+        if frame.pixels.is_empty() {
+            return Err(not_init_error());
+        }
+        Ok(frame.resolution)
+    }
+
+    pub fn palette(&self) -> Result<Palette, Box<dyn Msg>> {
+        let frame = self.frame();
+        if frame.pixels.is_empty() {
+            return Err(not_init_error());
+        }
+        Ok(frame.palette.clone())
+    }
+}
+
+impl Drop for EmulThread {
+    fn drop(&mut self) {
+        self.send(Command::Quit);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run(
+    mut emul: EmulMgr,
+    commands: Receiver<Command>,
+    events: Sender<Event>,
+    mut frames: Writer<Frame>,
+    sample_queue: SyncSender<Vec<i16>>,
+) {
+    let mut scheduler = Scheduler::default();
+    let mut samples: Vec<i16> = Vec::new();
+    let mut recorder: Option<Recorder> = None;
+    let frame_duration = Duration::from_secs(1) / FRAME_RATE as u32;
+    let mut last_frame = Instant::now();
+    'run: loop {
+        let mut changed = false;
+        loop {
+            let command = match commands.try_recv() {
+                Ok(command) => command,
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => break 'run,
+            };
+            if let Command::Quit = command {
+                break 'run;
+            }
+            changed |= apply(&mut emul, &mut scheduler, &mut recorder, &events, command);
+        }
+
+        let now = Instant::now();
+        let mut record_error = None;
+        let on_frame = |emul: &EmulMgr| {
+            samples.extend(emul.audio_samples().unwrap_or_default());
+            if let Some(rec) = recorder.as_mut() {
+                if let Err(err) = record_frame(emul, rec) {
+                    recorder = None;
+                    record_error = Some(err);
+                }
+            }
+        };
+        match scheduler.run(&mut emul, now - last_frame, on_frame) {
+            Ok(frames_run) => changed |= frames_run > 0,
+            Err(_) if !emul.has_emulator() => {}
+            Err(err) => {
+                // the emulator paused itself, publish that along with the error
+                changed = true;
+                send(&events, Event::Error(err));
+            }
+        }
+        if let Some(err) = record_error {
+            changed = true;
+            send(&events, Event::Error(err));
+        }
+        last_frame = now;
+        if changed {
+            publish(&emul, &mut frames, recorder.is_some());
+        }
+        if !samples.is_empty() {
+            // a full queue means nobody drains it, the chunk is dropped
+            let _ = sample_queue.try_send(std::mem::take(&mut samples));
+        }
+
+        // sleep until the next frame unless a command arrives first
+        let wait = frame_duration.saturating_sub(last_frame.elapsed());
+        match commands.recv_timeout(wait) {
+            Ok(Command::Quit) | Err(RecvTimeoutError::Disconnected) => break 'run,
+            Ok(command) => {
+                if apply(&mut emul, &mut scheduler, &mut recorder, &events, command) {
+                    publish(&emul, &mut frames, recorder.is_some());
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+    }
+    // the file is only complete with the sizes `finish` writes
+    if let Some(recorder) = recorder {
+        if let Err(err) = recorder.finish() {
+            log::error!("{}", err);
+        }
+    }
+}
+
+/// Returns whether the published frame is out of date
+fn apply(
+    emul: &mut EmulMgr,
+    scheduler: &mut Scheduler,
+    recorder: &mut Option<Recorder>,
+    events: &Sender<Event>,
+    command: Command,
+) -> bool {
+    let result = match command {
+        Command::Input { key, pressed } => {
+            emul.process_input(key, pressed);
+            return false;
+        }
+        Command::SetPause(pause) => {
+            emul.set_pause(pause);
+            Ok(())
+        }
+        Command::Reset => {
+            emul.reset();
+            Ok(())
+        }
+        Command::PowerCycle => {
+            emul.power_cycle();
+            Ok(())
+        }
+        Command::LoadRom(file_name) => emul.open(&file_name),
+        Command::SaveState(slot) => match emul.state_file_name(slot) {
+            Some(file_name) => emul
+                .save_state(&file_name)
+                .map(|_| send(events, Event::StateSaved(file_name))),
+            None => Err(not_init_error()),
+        },
+        Command::LoadState(slot) => match emul.state_file_name(slot) {
+            Some(file_name) => emul
+                .load_state(&file_name)
+                .map(|_| send(events, Event::StateLoaded(file_name))),
+            None => Err(not_init_error()),
+        },
+        Command::SetSpeed(speed) => {
+            scheduler.set_speed(speed);
+            return false;
+        }
+        Command::SetTurbo(turbo) => {
+            scheduler.set_turbo(turbo);
+            return false;
+        }
+        Command::AdvanceFrame => {
+            scheduler.advance_frame();
+            return false;
+        }
+        Command::Debug(DebugCommand::Step) => emul.step().map(|_| ()),
+        Command::StartRecording { file_name, format, palette, scale } => {
+            let finished = match recorder.take() {
+                Some(previous) => finish_recording(previous, events),
+                None => Ok(()),
+            };
+            finished
+                .and_then(|_| emul.resolution())
+                .and_then(|resolution| Recorder::start(&file_name, format, resolution, &palette, scale))
+                .map(|started| *recorder = Some(started))
+        }
+        Command::StopRecording => match recorder.take() {
+            Some(previous) => finish_recording(previous, events),
+            None => Ok(()),
+        },
+        Command::Quit => return false,
+    };
+    if let Err(err) = result {
+        send(events, Event::Error(err));
+    }
+    true
+}
+
+/// The emulator state after a frame, the recorder gets every frame and its samples once
+fn record_frame(emul: &EmulMgr, recorder: &mut Recorder) -> Result<(), Box<dyn Msg>> {
+    let sound = FrameSound::new(emul.sound_active(), emul.audio_samples());
+    recorder.push_frame(emul.video_buffer()?, sound)
+}
+
+fn finish_recording(recorder: Recorder, events: &Sender<Event>) -> Result<(), Box<dyn Msg>> {
+    let file_name = String::from(recorder.file_name());
+    let audio_file = recorder.audio_file();
+    recorder.finish()?;
+    send(events, Event::RecordingSaved { file_name, audio_file });
+    Ok(())
+}
+
+fn send(events: &Sender<Event>, event: Event) {
+    let _ = events.send(event);
+}
+
+fn publish(emul: &EmulMgr, frames: &mut Writer<Frame>, recording: bool) {
+    let frame = frames.input();
+    frame.pixels.clear();
+    if let Ok(buffer) = emul.video_buffer() {
+        frame.pixels.extend_from_slice(buffer);
+    }
+    frame.resolution = emul.resolution().unwrap_or([0, 0]);
+    if let Ok(palette) = emul.palette() {
+        if frame.palette != palette {
+            frame.palette = palette;
+        }
+    }
+    frame.version = emul.version();
+    frame.frame_version = emul.frame_version();
+    frame.rom_name = emul.rom_name();
+    frame.system = emul.system().map(|s| s.name);
+    frame.sound_active = emul.sound_active();
+    frame.paused = emul.is_paused();
+    frame.recording = recording;
+    frames.publish();
+}
+
+fn not_init_error() -> Box<dyn Msg> {
+    let err = ErrorMsg::new(
+        ErrorTopicId::Emulator.into(),
+        ErrorMsgId::NotInitialized.into(),
+    );
+    Box::new(err)
+}
+
+#[cfg(test)]
+mod emul_thread_tests {
+
+    use super::*;
+    use crate::chip8::chip8::Chip8;
+    use crate::common::emulator::{CycleResult, Emulator};
+
+    #[test]
+    fn test_pause_is_published() {
+        let mut emul = EmulMgr::default();
+        emul.set_emulator(Box::new(Chip8::new()));
+        let mut thread = EmulThread::spawn(emul);
+        assert_eq!(thread.resolution().unwrap(), [64, 32]);
+        thread.send(Command::SetPause(true));
+        let start = Instant::now();
+        while !thread.is_paused() && start.elapsed() < Duration::from_secs(5) {
+            thread.update();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(thread.is_paused());
+    }
+
+    /// Four samples per frame holding the frame number
+    struct Counter {
+        samples: Vec<i16>,
+    }
+
+    impl Emulator for Counter {
+        fn video_buffer(&self) -> &[u8] {
+            &[0]
+        }
+        fn palette(&self) -> Palette {
+            Palette::new("test", vec![0x000000FF])
+        }
+        fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
+            Ok(CycleResult::default())
+        }
+        fn end_frame(&mut self) {
+            let frame = self.samples.first().map_or(0, |s| s + 1);
+            self.samples = vec![frame; 4];
+        }
+        fn process_input(&mut self, _emul_key: u32, _pressed: bool) {}
+        fn load_rom(&mut self, _file_name: &String) {}
+        fn resolution(&self) -> [u32; 2] {
+            [1, 1]
+        }
+        fn cycles_in_sec(&self) -> u64 {
+            FRAME_RATE
+        }
+        fn sound_active(&self) -> bool {
+            false
+        }
+        fn audio_samples(&self) -> Option<&[i16]> {
+            Some(&self.samples)
+        }
+        fn reset(&mut self) {}
+        fn power_cycle(&mut self) {}
+        fn save_state(&self) -> Result<Vec<u8>, Box<dyn Msg>> {
+            Ok(Vec::new())
+        }
+        fn load_state(&mut self, _state: &[u8]) -> Result<(), Box<dyn Msg>> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_samples_of_every_frame() {
+        let mut emul = EmulMgr::default();
+        emul.set_emulator(Box::new(Counter { samples: Vec::new() }));
+        emul.set_pause(true);
+        let thread = EmulThread::spawn(emul);
+        for _ in 0..3 {
+            thread.send(Command::AdvanceFrame);
+        }
+        let start = Instant::now();
+        let mut samples = Vec::new();
+        while samples.len() < 12 && start.elapsed() < Duration::from_secs(5) {
+            samples.extend(thread.drain_samples());
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(samples, [[0; 4], [1; 4], [2; 4]].concat());
+    }
+
+    #[test]
+    fn test_recording_gets_every_frame() {
+        let file_name = std::env::temp_dir()
+            .join(format!("emul_thread_test_{}.y4m", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let mut emul = EmulMgr::default();
+        emul.set_emulator(Box::new(Counter { samples: Vec::new() }));
+        emul.set_pause(true);
+        let thread = EmulThread::spawn(emul);
+        let palette = Palette::new("test", vec![0x000000FF]);
+        thread.send(Command::StartRecording { file_name: file_name.clone(), format: RecordFormat::Y4m, palette, scale: 1 });
+        for _ in 0..3 {
+            thread.send(Command::AdvanceFrame);
+        }
+        // nothing is recorded while paused
+        thread::sleep(Duration::from_millis(100));
+        thread.send(Command::StopRecording);
+        let start = Instant::now();
+        let audio_file = loop {
+            match thread.poll_event() {
+                Some(Event::RecordingSaved { audio_file, .. }) => break audio_file.unwrap(),
+                Some(Event::Error(err)) => panic!("{}", err),
+                _ => {}
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        };
+        let video = std::fs::read(&file_name).unwrap();
+        let audio = std::fs::read(&audio_file).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+        std::fs::remove_file(&audio_file).unwrap();
+        let header = "YUV4MPEG2 W1 H1 F60:1 Ip A1:1 C444\n".len();
+        assert_eq!(video.len(), header + 3 * ("FRAME\n".len() + 3));
+        assert_eq!(&audio[44..], [[0, 0].repeat(4), [1, 0].repeat(4), [2, 0].repeat(4)].concat());
+    }
+}
@@ -1,5 +1,14 @@
 use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::registry::{Registry, SystemInfo};
+use std::path::Path;
 
+/// Frames per second the systems are run at
+pub const FRAME_RATE: u64 = 60;
+/// Sample rate of the PCM sound the systems produce
+pub const SAMPLE_RATE: u32 = 44100;
+
+#[derive(Debug)]
 pub struct CycleResult {
     pub video_buff_changed: bool,
     pub total_cycle_count: u128,
@@ -16,27 +25,69 @@ impl Default for CycleResult {
     }
 }
 
-pub trait Emulator {
-    fn video_buffer(&self) -> Vec<u8>;
+/// Runs on the emulation thread, hence `Send`
+pub trait Emulator: Send {
+    /// The framebuffer in the layout `palette()` describes: one palette index per pixel,
+    /// or 4 RGBA bytes per pixel when `palette().is_true_color()`
+    fn video_buffer(&self) -> &[u8];
+    /// Palette the system suggests for its pixel indices
+    fn palette(&self) -> Palette;
     fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>>;
+    /// Called after the cycles of a 60Hz frame, timers and interrupts tied to the frame go here
+    fn end_frame(&mut self);
     fn process_input(&mut self, emul_key: u32, pressed: bool);
     fn load_rom(&mut self, file_name: &String);
     fn resolution(&self) -> [u32; 2];
     fn cycles_in_sec(&self) -> u64;
+    /// Whether the buzzer/speaker is on at the moment
+    fn sound_active(&self) -> bool;
+    /// Mono PCM at `SAMPLE_RATE` for the last frame, `None` when the sound is only the buzzer
+    fn audio_samples(&self) -> Option<&[i16]> {
+        None
+    }
+    /// Soft reset: CPU state back to the start, the loaded program is kept
+    fn reset(&mut self);
+    /// Back to the power-on state without a program, `EmulMgr` loads the ROM again
+    fn power_cycle(&mut self);
+    /// Snapshot of the machine state in a system specific format
+    fn save_state(&self) -> Result<Vec<u8>, Box<dyn Msg>>;
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Msg>>;
+    /// The next instruction in the system's trace log layout, for comparing against reference logs
+    fn trace(&self) -> Option<String> {
+        None
+    }
+    /// Text sent out of the serial port, test ROMs like Blargg's report their results there
+    fn serial_output(&self) -> Option<&str> {
+        None
+    }
+    /// Moves the program counter, test ROMs like nestest have an automated entry point
+    fn set_pc(&mut self, _pc: u32) -> Result<(), Box<dyn Msg>> {
+        let err = ErrorMsg::new(ErrorTopicId::Emulator.into(), ErrorMsgId::NotSupported.into());
+        Err(Box::new(err))
+    }
 }
 
 pub struct EmulMgr {
     emulator: Option<Box<dyn Emulator>>,
+    registry: Registry,
+    /// Registry name of the running core
+    system: Option<&'static str>,
     version: u32,
     pause: bool,
+    rom_path: Option<String>,
+    frame_version: u64,
 }
 
 impl Default for EmulMgr {
     fn default() -> Self {
         Self {
             emulator: None,
+            registry: Registry::default(),
+            system: None,
             version: 0,
             pause: false,
+            rom_path: None,
+            frame_version: 0,
         }
     }
 }
@@ -44,13 +95,97 @@ impl Default for EmulMgr {
 impl EmulMgr {
     pub fn set_emulator(&mut self, emulator: Box<dyn Emulator>) {
         self.emulator.replace(emulator);
+        self.system = None;
         self.version += 1;
+        self.frame_version += 1;
+    }
+
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    pub fn registry_mut(&mut self) -> &mut Registry {
+        &mut self.registry
+    }
+
+    pub fn system(&self) -> Option<&SystemInfo> {
+        self.registry.find(self.system?)
+    }
+
+    /// Loads a ROM into the core the registry picks for it,
+    /// the running core is kept when it is the same system
+    pub fn open(&mut self, file_name: &str) -> Result<(), Box<dyn Msg>> {
+        let rom = std::fs::read(file_name).map_err(|e| {
+            let err = ErrorMsg::new(ErrorTopicId::Emulator.into(), ErrorMsgId::RomFileNotFound.into())
+                .add_param(file_name.to_string())
+                .set_source(Box::new(e));
+            Box::new(err) as Box<dyn Msg>
+        })?;
+        let system = match self.registry.detect(file_name, &rom) {
+            Some(system) => system.clone(),
+            None => {
+                let err = ErrorMsg::new(ErrorTopicId::Emulator.into(), ErrorMsgId::UnknownSystem.into())
+                    .add_param(file_name.to_string());
+                return Err(Box::new(err));
+            }
+        };
+        if self.emulator.is_none() || self.system != Some(system.name) {
+            self.set_emulator((system.factory)());
+            self.system = Some(system.name);
+        }
+        self.load_rom(&file_name.to_string());
+        log::info!(file = file_name, system = system.name, size = rom.len(); "ROM loaded");
+        Ok(())
+    }
+
+    pub fn has_emulator(&self) -> bool {
+        self.emulator.is_some()
+    }
+
+    pub fn load_rom(&mut self, file_name: &String) {
+        if let Some(emul) = self.emulator.as_mut() {
+            emul.load_rom(file_name);
+            self.rom_path = Some(file_name.clone());
+            self.frame_version += 1;
+        }
+    }
+
+    pub fn reset(&mut self) {
+        if let Some(emul) = self.emulator.as_mut() {
+            emul.reset();
+            self.frame_version += 1;
+        }
+    }
+
+    /// Hard reset, the ROM is read from disk again
+    pub fn power_cycle(&mut self) {
+        if let Some(emul) = self.emulator.as_mut() {
+            emul.power_cycle();
+            self.frame_version += 1;
+        }
+        if let Some(path) = self.rom_path.clone() {
+            self.load_rom(&path);
+        }
+    }
+
+    /// File name of the loaded ROM without extension
+    pub fn rom_name(&self) -> Option<String> {
+        let path = self.rom_path.as_ref()?;
+        Path::new(path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
     }
 
     pub fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
         if let Some(emul) = self.emulator.as_mut() {
             if !self.pause {
-                return emul.cycle();
+                let res = emul.cycle();
+                if let Ok(cycle) = &res {
+                    if cycle.video_buff_changed {
+                        self.frame_version += 1;
+                    }
+                }
+                return res;
             }
         }
         let err = ErrorMsg::new(
@@ -60,6 +195,93 @@ impl EmulMgr {
         Err(Box::new(err))
     }
 
+    /// Single cycle that ignores pause, for stepping through a program
+    pub fn step(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
+        let emul = match self.emulator.as_mut() {
+            Some(e) => e,
+            None => return Err(self.not_init_error()),
+        };
+        let res = match emul.cycle() {
+            Ok(res) => res,
+            Err(err) => return Err(self.fault(err)),
+        };
+        if res.video_buff_changed {
+            self.frame_version += 1;
+        }
+        Ok(res)
+    }
+
+    /// Runs `cycles_in_sec / FRAME_RATE` clock cycles and ends the frame, ignores pause.
+    /// An error pauses the emulator with the machine left at the failing instruction
+    pub fn run_frame(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
+        let emul = match self.emulator.as_mut() {
+            Some(e) => e,
+            None => return Err(self.not_init_error()),
+        };
+        let cycles = (emul.cycles_in_sec() / FRAME_RATE).max(1) as u128;
+        let mut result = CycleResult::default();
+        while result.last_cycle_count < cycles {
+            let res = match emul.cycle() {
+                Ok(res) => res,
+                Err(err) => return Err(self.fault(err)),
+            };
+            if res.video_buff_changed {
+                self.frame_version += 1;
+                result.video_buff_changed = true;
+            }
+            // an idle core reports no cycles, count one so the frame ends
+            result.last_cycle_count += res.last_cycle_count.max(1);
+            result.total_cycle_count = res.total_cycle_count;
+        }
+        emul.end_frame();
+        Ok(result)
+    }
+
+    pub fn save_state(&self, file_name: &str) -> Result<(), Box<dyn Msg>> {
+        let emul = match &self.emulator {
+            Some(e) => e,
+            None => return Err(self.not_init_error()),
+        };
+        let state = emul.save_state()?;
+        std::fs::write(file_name, &state).map_err(|e| {
+            let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::FileWrite.into())
+                .add_param(file_name.to_string())
+                .set_source(Box::new(e));
+            Box::new(err) as Box<dyn Msg>
+        })?;
+        log::info!(file = file_name, size = state.len(); "State saved");
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, file_name: &str) -> Result<(), Box<dyn Msg>> {
+        let state = std::fs::read(file_name).map_err(|e| {
+            let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::FileRead.into())
+                .add_param(file_name.to_string())
+                .set_source(Box::new(e));
+            Box::new(err) as Box<dyn Msg>
+        })?;
+        let emul = match self.emulator.as_mut() {
+            Some(e) => e,
+            None => return Err(self.not_init_error()),
+        };
+        emul.load_state(&state)?;
+        self.frame_version += 1;
+        log::info!(file = file_name, size = state.len(); "State loaded");
+        Ok(())
+    }
+
+    /// Pauses on a guest fault, the machine is left at the failing instruction
+    fn fault(&mut self, err: Box<dyn Msg>) -> Box<dyn Msg> {
+        log::error!(topic = err.topic_id(), id = err.msg_id(); "{}", err);
+        self.pause = true;
+        err
+    }
+
+    /// Save state file next to the ROM, e.g. `roms/Airplane.ch8.state1`
+    pub fn state_file_name(&self, slot: u8) -> Option<String> {
+        self.rom_path.as_ref().map(|path| format!("{}.state{}", path, slot))
+    }
+
     pub fn set_pause(&mut self, pause: bool) {
         if self.emulator.is_some() {
             self.pause = pause;
@@ -70,13 +292,36 @@ impl EmulMgr {
         self.pause
     }
 
-    pub fn video_buffer(&self) -> Result<Vec<u8>, Box<dyn Msg>> {
+    /// Borrowed framebuffer, check `frame_version` to skip unchanged frames
+    pub fn video_buffer(&self) -> Result<&[u8], Box<dyn Msg>> {
         if let Some(emul) = &self.emulator {
             return Ok(emul.video_buffer());
         }
         Err(self.not_init_error())
     }
 
+    pub fn palette(&self) -> Result<Palette, Box<dyn Msg>> {
+        if let Some(emul) = &self.emulator {
+            return Ok(emul.palette());
+        }
+        Err(self.not_init_error())
+    }
+
+    pub fn trace(&self) -> Option<String> {
+        self.emulator.as_ref()?.trace()
+    }
+
+    pub fn serial_output(&self) -> Option<&str> {
+        self.emulator.as_ref()?.serial_output()
+    }
+
+    pub fn set_pc(&mut self, pc: u32) -> Result<(), Box<dyn Msg>> {
+        match self.emulator.as_mut() {
+            Some(emul) => emul.set_pc(pc),
+            None => Err(self.not_init_error()),
+        }
+    }
+
     pub fn process_input(&mut self, emul_key: u32, pressed: bool) {
         if let Some(emul) = self.emulator.as_mut() {
             emul.process_input(emul_key, pressed);
@@ -87,6 +332,11 @@ impl EmulMgr {
         self.version
     }
 
+    /// Changes whenever the framebuffer content may have changed
+    pub fn frame_version(&self) -> u64 {
+        self.frame_version
+    }
+
     pub fn resolution(&self) -> Result<[u32; 2], Box<dyn Msg>> {
         if let Some(emul) = &self.emulator {
             return Ok(emul.resolution());
@@ -101,6 +351,17 @@ impl EmulMgr {
         Err(self.not_init_error())
     }
 
+    pub fn sound_active(&self) -> bool {
+        match &self.emulator {
+            Some(emul) => emul.sound_active(),
+            None => false,
+        }
+    }
+
+    pub fn audio_samples(&self) -> Option<&[i16]> {
+        self.emulator.as_ref()?.audio_samples()
+    }
+
     fn not_init_error(&self) -> Box<dyn Msg> {
         let err = ErrorMsg::new(
             ErrorTopicId::Emulator.into(),
@@ -0,0 +1,227 @@
+/// Pixel-art upscaling filters working on RGBA colours (0xRRGGBBAA)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Filter {
+    #[default]
+    Nearest,
+    /// EPX / AdvMAME2x
+    Scale2x,
+    /// AdvMAME3x
+    Scale3x,
+    /// Per-corner HQ2x rules with the hqx YUV similarity thresholds
+    Hq2x,
+    /// xBR level 1 at 2x
+    XbrLite,
+}
+
+impl Filter {
+    pub fn all() -> [Filter; 5] {
+        [Filter::Nearest, Filter::Scale2x, Filter::Scale3x, Filter::Hq2x, Filter::XbrLite]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Filter::Nearest => "Nearest neighbour",
+            Filter::Scale2x => "Scale2x",
+            Filter::Scale3x => "Scale3x",
+            Filter::Hq2x => "HQ2x",
+            Filter::XbrLite => "xBR-lite",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        Filter::all()
+            .into_iter()
+            .find(|f| f.name().to_lowercase() == name || (name == "nearest" && *f == Filter::Nearest))
+    }
+
+    /// Size multiplier of `apply`
+    pub fn factor(&self) -> u32 {
+        match self {
+            Filter::Nearest => 1,
+            Filter::Scale3x => 3,
+            _ => 2,
+        }
+    }
+
+    pub fn apply(&self, src: &[u32], size: [u32; 2]) -> Vec<u32> {
+        match self {
+            Filter::Nearest => src.to_vec(),
+            Filter::Scale2x => scale_by_corners(src, size, scale2x_corner),
+            Filter::Scale3x => scale3x(src, size),
+            Filter::Hq2x => scale_by_corners(src, size, hq2x_corner),
+            Filter::XbrLite => scale_by_corners(src, size, xbr_corner),
+        }
+    }
+}
+
+/// Pixels around a source pixel, mirrored so that rules are written for the bottom-right corner:
+/// (1, 0) is the neighbour next to the output corner horizontally, (0, 1) vertically
+struct Neighbourhood<'a> {
+    src: &'a [u32],
+    size: [u32; 2],
+    x: i32,
+    y: i32,
+    sx: i32,
+    sy: i32,
+}
+
+impl<'a> Neighbourhood<'a> {
+    fn at(&self, i: i32, j: i32) -> u32 {
+        let x = (self.x + i * self.sx).clamp(0, self.size[0] as i32 - 1);
+        let y = (self.y + j * self.sy).clamp(0, self.size[1] as i32 - 1);
+        self.src[(y * self.size[0] as i32 + x) as usize]
+    }
+}
+
+fn scale_by_corners(src: &[u32], size: [u32; 2], corner: fn(&Neighbourhood) -> u32) -> Vec<u32> {
+    let out_width = size[0] as usize * 2;
+    let mut out = vec![0u32; src.len() * 4];
+    for y in 0..size[1] as i32 {
+        for x in 0..size[0] as i32 {
+            for (sx, sy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                let n = Neighbourhood { src, size, x, y, sx, sy };
+                let ox = x as usize * 2 + (sx == 1) as usize;
+                let oy = y as usize * 2 + (sy == 1) as usize;
+                out[oy * out_width + ox] = corner(&n);
+            }
+        }
+    }
+    out
+}
+
+fn scale2x_corner(n: &Neighbourhood) -> u32 {
+    let (e, b, d, f, h) = (n.at(0, 0), n.at(0, -1), n.at(-1, 0), n.at(1, 0), n.at(0, 1));
+    if h == f && d != h && b != f {
+        f
+    } else {
+        e
+    }
+}
+
+fn hq2x_corner(n: &Neighbourhood) -> u32 {
+    let (e, f, h, i) = (n.at(0, 0), n.at(1, 0), n.at(0, 1), n.at(1, 1));
+    if similar(f, h) && !similar(e, f) && !similar(e, h) {
+        if similar(i, f) {
+            interpolate(&[(e, 2), (f, 1), (h, 1)])
+        } else {
+            interpolate(&[(e, 2), (f, 3), (h, 3)])
+        }
+    } else if !similar(e, i) && similar(e, f) && similar(e, h) {
+        interpolate(&[(e, 7), (i, 1)])
+    } else {
+        e
+    }
+}
+
+fn xbr_corner(n: &Neighbourhood) -> u32 {
+    let e = n.at(0, 0);
+    let (b, c, d, f, g, h, i) = (n.at(0, -1), n.at(1, -1), n.at(-1, 0), n.at(1, 0), n.at(-1, 1), n.at(0, 1), n.at(1, 1));
+    let (f4, h5, i4, i5) = (n.at(2, 0), n.at(0, 2), n.at(2, 1), n.at(1, 2));
+    if e == f || e == h {
+        return e;
+    }
+    let edge_fh = distance(e, c) + distance(e, g) + distance(i, f4) + distance(i, h5) + 4 * distance(h, f);
+    let edge_ei = distance(h, d) + distance(h, i5) + distance(f, i4) + distance(f, b) + 4 * distance(e, i);
+    if edge_fh < edge_ei {
+        let color = if distance(e, f) <= distance(e, h) { f } else { h };
+        interpolate(&[(e, 1), (color, 1)])
+    } else {
+        e
+    }
+}
+
+fn scale3x(src: &[u32], size: [u32; 2]) -> Vec<u32> {
+    let out_width = size[0] as usize * 3;
+    let mut out = vec![0u32; src.len() * 9];
+    for y in 0..size[1] as i32 {
+        for x in 0..size[0] as i32 {
+            let n = Neighbourhood { src, size, x, y, sx: 1, sy: 1 };
+            let (a, b, c) = (n.at(-1, -1), n.at(0, -1), n.at(1, -1));
+            let (d, e, f) = (n.at(-1, 0), n.at(0, 0), n.at(1, 0));
+            let (g, h, i) = (n.at(-1, 1), n.at(0, 1), n.at(1, 1));
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) { b } else { e },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) { d } else { e },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) { f } else { e },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) { h } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+            for (k, color) in block.iter().enumerate() {
+                let ox = x as usize * 3 + k % 3;
+                let oy = y as usize * 3 + k / 3;
+                out[oy * out_width + ox] = *color;
+            }
+        }
+    }
+    out
+}
+
+fn yuv(color: u32) -> [i32; 3] {
+    let [r, g, b, _] = color.to_be_bytes().map(|c| c as i32);
+    [
+        (r + g + b) / 3,
+        (r - b) / 4 + 128,
+        (2 * g - r - b) / 8 + 128,
+    ]
+}
+
+/// hqx thresholds
+fn similar(a: u32, b: u32) -> bool {
+    let (a, b) = (yuv(a), yuv(b));
+    (a[0] - b[0]).abs() <= 0x30 && (a[1] - b[1]).abs() <= 0x07 && (a[2] - b[2]).abs() <= 0x06
+}
+
+fn distance(a: u32, b: u32) -> i32 {
+    let (a, b) = (yuv(a), yuv(b));
+    48 * (a[0] - b[0]).abs() + 7 * (a[1] - b[1]).abs() + 6 * (a[2] - b[2]).abs()
+}
+
+fn interpolate(colors: &[(u32, u32)]) -> u32 {
+    let total: u32 = colors.iter().map(|(_, w)| w).sum();
+    let mut channels = [0u32; 4];
+    for (color, weight) in colors {
+        for (channel, value) in channels.iter_mut().zip(color.to_be_bytes()) {
+            *channel += value as u32 * weight;
+        }
+    }
+    u32::from_be_bytes(channels.map(|c| (c / total) as u8))
+}
+
+#[cfg(test)]
+mod filter_tests {
+
+    use super::*;
+
+    const B: u32 = 0x000000FF;
+    const W: u32 = 0xFFFFFFFF;
+
+    #[test]
+    fn test_scale2x_diagonal() {
+        let src = [W, B, B, W];
+        let out = Filter::Scale2x.apply(&src, [2, 2]);
+        assert_eq!(out, vec![
+            W, W, B, B,
+            W, B, W, B,
+            B, W, B, W,
+            B, B, W, W,
+        ]);
+    }
+
+    #[test]
+    fn test_flat_image_unchanged() {
+        for filter in Filter::all() {
+            let factor = filter.factor() as usize;
+            let out = filter.apply(&[W; 4], [2, 2]);
+            assert_eq!(out, vec![W; 4 * factor * factor], "{}", filter.name());
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+/// Host key codes to the keys of `Emulator::process_input`
 pub struct InputMap {
     input_map: HashMap<u32, u32>,
 }
@@ -13,7 +14,15 @@ impl Default for InputMap {
 }
 
 impl InputMap {
-    
+    pub fn new(keys: &[(u32, u32)]) -> Self {
+        Self {
+            input_map: keys.iter().copied().collect()
+        }
+    }
+
+    pub fn emul_key(&self, host_key: u32) -> Option<u32> {
+        self.input_map.get(&host_key).copied()
+    }
 }
 
 pub struct InputMgr {
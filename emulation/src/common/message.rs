@@ -9,6 +9,24 @@ pub enum ErrorMsgId {
     RomFileNotFound,
     UnknownInstruction,
     NotInitialized,
+    InvalidFormat,
+    FileRead,
+    FileWrite,
+    NotSupported,
+    UnknownSystem,
+    StackOverflow,
+    StackUnderflow,
+    InvalidKey,
+    MemoryFault,
+}
+
+#[derive(Debug, PartialEq, IntoStaticStr)]
+pub enum InfoMsgId {
+    StateSaved,
+    StateLoaded,
+    ScreenshotSaved,
+    RecordingSaved,
+    RecordingSavedWithAudio,
 }
 
 #[derive(Debug, PartialEq, IntoStaticStr)]
@@ -18,6 +36,13 @@ pub enum ErrorTopicId {
     VramRead,
     VramWrite,
     Emulator,
+    RomDb,
+    Screenshot,
+    Record,
+    SaveState,
+    /// Error of the emulated program rather than of the emulator
+    GuestFault,
+    Cartridge,
 }
 
 pub trait MsgInfo {
@@ -25,14 +50,15 @@ pub trait MsgInfo {
     fn topic_id(&self) -> &'static str;
     fn msg_id(&self) -> &'static str;
     fn params(&self) -> &Option<Vec<String>>;
-    fn source(&self) -> &Option<Box<dyn Error>>;
+    fn source(&self) -> &Option<Box<dyn Error + Send + Sync>>;
 }
 
-pub trait Msg: MsgInfo + Display + Debug {
+/// Messages are `Send` so they can be reported from the emulation thread
+pub trait Msg: MsgInfo + Display + Debug + Send {
 
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MsgKind {
     Error,
     Warning,
@@ -44,7 +70,7 @@ pub struct ErrorMsg {
     pub msg_id: &'static str,
     pub topic_id: &'static str,
     pub params: Option<Vec<String>>,
-    pub source: Option<Box<dyn Error>>,
+    pub source: Option<Box<dyn Error + Send + Sync>>,
 }
 
 impl ErrorMsg {
@@ -65,7 +91,7 @@ impl ErrorMsg {
         self
     }
 
-    pub fn set_source(mut self, source: Box<dyn std::error::Error>) -> Self{
+    pub fn set_source(mut self, source: Box<dyn Error + Send + Sync>) -> Self{
         self.source = Some(source);
         self
     }
@@ -88,14 +114,15 @@ impl MsgInfo for ErrorMsg {
         &self.params
     }
 
-    fn source(&self) -> &Option<Box<dyn Error>> {
+    fn source(&self) -> &Option<Box<dyn Error + Send + Sync>> {
         &self.source
     }
 }
 
 impl Display for ErrorMsg {
     fn fmt(&self, err: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
-        write!(err, "{}: {}", self.topic_id, self.msg_id)
+        write!(err, "{}: {}", self.topic_id, self.msg_id)?;
+        write_params(err, &self.params)
     }
 }
 
@@ -108,4 +135,100 @@ impl Error for ErrorMsg {
     }
 }
 
-impl Msg for ErrorMsg {}
\ No newline at end of file
+impl Msg for ErrorMsg {}
+
+/// Warning or info message, e.g. a status shown to the user after an action
+#[derive(Debug)]
+pub struct StatusMsg {
+    pub kind: MsgKind,
+    pub msg_id: &'static str,
+    pub topic_id: &'static str,
+    pub params: Option<Vec<String>>,
+}
+
+impl StatusMsg {
+    pub fn info(topic_id: &'static str, msg_id: &'static str) -> Self {
+        Self {
+            kind: MsgKind::Info,
+            msg_id,
+            topic_id,
+            params: None,
+        }
+    }
+
+    pub fn warning(topic_id: &'static str, msg_id: &'static str) -> Self {
+        Self {
+            kind: MsgKind::Warning,
+            ..StatusMsg::info(topic_id, msg_id)
+        }
+    }
+
+    pub fn add_param(mut self, param: String) -> Self {
+        self.params.get_or_insert_with(Vec::new).push(param);
+        self
+    }
+}
+
+impl MsgInfo for StatusMsg {
+    fn kind(&self) -> MsgKind {
+        self.kind
+    }
+
+    fn topic_id(&self) -> &'static str {
+        self.topic_id
+    }
+
+    fn msg_id(&self) -> &'static str {
+        self.msg_id
+    }
+
+    fn params(&self) -> &Option<Vec<String>> {
+        &self.params
+    }
+
+    fn source(&self) -> &Option<Box<dyn Error + Send + Sync>> {
+        &None
+    }
+}
+
+impl Display for StatusMsg {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), std::fmt::Error> {
+        write!(f, "{}: {}", self.topic_id, self.msg_id)?;
+        write_params(f, &self.params)
+    }
+}
+
+impl Msg for StatusMsg {}
+
+fn write_params(f: &mut Formatter<'_>, params: &Option<Vec<String>>) -> Result<(), std::fmt::Error> {
+    match params {
+        Some(params) if !params.is_empty() => write!(f, " ({})", params.join(", ")),
+        _ => Ok(()),
+    }
+}
+
+/// Replaces `{0}`, `{1}`... in a message template with the message params,
+/// placeholders without a param are left as they are
+pub fn format_template(template: &str, params: &Option<Vec<String>>) -> String {
+    let mut text = String::from(template);
+    if let Some(params) = params {
+        for (i, param) in params.iter().enumerate() {
+            text = text.replace(&format!("{{{}}}", i), param);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod message_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_format_template() {
+        let params = Some(vec![String::from("0x2FE"), String::from("0x00EE")]);
+        assert_eq!(format_template("{1} at {0}, {2}", &params), "0x00EE at 0x2FE, {2}");
+        let msg = StatusMsg::info("SaveState", "StateSaved").add_param(String::from("a.state1"));
+        assert_eq!(msg.to_string(), "SaveState: StateSaved (a.state1)");
+    }
+}
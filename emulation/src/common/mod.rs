@@ -3,4 +3,15 @@ pub mod vram;
 pub mod utils;
 pub mod input;
 pub mod emulator;
-pub mod message;
\ No newline at end of file
+pub mod message;
+pub mod palette;
+pub mod screenshot;
+pub mod record;
+pub mod scaler;
+pub mod filter;
+pub mod phosphor;
+pub mod scheduler;
+pub mod triple_buffer;
+pub mod emul_thread;
+pub mod registry;
+pub mod bus;
\ No newline at end of file
@@ -0,0 +1,129 @@
+/// Maps logical pixel indices stored in `Vram` to RGBA colours (0xRRGGBBAA).
+/// A true colour palette has no entries, its framebuffers hold 4 RGBA bytes per pixel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    name: String,
+    colors: Vec<u32>,
+    true_color: bool,
+    composed: bool,
+}
+
+pub const BACKGROUND: u8 = 0;
+pub const FOREGROUND: u8 = 1;
+pub const FOREGROUND_2: u8 = 2;
+pub const BLEND: u8 = 3;
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::new("Classic", vec![0x000000FF, 0x00FF00FF, 0x008000FF, 0x004000FF])
+    }
+}
+
+impl Palette {
+    pub fn new(name: &str, colors: Vec<u32>) -> Self {
+        Self {
+            name: String::from(name),
+            colors,
+            true_color: false,
+            composed: false,
+        }
+    }
+
+    /// The indices already combine lit pixels with their colour, like the CHIP-8X colour zones,
+    /// so index 0 is not the background and the indices are not bit planes
+    pub fn composed(name: &str, colors: Vec<u32>) -> Self {
+        Self {
+            composed: true,
+            ..Palette::new(name, colors)
+        }
+    }
+
+    /// For the framebuffers of true colour `Vram`
+    pub fn true_color() -> Self {
+        Self {
+            name: String::from("True colour"),
+            colors: Vec::new(),
+            true_color: true,
+            composed: false,
+        }
+    }
+
+    pub fn is_true_color(&self) -> bool {
+        self.true_color
+    }
+
+    pub fn is_composed(&self) -> bool {
+        self.composed
+    }
+
+    /// Builtin presets, the four-colour ones follow the Octo themes
+    pub fn presets() -> Vec<Palette> {
+        vec![
+            Palette::default(),
+            Palette::new("Octo", vec![0x996600FF, 0xFFCC00FF, 0xFF6600FF, 0x662200FF]),
+            Palette::new("LCD", vec![0xF9FFB3FF, 0x3D8026FF, 0xABCC47FF, 0x00131AFF]),
+            Palette::new("Hotdog", vec![0x000000FF, 0xFF0000FF, 0xFFFF00FF, 0xFFFFFFFF]),
+            Palette::new("Gray", vec![0xAAAAAAFF, 0x000000FF, 0xFFFFFFFF, 0x666666FF]),
+            Palette::new("CGA 0", vec![0x000000FF, 0x00FF00FF, 0xFF0000FF, 0xFFFF00FF]),
+            Palette::new("CGA 1", vec![0x000000FF, 0xFF00FFFF, 0x00FFFFFF, 0xFFFFFFFF]),
+        ]
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = String::from(name);
+    }
+
+    pub fn colors(&self) -> &Vec<u32> {
+        &self.colors
+    }
+
+    pub fn colors_mut(&mut self) -> &mut Vec<u32> {
+        &mut self.colors
+    }
+
+    /// Indices without a colour are drawn with the last one
+    pub fn color(&self, index: u8) -> u32 {
+        match self.colors.get(index as usize) {
+            Some(color) => *color,
+            None => self.colors.last().copied().unwrap_or(0),
+        }
+    }
+
+    /// Colour of pixel `pixel` of a framebuffer, black past its end
+    pub fn pixel_color(&self, buffer: &[u8], pixel: usize) -> u32 {
+        if !self.true_color {
+            return self.color(buffer.get(pixel).copied().unwrap_or(0));
+        }
+        match buffer.get(pixel * 4..pixel * 4 + 4) {
+            Some(bytes) => u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            None => 0x000000FF,
+        }
+    }
+
+    /// Pixels in a framebuffer mapped by this palette
+    pub fn pixel_count(&self, buffer: &[u8]) -> usize {
+        match self.true_color {
+            true => buffer.len() / 4,
+            false => buffer.len(),
+        }
+    }
+
+    pub fn to_colors(&self, buffer: &[u8]) -> Vec<u32> {
+        (0..self.pixel_count(buffer)).map(|i| self.pixel_color(buffer, i)).collect()
+    }
+
+    pub fn to_rgba(&self, indices: &[u8]) -> Vec<u8> {
+        if self.true_color {
+            return indices.to_vec();
+        }
+        let mut out: Vec<u8> = Vec::with_capacity(4 * indices.len());
+        for index in indices {
+            out.extend(self.color(*index).to_be_bytes());
+        }
+        out
+    }
+}
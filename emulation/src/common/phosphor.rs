@@ -0,0 +1,154 @@
+use crate::common::palette::Palette;
+
+pub const DEFAULT_DECAY: f32 = 0.6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DisplayMode {
+    #[default]
+    Normal,
+    /// Lit pixels fade out, keeping `decay` of their intensity every frame
+    Blend { decay: f32 },
+    /// A pixel is shown if it is lit in the current or the previous frame
+    OrLastTwo,
+}
+
+impl DisplayMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayMode::Normal => "Normal",
+            DisplayMode::Blend { .. } => "Phosphor blend",
+            DisplayMode::OrLastTwo => "OR last two frames",
+        }
+    }
+}
+
+/// Post-process of the pixel indices produced by `Vram` imitating CRT phosphor persistence
+pub struct Phosphor {
+    mode: DisplayMode,
+    previous: Vec<u8>,
+    /// Index that lit the pixel last and its remaining intensity
+    glow: Vec<(u8, f32)>,
+    /// Processing the same frame again would give the same output
+    settled: bool,
+}
+
+impl Phosphor {
+    pub fn new(mode: DisplayMode) -> Self {
+        Self {
+            mode,
+            previous: Vec::new(),
+            glow: Vec::new(),
+            settled: false,
+        }
+    }
+
+    pub fn mode(&self) -> DisplayMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DisplayMode) {
+        if self.mode != mode {
+            self.mode = mode;
+            self.reset();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.previous.clear();
+        self.glow.clear();
+        self.settled = false;
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+
+    /// RGBA colours of the processed frame, one per pixel.
+    /// The effects follow lit pixel indices, true colour and composed frames pass through
+    pub fn process(&mut self, buffer: &[u8], palette: &Palette) -> Vec<u32> {
+        if palette.is_true_color() || palette.is_composed() {
+            self.settled = true;
+            return palette.to_colors(buffer);
+        }
+        match self.mode {
+            DisplayMode::Normal => {
+                self.settled = true;
+                buffer.iter().map(|i| palette.color(*i)).collect()
+            }
+            DisplayMode::OrLastTwo => {
+                if self.previous.len() != buffer.len() {
+                    self.previous = buffer.to_vec();
+                }
+                let out = buffer
+                    .iter()
+                    .zip(&self.previous)
+                    .map(|(cur, prev)| palette.color(cur | prev))
+                    .collect();
+                self.settled = self.previous == buffer;
+                self.previous.copy_from_slice(buffer);
+                out
+            }
+            DisplayMode::Blend { decay } => {
+                if self.glow.len() != buffer.len() {
+                    self.glow = vec![(0, 0.0); buffer.len()];
+                }
+                let background = palette.color(0);
+                let mut glowing = false;
+                let out = buffer
+                    .iter()
+                    .zip(self.glow.iter_mut())
+                    .map(|(index, glow)| {
+                        if *index != 0 {
+                            *glow = (*index, 1.0);
+                            return palette.color(*index);
+                        }
+                        glow.1 *= decay;
+                        if glow.1 < 1.0 / 255.0 {
+                            glow.1 = 0.0;
+                            return background;
+                        }
+                        glowing = true;
+                        Phosphor::mix(background, palette.color(glow.0), glow.1)
+                    })
+                    .collect();
+                self.settled = !glowing;
+                out
+            }
+        }
+    }
+
+    fn mix(from: u32, to: u32, amount: f32) -> u32 {
+        let (from, to) = (from.to_be_bytes(), to.to_be_bytes());
+        let mut out = [0u8; 4];
+        for i in 0..4 {
+            out[i] = (from[i] as f32 + (to[i] as f32 - from[i] as f32) * amount).round() as u8;
+        }
+        u32::from_be_bytes(out)
+    }
+}
+
+#[cfg(test)]
+mod phosphor_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_or_last_two() {
+        let palette = Palette::default();
+        let mut phosphor = Phosphor::new(DisplayMode::OrLastTwo);
+        phosphor.process(&[1, 0], &palette);
+        assert_eq!(phosphor.process(&[0, 2], &palette), vec![palette.color(1), palette.color(2)]);
+        assert!(!phosphor.is_settled());
+    }
+
+    #[test]
+    fn test_composed_pass_through() {
+        let palette = Palette::composed("Zones", vec![0x000080FF, 0x000000FF, 0xFF0000FF, 0x00FF00FF]);
+        for mode in [DisplayMode::OrLastTwo, DisplayMode::Blend { decay: DEFAULT_DECAY }] {
+            let mut phosphor = Phosphor::new(mode);
+            phosphor.process(&[2, 1], &palette);
+            assert_eq!(phosphor.process(&[1, 3], &palette), vec![0x000000FF, 0x00FF00FF]);
+            assert!(phosphor.is_settled());
+        }
+    }
+}
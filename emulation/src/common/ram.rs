@@ -1,89 +1,162 @@
 use crate::common::message::*;
 
+/// How addresses past the end of the memory are treated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressMode {
+    /// Accesses fail with `OutOfBounds`
+    Checked,
+    /// Addresses wrap around the memory size, like the CHIP-8 I register at 4K
+    Wrap,
+}
+
+/// Flat memory, no access panics: out of range addresses either fail or wrap
 pub struct Ram {
     memory: Vec<u8>,
-    size: usize,
+    mode: AddressMode,
 }
 
 impl Ram {
-    
+
     pub fn new(size: usize) -> Self {
+        Ram::with_mode(size, AddressMode::Checked)
+    }
+
+    pub fn with_mode(size: usize, mode: AddressMode) -> Self {
         Ram {
-            size: size,
             memory: vec![0u8; size],
+            mode,
         }
     }
 
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn mode(&self) -> AddressMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: AddressMode) {
+        self.mode = mode;
+    }
+
     pub fn read_byte(&self, addr: usize) -> Result<u8, Box<dyn Msg>> {
-        if self.size < addr {
-            let err = ErrorMsg::new(ErrorTopicId::RamRead.into(), ErrorMsgId::OutOfBounds.into())
-                .add_param(addr.to_string())
-                .add_param(self.memory.len().to_string())
-                .add_param(String::from("1"));
-            return Err(Box::new(err));
-        }
-        Ok(self.memory[addr])
+        let index = self.index(ErrorTopicId::RamRead, addr, 1)?;
+        Ok(self.memory[index])
     }
 
+    /// Big endian word
     pub fn read_word(&self, addr: usize) -> Result<u16, Box<dyn Msg>> {
-        if self.size < addr || self.size < addr + 1 {
-            let err = ErrorMsg::new(ErrorTopicId::RamRead.into(), ErrorMsgId::OutOfBounds.into())
-                .add_param(addr.to_string())
-                .add_param(self.memory.len().to_string())
-                .add_param(String::from("2"));
-            return Err(Box::new(err));
-        }
-        let first_byte = self.memory[addr] as u16;
-        let second_byte = self.memory[addr + 1] as u16;
-        let mut word: u16 = first_byte << 8;
-        word = word | second_byte;
-        Ok(word)
+        let high = self.index(ErrorTopicId::RamRead, addr, 2)?;
+        let low = (high + 1) % self.memory.len();
+        Ok(u16::from_be_bytes([self.memory[high], self.memory[low]]))
     }
 
     pub fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Box<dyn Msg>> {
-        if addr > self.memory.len() {
-            let err = ErrorMsg::new(ErrorTopicId::RamWrite.into(), ErrorMsgId::OutOfBounds.into())
-                .add_param(addr.to_string())
-                .add_param(self.memory.len().to_string())
-                .add_param(String::from("1"));
-            return Err(Box::new(err));
-        }
-        Ok(self.memory[addr] = value)
+        let index = self.index(ErrorTopicId::RamWrite, addr, 1)?;
+        self.memory[index] = value;
+        Ok(())
     }
 
+    /// Big endian word
+    pub fn write_word(&mut self, addr: usize, value: u16) -> Result<(), Box<dyn Msg>> {
+        let high = self.index(ErrorTopicId::RamWrite, addr, 2)?;
+        let low = (high + 1) % self.memory.len();
+        let [left, right] = value.to_be_bytes();
+        self.memory[high] = left;
+        self.memory[low] = right;
+        Ok(())
+    }
+
+    /// Nothing is written if the block does not fit
     pub fn write_block(&mut self, start_addr: usize, data: Vec<u8>) -> Result<(), Box<dyn Msg>> {
-        let block_end = start_addr + data.len();
-        if block_end > self.size {
-            let err = ErrorMsg::new(ErrorTopicId::RamRead.into(), ErrorMsgId::OutOfBounds.into())
-                .add_param(start_addr.to_string())
-                .add_param(self.memory.len().to_string())
-                .add_param(data.len().to_string());
-            return Err(Box::new(err));
-        }
-        let mut i = start_addr;
-        for byte in data {
-            let res = self.write_byte(i, byte);
-            i += 1;
-            match res {
-                Err(err) => return Err(err),
-                _ => {}
-            }
+        let first = self.index(ErrorTopicId::RamWrite, start_addr, data.len())?;
+        let size = self.memory.len();
+        for (i, byte) in data.into_iter().enumerate() {
+            self.memory[(first + i) % size] = byte;
         }
         Ok(())
     }
-    
-    pub fn write_word(&mut self, addr: usize, value: u16) -> Result<(), Box<dyn Msg>> {
-        if addr > self.memory.len() + 1 {
-            let err = ErrorMsg::new(ErrorTopicId::RamWrite.into(), ErrorMsgId::OutOfBounds.into())
-                .add_param(addr.to_string())
-                .add_param(self.memory.len().to_string())
-                .add_param(String::from("2"));
-            return Err(Box::new(err));
+
+    pub fn data(&self) -> &[u8] {
+        &self.memory
+    }
+
+    /// Replaces the whole content, used to restore a saved state
+    pub fn load(&mut self, data: &[u8]) -> Result<(), Box<dyn Msg>> {
+        if data.len() != self.memory.len() {
+            return Err(self.out_of_bounds(ErrorTopicId::RamWrite, 0, data.len()));
         }
-        let left = (value >> 8) as u8;
-        let right = (value & 0x00FF) as u8;
-        self.memory[addr] = left;
-        self.memory[addr+1] = right;
+        self.memory.copy_from_slice(data);
         Ok(())
     }
+
+    /// Index of `addr` in the memory, checks that `len` bytes from `addr` fit in checked mode
+    fn index(&self, topic: ErrorTopicId, addr: usize, len: usize) -> Result<usize, Box<dyn Msg>> {
+        let size = self.memory.len();
+        match self.mode {
+            AddressMode::Wrap if size > 0 && len <= size => Ok(addr % size),
+            AddressMode::Checked if addr.checked_add(len).is_some_and(|end| end <= size) => Ok(addr),
+            _ => Err(self.out_of_bounds(topic, addr, len)),
+        }
+    }
+
+    fn out_of_bounds(&self, topic: ErrorTopicId, addr: usize, len: usize) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(topic.into(), ErrorMsgId::OutOfBounds.into())
+            .add_param(addr.to_string())
+            .add_param(self.memory.len().to_string())
+            .add_param(len.to_string());
+        Box::new(err)
+    }
+}
+
+#[cfg(test)]
+mod ram_tests {
+
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_last_byte() {
+        let mut ram = Ram::new(16);
+        assert!(ram.write_byte(15, 1).is_ok());
+        assert!(ram.write_byte(16, 1).is_err());
+        assert!(ram.read_word(15).is_err());
+        let err = ram.write_block(10, vec![0; 7]).unwrap_err();
+        assert_eq!(err.topic_id(), "RamWrite");
+        assert_eq!(err.msg_id(), "OutOfBounds");
+    }
+
+    proptest! {
+        #[test]
+        fn checked_access_never_panics(size in 0usize..64, addr in any::<usize>(), value in any::<u16>()) {
+            let mut ram = Ram::new(size);
+            prop_assert_eq!(ram.read_byte(addr).is_ok(), addr < size);
+            prop_assert_eq!(ram.write_byte(addr, value as u8).is_ok(), addr < size);
+            let word_fits = addr < size.saturating_sub(1);
+            prop_assert_eq!(ram.write_word(addr, value).is_ok(), word_fits);
+            prop_assert_eq!(ram.read_word(addr).ok(), if word_fits { Some(value) } else { None });
+        }
+
+        #[test]
+        fn wrapped_access_matches_modulo(size in 2usize..64, addr in any::<usize>(), value in any::<u16>()) {
+            let mut ram = Ram::with_mode(size, AddressMode::Wrap);
+            ram.write_word(addr, value).unwrap();
+            prop_assert_eq!(ram.read_word(addr).unwrap(), value);
+            prop_assert_eq!(ram.read_byte(addr % size).unwrap(), (value >> 8) as u8);
+            prop_assert_eq!(ram.read_byte((addr % size + 1) % size).unwrap(), value as u8);
+        }
+
+        #[test]
+        fn block_is_all_or_nothing(size in 0usize..64, start in 0usize..80, data in prop::collection::vec(1u8.., 0..32)) {
+            let mut ram = Ram::new(size);
+            let fits = start + data.len() <= size;
+            prop_assert_eq!(ram.write_block(start, data.clone()).is_ok(), fits);
+            if fits {
+                prop_assert_eq!(&ram.data()[start..start + data.len()], &data[..]);
+            } else {
+                prop_assert!(ram.data().iter().all(|b| *b == 0));
+            }
+        }
+    }
 }
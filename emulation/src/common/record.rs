@@ -0,0 +1,546 @@
+use crate::common::emulator::SAMPLE_RATE;
+use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::scaler;
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const FRAME_RATE: u32 = 60;
+/// NeuQuant sampling factor for true colour GIF frames, 1 is the best and slowest
+const GIF_QUANTIZE_SPEED: i32 = 10;
+const BUZZER_FREQUENCY: u32 = 440;
+const BUZZER_VOLUME: i16 = 8000;
+/// Size of the AVI headers up to the `movi` list data, they are written again with the totals on `finish`
+const AVI_HEADER_SIZE: u32 = 324;
+const AVIF_HAS_INDEX: u32 = 0x10;
+const AVIIF_KEYFRAME: u32 = 0x10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RecordFormat {
+    /// Animated GIF using the palette indices directly, true colour frames are quantized
+    Gif,
+    /// YUV4MPEG2 raw frames. The format has no audio track, the sound goes to a WAV file
+    /// next to it, see `Recorder::audio_file_name`
+    Y4m,
+    /// AVI with uncompressed frames and the sound interleaved. RIFF sizes are 32-bit and there is
+    /// no OpenDML index, so recordings should stay below 1 GB
+    Avi,
+}
+
+impl RecordFormat {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let ext = Path::new(file_name).extension()?.to_string_lossy().to_lowercase();
+        match ext.as_str() {
+            "gif" => Some(RecordFormat::Gif),
+            "y4m" => Some(RecordFormat::Y4m),
+            "avi" => Some(RecordFormat::Avi),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RecordFormat::Gif => "gif",
+            RecordFormat::Y4m => "y4m",
+            RecordFormat::Avi => "avi",
+        }
+    }
+}
+
+/// Sound of a recorded frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameSound<'a> {
+    /// Buzzer on or off, recorded as a square wave
+    Buzzer(bool),
+    /// Mono samples at `SAMPLE_RATE`
+    Pcm(&'a [i16]),
+}
+
+impl<'a> FrameSound<'a> {
+    /// PCM when the system produced samples, the buzzer state otherwise
+    pub fn new(sound_active: bool, samples: Option<&'a [i16]>) -> Self {
+        match samples {
+            Some(samples) => FrameSound::Pcm(samples),
+            None => FrameSound::Buzzer(sound_active),
+        }
+    }
+}
+
+enum Output {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        last_frame: Option<Vec<u8>>,
+        /// Frames the last one has been shown for
+        last_frame_count: u32,
+        /// Centiseconds already written, GIF delays are in 1/100 s
+        written_time: u32,
+        frame_count: u32,
+    },
+    Y4m {
+        video: BufWriter<File>,
+        audio: BufWriter<File>,
+        audio_samples: u32,
+    },
+    Avi {
+        file: BufWriter<File>,
+        /// `idx1` entries of the chunks written so far
+        index: Vec<u8>,
+        /// Bytes of the `movi` list after its type
+        movi_size: u32,
+        frames: u32,
+        audio_samples: u32,
+    },
+}
+
+/// Records the framebuffer stream, one `push_frame` call per emulated 60Hz frame
+pub struct Recorder {
+    output: Output,
+    file_name: String,
+    resolution: [u32; 2],
+    palette: Palette,
+    scale: u32,
+}
+
+impl Recorder {
+    pub fn start(
+        file_name: &str,
+        format: RecordFormat,
+        resolution: [u32; 2],
+        palette: &Palette,
+        scale: u32,
+    ) -> Result<Self, Box<dyn Msg>> {
+        let scale = scale.max(1);
+        let width = resolution[0] * scale;
+        let height = resolution[1] * scale;
+        let output = match format {
+            RecordFormat::Gif => {
+                let file = Recorder::create(file_name)?;
+                let mut colors = Vec::with_capacity(palette.colors().len() * 3);
+                for color in palette.colors().iter().take(256) {
+                    colors.extend(&color.to_be_bytes()[..3]);
+                }
+                let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &colors)
+                    .map_err(|e| Recorder::error(file_name, Box::new(e)))?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(|e| Recorder::error(file_name, Box::new(e)))?;
+                Output::Gif {
+                    encoder,
+                    last_frame: None,
+                    last_frame_count: 0,
+                    written_time: 0,
+                    frame_count: 0,
+                }
+            }
+            RecordFormat::Y4m => {
+                let mut video = Recorder::create(file_name)?;
+                let header = format!("YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444\n", width, height, FRAME_RATE);
+                video
+                    .write_all(header.as_bytes())
+                    .map_err(|e| Recorder::error(file_name, Box::new(e)))?;
+                let audio_name = Recorder::audio_file_name(file_name);
+                let mut audio = Recorder::create(&audio_name)?;
+                Recorder::write_wav_header(&mut audio, 0)
+                    .map_err(|e| Recorder::error(&audio_name, Box::new(e)))?;
+                Output::Y4m {
+                    video,
+                    audio,
+                    audio_samples: 0,
+                }
+            }
+            RecordFormat::Avi => {
+                let mut file = Recorder::create(file_name)?;
+                Recorder::write_avi_header(&mut file, [width, height], 0, 0, 0, 0)
+                    .map_err(|e| Recorder::error(file_name, Box::new(e)))?;
+                Output::Avi {
+                    file,
+                    index: Vec::new(),
+                    movi_size: 0,
+                    frames: 0,
+                    audio_samples: 0,
+                }
+            }
+        };
+        Ok(Self {
+            output,
+            file_name: String::from(file_name),
+            resolution,
+            palette: palette.clone(),
+            scale,
+        })
+    }
+
+    /// WAV file written next to a Y4M recording
+    pub fn audio_file_name(file_name: &str) -> String {
+        Path::new(file_name).with_extension("wav").to_string_lossy().into_owned()
+    }
+
+    /// Where the sound of this recording goes when the video format cannot carry it
+    pub fn audio_file(&self) -> Option<String> {
+        match self.output {
+            Output::Y4m { .. } => Some(Recorder::audio_file_name(&self.file_name)),
+            _ => None,
+        }
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn push_frame(&mut self, buffer: &[u8], sound: FrameSound) -> Result<(), Box<dyn Msg>> {
+        let pixel_size = if self.palette.is_true_color() { 4 } else { 1 };
+        let frame = scaler::scale_pixels(buffer, self.resolution, self.scale, pixel_size);
+        let result = match &mut self.output {
+            Output::Gif { last_frame, last_frame_count, .. } => {
+                if last_frame.as_ref() == Some(&frame) {
+                    *last_frame_count += 1;
+                    return Ok(());
+                }
+                let count = std::mem::replace(last_frame_count, 1);
+                match last_frame.replace(frame) {
+                    Some(previous) => self.write_gif_frame(&previous, count),
+                    None => Ok(()),
+                }
+            }
+            Output::Y4m { video, audio, audio_samples } => {
+                Recorder::write_y4m_frame(video, &frame, &self.palette)
+                    .and_then(|_| audio.write_all(&Recorder::audio_frame(audio_samples, sound)))
+                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+            }
+            Output::Avi { file, index, movi_size, frames, audio_samples } => {
+                *frames += 1;
+                let width = self.resolution[0] * self.scale;
+                let video = Recorder::avi_video_frame(&frame, width, &self.palette);
+                let audio = Recorder::audio_frame(audio_samples, sound);
+                Recorder::write_avi_chunk(file, index, movi_size, b"00dc", &video)
+                    .and_then(|_| match audio.is_empty() {
+                        true => Ok(()),
+                        false => Recorder::write_avi_chunk(file, index, movi_size, b"01wb", &audio),
+                    })
+                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+            }
+        };
+        result.map_err(|e| Recorder::error(&self.file_name, e))
+    }
+
+    pub fn finish(mut self) -> Result<(), Box<dyn Msg>> {
+        let result = match &mut self.output {
+            Output::Gif { last_frame, last_frame_count, .. } => {
+                let count = *last_frame_count;
+                match last_frame.take() {
+                    Some(frame) => self.write_gif_frame(&frame, count),
+                    None => Ok(()),
+                }
+            }
+            Output::Y4m { video, audio, audio_samples } => video
+                .flush()
+                .and_then(|_| audio.seek(SeekFrom::Start(0)))
+                .and_then(|_| Recorder::write_wav_header(audio, *audio_samples))
+                .and_then(|_| audio.flush())
+                .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>),
+            Output::Avi { file, index, movi_size, frames, audio_samples } => {
+                let size = [self.resolution[0] * self.scale, self.resolution[1] * self.scale];
+                file.write_all(b"idx1")
+                    .and_then(|_| file.write_all(&(index.len() as u32).to_le_bytes()))
+                    .and_then(|_| file.write_all(index))
+                    .and_then(|_| file.seek(SeekFrom::Start(0)))
+                    .and_then(|_| {
+                        Recorder::write_avi_header(file, size, *frames, *audio_samples, *movi_size, index.len() as u32)
+                    })
+                    .and_then(|_| file.flush())
+                    .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+            }
+        };
+        result.map_err(|e| Recorder::error(&self.file_name, e))
+    }
+
+    fn write_gif_frame(&mut self, frame: &[u8], count: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let width = (self.resolution[0] * self.scale) as u16;
+        let height = (self.resolution[1] * self.scale) as u16;
+        let true_color = self.palette.is_true_color();
+        if let Output::Gif { encoder, written_time, frame_count, .. } = &mut self.output {
+            *frame_count += count;
+            let end_time = *frame_count * 100 / FRAME_RATE;
+            let delay = end_time.saturating_sub(*written_time).max(1);
+            *written_time += delay;
+            let mut gif_frame = if true_color {
+                let mut rgba = frame.to_vec();
+                gif::Frame::from_rgba_speed(width, height, &mut rgba, GIF_QUANTIZE_SPEED)
+            } else {
+                gif::Frame {
+                    width,
+                    height,
+                    buffer: Cow::Borrowed(frame),
+                    ..Default::default()
+                }
+            };
+            gif_frame.delay = delay.min(u16::MAX as u32) as u16;
+            encoder.write_frame(&gif_frame)?;
+        }
+        Ok(())
+    }
+
+    fn write_y4m_frame(video: &mut BufWriter<File>, frame: &[u8], palette: &Palette) -> std::io::Result<()> {
+        let mut y_plane = Vec::with_capacity(frame.len());
+        let mut u_plane = Vec::with_capacity(frame.len());
+        let mut v_plane = Vec::with_capacity(frame.len());
+        for pixel in 0..palette.pixel_count(frame) {
+            let [r, g, b, _] = palette.pixel_color(frame, pixel).to_be_bytes().map(|c| c as f32);
+            y_plane.push((16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8);
+            u_plane.push((128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8);
+            v_plane.push((128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8);
+        }
+        video.write_all(b"FRAME\n")?;
+        video.write_all(&y_plane)?;
+        video.write_all(&u_plane)?;
+        video.write_all(&v_plane)
+    }
+
+    /// Little endian PCM of the frame, for the buzzer a square wave while it is on and silence otherwise.
+    /// `samples` counts the samples written so far
+    fn audio_frame(samples: &mut u32, sound: FrameSound) -> Vec<u8> {
+        let sound_active = match sound {
+            FrameSound::Pcm(pcm) => {
+                *samples += pcm.len() as u32;
+                return pcm.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+            }
+            FrameSound::Buzzer(sound_active) => sound_active,
+        };
+        let half_period = SAMPLE_RATE / BUZZER_FREQUENCY / 2;
+        let count = SAMPLE_RATE / FRAME_RATE;
+        let mut data = Vec::with_capacity(count as usize * 2);
+        for i in *samples..*samples + count {
+            let sample = match sound_active {
+                true if (i / half_period).is_multiple_of(2) => BUZZER_VOLUME,
+                true => -BUZZER_VOLUME,
+                false => 0,
+            };
+            data.extend(sample.to_le_bytes());
+        }
+        *samples += count;
+        data
+    }
+
+    /// Bottom-up 24-bit BGR rows padded to 4 bytes, the layout of an uncompressed DIB
+    fn avi_video_frame(frame: &[u8], width: u32, palette: &Palette) -> Vec<u8> {
+        let width = width as usize;
+        let colors = palette.to_colors(frame);
+        let stride = (width * 3).next_multiple_of(4);
+        let mut data = Vec::with_capacity(stride * colors.len() / width.max(1));
+        for row in colors.chunks(width.max(1)).rev() {
+            for color in row {
+                let [r, g, b, _] = color.to_be_bytes();
+                data.extend([b, g, r]);
+            }
+            data.resize(data.len().next_multiple_of(4), 0);
+        }
+        data
+    }
+
+    fn write_avi_chunk(
+        file: &mut BufWriter<File>,
+        index: &mut Vec<u8>,
+        movi_size: &mut u32,
+        id: &[u8; 4],
+        data: &[u8],
+    ) -> std::io::Result<()> {
+        // offsets count from the `movi` type, the sizes leave out the padding to an even length
+        index.extend(id);
+        index.extend(AVIIF_KEYFRAME.to_le_bytes());
+        index.extend((4 + *movi_size).to_le_bytes());
+        index.extend((data.len() as u32).to_le_bytes());
+        file.write_all(id)?;
+        file.write_all(&(data.len() as u32).to_le_bytes())?;
+        file.write_all(data)?;
+        if data.len() % 2 == 1 {
+            file.write_all(&[0])?;
+        }
+        *movi_size += 8 + data.len().next_multiple_of(2) as u32;
+        Ok(())
+    }
+
+    /// `hdrl` with a video and an audio stream and the start of the `movi` list, `AVI_HEADER_SIZE` bytes
+    fn write_avi_header(
+        file: &mut BufWriter<File>,
+        size: [u32; 2],
+        frames: u32,
+        samples: u32,
+        movi_size: u32,
+        index_size: u32,
+    ) -> std::io::Result<()> {
+        let [width, height] = size;
+        let frame_size = (width * 3).next_multiple_of(4) * height;
+        let audio_frame_size = SAMPLE_RATE / FRAME_RATE * 2;
+        let mut header: Vec<u8> = Vec::with_capacity(AVI_HEADER_SIZE as usize);
+        let mut put = |values: &[u32]| values.iter().for_each(|v| header.extend(v.to_le_bytes()));
+        let fourcc = |id: &[u8; 4]| u32::from_le_bytes(*id);
+        let riff_size = AVI_HEADER_SIZE - 8 + movi_size + 8 + index_size;
+        put(&[fourcc(b"RIFF"), riff_size, fourcc(b"AVI ")]);
+        put(&[fourcc(b"LIST"), 292, fourcc(b"hdrl")]);
+        // main header: µs per frame, max bytes per second, padding, flags, frames, initial frames,
+        // streams, buffer size, width, height and 4 reserved
+        put(&[fourcc(b"avih"), 56, 1_000_000 / FRAME_RATE, (frame_size + audio_frame_size) * FRAME_RATE, 0]);
+        put(&[AVIF_HAS_INDEX, frames, 0, 2, frame_size, width, height, 0, 0, 0, 0]);
+        // stream headers: type, handler, flags, priority and language, initial frames, scale, rate,
+        // start, length, buffer size, quality, sample size and the frame rectangle
+        put(&[fourcc(b"LIST"), 116, fourcc(b"strl")]);
+        put(&[fourcc(b"strh"), 56, fourcc(b"vids"), fourcc(b"DIB "), 0, 0, 0, 1, FRAME_RATE, 0, frames]);
+        put(&[frame_size, u32::MAX, 0, 0, width | (height << 16)]);
+        // BITMAPINFOHEADER, a positive height is bottom-up
+        put(&[fourcc(b"strf"), 40, 40, width, height, 1 | (24 << 16), 0, frame_size, 0, 0, 0, 0]);
+        put(&[fourcc(b"LIST"), 92, fourcc(b"strl")]);
+        put(&[fourcc(b"strh"), 56, fourcc(b"auds"), 0, 0, 0, 0, 1, SAMPLE_RATE, 0, samples]);
+        put(&[audio_frame_size, u32::MAX, 2, 0, 0]);
+        // WAVEFORMATEX of 16-bit mono PCM
+        put(&[fourcc(b"strf"), 16, 1 | (1 << 16), SAMPLE_RATE, SAMPLE_RATE * 2, 2 | (16 << 16)]);
+        put(&[fourcc(b"LIST"), 4 + movi_size, fourcc(b"movi")]);
+        file.write_all(&header)
+    }
+
+    fn write_wav_header(audio: &mut BufWriter<File>, samples: u32) -> std::io::Result<()> {
+        let data_size = samples * 2;
+        audio.write_all(b"RIFF")?;
+        audio.write_all(&(36 + data_size).to_le_bytes())?;
+        audio.write_all(b"WAVEfmt ")?;
+        audio.write_all(&16u32.to_le_bytes())?;
+        audio.write_all(&1u16.to_le_bytes())?; // PCM
+        audio.write_all(&1u16.to_le_bytes())?; // mono
+        audio.write_all(&SAMPLE_RATE.to_le_bytes())?;
+        audio.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+        audio.write_all(&2u16.to_le_bytes())?;
+        audio.write_all(&16u16.to_le_bytes())?;
+        audio.write_all(b"data")?;
+        audio.write_all(&data_size.to_le_bytes())
+    }
+
+    fn create(file_name: &str) -> Result<BufWriter<File>, Box<dyn Msg>> {
+        File::create(file_name)
+            .map(BufWriter::new)
+            .map_err(|e| Recorder::error(file_name, Box::new(e)))
+    }
+
+    fn error(file_name: &str, source: Box<dyn std::error::Error + Send + Sync>) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(ErrorTopicId::Record.into(), ErrorMsgId::FileWrite.into())
+            .add_param(String::from(file_name))
+            .set_source(source);
+        Box::new(err)
+    }
+}
+
+#[cfg(test)]
+mod record_tests {
+
+    use super::*;
+
+    fn temp_file(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("record_test_{}_{}", std::process::id(), name));
+        path.to_string_lossy().into_owned()
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn palette() -> Palette {
+        Palette::new("test", vec![0x000000FF, 0xFFFFFFFF])
+    }
+
+    #[test]
+    fn test_gif_delays() {
+        let file_name = temp_file("delays.gif");
+        let mut recorder = Recorder::start(&file_name, RecordFormat::Gif, [2, 1], &palette(), 1).unwrap();
+        // held frames are merged, 3 frames last 5 centiseconds
+        for frame in [[0, 1], [0, 1], [0, 1], [1, 0], [1, 0], [1, 0]] {
+            recorder.push_frame(&frame, FrameSound::Buzzer(false)).unwrap();
+        }
+        // the first frame is written once the second one differs
+        if let Output::Gif { written_time, frame_count, .. } = &recorder.output {
+            assert_eq!((*written_time, *frame_count), (5, 3));
+        }
+        // single frames alternate between 1 and 2 centiseconds so 60 of them last a second
+        for i in 0..60 {
+            recorder.push_frame(&[i % 2, 1], FrameSound::Buzzer(false)).unwrap();
+        }
+        recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(File::open(&file_name).unwrap()).unwrap();
+        let mut delays = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay as u32);
+        }
+        std::fs::remove_file(&file_name).unwrap();
+        assert_eq!(delays[..2], [5, 5]);
+        assert_eq!(delays.len(), 2 + 60);
+        assert_eq!(delays.iter().sum::<u32>(), 10 + 100);
+    }
+
+    #[test]
+    fn test_y4m_and_wav() {
+        let file_name = temp_file("frames.y4m");
+        let mut recorder = Recorder::start(&file_name, RecordFormat::Y4m, [2, 1], &palette(), 2).unwrap();
+        let audio_file = recorder.audio_file().unwrap();
+        recorder.push_frame(&[0, 1], FrameSound::Pcm(&[1; 100])).unwrap();
+        recorder.push_frame(&[1, 0], FrameSound::Buzzer(true)).unwrap();
+        recorder.finish().unwrap();
+
+        let video = std::fs::read(&file_name).unwrap();
+        let audio = std::fs::read(&audio_file).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+        std::fs::remove_file(&audio_file).unwrap();
+        let header = b"YUV4MPEG2 W4 H2 F60:1 Ip A1:1 C444\n";
+        assert!(video.starts_with(header));
+        // FRAME line and the full size Y, U and V planes of 4x2 pixels
+        let frame_size = 6 + 3 * 8;
+        assert_eq!(video.len(), header.len() + 2 * frame_size);
+        assert_eq!(&video[header.len()..header.len() + 6], b"FRAME\n");
+
+        let data_size = (100 + SAMPLE_RATE / FRAME_RATE) * 2;
+        assert_eq!(u32_at(&audio, 4), 36 + data_size);
+        assert_eq!(u32_at(&audio, 40), data_size);
+        assert_eq!(audio.len() as u32, 44 + data_size);
+    }
+
+    #[test]
+    fn test_avi() {
+        let file_name = temp_file("muxed.avi");
+        let mut recorder = Recorder::start(&file_name, RecordFormat::Avi, [3, 2], &palette(), 1).unwrap();
+        assert_eq!(recorder.audio_file(), None);
+        recorder.push_frame(&[1, 0, 0, 0, 0, 0], FrameSound::Pcm(&[7; 735])).unwrap();
+        recorder.push_frame(&[0; 6], FrameSound::Pcm(&[])).unwrap();
+        recorder.push_frame(&[0; 6], FrameSound::Buzzer(true)).unwrap();
+        recorder.finish().unwrap();
+
+        let avi = std::fs::read(&file_name).unwrap();
+        std::fs::remove_file(&file_name).unwrap();
+        assert_eq!(&avi[0..4], b"RIFF");
+        assert_eq!(u32_at(&avi, 4) as usize, avi.len() - 8);
+        assert_eq!(&avi[8..12], b"AVI ");
+        // total frames of the main header, the lengths of the video and the audio stream
+        assert_eq!(u32_at(&avi, 48), 3);
+        assert_eq!(u32_at(&avi, 140), 3);
+        assert_eq!(u32_at(&avi, 264), 2 * 735);
+
+        let header = AVI_HEADER_SIZE as usize;
+        assert_eq!(&avi[header - 4..header], b"movi");
+        let movi_size = u32_at(&avi, header - 8) as usize - 4;
+        // 3 rows of 12 bytes, the first frame is bottom-up so the white pixel is in the last row
+        let stride = 12;
+        assert_eq!(&avi[header..header + 8], [b"00dc".as_slice(), &(2 * stride as u32).to_le_bytes()].concat());
+        assert_eq!(&avi[header + 8 + stride..header + 8 + stride + 3], [0xFF; 3]);
+        let audio = header + 8 + 2 * stride;
+        assert_eq!(&avi[audio..audio + 4], b"01wb");
+        assert_eq!(&avi[audio + 8..audio + 10], 7i16.to_le_bytes());
+
+        // 3 video and 2 audio chunks in the index, the offsets count from the movi type
+        let index = header + movi_size;
+        assert_eq!(&avi[index..index + 4], b"idx1");
+        assert_eq!(u32_at(&avi, index + 4), 5 * 16);
+        assert_eq!(avi.len(), index + 8 + 5 * 16);
+        let second_entry = index + 8 + 16;
+        assert_eq!(&avi[second_entry..second_entry + 4], b"01wb");
+        assert_eq!(u32_at(&avi, second_entry + 8) as usize, audio - (header - 4));
+    }
+}
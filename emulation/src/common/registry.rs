@@ -0,0 +1,113 @@
+use crate::atari2600::system::Atari2600;
+use crate::chip8::chip8::Chip8;
+use crate::common::emulator::Emulator;
+use crate::invaders::system::Invaders;
+use crate::gb::system::Gb;
+use crate::nes::system::Nes;
+
+use std::path::Path;
+
+/// Optional features of a system the UI can offer
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Capabilities {
+    pub save_states: bool,
+    pub reset: bool,
+    pub sound: bool,
+}
+
+/// Registry entry of an emulated system
+#[derive(Clone)]
+pub struct SystemInfo {
+    pub name: &'static str,
+    /// Lower case file extensions without the dot
+    pub extensions: &'static [&'static str],
+    pub capabilities: Capabilities,
+    pub factory: fn() -> Box<dyn Emulator>,
+    /// Recognises a ROM by its content, checked before the extension
+    pub sniff: Option<fn(&[u8]) -> bool>,
+}
+
+impl SystemInfo {
+    pub fn handles_extension(&self, extension: &str) -> bool {
+        let extension = extension.to_ascii_lowercase();
+        self.extensions.iter().any(|e| *e == extension)
+    }
+}
+
+/// Systems `EmulMgr` can create a core for
+#[derive(Clone)]
+pub struct Registry {
+    systems: Vec<SystemInfo>,
+}
+
+impl Default for Registry {
+    /// All built-in systems
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        registry.register(Chip8::system_info());
+        registry.register(Invaders::system_info());
+        registry.register(Gb::system_info());
+        registry.register(Nes::system_info());
+        registry.register(Atari2600::system_info());
+        registry
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self { systems: Vec::new() }
+    }
+
+    /// A system registered again under the same name replaces the old entry
+    pub fn register(&mut self, system: SystemInfo) {
+        self.systems.retain(|s| s.name != system.name);
+        self.systems.push(system);
+    }
+
+    pub fn systems(&self) -> &[SystemInfo] {
+        &self.systems
+    }
+
+    pub fn find(&self, name: &str) -> Option<&SystemInfo> {
+        self.systems.iter().find(|s| s.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Picks a system by the ROM header first and by the file extension after
+    pub fn detect(&self, file_name: &str, rom: &[u8]) -> Option<&SystemInfo> {
+        let sniffed = self
+            .systems
+            .iter()
+            .find(|s| s.sniff.is_some_and(|sniff| sniff(rom)));
+        if sniffed.is_some() {
+            return sniffed;
+        }
+        let extension = Path::new(file_name).extension()?.to_str()?;
+        self.systems.iter().find(|s| s.handles_extension(extension))
+    }
+}
+
+#[cfg(test)]
+mod registry_tests {
+
+    use super::*;
+
+    fn sniffing_system() -> SystemInfo {
+        SystemInfo {
+            name: "Test",
+            extensions: &["bin"],
+            capabilities: Capabilities::default(),
+            factory: || Box::new(Chip8::new()),
+            sniff: Some(|rom| rom.starts_with(b"TEST")),
+        }
+    }
+
+    #[test]
+    fn test_detect() {
+        let mut registry = Registry::default();
+        registry.register(sniffing_system());
+        assert_eq!(registry.detect("roms/Pong.CH8", &[0x00, 0xE0]).unwrap().name, "CHIP-8");
+        assert_eq!(registry.detect("roms/game.ch8", b"TEST....").unwrap().name, "Test");
+        assert_eq!(registry.detect("roms/game.bin", &[]).unwrap().name, "Test");
+        assert!(registry.detect("roms/game.xyz", &[]).is_none());
+    }
+}
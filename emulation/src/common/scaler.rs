@@ -0,0 +1,131 @@
+use crate::common::filter::Filter;
+use crate::common::palette::Palette;
+
+/// Turns `Vram` pixel indices (or true colour pixels) into an RGBA buffer scaled by an integer factor.
+/// With a filter the frame is filtered first and then enlarged with nearest neighbour,
+/// the scale is rounded down to a multiple of the filter factor but never below it
+pub struct Scaler {
+    size: [u32; 2],
+    scale: u32,
+    filter: Filter,
+    scaled_size: [u32; 2],
+    output: Vec<u8>,
+}
+
+impl Scaler {
+    pub fn new(size: [u32; 2], scale: u32) -> Self {
+        Scaler::with_filter(size, scale, Filter::Nearest)
+    }
+
+    pub fn with_filter(size: [u32; 2], scale: u32, filter: Filter) -> Self {
+        let scale = scale.max(1);
+        let factor = filter.factor();
+        let output_scale = factor * (scale / factor).max(1);
+        let scaled_size = [size[0] * output_scale, size[1] * output_scale];
+        Self {
+            size,
+            scale,
+            filter,
+            scaled_size,
+            output: vec![0u8; (scaled_size[0] * scaled_size[1] * 4) as usize],
+        }
+    }
+
+    /// Output buffer is reused between calls
+    pub fn scale_rgba(&mut self, buffer: &[u8], palette: &Palette) -> &[u8] {
+        let colors: Vec<u32> = (0..(self.size[0] * self.size[1]) as usize)
+            .map(|i| palette.pixel_color(buffer, i))
+            .collect();
+        self.scale_colors(&colors)
+    }
+
+    /// Same as `scale_rgba` for frames already mapped to RGBA colours
+    pub fn scale_colors(&mut self, colors: &[u32]) -> &[u8] {
+        let filtered;
+        let mut src = colors;
+        if self.filter != Filter::Nearest {
+            filtered = self.filter.apply(colors, self.size);
+            src = &filtered;
+        }
+        let src_width = (self.size[0] * self.filter.factor()) as usize;
+        let enlarge = (self.scaled_size[0] as usize / src_width.max(1)).max(1);
+        let row_bytes = self.scaled_size[0] as usize * 4;
+        for (y, row) in self.output.chunks_exact_mut(row_bytes * enlarge).enumerate() {
+            let (first_row, copies) = row.split_at_mut(row_bytes);
+            for (x, px) in first_row.chunks_exact_mut(4 * enlarge).enumerate() {
+                let color = src.get(y * src_width + x).copied().unwrap_or(0).to_be_bytes();
+                for p in px.chunks_exact_mut(4) {
+                    p.copy_from_slice(&color);
+                }
+            }
+            for copy in copies.chunks_exact_mut(row_bytes) {
+                copy.copy_from_slice(first_row);
+            }
+        }
+        &self.output
+    }
+
+    pub fn filter(&self) -> Filter { self.filter }
+
+    pub fn size(&self) -> &[u32; 2] { &self.size }
+
+    pub fn scale(&self) -> &u32 { &self.scale }
+
+    pub fn scaled_size(&self) -> &[u32; 2] { &self.scaled_size }
+}
+
+/// Nearest neighbour scaling of pixel indices
+pub fn scale_indices(buffer: &[u8], size: [u32; 2], scale: u32) -> Vec<u8> {
+    scale_pixels(buffer, size, scale, 1)
+}
+
+/// Same as `scale_indices` for pixels of `pixel_size` bytes, 4 for true colour
+pub fn scale_pixels(buffer: &[u8], size: [u32; 2], scale: u32, pixel_size: usize) -> Vec<u8> {
+    let scale = scale.max(1) as usize;
+    let width = size[0] as usize;
+    let blank = vec![0u8; pixel_size];
+    let mut out = Vec::with_capacity(buffer.len() * scale * scale);
+    for y in 0..size[1] as usize {
+        let row_start = out.len();
+        for x in 0..width {
+            let start = (y * width + x) * pixel_size;
+            let pixel = buffer.get(start..start + pixel_size).unwrap_or(&blank);
+            for _ in 0..scale {
+                out.extend_from_slice(pixel);
+            }
+        }
+        let row_end = out.len();
+        for _ in 1..scale {
+            out.extend_from_within(row_start..row_end);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod scaler_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_scale_rgba() {
+        let palette = Palette::new("test", vec![0x000000FF, 0xFFFFFFFF]);
+        let mut scaler = Scaler::new([2, 1], 2);
+        let out = scaler.scale_rgba(&[1, 0], &palette).to_vec();
+        let white = [0xFF, 0xFF, 0xFF, 0xFF];
+        let black = [0x00, 0x00, 0x00, 0xFF];
+        let row = [white, white, black, black].concat();
+        assert_eq!(out, [row.clone(), row].concat());
+    }
+
+    #[test]
+    fn test_scale_indices() {
+        assert_eq!(scale_indices(&[1, 2, 3, 4], [2, 2], 2), vec![
+            1, 1, 2, 2,
+            1, 1, 2, 2,
+            3, 3, 4, 4,
+            3, 3, 4, 4,
+        ]);
+        assert_eq!(scale_pixels(&[1, 2, 3, 4], [1, 1], 2, 4), [[1, 2, 3, 4]; 4].concat());
+    }
+}
@@ -0,0 +1,96 @@
+use crate::common::emulator::{EmulMgr, FRAME_RATE};
+use crate::common::message::*;
+
+use std::time::Duration;
+
+/// Speed multipliers offered to the user
+pub const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+pub const MIN_SPEED: f64 = 0.25;
+pub const MAX_SPEED: f64 = 8.0;
+pub const TURBO_SPEED: f64 = MAX_SPEED;
+/// Frames run at most per `run` call, the rest of a long stall is dropped
+const MAX_CATCH_UP_FRAMES: u32 = 4;
+
+/// Runs the emulator in fixed 60Hz frames from the elapsed wall clock time
+pub struct Scheduler {
+    speed: f64,
+    turbo: bool,
+    pending_advance: u32,
+    accumulator: Duration,
+    frame_duration: Duration,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self {
+            speed: 1.0,
+            turbo: false,
+            pending_advance: 0,
+            accumulator: Duration::ZERO,
+            frame_duration: Duration::from_secs(1) / FRAME_RATE as u32,
+        }
+    }
+}
+
+impl Scheduler {
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    pub fn is_turbo(&self) -> bool {
+        self.turbo
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+    }
+
+    /// Runs a single frame on the next `run` call even if paused
+    pub fn advance_frame(&mut self) {
+        self.pending_advance += 1;
+    }
+
+    /// Returns the number of frames run, `on_frame` sees the emulator after each one
+    pub fn run(
+        &mut self,
+        emul: &mut EmulMgr,
+        elapsed: Duration,
+        mut on_frame: impl FnMut(&EmulMgr),
+    ) -> Result<u32, Box<dyn Msg>> {
+        if emul.is_paused() {
+            self.accumulator = Duration::ZERO;
+            let mut frames = 0;
+            while self.pending_advance > 0 {
+                self.pending_advance -= 1;
+                emul.run_frame()?;
+                on_frame(emul);
+                frames += 1;
+            }
+            return Ok(frames);
+        }
+        self.pending_advance = 0;
+
+        let speed = if self.turbo { TURBO_SPEED } else { self.speed };
+        self.accumulator += elapsed.mul_f64(speed);
+        let max_frames = (MAX_CATCH_UP_FRAMES as f64 * speed).ceil() as u32;
+        let mut frames = 0;
+        while self.accumulator >= self.frame_duration {
+            if frames >= max_frames {
+                self.accumulator = Duration::ZERO;
+                break;
+            }
+            self.accumulator -= self.frame_duration;
+            if let Err(err) = emul.run_frame() {
+                self.accumulator = Duration::ZERO;
+                return Err(err);
+            }
+            on_frame(emul);
+            frames += 1;
+        }
+        Ok(frames)
+    }
+}
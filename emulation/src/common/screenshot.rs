@@ -0,0 +1,118 @@
+use crate::common::emulator::EmulMgr;
+use crate::common::filter::Filter;
+use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::scaler::Scaler;
+use crate::common::utils;
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// RGBA image of the emulator framebuffer
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    /// Applies the palette to the pixel indices and scales them with the filter
+    pub fn capture(buffer: &[u8], resolution: [u32; 2], palette: &Palette, scale: u32, filter: Filter) -> Self {
+        let mut scaler = Scaler::with_filter(resolution, scale, filter);
+        let pixels = scaler.scale_rgba(buffer, palette).to_vec();
+        let [width, height] = *scaler.scaled_size();
+        Self { width, height, pixels }
+    }
+
+    pub fn from_emulator(emul: &EmulMgr, palette: &Palette, scale: u32, filter: Filter) -> Result<Self, Box<dyn Msg>> {
+        let buffer = emul.video_buffer()?;
+        Ok(RgbaImage::capture(buffer, emul.resolution()?, palette, scale, filter))
+    }
+
+    pub fn save_png(&self, file_name: &str) -> Result<(), Box<dyn Msg>> {
+        let file = File::create(file_name).map_err(|e| RgbaImage::error(ErrorMsgId::FileWrite, file_name, Box::new(e)))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.pixels))
+            .map_err(|e| RgbaImage::error(ErrorMsgId::FileWrite, file_name, Box::new(e)))
+    }
+
+    pub fn load_png(file_name: &str) -> Result<Self, Box<dyn Msg>> {
+        let file = File::open(file_name).map_err(|e| RgbaImage::error(ErrorMsgId::FileRead, file_name, Box::new(e)))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| RgbaImage::error(ErrorMsgId::FileRead, file_name, Box::new(e)))?;
+        let mut buffer = vec![0u8; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .map_err(|e| RgbaImage::error(ErrorMsgId::FileRead, file_name, Box::new(e)))?;
+        if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+            let err = ErrorMsg::new(ErrorTopicId::Screenshot.into(), ErrorMsgId::InvalidFormat.into())
+                .add_param(String::from(file_name));
+            return Err(Box::new(err));
+        }
+        buffer.truncate(info.buffer_size());
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            pixels: buffer,
+        })
+    }
+
+    fn error(msg_id: ErrorMsgId, file_name: &str, source: Box<dyn std::error::Error + Send + Sync>) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(ErrorTopicId::Screenshot.into(), msg_id.into())
+            .add_param(String::from(file_name))
+            .set_source(source);
+        Box::new(err)
+    }
+}
+
+pub fn file_name(dir: &Path, rom_name: Option<&str>) -> String {
+    utils::capture_file_name(dir, rom_name, "png")
+}
+
+#[cfg(test)]
+mod screenshot_tests {
+
+    use super::*;
+
+    fn round_trip(image: &RgbaImage, name: &str) -> RgbaImage {
+        let path = std::env::temp_dir().join(format!("screenshot_test_{}_{}.png", std::process::id(), name));
+        let file_name = path.to_str().unwrap();
+        image.save_png(file_name).unwrap();
+        let loaded = RgbaImage::load_png(file_name).unwrap();
+        std::fs::remove_file(file_name).unwrap();
+        loaded
+    }
+
+    #[test]
+    fn test_indexed_png() {
+        let palette = Palette::new("test", vec![0x000000FF, 0xFFFFFFFF, 0xFF000080]);
+        let image = RgbaImage::capture(&[0, 1, 2, 1, 0, 2], [3, 2], &palette, 2, Filter::Nearest);
+        let loaded = round_trip(&image, "indexed");
+        assert_eq!((loaded.width, loaded.height), (6, 4));
+        assert_eq!(&loaded.pixels[0..4], &[0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(&loaded.pixels[8..12], &[0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(&loaded.pixels[16..20], &[0xFF, 0x00, 0x00, 0x80]);
+        assert_eq!(loaded, image);
+    }
+
+    #[test]
+    fn test_true_color_png() {
+        let buffer = [
+            0x12, 0x34, 0x56, 0xFF, 0xAB, 0xCD, 0xEF, 0xFF,
+            0x00, 0x80, 0x00, 0x40, 0xFF, 0xFF, 0x00, 0xFF,
+        ];
+        let image = RgbaImage::capture(&buffer, [2, 2], &Palette::true_color(), 1, Filter::Nearest);
+        let loaded = round_trip(&image, "true_color");
+        assert_eq!((loaded.width, loaded.height), (2, 2));
+        assert_eq!(loaded.pixels, buffer);
+    }
+}
@@ -0,0 +1,111 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Set in the shared index when the middle slot holds a frame the reader has not seen
+const FRESH: u8 = 0b100;
+const INDEX_MASK: u8 = 0b011;
+
+/// Three slots, the writer and the reader own one each and swap theirs with the middle one,
+/// so neither side ever waits for the other
+struct Shared<T> {
+    slots: [UnsafeCell<T>; 3],
+    middle: AtomicU8,
+}
+
+// A slot is only touched by the side whose index points at it, the atomic swap hands it over
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+pub struct Writer<T> {
+    shared: Arc<Shared<T>>,
+    back: u8,
+}
+
+pub struct Reader<T> {
+    shared: Arc<Shared<T>>,
+    front: u8,
+}
+
+/// Lock-free single producer single consumer buffer holding the latest value
+pub fn triple_buffer<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+    let shared = Arc::new(Shared {
+        slots: [
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial.clone()),
+            UnsafeCell::new(initial),
+        ],
+        middle: AtomicU8::new(1),
+    });
+    let writer = Writer { shared: shared.clone(), back: 0 };
+    let reader = Reader { shared, front: 2 };
+    (writer, reader)
+}
+
+impl<T> Writer<T> {
+    /// Slot to fill before `publish`, it may hold any older value
+    pub fn input(&mut self) -> &mut T {
+        unsafe { &mut *self.shared.slots[self.back as usize].get() }
+    }
+
+    /// Makes the input slot the latest value, a value the reader has not taken is dropped
+    pub fn publish(&mut self) {
+        let previous = self.shared.middle.swap(self.back | FRESH, Ordering::AcqRel);
+        self.back = previous & INDEX_MASK;
+    }
+}
+
+impl<T> Reader<T> {
+    /// Takes the latest published value if there is one, returns whether it changed
+    pub fn update(&mut self) -> bool {
+        if self.shared.middle.load(Ordering::Relaxed) & FRESH == 0 {
+            return false;
+        }
+        let previous = self.shared.middle.swap(self.front, Ordering::AcqRel);
+        self.front = previous & INDEX_MASK;
+        true
+    }
+
+    pub fn read(&self) -> &T {
+        unsafe { &*self.shared.slots[self.front as usize].get() }
+    }
+}
+
+#[cfg(test)]
+mod triple_buffer_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_reader_gets_latest() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        assert!(!reader.update());
+        *writer.input() = 1;
+        writer.publish();
+        *writer.input() = 2;
+        writer.publish();
+        assert!(reader.update());
+        assert_eq!(*reader.read(), 2);
+        assert!(!reader.update());
+        assert_eq!(*reader.read(), 2);
+    }
+
+    #[test]
+    fn test_across_threads() {
+        let (mut writer, mut reader) = triple_buffer(vec![0u32; 64]);
+        let producer = std::thread::spawn(move || {
+            for i in 1..=10_000u32 {
+                writer.input().fill(i);
+                writer.publish();
+            }
+        });
+        let mut last = 0;
+        while last < 10_000 {
+            reader.update();
+            let value = reader.read();
+            assert!(value.iter().all(|v| *v == value[0]));
+            assert!(value[0] >= last);
+            last = value[0];
+        }
+        producer.join().unwrap();
+    }
+}
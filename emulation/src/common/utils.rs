@@ -1,5 +1,6 @@
 use std::io::{BufReader, Read};
 use std::fs::File;
+use std::path::Path;
 
 pub fn load_rom(file_name: &str) -> Result<Vec<u8>, std::io::Error> {
     let mut input = BufReader::new(File::open(file_name).expect("Cannot open file file_name"));
@@ -20,4 +21,44 @@ pub fn load_rom(file_name: &str) -> Result<Vec<u8>, std::io::Error> {
         target.push(u8::from_le_bytes(buffer));
     }
     Ok(target)
-}
\ No newline at end of file
+}
+
+/// Timestamped file name carrying the ROM name, e.g. `Airplane_20230101-120000.png`
+pub fn capture_file_name(dir: &Path, rom_name: Option<&str>, extension: &str) -> String {
+    let name = format!("{}_{}.{}", rom_name.unwrap_or("capture"), timestamp(), extension);
+    dir.join(name).to_string_lossy().into_owned()
+}
+
+/// Current UTC time as YYYYMMDD-HHMMSS, used to name captured files
+pub fn timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let days = (secs / 86400) as i64;
+    let time = secs % 86400;
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year, month, day, time / 3600, time % 3600 / 60, time % 60
+    )
+}
+
+/// Current UTC time as HH:MM:SS, used to stamp log entries
+pub fn clock() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let time = secs % 86400;
+    format!("{:02}:{:02}:{:02}", time / 3600, time % 3600 / 60, time % 60)
+}
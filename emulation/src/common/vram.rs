@@ -1,18 +1,32 @@
 
+/// Video memory with logical pixel indices, colours are applied by a `Palette`.
+/// True colour memory holds the RGBA bytes of every pixel instead, see `Palette::true_color`
 pub struct Vram {
-    memory: Vec<u32>,
+    memory: Vec<u8>,
     width: usize,
     height: usize,
     size: usize,
+    pixel_size: usize,
 }
 
 impl Vram {
     pub fn new(width: usize, height: usize) -> Self {
         Vram {
-            memory: vec![0u32; width * height],
+            memory: vec![0u8; width * height],
             width: width,
             height: height,
             size: width * height,
+            pixel_size: 1,
+        }
+    }
+
+    pub fn true_color(width: usize, height: usize) -> Self {
+        Vram {
+            memory: vec![0u8; width * height * 4],
+            width,
+            height,
+            size: width * height,
+            pixel_size: 4,
         }
     }
 
@@ -20,27 +34,37 @@ impl Vram {
 
     pub fn height(&self) -> usize { self.height }
 
+    /// Pixels, not bytes
     pub fn size(&self) -> usize { self.size }
 
+    pub fn is_true_color(&self) -> bool { self.pixel_size == 4 }
+
     pub fn clear(&mut self) {
         self.memory.fill(0)
     }
 
-    pub fn read_pixel(&self, addr: usize) -> u32 {
+    pub fn read_pixel(&self, addr: usize) -> u8 {
         self.memory[addr]
     }
 
-    pub fn write_pixel(&mut self, addr: usize, pixel: u32) {
+    pub fn write_pixel(&mut self, addr: usize, pixel: u8) {
         self.memory[addr] = pixel;
     }
 
-    pub fn video_32(&self) -> &Vec<u32> { &self.memory }
+    /// RGBA colour of a pixel of true colour memory
+    pub fn read_color(&self, addr: usize) -> u32 {
+        let bytes = &self.memory[addr * 4..addr * 4 + 4];
+        u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+    }
 
-    pub fn video_8(&self) -> Vec<u8> {
-        let mut out: Vec<u8> = Vec::with_capacity(4 * self.memory.len());
-        for value in &self.memory {
-            out.extend(value.to_be_bytes());
-        }
-        out
+    pub fn write_color(&mut self, addr: usize, color: u32) {
+        self.memory[addr * 4..addr * 4 + 4].copy_from_slice(&color.to_be_bytes());
+    }
+
+    pub fn video(&self) -> &Vec<u8> { &self.memory }
+
+    /// Replaces the whole content, `data` must hold `size` pixels of this memory
+    pub fn load(&mut self, data: &[u8]) {
+        self.memory.copy_from_slice(data);
     }
 }
@@ -0,0 +1,346 @@
+use crate::common::message::*;
+use crate::common::ram::Ram;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+/// The header ends with its checksums
+const HEADER_END: usize = 0x150;
+const TITLE: std::ops::Range<usize> = 0x134..0x144;
+const TYPE_ADDRESS: usize = 0x147;
+const RAM_SIZE_ADDRESS: usize = 0x149;
+/// Start of the Nintendo logo the boot ROM checks, used to recognise Game Boy ROMs
+pub const LOGO_ADDRESS: usize = 0x104;
+pub const LOGO_START: [u8; 8] = [0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B];
+/// Clock cycles per second, the MBC3 clock counts in emulated time
+const CLOCK: u32 = 4_194_304;
+/// Halt flag of the MBC3 clock, in the day high register
+const RTC_HALT: u8 = 0x40;
+
+/// Real time clock of MBC3: seconds, minutes, hours, day low, day high
+struct Rtc {
+    registers: [u8; 5],
+    latched: [u8; 5],
+    cycles: u32,
+}
+
+impl Rtc {
+    fn new() -> Self {
+        Self {
+            registers: [0; 5],
+            latched: [0; 5],
+            cycles: 0,
+        }
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        if self.registers[4] & RTC_HALT != 0 {
+            return;
+        }
+        self.cycles += cycles;
+        while self.cycles >= CLOCK {
+            self.cycles -= CLOCK;
+            self.advance_second();
+        }
+    }
+
+    /// The 9-bit day counter sets the carry bit of the day high register when it overflows
+    fn advance_second(&mut self) {
+        let [seconds, minutes, hours, day_low, day_high] = &mut self.registers;
+        *seconds = (*seconds + 1) % 60;
+        if *seconds != 0 {
+            return;
+        }
+        *minutes = (*minutes + 1) % 60;
+        if *minutes != 0 {
+            return;
+        }
+        *hours = (*hours + 1) % 24;
+        if *hours != 0 {
+            return;
+        }
+        let day = (((*day_high & 0x01) as u16) << 8 | *day_low as u16) + 1;
+        *day_low = day as u8;
+        *day_high = (*day_high & 0xFE) | ((day >> 8) & 0x01) as u8;
+        if day > 0x1FF {
+            *day_high |= 0x80;
+        }
+    }
+}
+
+/// Memory bank controller, selects the ROM and RAM banks seen by the CPU
+enum Mbc {
+    None,
+    /// 5-bit ROM bank, 2-bit upper bank used for the ROM or the RAM by `advanced`
+    Mbc1 { rom_bank: u8, upper: u8, advanced: bool },
+    /// 7-bit ROM bank; 0..=3 select a RAM bank, 0x08..=0x0C a clock register
+    Mbc3 { rom_bank: u8, select: u8, latch: u8, rtc: Rtc },
+    /// 9-bit ROM bank where bank 0 is allowed, 4-bit RAM bank
+    Mbc5 { rom_bank: u16, ram_bank: u8 },
+}
+
+/// Cartridge ROM, optional RAM and the bank controller
+pub struct Cartridge {
+    rom: Vec<u8>,
+    ram: Ram,
+    mbc: Mbc,
+    ram_enabled: bool,
+    battery: bool,
+    title: String,
+    /// RAM written since it was enabled
+    modified: bool,
+    /// RAM written and disabled again since the last `take_dirty`
+    dirty: bool,
+}
+
+impl Cartridge {
+    /// Parses the header, the ROM is padded to a whole number of banks
+    pub fn from_rom(mut rom: Vec<u8>) -> Result<Self, Box<dyn Msg>> {
+        if rom.len() < HEADER_END {
+            let err = ErrorMsg::new(ErrorTopicId::Cartridge.into(), ErrorMsgId::InvalidFormat.into())
+                .add_param(rom.len().to_string());
+            return Err(Box::new(err));
+        }
+        let cart_type = rom[TYPE_ADDRESS];
+        let (mbc, battery) = match cart_type {
+            0x00 => (Mbc::None, false),
+            0x01..=0x03 => (Mbc::Mbc1 { rom_bank: 1, upper: 0, advanced: false }, cart_type == 0x03),
+            0x0F..=0x13 => {
+                let mbc = Mbc::Mbc3 { rom_bank: 1, select: 0, latch: 0xFF, rtc: Rtc::new() };
+                (mbc, matches!(cart_type, 0x0F | 0x10 | 0x13))
+            }
+            0x19..=0x1E => (Mbc::Mbc5 { rom_bank: 1, ram_bank: 0 }, matches!(cart_type, 0x1B | 0x1E)),
+            _ => {
+                let err = ErrorMsg::new(ErrorTopicId::Cartridge.into(), ErrorMsgId::NotSupported.into())
+                    .add_param(format!("{:#04X}", cart_type));
+                return Err(Box::new(err));
+            }
+        };
+        let ram_size = match rom[RAM_SIZE_ADDRESS] {
+            0x02 => 0x2000,
+            0x03 => 0x8000,
+            0x04 => 0x20000,
+            0x05 => 0x10000,
+            _ => 0,
+        };
+        let title = String::from_utf8_lossy(&rom[TITLE])
+            .trim_end_matches('\0')
+            .trim()
+            .to_string();
+        let banks = rom.len().div_ceil(ROM_BANK_SIZE).max(2);
+        rom.resize(banks * ROM_BANK_SIZE, 0xFF);
+        Ok(Self {
+            rom,
+            ram: Ram::new(ram_size),
+            mbc,
+            ram_enabled: false,
+            battery,
+            title,
+            modified: false,
+            dirty: false,
+        })
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// The RAM keeps its content when the power is off
+    pub fn has_battery(&self) -> bool {
+        self.battery && self.ram.size() > 0
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        self.ram.data()
+    }
+
+    /// Restores the battery RAM, `data` must have the RAM size
+    pub fn load_ram(&mut self, data: &[u8]) -> Result<(), Box<dyn Msg>> {
+        self.ram.load(data)
+    }
+
+    /// Whether the RAM was written since the last call, counted once the game disables the RAM after saving
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if let Mbc::Mbc3 { rtc, .. } = &mut self.mbc {
+            rtc.tick(cycles);
+        }
+    }
+
+    /// 0x0000..=0x7FFF
+    pub fn read_rom(&self, addr: u16) -> u8 {
+        let addr = addr as usize;
+        let bank = if addr < ROM_BANK_SIZE { self.low_bank() } else { self.high_bank() };
+        let banks = self.rom.len() / ROM_BANK_SIZE;
+        self.rom[(bank % banks) * ROM_BANK_SIZE + addr % ROM_BANK_SIZE]
+    }
+
+    /// 0x0000..=0x7FFF, bank controller registers
+    pub fn write_rom(&mut self, addr: u16, value: u8) {
+        if addr < 0x2000 {
+            let enabled = value & 0x0F == 0x0A;
+            if self.ram_enabled && !enabled {
+                // a game is done with the RAM, the battery file can be written
+                self.dirty |= self.modified && self.has_battery();
+                self.modified = false;
+            }
+            self.ram_enabled = enabled;
+            return;
+        }
+        match &mut self.mbc {
+            Mbc::None => {}
+            Mbc::Mbc1 { rom_bank, upper, advanced } => match addr {
+                0x2000..=0x3FFF => *rom_bank = (value & 0x1F).max(1),
+                0x4000..=0x5FFF => *upper = value & 0x03,
+                _ => *advanced = value & 0x01 != 0,
+            },
+            Mbc::Mbc3 { rom_bank, select, latch, rtc } => match addr {
+                0x2000..=0x3FFF => *rom_bank = (value & 0x7F).max(1),
+                0x4000..=0x5FFF => *select = value,
+                _ => {
+                    if *latch == 0 && value == 1 {
+                        rtc.latched = rtc.registers;
+                    }
+                    *latch = value;
+                }
+            },
+            Mbc::Mbc5 { rom_bank, ram_bank } => match addr {
+                0x2000..=0x2FFF => *rom_bank = (*rom_bank & 0x100) | value as u16,
+                0x3000..=0x3FFF => *rom_bank = (*rom_bank & 0xFF) | ((value & 0x01) as u16) << 8,
+                0x4000..=0x5FFF => *ram_bank = value & 0x0F,
+                _ => {}
+            },
+        }
+    }
+
+    /// 0xA000..=0xBFFF, open bus when the RAM is disabled or missing
+    pub fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        if let Mbc::Mbc3 { select: select @ 0x08..=0x0C, rtc, .. } = &self.mbc {
+            return rtc.latched[(*select - 0x08) as usize];
+        }
+        match self.ram_offset(addr) {
+            Some(offset) => self.ram.read_byte(offset).unwrap_or(0xFF),
+            None => 0xFF,
+        }
+    }
+
+    pub fn write_ram(&mut self, addr: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        if let Mbc::Mbc3 { select: select @ 0x08..=0x0C, rtc, .. } = &mut self.mbc {
+            let index = (*select - 0x08) as usize;
+            rtc.registers[index] = value;
+            if index == 0 {
+                rtc.cycles = 0;
+            }
+            return;
+        }
+        if let Some(offset) = self.ram_offset(addr) {
+            self.modified |= self.ram.write_byte(offset, value).is_ok();
+        }
+    }
+
+    fn low_bank(&self) -> usize {
+        match &self.mbc {
+            Mbc::Mbc1 { upper, advanced: true, .. } => (*upper as usize) << 5,
+            _ => 0,
+        }
+    }
+
+    fn high_bank(&self) -> usize {
+        match &self.mbc {
+            Mbc::None => 1,
+            Mbc::Mbc1 { rom_bank, upper, .. } => (*upper as usize) << 5 | *rom_bank as usize,
+            Mbc::Mbc3 { rom_bank, .. } => *rom_bank as usize,
+            Mbc::Mbc5 { rom_bank, .. } => *rom_bank as usize,
+        }
+    }
+
+    fn ram_offset(&self, addr: u16) -> Option<usize> {
+        if self.ram.size() == 0 {
+            return None;
+        }
+        let bank = match &self.mbc {
+            Mbc::Mbc1 { upper, advanced: true, .. } => *upper as usize,
+            Mbc::Mbc3 { select, .. } => (*select & 0x03) as usize,
+            Mbc::Mbc5 { ram_bank, .. } => *ram_bank as usize,
+            _ => 0,
+        };
+        Some((bank * RAM_BANK_SIZE + (addr as usize - 0xA000)) % self.ram.size())
+    }
+}
+
+#[cfg(test)]
+mod cartridge_tests {
+
+    use super::*;
+
+    /// ROM whose banks start with their own number
+    fn rom(cart_type: u8, banks: usize, ram_size: u8) -> Vec<u8> {
+        let mut rom = vec![0u8; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom[TYPE_ADDRESS] = cart_type;
+        rom[RAM_SIZE_ADDRESS] = ram_size;
+        rom
+    }
+
+    #[test]
+    fn test_mbc1_banking() {
+        let mut cart = Cartridge::from_rom(rom(0x03, 64, 0x03)).unwrap();
+        assert_eq!(cart.read_rom(0x4000), 1);
+        cart.write_rom(0x2000, 0x00);
+        assert_eq!(cart.read_rom(0x4000), 1);
+        cart.write_rom(0x2000, 0x05);
+        cart.write_rom(0x4000, 0x01);
+        assert_eq!(cart.read_rom(0x4000), 0x25);
+        assert_eq!(cart.read_rom(0x0000), 0);
+
+        // RAM banks need the advanced mode
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_rom(0x6000, 0x01);
+        assert_eq!(cart.read_rom(0x0000), 0x20);
+        cart.write_ram(0xA000, 0x42);
+        cart.write_rom(0x6000, 0x00);
+        assert_eq!(cart.read_ram(0xA000), 0x00);
+        assert_eq!(cart.ram()[RAM_BANK_SIZE], 0x42);
+        cart.write_rom(0x0000, 0x00);
+        assert_eq!(cart.read_ram(0xA000), 0xFF);
+        assert!(cart.take_dirty());
+        assert!(!cart.take_dirty());
+    }
+
+    #[test]
+    fn test_mbc3_and_mbc5() {
+        let mut cart = Cartridge::from_rom(rom(0x10, 128, 0x03)).unwrap();
+        cart.write_rom(0x2000, 0x7F);
+        assert_eq!(cart.read_rom(0x4000), 0x7F);
+        cart.write_rom(0x0000, 0x0A);
+        cart.write_rom(0x4000, 0x08);
+        cart.write_ram(0xA000, 59);
+        cart.tick(CLOCK);
+        cart.write_rom(0x6000, 0x00);
+        cart.write_rom(0x6000, 0x01);
+        assert_eq!(cart.read_ram(0xA000), 0);
+        cart.write_rom(0x4000, 0x09);
+        assert_eq!(cart.read_ram(0xA000), 1);
+
+        let mut cart = Cartridge::from_rom(rom(0x19, 512, 0x00)).unwrap();
+        cart.write_rom(0x2000, 0x00);
+        assert_eq!(cart.read_rom(0x4000), 0);
+        cart.write_rom(0x2000, 0x03);
+        cart.write_rom(0x3000, 0x01);
+        assert_eq!(cart.read_rom(0x4000), 0x03);
+        assert_eq!(cart.high_bank(), 0x103);
+
+        assert!(Cartridge::from_rom(rom(0x05, 2, 0)).is_err());
+        assert!(Cartridge::from_rom(vec![0; 0x100]).is_err());
+    }
+}
@@ -0,0 +1,762 @@
+use crate::common::bus::Bus;
+use crate::common::message::*;
+
+const FLAG_Z: u8 = 0x80;
+const FLAG_N: u8 = 0x40;
+const FLAG_H: u8 = 0x20;
+const FLAG_C: u8 = 0x10;
+
+/// Interrupt enable and request registers
+pub const IE_ADDRESS: usize = 0xFFFF;
+pub const IF_ADDRESS: usize = 0xFF0F;
+pub const VBLANK_INTERRUPT: u8 = 0x01;
+pub const STAT_INTERRUPT: u8 = 0x02;
+pub const TIMER_INTERRUPT: u8 = 0x04;
+pub const SERIAL_INTERRUPT: u8 = 0x08;
+pub const JOYPAD_INTERRUPT: u8 = 0x10;
+
+/// Clock cycles of each opcode when a condition is not taken, `CB` opcodes are counted apart
+const CYCLES: [u8; 256] = [
+    4, 12, 8, 8, 4, 4, 8, 4, 20, 8, 8, 8, 4, 4, 8, 4, // 0x00
+    4, 12, 8, 8, 4, 4, 8, 4, 12, 8, 8, 8, 4, 4, 8, 4, // 0x10
+    8, 12, 8, 8, 4, 4, 8, 4, 8, 8, 8, 8, 4, 4, 8, 4, // 0x20
+    8, 12, 8, 8, 12, 12, 12, 4, 8, 8, 8, 8, 4, 4, 8, 4, // 0x30
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x40
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x50
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x60
+    8, 8, 8, 8, 8, 8, 4, 8, 4, 4, 4, 4, 4, 4, 8, 4, // 0x70
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x80
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0x90
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0xA0
+    4, 4, 4, 4, 4, 4, 8, 4, 4, 4, 4, 4, 4, 4, 8, 4, // 0xB0
+    8, 12, 12, 16, 12, 16, 8, 16, 8, 16, 12, 4, 12, 24, 8, 16, // 0xC0
+    8, 12, 12, 0, 12, 16, 8, 16, 8, 16, 12, 0, 12, 0, 8, 16, // 0xD0
+    12, 12, 8, 0, 0, 16, 8, 16, 16, 4, 16, 0, 0, 0, 8, 16, // 0xE0
+    12, 12, 8, 4, 0, 16, 8, 16, 12, 8, 16, 4, 0, 0, 8, 16, // 0xF0
+];
+/// Extra cycles of a taken JR/JP and of a taken CALL/RET
+const JUMP_CYCLES: u32 = 4;
+const CALL_CYCLES: u32 = 12;
+const INTERRUPT_CYCLES: u32 = 20;
+/// A halted CPU still runs the clock
+const HALT_CYCLES: u32 = 4;
+
+/// Sharp LR35902, the Game Boy CPU. Starts with the register values the boot ROM leaves
+pub struct Lr35902 {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    /// Interrupt master enable
+    pub ime: bool,
+    pub halted: bool,
+    /// EI enables interrupts after the next instruction
+    ime_delay: bool,
+    /// HALT with IME off and a pending interrupt does not increment PC for the next fetch
+    halt_bug: bool,
+    opcode: u8,
+    /// Address of the instruction being executed, for fault reports
+    instr_pc: u16,
+}
+
+impl Default for Lr35902 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lr35902 {
+    pub fn new() -> Self {
+        Self {
+            a: 0x01,
+            f: 0xB0,
+            b: 0x00,
+            c: 0x13,
+            d: 0x00,
+            e: 0xD8,
+            h: 0x01,
+            l: 0x4D,
+            sp: 0xFFFE,
+            pc: 0x0100,
+            ime: false,
+            halted: false,
+            ime_delay: false,
+            halt_bug: false,
+            opcode: 0,
+            instr_pc: 0x0100,
+        }
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    /// The low nibble of F always reads 0
+    fn set_af(&mut self, value: u16) {
+        [self.a, self.f] = value.to_be_bytes();
+        self.f &= 0xF0;
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.f & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, value: bool) {
+        if value {
+            self.f |= flag;
+        } else {
+            self.f &= !flag;
+        }
+    }
+
+    fn set_flags(&mut self, z: bool, n: bool, h: bool, c: bool) {
+        self.f = (z as u8) << 7 | (n as u8) << 6 | (h as u8) << 5 | (c as u8) << 4;
+    }
+
+    /// Services a pending interrupt or executes one instruction, returns the clock cycles it took
+    pub fn step(&mut self, bus: &mut dyn Bus) -> Result<u32, Box<dyn Msg>> {
+        let pending = self.pending_interrupts(bus)?;
+        if pending != 0 {
+            self.halted = false;
+            if self.ime {
+                return self.service_interrupt(bus, pending);
+            }
+        }
+        if self.halted {
+            return Ok(HALT_CYCLES);
+        }
+        let enable_ime = self.ime_delay;
+        self.ime_delay = false;
+        self.instr_pc = self.pc;
+        let opcode = self.fetch(bus)?;
+        if self.halt_bug {
+            self.halt_bug = false;
+            self.pc = self.pc.wrapping_sub(1);
+        }
+        self.opcode = opcode;
+        let res = self.execute(bus, opcode);
+        if enable_ime && opcode != 0xF3 {
+            self.ime = true;
+        }
+        if res.is_err() {
+            // leave PC on the faulting instruction for the debugger
+            self.pc = self.instr_pc;
+        }
+        res
+    }
+
+    fn pending_interrupts(&self, bus: &mut dyn Bus) -> Result<u8, Box<dyn Msg>> {
+        let enabled = self.read(bus, IE_ADDRESS as u16)?;
+        let requested = self.read(bus, IF_ADDRESS as u16)?;
+        Ok(enabled & requested & 0x1F)
+    }
+
+    /// The lowest pending bit wins, it is acknowledged and its handler called at 0x40 + 8 * bit
+    fn service_interrupt(&mut self, bus: &mut dyn Bus, pending: u8) -> Result<u32, Box<dyn Msg>> {
+        let bit = pending.trailing_zeros() as u16;
+        self.ime = false;
+        let requested = self.read(bus, IF_ADDRESS as u16)?;
+        self.write(bus, IF_ADDRESS as u16, requested & !(1 << bit))?;
+        self.push(bus, self.pc)?;
+        self.pc = 0x40 + 8 * bit;
+        Ok(INTERRUPT_CYCLES)
+    }
+
+    fn execute(&mut self, bus: &mut dyn Bus, opcode: u8) -> Result<u32, Box<dyn Msg>> {
+        let mut cycles = CYCLES[opcode as usize] as u32;
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0x07;
+        let z = opcode & 0x07;
+        let p = y >> 1;
+        let q = y & 0x01;
+        match (x, z) {
+            (0, 0) => match y {
+                //NOP
+                0 => {}
+                //LD (a16), SP
+                1 => {
+                    let addr = self.fetch_word(bus)?;
+                    let [high, low] = self.sp.to_be_bytes();
+                    self.write(bus, addr, low)?;
+                    self.write(bus, addr.wrapping_add(1), high)?;
+                }
+                //STOP, the speed switch and the low power mode are not emulated
+                2 => {
+                    self.fetch(bus)?;
+                }
+                //JR d / JR cc, d
+                _ => {
+                    let offset = self.fetch(bus)? as i8;
+                    if y == 3 || self.condition(y - 4) {
+                        self.pc = self.pc.wrapping_add(offset as u16);
+                        if y != 3 {
+                            cycles += JUMP_CYCLES;
+                        }
+                    }
+                }
+            },
+            //LD rr, d16 / ADD HL, rr
+            (0, 1) => {
+                if q == 0 {
+                    let value = self.fetch_word(bus)?;
+                    self.set_pair(p, value);
+                } else {
+                    let hl = self.hl();
+                    let value = self.pair(p);
+                    let (sum, carry) = hl.overflowing_add(value);
+                    self.set_flag(FLAG_N, false);
+                    self.set_flag(FLAG_H, (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF);
+                    self.set_flag(FLAG_C, carry);
+                    self.set_hl(sum);
+                }
+            }
+            //LD (BC|DE|HL+|HL-), A / LD A, (BC|DE|HL+|HL-)
+            (0, 2) => {
+                let addr = match p {
+                    0 => self.bc(),
+                    1 => self.de(),
+                    _ => self.hl(),
+                };
+                match p {
+                    2 => self.set_hl(addr.wrapping_add(1)),
+                    3 => self.set_hl(addr.wrapping_sub(1)),
+                    _ => {}
+                }
+                if q == 0 {
+                    self.write(bus, addr, self.a)?;
+                } else {
+                    self.a = self.read(bus, addr)?;
+                }
+            }
+            //INC rr / DEC rr
+            (0, 3) => {
+                let value = self.pair(p);
+                let value = if q == 0 { value.wrapping_add(1) } else { value.wrapping_sub(1) };
+                self.set_pair(p, value);
+            }
+            //INC r
+            (0, 4) => {
+                let value = self.reg(bus, y)?;
+                let result = value.wrapping_add(1);
+                self.set_flag(FLAG_Z, result == 0);
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, value & 0x0F == 0x0F);
+                self.set_reg(bus, y, result)?;
+            }
+            //DEC r
+            (0, 5) => {
+                let value = self.reg(bus, y)?;
+                let result = value.wrapping_sub(1);
+                self.set_flag(FLAG_Z, result == 0);
+                self.set_flag(FLAG_N, true);
+                self.set_flag(FLAG_H, value & 0x0F == 0);
+                self.set_reg(bus, y, result)?;
+            }
+            //LD r, d8
+            (0, 6) => {
+                let value = self.fetch(bus)?;
+                self.set_reg(bus, y, value)?;
+            }
+            (0, _) => self.accumulator_op(y),
+            //HALT
+            (1, 6) if y == 6 => {
+                let pending = self.pending_interrupts(bus)?;
+                if !self.ime && pending != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
+            //LD r, r
+            (1, _) => {
+                let value = self.reg(bus, z)?;
+                self.set_reg(bus, y, value)?;
+            }
+            //ADD, ADC, SUB, SBC, AND, XOR, OR, CP r
+            (2, _) => {
+                let value = self.reg(bus, z)?;
+                self.alu(y, value);
+            }
+            (3, 0) => match y {
+                //RET cc
+                0..=3 => {
+                    if self.condition(y) {
+                        self.pc = self.pop(bus)?;
+                        cycles += CALL_CYCLES;
+                    }
+                }
+                //LDH (a8), A
+                4 => {
+                    let offset = self.fetch(bus)?;
+                    self.write(bus, 0xFF00 | offset as u16, self.a)?;
+                }
+                //ADD SP, d
+                5 => {
+                    self.sp = self.sp_offset(bus)?;
+                }
+                //LDH A, (a8)
+                6 => {
+                    let offset = self.fetch(bus)?;
+                    self.a = self.read(bus, 0xFF00 | offset as u16)?;
+                }
+                //LD HL, SP + d
+                _ => {
+                    let value = self.sp_offset(bus)?;
+                    self.set_hl(value);
+                }
+            },
+            (3, 1) => match (q, p) {
+                //POP rr
+                (0, _) => {
+                    let value = self.pop(bus)?;
+                    if p == 3 {
+                        self.set_af(value);
+                    } else {
+                        self.set_pair(p, value);
+                    }
+                }
+                //RET
+                (_, 0) => self.pc = self.pop(bus)?,
+                //RETI
+                (_, 1) => {
+                    self.pc = self.pop(bus)?;
+                    self.ime = true;
+                }
+                //JP HL
+                (_, 2) => self.pc = self.hl(),
+                //LD SP, HL
+                _ => self.sp = self.hl(),
+            },
+            (3, 2) => match y {
+                //JP cc, a16
+                0..=3 => {
+                    let addr = self.fetch_word(bus)?;
+                    if self.condition(y) {
+                        self.pc = addr;
+                        cycles += JUMP_CYCLES;
+                    }
+                }
+                //LD (C), A
+                4 => self.write(bus, 0xFF00 | self.c as u16, self.a)?,
+                //LD (a16), A
+                5 => {
+                    let addr = self.fetch_word(bus)?;
+                    self.write(bus, addr, self.a)?;
+                }
+                //LD A, (C)
+                6 => self.a = self.read(bus, 0xFF00 | self.c as u16)?,
+                //LD A, (a16)
+                _ => {
+                    let addr = self.fetch_word(bus)?;
+                    self.a = self.read(bus, addr)?;
+                }
+            },
+            (3, 3) => match y {
+                //JP a16
+                0 => self.pc = self.fetch_word(bus)?,
+                //CB prefix
+                1 => {
+                    let cb_opcode = self.fetch(bus)?;
+                    cycles = self.execute_cb(bus, cb_opcode)?;
+                }
+                //DI
+                6 => {
+                    self.ime = false;
+                    self.ime_delay = false;
+                }
+                //EI
+                7 => self.ime_delay = true,
+                _ => return Err(self.fault(ErrorMsgId::UnknownInstruction)),
+            },
+            //CALL cc, a16
+            (3, 4) if y < 4 => {
+                let addr = self.fetch_word(bus)?;
+                if self.condition(y) {
+                    self.push(bus, self.pc)?;
+                    self.pc = addr;
+                    cycles += CALL_CYCLES;
+                }
+            }
+            //PUSH rr
+            (3, 5) if q == 0 => {
+                let value = if p == 3 { self.af() } else { self.pair(p) };
+                self.push(bus, value)?;
+            }
+            //CALL a16
+            (3, 5) if p == 0 => {
+                let addr = self.fetch_word(bus)?;
+                self.push(bus, self.pc)?;
+                self.pc = addr;
+            }
+            //ALU d8
+            (3, 6) => {
+                let value = self.fetch(bus)?;
+                self.alu(y, value);
+            }
+            //RST n
+            (3, 7) => {
+                self.push(bus, self.pc)?;
+                self.pc = (y as u16) * 8;
+            }
+            _ => return Err(self.fault(ErrorMsgId::UnknownInstruction)),
+        }
+        Ok(cycles)
+    }
+
+    /// Rotates, shifts and bit operations, returns the clock cycles with the prefix
+    fn execute_cb(&mut self, bus: &mut dyn Bus, opcode: u8) -> Result<u32, Box<dyn Msg>> {
+        let x = opcode >> 6;
+        let y = (opcode >> 3) & 0x07;
+        let z = opcode & 0x07;
+        let value = self.reg(bus, z)?;
+        match x {
+            0 => {
+                let result = self.rotate(y, value);
+                self.set_reg(bus, z, result)?;
+            }
+            //BIT y, r
+            1 => {
+                self.set_flag(FLAG_Z, value & (1 << y) == 0);
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, true);
+            }
+            //RES y, r
+            2 => self.set_reg(bus, z, value & !(1 << y))?,
+            //SET y, r
+            _ => self.set_reg(bus, z, value | (1 << y))?,
+        }
+        Ok(match (x, z) {
+            (1, 6) => 12,
+            (_, 6) => 16,
+            _ => 8,
+        })
+    }
+
+    /// RLC, RRC, RL, RR, SLA, SRA, SWAP, SRL
+    fn rotate(&mut self, op: u8, value: u8) -> u8 {
+        let carry = self.flag(FLAG_C) as u8;
+        let (result, carry_out) = match op {
+            0 => (value.rotate_left(1), value & 0x80 != 0),
+            1 => (value.rotate_right(1), value & 0x01 != 0),
+            2 => (value << 1 | carry, value & 0x80 != 0),
+            3 => (value >> 1 | carry << 7, value & 0x01 != 0),
+            4 => (value << 1, value & 0x80 != 0),
+            5 => (value >> 1 | (value & 0x80), value & 0x01 != 0),
+            6 => (value.rotate_left(4), false),
+            _ => (value >> 1, value & 0x01 != 0),
+        };
+        self.set_flags(result == 0, false, false, carry_out);
+        result
+    }
+
+    /// RLCA, RRCA, RLA, RRA, DAA, CPL, SCF, CCF
+    fn accumulator_op(&mut self, op: u8) {
+        match op {
+            0..=3 => {
+                // same as the CB rotations except that Z is always cleared
+                self.a = self.rotate(op, self.a);
+                self.set_flag(FLAG_Z, false);
+            }
+            4 => self.daa(),
+            5 => {
+                self.a = !self.a;
+                self.set_flag(FLAG_N, true);
+                self.set_flag(FLAG_H, true);
+            }
+            6 => {
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, false);
+                self.set_flag(FLAG_C, true);
+            }
+            _ => {
+                self.set_flag(FLAG_N, false);
+                self.set_flag(FLAG_H, false);
+                self.set_flag(FLAG_C, !self.flag(FLAG_C));
+            }
+        }
+    }
+
+    /// Corrects A after a BCD addition or subtraction, depending on N
+    fn daa(&mut self) {
+        let mut correction = 0;
+        let mut carry = self.flag(FLAG_C);
+        if self.flag(FLAG_N) {
+            if self.flag(FLAG_H) {
+                correction |= 0x06;
+            }
+            if carry {
+                correction |= 0x60;
+            }
+            self.a = self.a.wrapping_sub(correction);
+        } else {
+            if self.flag(FLAG_H) || self.a & 0x0F > 9 {
+                correction |= 0x06;
+            }
+            if carry || self.a > 0x99 {
+                correction |= 0x60;
+                carry = true;
+            }
+            self.a = self.a.wrapping_add(correction);
+        }
+        self.set_flag(FLAG_Z, self.a == 0);
+        self.set_flag(FLAG_H, false);
+        self.set_flag(FLAG_C, carry);
+    }
+
+    /// ADD, ADC, SUB, SBC, AND, XOR, OR, CP with the accumulator
+    fn alu(&mut self, op: u8, value: u8) {
+        let carry = (op == 1 || op == 3) && self.flag(FLAG_C);
+        match op {
+            0 | 1 => {
+                let sum = self.a as u16 + value as u16 + carry as u16;
+                let half = (self.a & 0x0F) + (value & 0x0F) + carry as u8 > 0x0F;
+                self.a = sum as u8;
+                self.set_flags(self.a == 0, false, half, sum > 0xFF);
+            }
+            4 => {
+                self.a &= value;
+                self.set_flags(self.a == 0, false, true, false);
+            }
+            5 => {
+                self.a ^= value;
+                self.set_flags(self.a == 0, false, false, false);
+            }
+            6 => {
+                self.a |= value;
+                self.set_flags(self.a == 0, false, false, false);
+            }
+            _ => {
+                let result = self.a.wrapping_sub(value).wrapping_sub(carry as u8);
+                let half = (self.a & 0x0F) < (value & 0x0F) + carry as u8;
+                let borrow = (self.a as u16) < value as u16 + carry as u16;
+                self.set_flags(result == 0, true, half, borrow);
+                if op != 7 {
+                    self.a = result;
+                }
+            }
+        }
+    }
+
+    /// SP plus a signed byte, the flags come from the unsigned addition of the low byte
+    fn sp_offset(&mut self, bus: &mut dyn Bus) -> Result<u16, Box<dyn Msg>> {
+        let offset = self.fetch(bus)?;
+        let sp = self.sp;
+        let half = (sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F;
+        let carry = (sp & 0xFF) + offset as u16 > 0xFF;
+        self.set_flags(false, false, half, carry);
+        Ok(sp.wrapping_add(offset as i8 as u16))
+    }
+
+    /// NZ, Z, NC, C
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.flag(FLAG_Z),
+            1 => self.flag(FLAG_Z),
+            2 => !self.flag(FLAG_C),
+            _ => self.flag(FLAG_C),
+        }
+    }
+
+    /// BC, DE, HL or SP
+    fn pair(&self, pair: u8) -> u16 {
+        match pair {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.hl(),
+            _ => self.sp,
+        }
+    }
+
+    fn set_pair(&mut self, pair: u8, value: u16) {
+        match pair {
+            0 => self.set_bc(value),
+            1 => self.set_de(value),
+            2 => self.set_hl(value),
+            _ => self.sp = value,
+        }
+    }
+
+    /// B, C, D, E, H, L, (HL) or A by the register bits of the opcode
+    fn reg(&self, bus: &mut dyn Bus, index: u8) -> Result<u8, Box<dyn Msg>> {
+        Ok(match index {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => self.h,
+            5 => self.l,
+            6 => self.read(bus, self.hl())?,
+            _ => self.a,
+        })
+    }
+
+    fn set_reg(&mut self, bus: &mut dyn Bus, index: u8, value: u8) -> Result<(), Box<dyn Msg>> {
+        match index {
+            0 => self.b = value,
+            1 => self.c = value,
+            2 => self.d = value,
+            3 => self.e = value,
+            4 => self.h = value,
+            5 => self.l = value,
+            6 => self.write(bus, self.hl(), value)?,
+            _ => self.a = value,
+        }
+        Ok(())
+    }
+
+    fn fetch(&mut self, bus: &mut dyn Bus) -> Result<u8, Box<dyn Msg>> {
+        let value = self.read(bus, self.pc)?;
+        self.pc = self.pc.wrapping_add(1);
+        Ok(value)
+    }
+
+    fn fetch_word(&mut self, bus: &mut dyn Bus) -> Result<u16, Box<dyn Msg>> {
+        let low = self.fetch(bus)?;
+        let high = self.fetch(bus)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn push(&mut self, bus: &mut dyn Bus, value: u16) -> Result<(), Box<dyn Msg>> {
+        let [high, low] = value.to_be_bytes();
+        self.sp = self.sp.wrapping_sub(2);
+        self.write(bus, self.sp.wrapping_add(1), high)?;
+        self.write(bus, self.sp, low)
+    }
+
+    fn pop(&mut self, bus: &mut dyn Bus) -> Result<u16, Box<dyn Msg>> {
+        let low = self.read(bus, self.sp)?;
+        let high = self.read(bus, self.sp.wrapping_add(1))?;
+        self.sp = self.sp.wrapping_add(2);
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn read(&self, bus: &mut dyn Bus, addr: u16) -> Result<u8, Box<dyn Msg>> {
+        bus.read_byte(addr as usize).map_err(|_| self.memory_fault(addr))
+    }
+
+    fn write(&self, bus: &mut dyn Bus, addr: u16, value: u8) -> Result<(), Box<dyn Msg>> {
+        bus.write_byte(addr as usize, value).map_err(|_| self.memory_fault(addr))
+    }
+
+    /// Guest program error, reported with the address and the opcode of the faulting instruction
+    fn fault(&self, msg_id: ErrorMsgId) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(ErrorTopicId::GuestFault.into(), msg_id.into())
+            .add_param(format!("{:#06X}", self.instr_pc))
+            .add_param(format!("{:#04X}", self.opcode));
+        Box::new(err)
+    }
+
+    fn memory_fault(&self, addr: u16) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(ErrorTopicId::GuestFault.into(), ErrorMsgId::MemoryFault.into())
+            .add_param(format!("{:#06X}", self.instr_pc))
+            .add_param(format!("{:#04X}", self.opcode))
+            .add_param(format!("{:#06X}", addr));
+        Box::new(err)
+    }
+}
+
+#[cfg(test)]
+mod cpu_tests {
+
+    use super::*;
+    use crate::common::bus::{MappedBus, OpenBus};
+
+    fn run(program: &[u8], steps: usize) -> (Lr35902, MappedBus, u32) {
+        let mut bus = MappedBus::new(OpenBus::Error);
+        bus.map_ram("ram", 0, 0x10000);
+        for (i, byte) in program.iter().enumerate() {
+            bus.write_byte(0x100 + i, *byte).unwrap();
+        }
+        let mut cpu = Lr35902::new();
+        let mut cycles = 0;
+        for _ in 0..steps {
+            cycles += cpu.step(&mut bus).unwrap();
+        }
+        (cpu, bus, cycles)
+    }
+
+    #[test]
+    fn test_alu_flags() {
+        // LD A,0x45; ADD A,0x38; DAA
+        let (cpu, _, _) = run(&[0x3E, 0x45, 0xC6, 0x38, 0x27], 3);
+        assert_eq!(cpu.a, 0x83);
+        assert_eq!(cpu.f, 0x00);
+        // LD A,0x10; SUB 0x01; CP 0x0F
+        let (cpu, _, _) = run(&[0x3E, 0x10, 0xD6, 0x01, 0xFE, 0x0F], 3);
+        assert_eq!(cpu.a, 0x0F);
+        assert_eq!(cpu.f, FLAG_Z | FLAG_N);
+        // LD SP,0xFFF8; LD HL,SP+0x08
+        let (cpu, _, _) = run(&[0x31, 0xF8, 0xFF, 0xF8, 0x08], 2);
+        assert_eq!(cpu.hl(), 0x0000);
+        assert_eq!(cpu.f, FLAG_H | FLAG_C);
+        // LD B,0x81; SRA B; SWAP B
+        let (cpu, _, cycles) = run(&[0x06, 0x81, 0xCB, 0x28, 0xCB, 0x30], 3);
+        assert_eq!(cpu.b, 0x0C);
+        assert_eq!(cycles, 8 + 8 + 8);
+    }
+
+    #[test]
+    fn test_stack_and_calls() {
+        // LD BC,0x1234; PUSH BC; POP AF; CALL 0x0110; ... 0x0110: RET NZ
+        let mut program = vec![0x01, 0x34, 0x12, 0xC5, 0xF1, 0xCD, 0x10, 0x01];
+        program.resize(0x10, 0);
+        program.push(0xC0);
+        let (cpu, bus, cycles) = run(&program, 5);
+        assert_eq!(cpu.af(), 0x1230);
+        assert_eq!(cpu.pc, 0x0108);
+        assert_eq!(bus.peek(0xFFFC), Some(0x08));
+        assert_eq!(cycles, 12 + 16 + 12 + 24 + 20);
+    }
+
+    #[test]
+    fn test_interrupts() {
+        // EI; HALT; NOP
+        let (mut cpu, mut bus, _) = run(&[0xFB, 0x76, 0x00], 2);
+        assert!(cpu.halted && cpu.ime);
+        bus.write_byte(IE_ADDRESS, TIMER_INTERRUPT).unwrap();
+        bus.write_byte(IF_ADDRESS, TIMER_INTERRUPT).unwrap();
+        assert_eq!(cpu.step(&mut bus).unwrap(), INTERRUPT_CYCLES);
+        assert_eq!(cpu.pc, 0x50);
+        assert!(!cpu.halted && !cpu.ime);
+        assert_eq!(bus.peek(IF_ADDRESS), Some(0));
+
+        // 0xD3 does not exist
+        let mut bus = MappedBus::new(OpenBus::Error);
+        bus.map_ram("ram", 0, 0x10000);
+        bus.write_byte(0x100, 0xD3).unwrap();
+        let mut cpu = Lr35902::new();
+        let err = cpu.step(&mut bus).unwrap_err();
+        assert_eq!(err.msg_id(), "UnknownInstruction");
+        assert_eq!(cpu.pc, 0x100);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::gb::cpu::JOYPAD_INTERRUPT;
+
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_BUTTONS: u8 = 0x20;
+
+/// The P1 register, keys read as 0 when pressed in the selected group
+pub struct Joypad {
+    select: u8,
+    /// Pressed keys as bits: Right, Left, Up, Down
+    directions: u8,
+    /// Pressed keys as bits: A, B, Select, Start
+    buttons: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: SELECT_DIRECTIONS | SELECT_BUTTONS,
+            directions: 0,
+            buttons: 0,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            pressed |= self.directions;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            pressed |= self.buttons;
+        }
+        0xC0 | self.select | (!pressed & 0x0F)
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.select = value & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+    }
+
+    /// `key` is a `GbKeys` value. Returns the interrupts to request
+    pub fn set_key(&mut self, key: u32, pressed: bool) -> u8 {
+        let (group, bit) = match key {
+            0..=3 => (&mut self.directions, key),
+            4..=7 => (&mut self.buttons, key - 4),
+            _ => return 0,
+        };
+        let was_pressed = *group & (1 << bit) != 0;
+        if pressed {
+            *group |= 1 << bit;
+        } else {
+            *group &= !(1 << bit);
+        }
+        if pressed && !was_pressed {
+            JOYPAD_INTERRUPT
+        } else {
+            0
+        }
+    }
+}
@@ -0,0 +1,6 @@
+pub mod system;
+pub mod cpu;
+pub mod ppu;
+pub mod timer;
+pub mod joypad;
+pub mod cartridge;
\ No newline at end of file
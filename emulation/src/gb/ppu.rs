@@ -0,0 +1,372 @@
+use crate::common::ram::Ram;
+use crate::common::vram::Vram;
+use crate::gb::cpu::{STAT_INTERRUPT, VBLANK_INTERRUPT};
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+pub const VRAM_SIZE: usize = 0x2000;
+pub const OAM_SIZE: usize = 0xA0;
+
+const LINE_CYCLES: u32 = 456;
+const OAM_SCAN_END: u32 = 80;
+/// The drawing mode length varies with sprites and scrolling, the shortest one is used
+const DRAWING_END: u32 = OAM_SCAN_END + 172;
+const LINES: u8 = 154;
+const SPRITES_PER_LINE: usize = 10;
+
+const MODE_HBLANK: u8 = 0;
+const MODE_VBLANK: u8 = 1;
+const MODE_OAM_SCAN: u8 = 2;
+const MODE_DRAWING: u8 = 3;
+
+const LCDC_BG_ENABLE: u8 = 0x01;
+const LCDC_OBJ_ENABLE: u8 = 0x02;
+const LCDC_OBJ_TALL: u8 = 0x04;
+const LCDC_BG_MAP: u8 = 0x08;
+const LCDC_TILE_DATA: u8 = 0x10;
+const LCDC_WINDOW_ENABLE: u8 = 0x20;
+const LCDC_WINDOW_MAP: u8 = 0x40;
+const LCDC_LCD_ENABLE: u8 = 0x80;
+
+const STAT_HBLANK_SOURCE: u8 = 0x08;
+const STAT_VBLANK_SOURCE: u8 = 0x10;
+const STAT_OAM_SOURCE: u8 = 0x20;
+const STAT_LYC_SOURCE: u8 = 0x40;
+
+const ATTR_PALETTE: u8 = 0x10;
+const ATTR_FLIP_X: u8 = 0x20;
+const ATTR_FLIP_Y: u8 = 0x40;
+const ATTR_BEHIND_BG: u8 = 0x80;
+
+/// Scanline renderer: a line is drawn at once when the PPU leaves the drawing mode.
+/// The framebuffer holds shades 0 (lightest) to 3
+pub struct Ppu {
+    vram: Ram,
+    oam: Ram,
+    lcdc: u8,
+    /// Only the interrupt source bits are kept, mode and coincidence are computed
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: u8,
+    /// Cycles into the current line
+    dot: u32,
+    /// Window lines drawn in this frame, the window does not skip lines when it is hidden
+    window_line: u8,
+    /// STAT interrupt line, the interrupt is raised on its rising edge
+    stat_line: bool,
+    framebuffer: Vram,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    /// Registers as the boot ROM leaves them
+    pub fn new() -> Self {
+        Self {
+            vram: Ram::new(VRAM_SIZE),
+            oam: Ram::new(OAM_SIZE),
+            lcdc: 0x91,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0xFC,
+            obp0: 0xFF,
+            obp1: 0xFF,
+            wy: 0,
+            wx: 0,
+            mode: MODE_OAM_SCAN,
+            dot: 0,
+            window_line: 0,
+            stat_line: false,
+            framebuffer: Vram::new(WIDTH, HEIGHT),
+        }
+    }
+
+    pub fn framebuffer(&self) -> &Vram {
+        &self.framebuffer
+    }
+
+    /// Advances by `cycles`, a multiple of 4. Returns the interrupts to request
+    /// and whether a frame was completed
+    pub fn tick(&mut self, cycles: u32) -> (u8, bool) {
+        let mut interrupts = 0;
+        let mut frame_done = false;
+        if self.lcdc & LCDC_LCD_ENABLE == 0 {
+            return (0, false);
+        }
+        for _ in 0..cycles / 4 {
+            self.dot += 4;
+            if self.ly < HEIGHT as u8 {
+                if self.dot == OAM_SCAN_END {
+                    self.mode = MODE_DRAWING;
+                } else if self.dot == DRAWING_END {
+                    self.mode = MODE_HBLANK;
+                    self.render_line();
+                }
+            }
+            if self.dot == LINE_CYCLES {
+                self.dot = 0;
+                self.ly += 1;
+                if self.ly == HEIGHT as u8 {
+                    self.mode = MODE_VBLANK;
+                    interrupts |= VBLANK_INTERRUPT;
+                    frame_done = true;
+                } else if self.ly == LINES {
+                    self.ly = 0;
+                    self.window_line = 0;
+                    self.mode = MODE_OAM_SCAN;
+                } else if self.ly < HEIGHT as u8 {
+                    self.mode = MODE_OAM_SCAN;
+                }
+            }
+            interrupts |= self.update_stat_line();
+        }
+        (interrupts, frame_done)
+    }
+
+    pub fn read_vram(&self, offset: usize) -> u8 {
+        self.vram.read_byte(offset).unwrap_or(0xFF)
+    }
+
+    pub fn write_vram(&mut self, offset: usize, value: u8) {
+        let _ = self.vram.write_byte(offset, value);
+    }
+
+    pub fn read_oam(&self, offset: usize) -> u8 {
+        self.oam.read_byte(offset).unwrap_or(0xFF)
+    }
+
+    pub fn write_oam(&mut self, offset: usize, value: u8) {
+        let _ = self.oam.write_byte(offset, value);
+    }
+
+    /// `addr` is 0xFF40..=0xFF4B without DMA at 0xFF46
+    pub fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF40 => self.lcdc,
+            0xFF41 => 0x80 | self.stat | ((self.ly == self.lyc) as u8) << 2 | self.mode,
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => 0xFF,
+        }
+    }
+
+    /// Returns the interrupts to request
+    pub fn write_register(&mut self, addr: u16, value: u8) -> u8 {
+        match addr {
+            0xFF40 => self.write_lcdc(value),
+            0xFF41 => self.stat = value & 0x78,
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF45 => self.lyc = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            // LY is read only
+            _ => {}
+        }
+        if self.lcdc & LCDC_LCD_ENABLE == 0 {
+            return 0;
+        }
+        self.update_stat_line()
+    }
+
+    /// A disabled LCD stays at line 0 and shows a blank screen
+    fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcdc & LCDC_LCD_ENABLE != 0;
+        self.lcdc = value;
+        let enabled = value & LCDC_LCD_ENABLE != 0;
+        if was_enabled && !enabled {
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = MODE_HBLANK;
+            self.window_line = 0;
+            self.stat_line = false;
+            self.framebuffer.clear();
+        } else if !was_enabled && enabled {
+            self.mode = MODE_OAM_SCAN;
+        }
+    }
+
+    fn update_stat_line(&mut self) -> u8 {
+        let line = (self.stat & STAT_LYC_SOURCE != 0 && self.ly == self.lyc)
+            || (self.stat & STAT_HBLANK_SOURCE != 0 && self.mode == MODE_HBLANK)
+            || (self.stat & STAT_VBLANK_SOURCE != 0 && self.mode == MODE_VBLANK)
+            || (self.stat & STAT_OAM_SOURCE != 0 && self.mode == MODE_OAM_SCAN);
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        if rising {
+            STAT_INTERRUPT
+        } else {
+            0
+        }
+    }
+
+    fn render_line(&mut self) {
+        let line = self.ly as usize;
+        // colour numbers before the palette, sprites behind the background need them
+        let mut colors = [0u8; WIDTH];
+        if self.lcdc & LCDC_BG_ENABLE != 0 {
+            let map = if self.lcdc & LCDC_BG_MAP != 0 { 0x1C00 } else { 0x1800 };
+            let y = self.scy.wrapping_add(self.ly);
+            for (x, color) in colors.iter_mut().enumerate() {
+                *color = self.tile_color(map, (x as u8).wrapping_add(self.scx), y);
+            }
+            self.render_window(&mut colors);
+        }
+        for (x, color) in colors.iter().enumerate() {
+            self.framebuffer.write_pixel(line * WIDTH + x, Ppu::shade(self.bgp, *color));
+        }
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            self.render_sprites(&colors);
+        }
+    }
+
+    /// WX is the screen column plus 7
+    fn render_window(&mut self, colors: &mut [u8; WIDTH]) {
+        if self.lcdc & LCDC_WINDOW_ENABLE == 0 || self.wy > self.ly || self.wx > 166 {
+            return;
+        }
+        let map = if self.lcdc & LCDC_WINDOW_MAP != 0 { 0x1C00 } else { 0x1800 };
+        let start = self.wx as i32 - 7;
+        for (x, color) in colors.iter_mut().enumerate().skip(start.max(0) as usize) {
+            *color = self.tile_color(map, (x as i32 - start) as u8, self.window_line);
+        }
+        self.window_line += 1;
+    }
+
+    /// Up to 10 sprites of the line, the lowest X then the lowest OAM index is on top
+    fn render_sprites(&mut self, colors: &[u8; WIDTH]) {
+        let height = if self.lcdc & LCDC_OBJ_TALL != 0 { 16 } else { 8 };
+        let line = self.ly as i32;
+        let oam = self.oam.data();
+        let mut sprites: Vec<&[u8]> = oam
+            .chunks_exact(4)
+            .filter(|s| {
+                let top = s[0] as i32 - 16;
+                top <= line && line < top + height
+            })
+            .take(SPRITES_PER_LINE)
+            .collect();
+        // drawn from the lowest priority up
+        sprites.sort_by_key(|s| s[1]);
+        for sprite in sprites.iter().rev() {
+            let (top, left, attributes) = (sprite[0] as i32 - 16, sprite[1] as i32 - 8, sprite[3]);
+            let mut tile = sprite[2] as usize;
+            if height == 16 {
+                tile &= 0xFE;
+            }
+            let mut row = line - top;
+            if attributes & ATTR_FLIP_Y != 0 {
+                row = height - 1 - row;
+            }
+            let palette = if attributes & ATTR_PALETTE != 0 { self.obp1 } else { self.obp0 };
+            for column in 0..8 {
+                let x = left + column;
+                if !(0..WIDTH as i32).contains(&x) {
+                    continue;
+                }
+                let bit = if attributes & ATTR_FLIP_X != 0 { column } else { 7 - column };
+                let color = Ppu::tile_data_color(self.vram.data(), tile * 16, row as usize, bit as u8);
+                if color == 0 || (attributes & ATTR_BEHIND_BG != 0 && colors[x as usize] != 0) {
+                    continue;
+                }
+                self.framebuffer.write_pixel(line as usize * WIDTH + x as usize, Ppu::shade(palette, color));
+            }
+        }
+    }
+
+    /// Colour number at `x`, `y` of a 32x32 tile map. Background and window tiles
+    /// are unsigned from 0x8000 or signed from 0x9000 depending on LCDC
+    fn tile_color(&self, map: usize, x: u8, y: u8) -> u8 {
+        let vram = self.vram.data();
+        let index = vram[map + (y as usize / 8) * 32 + x as usize / 8];
+        let tile = if self.lcdc & LCDC_TILE_DATA != 0 {
+            index as usize * 16
+        } else {
+            (0x1000 + (index as i8 as i32) * 16) as usize
+        };
+        Ppu::tile_data_color(vram, tile, (y % 8) as usize, 7 - x % 8)
+    }
+
+    fn tile_data_color(vram: &[u8], tile: usize, row: usize, bit: u8) -> u8 {
+        let low = vram[tile + row * 2];
+        let high = vram[tile + row * 2 + 1];
+        ((high >> bit) & 1) << 1 | ((low >> bit) & 1)
+    }
+
+    fn shade(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0x03
+    }
+}
+
+#[cfg(test)]
+mod ppu_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_frame_timing() {
+        let mut ppu = Ppu::new();
+        ppu.write_register(0xFF41, STAT_LYC_SOURCE);
+        ppu.write_register(0xFF45, 2);
+        let (interrupts, done) = ppu.tick(LINE_CYCLES * 2);
+        assert_eq!((interrupts, done), (STAT_INTERRUPT, false));
+        assert_eq!(ppu.read_register(0xFF41) & 0x07, 0x04 | MODE_OAM_SCAN);
+        let (interrupts, done) = ppu.tick(LINE_CYCLES * (HEIGHT as u32 - 2));
+        assert_eq!((interrupts, done), (VBLANK_INTERRUPT, true));
+        assert_eq!(ppu.read_register(0xFF44), HEIGHT as u8);
+        ppu.tick(LINE_CYCLES * (LINES as u32 - HEIGHT as u32));
+        assert_eq!(ppu.read_register(0xFF44), 0);
+    }
+
+    #[test]
+    fn test_background_and_sprite() {
+        let mut ppu = Ppu::new();
+        // tile 1 is solid colour 3, tile 2 has its left column in colour 1
+        for row in 0..8 {
+            ppu.write_vram(16 + row * 2, 0xFF);
+            ppu.write_vram(16 + row * 2 + 1, 0xFF);
+            ppu.write_vram(32 + row * 2, 0x80);
+        }
+        ppu.write_vram(0x1800, 1);
+        // sprite with tile 2 at the screen origin, behind the background
+        for (i, byte) in [16, 8, 2, ATTR_BEHIND_BG].iter().enumerate() {
+            ppu.write_oam(i, *byte);
+        }
+        ppu.write_register(0xFF40, 0x93);
+        ppu.tick(DRAWING_END);
+        let pixels = ppu.framebuffer().video();
+        assert_eq!(pixels[0], 3);
+        assert_eq!(pixels[8], 0);
+
+        ppu.write_oam(1, 16);
+        ppu.write_oam(3, 0);
+        ppu.tick(LINE_CYCLES);
+        assert_eq!(ppu.framebuffer().video()[WIDTH + 8], 3);
+        assert_eq!(ppu.framebuffer().video()[WIDTH + 9], 0);
+    }
+}
@@ -0,0 +1,445 @@
+use crate::common::bus::Bus;
+use crate::common::emulator::*;
+use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::ram::Ram;
+use crate::common::registry::{Capabilities, SystemInfo};
+use crate::common::utils;
+use crate::gb::cartridge::{self, Cartridge};
+use crate::gb::cpu::{Lr35902, SERIAL_INTERRUPT};
+use crate::gb::joypad::Joypad;
+use crate::gb::ppu::{self, Ppu};
+use crate::gb::timer::Timer;
+
+use std::path::Path;
+
+const CLOCK: u64 = 4_194_304;
+const WRAM_SIZE: usize = 0x2000;
+const HRAM_SIZE: usize = 0x7F;
+/// Sound registers read back what was written, the APU is not emulated
+const SOUND_REGISTERS_SIZE: usize = 0x30;
+const OAM_DMA_LENGTH: usize = 0xA0;
+/// Transfer requested with the internal clock
+const SERIAL_START: u8 = 0x81;
+/// Battery RAM file next to the ROM, e.g. `roms/Tetris.sav`
+const SAVE_EXTENSION: &str = "sav";
+
+/// Everything the CPU sees on its bus
+struct Mmu {
+    cartridge: Option<Cartridge>,
+    ppu: Ppu,
+    timer: Timer,
+    joypad: Joypad,
+    wram: Ram,
+    hram: Ram,
+    sound: Ram,
+    interrupt_flags: u8,
+    interrupt_enable: u8,
+    serial_data: u8,
+    serial_control: u8,
+    /// Bytes sent over the link cable, test ROMs print their results there
+    serial_output: String,
+}
+
+impl Mmu {
+    fn new(cartridge: Option<Cartridge>) -> Self {
+        Self {
+            cartridge,
+            ppu: Ppu::new(),
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            wram: Ram::new(WRAM_SIZE),
+            hram: Ram::new(HRAM_SIZE),
+            sound: Ram::new(SOUND_REGISTERS_SIZE),
+            interrupt_flags: 0x01,
+            interrupt_enable: 0,
+            serial_data: 0,
+            serial_control: 0,
+            serial_output: String::new(),
+        }
+    }
+
+    /// Runs the devices for `cycles`, returns whether a frame was completed
+    fn tick(&mut self, cycles: u32) -> bool {
+        self.interrupt_flags |= self.timer.tick(cycles);
+        let (interrupts, frame_done) = self.ppu.tick(cycles);
+        self.interrupt_flags |= interrupts;
+        if let Some(cartridge) = self.cartridge.as_mut() {
+            cartridge.tick(cycles);
+        }
+        frame_done
+    }
+
+    fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.cartridge.as_ref().map_or(0xFF, |c| c.read_rom(addr)),
+            0x8000..=0x9FFF => self.ppu.read_vram((addr - 0x8000) as usize),
+            0xA000..=0xBFFF => self.cartridge.as_ref().map_or(0xFF, |c| c.read_ram(addr)),
+            // 0xE000..=0xFDFF echoes the work RAM
+            0xC000..=0xFDFF => self.wram.read_byte((addr as usize - 0xC000) % WRAM_SIZE).unwrap_or(0xFF),
+            0xFE00..=0xFE9F => self.ppu.read_oam((addr - 0xFE00) as usize),
+            0xFF00 => self.joypad.read(),
+            0xFF01 => self.serial_data,
+            0xFF02 => self.serial_control | 0x7E,
+            0xFF04..=0xFF07 => self.timer.read(addr),
+            0xFF0F => self.interrupt_flags | 0xE0,
+            0xFF10..=0xFF3F => self.sound.read_byte((addr - 0xFF10) as usize).unwrap_or(0xFF),
+            0xFF40..=0xFF4B => self.ppu.read_register(addr),
+            0xFF80..=0xFFFE => self.hram.read_byte((addr - 0xFF80) as usize).unwrap_or(0xFF),
+            0xFFFF => self.interrupt_enable,
+            // unusable area and unmapped registers
+            _ => 0xFF,
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x7FFF => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_rom(addr, value);
+                }
+            }
+            0x8000..=0x9FFF => self.ppu.write_vram((addr - 0x8000) as usize, value),
+            0xA000..=0xBFFF => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write_ram(addr, value);
+                }
+            }
+            0xC000..=0xFDFF => {
+                let _ = self.wram.write_byte((addr as usize - 0xC000) % WRAM_SIZE, value);
+            }
+            0xFE00..=0xFE9F => self.ppu.write_oam((addr - 0xFE00) as usize, value),
+            0xFF00 => self.joypad.write(value),
+            0xFF01 => self.serial_data = value,
+            0xFF02 => {
+                self.serial_control = value;
+                if value & SERIAL_START == SERIAL_START {
+                    self.serial_transfer();
+                }
+            }
+            0xFF04..=0xFF07 => self.interrupt_flags |= self.timer.write(addr, value),
+            0xFF0F => self.interrupt_flags = value & 0x1F,
+            0xFF10..=0xFF3F => {
+                let _ = self.sound.write_byte((addr - 0xFF10) as usize, value);
+            }
+            0xFF46 => self.oam_dma(value),
+            0xFF40..=0xFF4B => self.interrupt_flags |= self.ppu.write_register(addr, value),
+            0xFF80..=0xFFFE => {
+                let _ = self.hram.write_byte((addr - 0xFF80) as usize, value);
+            }
+            0xFFFF => self.interrupt_enable = value,
+            _ => {}
+        }
+    }
+
+    /// Nothing is connected to the link port: the byte goes to `serial_output` and 0xFF comes back
+    fn serial_transfer(&mut self) {
+        let byte = self.serial_data;
+        if byte == b'\n' {
+            let line = self.serial_output.rsplit('\n').next().unwrap_or("");
+            log::info!(line = line; "Serial output");
+        }
+        self.serial_output.push(byte as char);
+        self.serial_data = 0xFF;
+        self.serial_control &= !0x80;
+        self.interrupt_flags |= SERIAL_INTERRUPT;
+    }
+
+    /// Copies 160 bytes from `page` * 0x100 to the OAM at once
+    fn oam_dma(&mut self, page: u8) {
+        let source = (page as u16) << 8;
+        for i in 0..OAM_DMA_LENGTH {
+            let value = self.read(source + i as u16);
+            self.ppu.write_oam(i, value);
+        }
+    }
+}
+
+impl Bus for Mmu {
+    fn read_byte(&mut self, addr: usize) -> Result<u8, Box<dyn Msg>> {
+        match u16::try_from(addr) {
+            Ok(addr) => Ok(self.read(addr)),
+            Err(_) => Err(Mmu::out_of_bounds(ErrorTopicId::RamRead, addr)),
+        }
+    }
+
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Box<dyn Msg>> {
+        match u16::try_from(addr) {
+            Ok(addr) => {
+                self.write(addr, value);
+                Ok(())
+            }
+            Err(_) => Err(Mmu::out_of_bounds(ErrorTopicId::RamWrite, addr)),
+        }
+    }
+
+    fn peek(&self, addr: usize) -> Option<u8> {
+        u16::try_from(addr).ok().map(|addr| self.read(addr))
+    }
+}
+
+impl Mmu {
+    fn out_of_bounds(topic: ErrorTopicId, addr: usize) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(topic.into(), ErrorMsgId::OutOfBounds.into())
+            .add_param(addr.to_string())
+            .add_param(String::from("65536"))
+            .add_param(String::from("1"));
+        Box::new(err)
+    }
+}
+
+/// Original Game Boy (DMG), started without the boot ROM
+pub struct Gb {
+    cpu: Lr35902,
+    mmu: Mmu,
+    /// Battery RAM file of the loaded cartridge
+    save_file: Option<String>,
+    cycle_count: u128,
+}
+
+impl Default for Gb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Gb {
+    pub fn new() -> Self {
+        Gb {
+            cpu: Lr35902::new(),
+            mmu: Mmu::new(None),
+            save_file: None,
+            cycle_count: 0,
+        }
+    }
+
+    /// Recognised by the Nintendo logo of the header
+    pub fn system_info() -> SystemInfo {
+        SystemInfo {
+            name: "Game Boy",
+            extensions: &["gb"],
+            capabilities: Capabilities {
+                save_states: false,
+                reset: true,
+                sound: false,
+            },
+            factory: || Box::new(Gb::new()),
+            sniff: Some(|rom| {
+                let logo = cartridge::LOGO_ADDRESS..cartridge::LOGO_ADDRESS + cartridge::LOGO_START.len();
+                rom.get(logo) == Some(&cartridge::LOGO_START[..])
+            }),
+        }
+    }
+
+    pub fn cpu(&self) -> &Lr35902 {
+        &self.cpu
+    }
+
+    pub fn load_rom(&mut self, file_name: &str) {
+        self.save_battery();
+        let rom = match utils::load_rom(file_name) {
+            Ok(rom) => rom,
+            Err(error) => {
+                log::error!(file = file_name; "Cannot load ROM: {}", error);
+                return;
+            }
+        };
+        let mut cartridge = match Cartridge::from_rom(rom) {
+            Ok(cartridge) => cartridge,
+            Err(err) => {
+                log::error!(file = file_name; "{}", err);
+                return;
+            }
+        };
+        log::debug!(title = cartridge.title(), battery = cartridge.has_battery(); "Cartridge inserted");
+        let save_file = Path::new(file_name).with_extension(SAVE_EXTENSION).to_string_lossy().into_owned();
+        if cartridge.has_battery() && Path::new(&save_file).exists() {
+            Gb::load_battery(&mut cartridge, &save_file);
+        }
+        self.insert(cartridge);
+        self.save_file = Some(save_file);
+    }
+
+    /// Powers the console on with `cartridge`
+    fn insert(&mut self, cartridge: Cartridge) {
+        self.cpu = Lr35902::new();
+        self.mmu = Mmu::new(Some(cartridge));
+        self.cycle_count = 0;
+    }
+
+    fn load_battery(cartridge: &mut Cartridge, file_name: &str) {
+        let loaded = std::fs::read(file_name)
+            .map_err(|e| {
+                let err = ErrorMsg::new(ErrorTopicId::Cartridge.into(), ErrorMsgId::FileRead.into())
+                    .add_param(file_name.to_string())
+                    .set_source(Box::new(e));
+                Box::new(err) as Box<dyn Msg>
+            })
+            .and_then(|data| cartridge.load_ram(&data));
+        match loaded {
+            Ok(_) => log::info!(file = file_name; "Save RAM loaded"),
+            Err(err) => log::warn!(file = file_name; "Save RAM ignored: {}", err),
+        }
+    }
+
+    fn save_battery(&self) {
+        let (cartridge, file_name) = match (&self.mmu.cartridge, &self.save_file) {
+            (Some(cartridge), Some(file_name)) if cartridge.has_battery() => (cartridge, file_name),
+            _ => return,
+        };
+        match std::fs::write(file_name, cartridge.ram()) {
+            Ok(_) => log::debug!(file = file_name.as_str(); "Save RAM written"),
+            Err(e) => {
+                let err = ErrorMsg::new(ErrorTopicId::Cartridge.into(), ErrorMsgId::FileWrite.into())
+                    .add_param(file_name.clone())
+                    .set_source(Box::new(e));
+                log::error!("{}", err);
+            }
+        }
+    }
+}
+
+impl Drop for Gb {
+    fn drop(&mut self) {
+        self.save_battery();
+    }
+}
+
+impl Emulator for Gb {
+    fn video_buffer(&self) -> &[u8] {
+        self.mmu.ppu.framebuffer().video()
+    }
+
+    fn palette(&self) -> Palette {
+        Palette::new("DMG", vec![0x9BBC0FFF, 0x8BAC0FFF, 0x306230FF, 0x0F380FFF])
+    }
+
+    fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
+        if self.mmu.cartridge.is_none() {
+            return Ok(CycleResult::default());
+        }
+        let cycles = self.cpu.step(&mut self.mmu)?;
+        let video_buff_changed = self.mmu.tick(cycles);
+        if self.mmu.cartridge.as_mut().is_some_and(|c| c.take_dirty()) {
+            self.save_battery();
+        }
+        self.cycle_count += cycles as u128;
+        Ok(CycleResult {
+            video_buff_changed,
+            total_cycle_count: self.cycle_count,
+            last_cycle_count: cycles as u128,
+        })
+    }
+
+    /// The PPU follows the clock in `cycle`, nothing is tied to the end of the frame
+    fn end_frame(&mut self) {}
+
+    fn process_input(&mut self, key: u32, pressed: bool) {
+        self.mmu.interrupt_flags |= self.mmu.joypad.set_key(key, pressed);
+    }
+
+    fn load_rom(&mut self, file_name: &String) {
+        self.load_rom(file_name);
+    }
+
+    fn resolution(&self) -> [u32; 2] {
+        [ppu::WIDTH as u32, ppu::HEIGHT as u32]
+    }
+
+    fn cycles_in_sec(&self) -> u64 {
+        CLOCK
+    }
+
+    fn sound_active(&self) -> bool {
+        false
+    }
+
+    /// The cartridge stays inserted with its RAM
+    fn reset(&mut self) {
+        if let Some(cartridge) = self.mmu.cartridge.take() {
+            self.insert(cartridge);
+        }
+    }
+
+    fn power_cycle(&mut self) {
+        self.save_battery();
+        self.cpu = Lr35902::new();
+        self.mmu = Mmu::new(None);
+        self.save_file = None;
+        self.cycle_count = 0;
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, Box<dyn Msg>> {
+        let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::NotSupported.into());
+        Err(Box::new(err))
+    }
+
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::NotSupported.into());
+        Err(Box::new(err))
+    }
+
+    /// Everything the program sent over the link port
+    fn serial_output(&self) -> Option<&str> {
+        Some(&self.mmu.serial_output)
+    }
+}
+
+pub enum GbKeys {
+    Right = 0,
+    Left = 1,
+    Up = 2,
+    Down = 3,
+    A = 4,
+    B = 5,
+    Select = 6,
+    Start = 7,
+}
+
+#[cfg(test)]
+mod gb_tests {
+
+    use super::*;
+
+    fn with_program(program: &[u8]) -> Gb {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x100..0x100 + program.len()].copy_from_slice(program);
+        let mut gb = Gb::new();
+        gb.insert(Cartridge::from_rom(rom).unwrap());
+        gb
+    }
+
+    #[test]
+    fn test_serial_output() {
+        // LD A,'o'; LDH (SB),A; LD A,0x81; LDH (SC),A; JR -2
+        let mut gb = with_program(&[0x3E, b'o', 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02, 0x18, 0xFE]);
+        for _ in 0..5 {
+            gb.cycle().unwrap();
+        }
+        assert_eq!(gb.serial_output(), Some("o"));
+        assert_eq!(gb.mmu.read(0xFF01), 0xFF);
+        assert_eq!(gb.mmu.read(0xFF0F) & SERIAL_INTERRUPT, SERIAL_INTERRUPT);
+    }
+
+    #[test]
+    fn test_memory_map_and_frame() {
+        // JR -2
+        let mut gb = with_program(&[0x18, 0xFE]);
+        gb.mmu.write(0xC010, 0x42);
+        assert_eq!(gb.mmu.read(0xE010), 0x42);
+        gb.mmu.write(0xFF46, 0xC0);
+        assert_eq!(gb.mmu.ppu.read_oam(0x10), 0x42);
+        gb.mmu.write(0x2000, 0x05);
+        assert_eq!(gb.mmu.read(0x0147), 0x00);
+
+        let mut frames = 0;
+        let mut cycles = 0;
+        while frames == 0 {
+            let res = gb.cycle().unwrap();
+            frames += res.video_buff_changed as u32;
+            cycles += res.last_cycle_count;
+        }
+        assert!((144 * 456..145 * 456).contains(&cycles));
+        assert!(gb.save_state().is_err());
+    }
+}
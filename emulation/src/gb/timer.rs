@@ -0,0 +1,106 @@
+use crate::gb::cpu::TIMER_INTERRUPT;
+
+/// Counter bit whose falling edge increments TIMA, by the TAC clock select
+const TAC_BITS: [u16; 4] = [1 << 9, 1 << 3, 1 << 5, 1 << 7];
+const TAC_ENABLE: u8 = 0x04;
+
+/// DIV and TIMA, both driven by one 16-bit counter running at the CPU clock
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    /// Counter value the boot ROM leaves behind
+    pub fn new() -> Self {
+        Self {
+            counter: 0xABCC,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+        }
+    }
+
+    /// Advances by `cycles`, a multiple of 4. Returns the interrupts to request
+    pub fn tick(&mut self, cycles: u32) -> u8 {
+        let mut interrupts = 0;
+        for _ in 0..cycles / 4 {
+            let before = self.input();
+            self.counter = self.counter.wrapping_add(4);
+            if before && !self.input() {
+                interrupts |= self.increment();
+            }
+        }
+        interrupts
+    }
+
+    /// `addr` is 0xFF04..=0xFF07
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            _ => self.tac | 0xF8,
+        }
+    }
+
+    /// Returns the interrupts to request, writes to DIV and TAC may tick TIMA
+    pub fn write(&mut self, addr: u16, value: u8) -> u8 {
+        let before = self.input();
+        match addr {
+            0xFF04 => self.counter = 0,
+            0xFF05 => self.tima = value,
+            0xFF06 => self.tma = value,
+            _ => self.tac = value & 0x07,
+        }
+        if before && !self.input() {
+            return self.increment();
+        }
+        0
+    }
+
+    fn input(&self) -> bool {
+        self.tac & TAC_ENABLE != 0 && self.counter & TAC_BITS[(self.tac & 0x03) as usize] != 0
+    }
+
+    /// TIMA reloads from TMA when it overflows
+    fn increment(&mut self) -> u8 {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        if overflow {
+            self.tima = self.tma;
+            return TIMER_INTERRUPT;
+        }
+        self.tima = tima;
+        0
+    }
+}
+
+#[cfg(test)]
+mod timer_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_tima_overflow() {
+        let mut timer = Timer::new();
+        timer.write(0xFF04, 0);
+        timer.write(0xFF06, 0xF0);
+        timer.write(0xFF05, 0xFE);
+        // 262144Hz, one increment every 16 cycles
+        timer.write(0xFF07, 0x05);
+        assert_eq!(timer.tick(16), 0);
+        assert_eq!(timer.read(0xFF05), 0xFF);
+        assert_eq!(timer.tick(16), TIMER_INTERRUPT);
+        assert_eq!(timer.read(0xFF05), 0xF0);
+        assert_eq!(timer.read(0xFF04), 0);
+        assert_eq!(timer.read(0xFF07), 0xFD);
+    }
+}
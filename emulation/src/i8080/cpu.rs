@@ -0,0 +1,625 @@
+use crate::common::bus::Bus;
+use crate::common::message::*;
+
+/// Clock cycles of each opcode, taken conditional calls and returns add `BRANCH_CYCLES`
+const CYCLES: [u8; 256] = [
+    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, // 0x00
+    4, 10, 7, 5, 5, 5, 7, 4, 4, 10, 7, 5, 5, 5, 7, 4, // 0x10
+    4, 10, 16, 5, 5, 5, 7, 4, 4, 10, 16, 5, 5, 5, 7, 4, // 0x20
+    4, 10, 13, 5, 10, 10, 10, 4, 4, 10, 13, 5, 5, 5, 7, 4, // 0x30
+    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, // 0x40
+    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, // 0x50
+    5, 5, 5, 5, 5, 5, 7, 5, 5, 5, 5, 5, 5, 5, 7, 5, // 0x60
+    7, 7, 7, 7, 7, 7, 7, 7, 5, 5, 5, 5, 5, 5, 7, 5, // 0x70
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, // 0x80
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, // 0x90
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, // 0xA0
+    4, 4, 4, 4, 4, 4, 7, 4, 4, 4, 4, 4, 4, 4, 7, 4, // 0xB0
+    5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11, // 0xC0
+    5, 10, 10, 10, 11, 11, 7, 11, 5, 10, 10, 10, 11, 17, 7, 11, // 0xD0
+    5, 10, 10, 18, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17, 7, 11, // 0xE0
+    5, 10, 10, 4, 11, 11, 7, 11, 5, 5, 10, 4, 11, 17, 7, 11, // 0xF0
+];
+const BRANCH_CYCLES: u32 = 6;
+/// A halted CPU burns NOPs until an interrupt arrives
+const HALT_CYCLES: u32 = 4;
+const INTERRUPT_CYCLES: u32 = 11;
+/// Registers, flags and the interrupt state, see `save`
+pub const STATE_SIZE: usize = 14;
+
+/// IN/OUT devices of the machine around the CPU
+pub trait Ports {
+    fn input(&mut self, port: u8) -> u8;
+    fn output(&mut self, port: u8, value: u8);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Flags {
+    pub s: bool,
+    pub z: bool,
+    pub ac: bool,
+    pub p: bool,
+    pub cy: bool,
+}
+
+impl Flags {
+    /// PSW layout `S Z 0 AC 0 P 1 CY`
+    pub fn to_byte(&self) -> u8 {
+        (self.s as u8) << 7 | (self.z as u8) << 6 | (self.ac as u8) << 4 | (self.p as u8) << 2 | 0x02 | self.cy as u8
+    }
+
+    pub fn from_byte(value: u8) -> Self {
+        Self {
+            s: value & 0x80 != 0,
+            z: value & 0x40 != 0,
+            ac: value & 0x10 != 0,
+            p: value & 0x04 != 0,
+            cy: value & 0x01 != 0,
+        }
+    }
+}
+
+/// Intel 8080, registers are public for the machines, debuggers and tests
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct I8080 {
+    pub a: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+    pub flags: Flags,
+    /// INTE flip-flop
+    pub interrupts_enabled: bool,
+    pub halted: bool,
+    opcode: u8,
+    /// Address of the instruction being executed, for fault reports
+    instr_pc: u16,
+}
+
+impl I8080 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    /// Executes one instruction, returns the clock cycles it took
+    pub fn step(&mut self, bus: &mut dyn Bus, ports: &mut dyn Ports) -> Result<u32, Box<dyn Msg>> {
+        if self.halted {
+            return Ok(HALT_CYCLES);
+        }
+        self.instr_pc = self.pc;
+        let opcode = self.fetch(bus)?;
+        self.opcode = opcode;
+        let res = self.execute(bus, ports, opcode);
+        if res.is_err() {
+            // leave PC on the faulting instruction for the debugger
+            self.pc = self.instr_pc;
+        }
+        res
+    }
+
+    /// Executes `RST vector` if interrupts are enabled, returns the clock cycles it took
+    pub fn interrupt(&mut self, bus: &mut dyn Bus, vector: u8) -> Result<u32, Box<dyn Msg>> {
+        if !self.interrupts_enabled {
+            return Ok(0);
+        }
+        self.interrupts_enabled = false;
+        self.halted = false;
+        self.instr_pc = self.pc;
+        self.opcode = 0xC7 | (vector & 0x07) << 3;
+        self.push(bus, self.pc)?;
+        self.pc = (vector as u16 & 0x07) * 8;
+        Ok(INTERRUPT_CYCLES)
+    }
+
+    pub fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.a, self.b, self.c, self.d, self.e, self.h, self.l, self.flags.to_byte()]);
+        state.extend_from_slice(&self.sp.to_be_bytes());
+        state.extend_from_slice(&self.pc.to_be_bytes());
+        state.extend_from_slice(&[self.interrupts_enabled as u8, self.halted as u8]);
+    }
+
+    /// `state` holds `STATE_SIZE` bytes written by `save`
+    pub fn load(&mut self, state: &[u8]) {
+        [self.a, self.b, self.c, self.d, self.e, self.h, self.l] =
+            [state[0], state[1], state[2], state[3], state[4], state[5], state[6]];
+        self.flags = Flags::from_byte(state[7]);
+        self.sp = u16::from_be_bytes([state[8], state[9]]);
+        self.pc = u16::from_be_bytes([state[10], state[11]]);
+        self.interrupts_enabled = state[12] != 0;
+        self.halted = state[13] != 0;
+    }
+
+    fn execute(&mut self, bus: &mut dyn Bus, ports: &mut dyn Ports, opcode: u8) -> Result<u32, Box<dyn Msg>> {
+        let mut cycles = CYCLES[opcode as usize] as u32;
+        let pair = (opcode >> 4) & 0x03;
+        let dst = (opcode >> 3) & 0x07;
+        let src = opcode & 0x07;
+        match opcode {
+            //NOP, the undocumented ones included
+            0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {}
+            //LXI rp, d16
+            0x01 | 0x11 | 0x21 | 0x31 => {
+                let value = self.fetch_word(bus)?;
+                self.set_pair(pair, value);
+            }
+            //STAX B / STAX D
+            0x02 | 0x12 => {
+                let addr = if opcode == 0x02 { self.bc() } else { self.de() };
+                self.write(bus, addr, self.a)?;
+            }
+            //LDAX B / LDAX D
+            0x0A | 0x1A => {
+                let addr = if opcode == 0x0A { self.bc() } else { self.de() };
+                self.a = self.read(bus, addr)?;
+            }
+            //INX rp
+            0x03 | 0x13 | 0x23 | 0x33 => self.set_pair(pair, self.pair(pair).wrapping_add(1)),
+            //DCX rp
+            0x0B | 0x1B | 0x2B | 0x3B => self.set_pair(pair, self.pair(pair).wrapping_sub(1)),
+            //DAD rp
+            0x09 | 0x19 | 0x29 | 0x39 => {
+                let sum = self.hl() as u32 + self.pair(pair) as u32;
+                self.flags.cy = sum > 0xFFFF;
+                self.set_hl(sum as u16);
+            }
+            //INR r
+            0x04 | 0x0C | 0x14 | 0x1C | 0x24 | 0x2C | 0x34 | 0x3C => {
+                let value = self.reg(bus, dst)?.wrapping_add(1);
+                self.set_zsp(value);
+                self.flags.ac = value & 0x0F == 0;
+                self.set_reg(bus, dst, value)?;
+            }
+            //DCR r
+            0x05 | 0x0D | 0x15 | 0x1D | 0x25 | 0x2D | 0x35 | 0x3D => {
+                let value = self.reg(bus, dst)?.wrapping_sub(1);
+                self.set_zsp(value);
+                self.flags.ac = value & 0x0F != 0x0F;
+                self.set_reg(bus, dst, value)?;
+            }
+            //MVI r, d8
+            0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
+                let value = self.fetch(bus)?;
+                self.set_reg(bus, dst, value)?;
+            }
+            //RLC
+            0x07 => {
+                self.flags.cy = self.a & 0x80 != 0;
+                self.a = self.a.rotate_left(1);
+            }
+            //RRC
+            0x0F => {
+                self.flags.cy = self.a & 0x01 != 0;
+                self.a = self.a.rotate_right(1);
+            }
+            //RAL
+            0x17 => {
+                let carry = self.flags.cy as u8;
+                self.flags.cy = self.a & 0x80 != 0;
+                self.a = self.a << 1 | carry;
+            }
+            //RAR
+            0x1F => {
+                let carry = self.flags.cy as u8;
+                self.flags.cy = self.a & 0x01 != 0;
+                self.a = self.a >> 1 | carry << 7;
+            }
+            //SHLD a16
+            0x22 => {
+                let addr = self.fetch_word(bus)?;
+                self.write(bus, addr, self.l)?;
+                self.write(bus, addr.wrapping_add(1), self.h)?;
+            }
+            //LHLD a16
+            0x2A => {
+                let addr = self.fetch_word(bus)?;
+                self.l = self.read(bus, addr)?;
+                self.h = self.read(bus, addr.wrapping_add(1))?;
+            }
+            //DAA
+            0x27 => self.daa(),
+            //CMA
+            0x2F => self.a = !self.a,
+            //STA a16
+            0x32 => {
+                let addr = self.fetch_word(bus)?;
+                self.write(bus, addr, self.a)?;
+            }
+            //LDA a16
+            0x3A => {
+                let addr = self.fetch_word(bus)?;
+                self.a = self.read(bus, addr)?;
+            }
+            //STC
+            0x37 => self.flags.cy = true,
+            //CMC
+            0x3F => self.flags.cy = !self.flags.cy,
+            //HLT
+            0x76 => self.halted = true,
+            //MOV r, r
+            0x40..=0x7F => {
+                let value = self.reg(bus, src)?;
+                self.set_reg(bus, dst, value)?;
+            }
+            //ADD, ADC, SUB, SBB, ANA, XRA, ORA, CMP r
+            0x80..=0xBF => {
+                let value = self.reg(bus, src)?;
+                self.alu(dst, value);
+            }
+            //ADI, ACI, SUI, SBI, ANI, XRI, ORI, CPI d8
+            0xC6 | 0xCE | 0xD6 | 0xDE | 0xE6 | 0xEE | 0xF6 | 0xFE => {
+                let value = self.fetch(bus)?;
+                self.alu(dst, value);
+            }
+            //Rcc
+            0xC0 | 0xC8 | 0xD0 | 0xD8 | 0xE0 | 0xE8 | 0xF0 | 0xF8 => {
+                if self.condition(dst) {
+                    self.pc = self.pop(bus)?;
+                    cycles += BRANCH_CYCLES;
+                }
+            }
+            //Jcc a16
+            0xC2 | 0xCA | 0xD2 | 0xDA | 0xE2 | 0xEA | 0xF2 | 0xFA => {
+                let addr = self.fetch_word(bus)?;
+                if self.condition(dst) {
+                    self.pc = addr;
+                }
+            }
+            //Ccc a16
+            0xC4 | 0xCC | 0xD4 | 0xDC | 0xE4 | 0xEC | 0xF4 | 0xFC => {
+                let addr = self.fetch_word(bus)?;
+                if self.condition(dst) {
+                    self.push(bus, self.pc)?;
+                    self.pc = addr;
+                    cycles += BRANCH_CYCLES;
+                }
+            }
+            //POP rp, PSW instead of SP
+            0xC1 | 0xD1 | 0xE1 | 0xF1 => {
+                let value = self.pop(bus)?;
+                if pair == 3 {
+                    let [a, flags] = value.to_be_bytes();
+                    self.a = a;
+                    self.flags = Flags::from_byte(flags);
+                } else {
+                    self.set_pair(pair, value);
+                }
+            }
+            //PUSH rp, PSW instead of SP
+            0xC5 | 0xD5 | 0xE5 | 0xF5 => {
+                let value = match pair {
+                    3 => u16::from_be_bytes([self.a, self.flags.to_byte()]),
+                    _ => self.pair(pair),
+                };
+                self.push(bus, value)?;
+            }
+            //RST n
+            0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => {
+                self.push(bus, self.pc)?;
+                self.pc = (opcode & 0x38) as u16;
+            }
+            //JMP a16
+            0xC3 | 0xCB => self.pc = self.fetch_word(bus)?,
+            //RET
+            0xC9 | 0xD9 => self.pc = self.pop(bus)?,
+            //CALL a16
+            0xCD | 0xDD | 0xED | 0xFD => {
+                let addr = self.fetch_word(bus)?;
+                self.push(bus, self.pc)?;
+                self.pc = addr;
+            }
+            //OUT d8
+            0xD3 => {
+                let port = self.fetch(bus)?;
+                ports.output(port, self.a);
+            }
+            //IN d8
+            0xDB => {
+                let port = self.fetch(bus)?;
+                self.a = ports.input(port);
+            }
+            //XTHL
+            0xE3 => {
+                let value = self.pop(bus)?;
+                self.push(bus, self.hl())?;
+                self.set_hl(value);
+            }
+            //PCHL
+            0xE9 => self.pc = self.hl(),
+            //XCHG
+            0xEB => {
+                let de = self.de();
+                self.set_de(self.hl());
+                self.set_hl(de);
+            }
+            //DI
+            0xF3 => self.interrupts_enabled = false,
+            //EI
+            0xFB => self.interrupts_enabled = true,
+            //SPHL
+            0xF9 => self.sp = self.hl(),
+        }
+        Ok(cycles)
+    }
+
+    /// BC, DE, HL or SP by the `rp` bits of the opcode
+    fn pair(&self, pair: u8) -> u16 {
+        match pair {
+            0 => self.bc(),
+            1 => self.de(),
+            2 => self.hl(),
+            _ => self.sp,
+        }
+    }
+
+    fn set_pair(&mut self, pair: u8, value: u16) {
+        match pair {
+            0 => self.set_bc(value),
+            1 => self.set_de(value),
+            2 => self.set_hl(value),
+            _ => self.sp = value,
+        }
+    }
+
+    /// B, C, D, E, H, L, M or A by the register bits of the opcode, M is the byte at HL
+    fn reg(&self, bus: &mut dyn Bus, index: u8) -> Result<u8, Box<dyn Msg>> {
+        Ok(match index {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => self.h,
+            5 => self.l,
+            6 => self.read(bus, self.hl())?,
+            _ => self.a,
+        })
+    }
+
+    fn set_reg(&mut self, bus: &mut dyn Bus, index: u8, value: u8) -> Result<(), Box<dyn Msg>> {
+        match index {
+            0 => self.b = value,
+            1 => self.c = value,
+            2 => self.d = value,
+            3 => self.e = value,
+            4 => self.h = value,
+            5 => self.l = value,
+            6 => self.write(bus, self.hl(), value)?,
+            _ => self.a = value,
+        }
+        Ok(())
+    }
+
+    /// NZ, Z, NC, C, PO, PE, P, M
+    fn condition(&self, index: u8) -> bool {
+        match index {
+            0 => !self.flags.z,
+            1 => self.flags.z,
+            2 => !self.flags.cy,
+            3 => self.flags.cy,
+            4 => !self.flags.p,
+            5 => self.flags.p,
+            6 => !self.flags.s,
+            _ => self.flags.s,
+        }
+    }
+
+    /// ADD, ADC, SUB, SBB, ANA, XRA, ORA, CMP with the accumulator
+    fn alu(&mut self, op: u8, value: u8) {
+        match op {
+            0 => self.a = self.add(value, false),
+            1 => self.a = self.add(value, self.flags.cy),
+            2 => self.a = self.sub(value, false),
+            3 => self.a = self.sub(value, self.flags.cy),
+            4 => {
+                // AND sets AC from bit 3 of the operands
+                self.flags.ac = (self.a | value) & 0x08 != 0;
+                self.a &= value;
+                self.flags.cy = false;
+                self.set_zsp(self.a);
+            }
+            5 => self.logic(self.a ^ value),
+            6 => self.logic(self.a | value),
+            _ => {
+                self.sub(value, false);
+            }
+        }
+    }
+
+    fn add(&mut self, value: u8, carry: bool) -> u8 {
+        let sum = self.a as u16 + value as u16 + carry as u16;
+        self.flags.ac = (self.a & 0x0F) + (value & 0x0F) + carry as u8 > 0x0F;
+        self.flags.cy = sum > 0xFF;
+        self.set_zsp(sum as u8);
+        sum as u8
+    }
+
+    /// The 8080 adds the complement, AC is the carry out of bit 3 of that addition
+    fn sub(&mut self, value: u8, borrow: bool) -> u8 {
+        let result = self.a.wrapping_sub(value).wrapping_sub(borrow as u8);
+        self.flags.ac = (self.a & 0x0F) + (!value & 0x0F) + !borrow as u8 > 0x0F;
+        self.flags.cy = (self.a as u16) < value as u16 + borrow as u16;
+        self.set_zsp(result);
+        result
+    }
+
+    fn logic(&mut self, result: u8) {
+        self.a = result;
+        self.flags.ac = false;
+        self.flags.cy = false;
+        self.set_zsp(result);
+    }
+
+    fn daa(&mut self) {
+        let mut correction = 0;
+        let mut carry = self.flags.cy;
+        if self.flags.ac || self.a & 0x0F > 9 {
+            correction |= 0x06;
+        }
+        if self.flags.cy || self.a > 0x99 {
+            correction |= 0x60;
+            carry = true;
+        }
+        self.a = self.add(correction, false);
+        self.flags.cy = carry;
+    }
+
+    fn set_zsp(&mut self, value: u8) {
+        self.flags.z = value == 0;
+        self.flags.s = value & 0x80 != 0;
+        self.flags.p = value.count_ones() & 1 == 0;
+    }
+
+    fn fetch(&mut self, bus: &mut dyn Bus) -> Result<u8, Box<dyn Msg>> {
+        let value = self.read(bus, self.pc)?;
+        self.pc = self.pc.wrapping_add(1);
+        Ok(value)
+    }
+
+    fn fetch_word(&mut self, bus: &mut dyn Bus) -> Result<u16, Box<dyn Msg>> {
+        let low = self.fetch(bus)?;
+        let high = self.fetch(bus)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn push(&mut self, bus: &mut dyn Bus, value: u16) -> Result<(), Box<dyn Msg>> {
+        let [high, low] = value.to_be_bytes();
+        self.sp = self.sp.wrapping_sub(2);
+        self.write(bus, self.sp.wrapping_add(1), high)?;
+        self.write(bus, self.sp, low)
+    }
+
+    fn pop(&mut self, bus: &mut dyn Bus) -> Result<u16, Box<dyn Msg>> {
+        let low = self.read(bus, self.sp)?;
+        let high = self.read(bus, self.sp.wrapping_add(1))?;
+        self.sp = self.sp.wrapping_add(2);
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn read(&self, bus: &mut dyn Bus, addr: u16) -> Result<u8, Box<dyn Msg>> {
+        bus.read_byte(addr as usize).map_err(|_| self.memory_fault(addr))
+    }
+
+    fn write(&self, bus: &mut dyn Bus, addr: u16, value: u8) -> Result<(), Box<dyn Msg>> {
+        bus.write_byte(addr as usize, value).map_err(|_| self.memory_fault(addr))
+    }
+
+    /// Guest program error, reported with the address and the opcode of the faulting instruction
+    fn memory_fault(&self, addr: u16) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(ErrorTopicId::GuestFault.into(), ErrorMsgId::MemoryFault.into())
+            .add_param(format!("{:#06X}", self.instr_pc))
+            .add_param(format!("{:#04X}", self.opcode))
+            .add_param(format!("{:#06X}", addr));
+        Box::new(err)
+    }
+}
+
+#[cfg(test)]
+mod i8080_tests {
+
+    use super::*;
+    use crate::common::bus::{MappedBus, OpenBus};
+
+    struct Latch(u8);
+
+    impl Ports for Latch {
+        fn input(&mut self, _port: u8) -> u8 {
+            self.0
+        }
+
+        fn output(&mut self, _port: u8, value: u8) {
+            self.0 = value;
+        }
+    }
+
+    fn run(program: &[u8], steps: usize) -> (I8080, MappedBus, u32) {
+        let mut bus = MappedBus::new(OpenBus::Error);
+        bus.map_ram("ram", 0, 0x10000);
+        for (i, byte) in program.iter().enumerate() {
+            bus.write_byte(i, *byte).unwrap();
+        }
+        let mut cpu = I8080::new();
+        let mut ports = Latch(0);
+        let mut cycles = 0;
+        for _ in 0..steps {
+            cycles += cpu.step(&mut bus, &mut ports).unwrap();
+        }
+        (cpu, bus, cycles)
+    }
+
+    #[test]
+    fn test_arithmetic_flags() {
+        // MVI A,0x99; ADI 0x01; DAA
+        let (cpu, _, _) = run(&[0x3E, 0x99, 0xC6, 0x01, 0x27], 3);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.flags.cy && cpu.flags.z && cpu.flags.p);
+        // MVI A,0x10; SUI 0x20
+        let (cpu, _, _) = run(&[0x3E, 0x10, 0xD6, 0x20], 2);
+        assert_eq!(cpu.a, 0xF0);
+        assert!(cpu.flags.cy && cpu.flags.s && !cpu.flags.z);
+        // MVI B,0x0F; INR B
+        let (cpu, _, _) = run(&[0x06, 0x0F, 0x04], 2);
+        assert_eq!(cpu.b, 0x10);
+        assert!(cpu.flags.ac);
+    }
+
+    #[test]
+    fn test_stack_and_calls() {
+        // LXI SP,0x100; MVI A,0x42; STC; PUSH PSW; CALL 0x20; POP B; ... 0x20: RET
+        let mut program = vec![0x31, 0x00, 0x01, 0x3E, 0x42, 0x37, 0xF5, 0xCD, 0x20, 0x00, 0xC1];
+        program.resize(0x20, 0);
+        program.push(0xC9);
+        let (cpu, bus, cycles) = run(&program, 7);
+        assert_eq!(cpu.bc(), 0x4203);
+        assert_eq!(cpu.sp, 0x100);
+        assert_eq!(cpu.pc, 0x0B);
+        assert_eq!(bus.peek(0xFC), Some(0x0A));
+        assert_eq!(cycles, 10 + 7 + 4 + 11 + 17 + 10 + 10);
+    }
+
+    #[test]
+    fn test_interrupts() {
+        // LXI SP,0x100; EI; HLT
+        let (mut cpu, mut bus, _) = run(&[0x31, 0x00, 0x01, 0xFB, 0x76], 3);
+        assert!(cpu.halted);
+        assert_eq!(cpu.interrupt(&mut bus, 2).unwrap(), INTERRUPT_CYCLES);
+        assert_eq!(cpu.pc, 0x10);
+        assert!(!cpu.halted && !cpu.interrupts_enabled);
+        assert_eq!(bus.peek(0xFE), Some(0x05));
+        assert_eq!(cpu.interrupt(&mut bus, 1).unwrap(), 0);
+
+        let mut state = Vec::new();
+        cpu.save(&mut state);
+        assert_eq!(state.len(), STATE_SIZE);
+        let mut restored = I8080::new();
+        restored.load(&state);
+        assert_eq!(restored.pc, cpu.pc);
+        assert_eq!(restored.flags, cpu.flags);
+    }
+}
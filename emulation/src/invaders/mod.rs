@@ -0,0 +1,2 @@
+pub mod system;
+pub mod sound;
\ No newline at end of file
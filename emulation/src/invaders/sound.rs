@@ -0,0 +1,189 @@
+use crate::common::emulator::{FRAME_RATE, SAMPLE_RATE};
+use crate::invaders::system::Sound;
+
+use std::f32::consts::TAU;
+
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE as u64 / FRAME_RATE) as usize;
+const VOLUME: f32 = 6000.0;
+/// Fleet notes and the UFO are quieter than the shots and explosions
+const FLEET_VOLUME: f32 = 0.6;
+
+/// Tone or noise burst standing in for the recorded sound, with a linear pitch sweep
+struct Shape {
+    /// Seconds
+    length: f32,
+    /// Start and end frequency in Hz, 0 for noise
+    pitch: (f32, f32),
+    /// Volume fades out over the length
+    decay: bool,
+    volume: f32,
+}
+
+fn shape(sound: Sound) -> Shape {
+    let (length, pitch, decay, volume) = match sound {
+        Sound::Ufo => (0.0, (0.0, 0.0), false, 0.0),
+        Sound::Shot => (0.25, (1200.0, 200.0), true, 1.0),
+        Sound::PlayerDeath => (1.0, (0.0, 0.0), true, 1.0),
+        Sound::InvaderDeath => (0.3, (0.0, 0.0), true, 1.0),
+        Sound::ExtraLife => (0.6, (880.0, 880.0), false, 0.8),
+        Sound::Fleet1 => (0.1, (98.0, 98.0), false, FLEET_VOLUME),
+        Sound::Fleet2 => (0.1, (87.0, 87.0), false, FLEET_VOLUME),
+        Sound::Fleet3 => (0.1, (78.0, 78.0), false, FLEET_VOLUME),
+        Sound::Fleet4 => (0.1, (73.0, 73.0), false, FLEET_VOLUME),
+        Sound::UfoHit => (1.0, (1000.0, 300.0), true, 1.0),
+    };
+    Shape { length, pitch, decay, volume }
+}
+
+struct Voice {
+    sound: Sound,
+    /// Samples played
+    position: u32,
+    phase: f32,
+}
+
+/// Synthesized stand-ins for the samples of the discrete sound board, the recordings are not shipped.
+/// One-shot sounds start on the latch triggers, the UFO warbles while its latch bit is held.
+pub struct SoundBoard {
+    voices: Vec<Voice>,
+    ufo_position: u32,
+    ufo_phase: f32,
+    /// 15-bit LFSR for the explosions
+    noise: u16,
+    samples: Vec<i16>,
+}
+
+impl Default for SoundBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SoundBoard {
+    pub fn new() -> Self {
+        Self {
+            voices: Vec::new(),
+            ufo_position: 0,
+            ufo_phase: 0.0,
+            noise: 1,
+            samples: Vec::new(),
+        }
+    }
+
+    /// Starts the sound again if it is still playing
+    pub fn trigger(&mut self, sound: Sound) {
+        if sound == Sound::Ufo {
+            return;
+        }
+        self.voices.retain(|voice| voice.sound != sound);
+        self.voices.push(Voice { sound, position: 0, phase: 0.0 });
+    }
+
+    /// Mixes a frame at `SAMPLE_RATE`
+    pub fn render_frame(&mut self, ufo: bool) {
+        self.samples.clear();
+        if self.voices.is_empty() && !ufo {
+            self.ufo_position = 0;
+            return;
+        }
+        for _ in 0..SAMPLES_PER_FRAME {
+            let mut mix = 0.0;
+            for i in 0..self.voices.len() {
+                mix += self.next_voice_sample(i);
+            }
+            if ufo {
+                let t = self.ufo_position as f32 / SAMPLE_RATE as f32;
+                let freq = 500.0 + 100.0 * (TAU * 6.0 * t).sin();
+                self.ufo_phase = (self.ufo_phase + freq / SAMPLE_RATE as f32).fract();
+                self.ufo_position += 1;
+                mix += FLEET_VOLUME * square(self.ufo_phase);
+            }
+            self.samples.push((mix * VOLUME).clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+        }
+        if !ufo {
+            self.ufo_position = 0;
+        }
+        self.voices
+            .retain(|voice| (voice.position as f32) < shape(voice.sound).length * SAMPLE_RATE as f32);
+    }
+
+    /// Samples of the last frame, `None` when silent
+    pub fn samples(&self) -> Option<&[i16]> {
+        match self.samples.is_empty() {
+            true => None,
+            false => Some(&self.samples),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        !self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.voices.clear();
+        self.samples.clear();
+        self.ufo_position = 0;
+    }
+
+    fn next_voice_sample(&mut self, i: usize) -> f32 {
+        let shape = shape(self.voices[i].sound);
+        let progress = self.voices[i].position as f32 / (shape.length * SAMPLE_RATE as f32);
+        if progress >= 1.0 {
+            return 0.0;
+        }
+        self.voices[i].position += 1;
+        let level = if shape.decay { shape.volume * (1.0 - progress) } else { shape.volume };
+        if shape.pitch.0 == 0.0 {
+            return level * self.next_noise();
+        }
+        let voice = &mut self.voices[i];
+        let freq = shape.pitch.0 + (shape.pitch.1 - shape.pitch.0) * progress;
+        voice.phase = (voice.phase + freq / SAMPLE_RATE as f32).fract();
+        level * square(voice.phase)
+    }
+
+    fn next_noise(&mut self) -> f32 {
+        let bit = (self.noise ^ (self.noise >> 1)) & 1;
+        self.noise = (self.noise >> 1) | (bit << 14);
+        if self.noise & 1 != 0 { 1.0 } else { -1.0 }
+    }
+}
+
+fn square(phase: f32) -> f32 {
+    if phase < 0.5 { 1.0 } else { -1.0 }
+}
+
+#[cfg(test)]
+mod sound_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_one_shot() {
+        let mut board = SoundBoard::new();
+        board.render_frame(false);
+        assert_eq!(board.samples(), None);
+        board.trigger(Sound::Fleet1);
+        // 0.1s is six frames
+        for _ in 0..6 {
+            board.render_frame(false);
+            let samples = board.samples().unwrap();
+            assert_eq!(samples.len(), SAMPLES_PER_FRAME);
+            assert!(samples.iter().any(|s| *s != 0));
+        }
+        board.render_frame(false);
+        assert!(!board.is_playing());
+    }
+
+    #[test]
+    fn test_ufo_held() {
+        let mut board = SoundBoard::new();
+        board.trigger(Sound::Ufo);
+        board.render_frame(false);
+        assert!(!board.is_playing());
+        for _ in 0..100 {
+            board.render_frame(true);
+            assert!(board.is_playing());
+        }
+    }
+}
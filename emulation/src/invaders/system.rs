@@ -0,0 +1,488 @@
+use crate::common::bus::{MappedBus, OpenBus};
+use crate::common::emulator::*;
+use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::registry::{Capabilities, SystemInfo};
+use crate::common::utils;
+use crate::common::vram::Vram;
+use crate::i8080::cpu::{self, I8080, Ports};
+use crate::invaders::sound::SoundBoard;
+
+/// 19.968MHz crystal divided by 10
+const CLOCK: u64 = 1_996_800;
+const CYCLES_PER_FRAME: u64 = CLOCK / FRAME_RATE;
+const ROM_REGION: &str = "rom";
+const ROM_SIZE: usize = 0x2000;
+const RAM_REGION: &str = "ram";
+const RAM_START: usize = 0x2000;
+const RAM_SIZE: usize = 0x2000;
+/// Offset of the framebuffer inside the RAM region
+const VIDEO_OFFSET: usize = 0x0400;
+/// The address decoder ignores A14 and A15, RAM repeats up to the end of the address space
+const ADDRESS_SPACE: usize = 0x10000;
+/// The monitor is rotated, the 256x224 framebuffer is shown as 224x256
+const WIDTH: usize = 224;
+const HEIGHT: usize = 256;
+/// RST 1 when the beam reaches the middle of the screen, RST 2 at vblank
+const RST_MID_SCREEN: u8 = 1;
+const RST_VBLANK: u8 = 2;
+/// Save state header, followed by the format version
+const STATE_MAGIC: &[u8; 4] = b"OXSI";
+const STATE_VERSION: u8 = 1;
+const HARDWARE_STATE_SIZE: usize = 7;
+
+/// Colours of the cellophane overlay on the monitor
+const WHITE: u8 = 1;
+const GREEN: u8 = 2;
+const RED: u8 = 3;
+
+/// Sounds of the discrete sound board, triggered on the rising edge of the latch bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sound {
+    Ufo,
+    Shot,
+    PlayerDeath,
+    InvaderDeath,
+    ExtraLife,
+    Fleet1,
+    Fleet2,
+    Fleet3,
+    Fleet4,
+    UfoHit,
+}
+
+/// Input port and bit of each `InvadersKeys`
+const KEY_BITS: [(u8, u8); 10] = [(1, 0), (1, 2), (1, 1), (1, 4), (1, 5), (1, 6), (2, 4), (2, 5), (2, 6), (2, 2)];
+
+/// Sounds of the port 3 and port 5 latches, by bit
+const PORT3_SOUNDS: [Sound; 5] = [Sound::Ufo, Sound::Shot, Sound::PlayerDeath, Sound::InvaderDeath, Sound::ExtraLife];
+const PORT5_SOUNDS: [Sound; 5] = [Sound::Fleet1, Sound::Fleet2, Sound::Fleet3, Sound::Fleet4, Sound::UfoHit];
+
+/// Everything behind the IN/OUT ports: inputs, the shift register and the sound latches
+struct Hardware {
+    /// Bit 3 always reads 1
+    port1: u8,
+    /// Low bits and bit 7 are DIP switches: 3 ships, extra ship at 1500, coin info shown
+    port2: u8,
+    shift: u16,
+    shift_offset: u8,
+    port3: u8,
+    port5: u8,
+    triggers: Vec<Sound>,
+}
+
+impl Hardware {
+    fn new() -> Self {
+        Self {
+            port1: 0x08,
+            port2: 0x00,
+            shift: 0,
+            shift_offset: 0,
+            port3: 0,
+            port5: 0,
+            triggers: Vec::new(),
+        }
+    }
+
+    fn latch_sounds(&mut self, old: u8, new: u8, sounds: &[Sound; 5]) {
+        let rising = !old & new;
+        for (bit, sound) in sounds.iter().enumerate() {
+            if rising & (1 << bit) != 0 {
+                self.triggers.push(*sound);
+            }
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.port1, self.port2]);
+        state.extend_from_slice(&self.shift.to_be_bytes());
+        state.extend_from_slice(&[self.shift_offset, self.port3, self.port5]);
+    }
+
+    fn load(&mut self, state: &[u8]) {
+        self.port1 = state[0];
+        self.port2 = state[1];
+        self.shift = u16::from_be_bytes([state[2], state[3]]);
+        self.shift_offset = state[4];
+        self.port3 = state[5];
+        self.port5 = state[6];
+        self.triggers.clear();
+    }
+}
+
+impl Ports for Hardware {
+    fn input(&mut self, port: u8) -> u8 {
+        match port {
+            0 => 0x0E,
+            1 => self.port1,
+            2 => self.port2,
+            3 => (self.shift >> (8 - self.shift_offset)) as u8,
+            _ => 0,
+        }
+    }
+
+    fn output(&mut self, port: u8, value: u8) {
+        match port {
+            2 => self.shift_offset = value & 0x07,
+            3 => {
+                self.latch_sounds(self.port3, value, &PORT3_SOUNDS);
+                self.port3 = value;
+            }
+            4 => self.shift = self.shift >> 8 | (value as u16) << 8,
+            5 => {
+                self.latch_sounds(self.port5, value, &PORT5_SOUNDS);
+                self.port5 = value;
+            }
+            // watchdog, not emulated
+            _ => {}
+        }
+    }
+}
+
+/// Space Invaders (Taito/Midway, 1978) arcade board
+pub struct Invaders {
+    cpu: I8080,
+    memory: MappedBus,
+    hardware: Hardware,
+    sound: SoundBoard,
+    video_memory: Vram,
+    /// Clock cycles since the last vblank
+    frame_cycles: u64,
+    mid_screen_done: bool,
+    cycle_count: u128,
+    active: bool,
+}
+
+impl Default for Invaders {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Invaders {
+    pub fn new() -> Self {
+        Invaders {
+            cpu: I8080::new(),
+            memory: Invaders::init_memory(),
+            hardware: Hardware::new(),
+            sound: SoundBoard::new(),
+            video_memory: Vram::new(WIDTH, HEIGHT),
+            frame_cycles: 0,
+            mid_screen_done: false,
+            cycle_count: 0,
+            active: false,
+        }
+    }
+
+    /// The ROM set concatenated into one 8K file, recognised by its size and the jump at the reset vector
+    pub fn system_info() -> SystemInfo {
+        SystemInfo {
+            name: "Space Invaders",
+            extensions: &["inv"],
+            capabilities: Capabilities {
+                save_states: true,
+                reset: true,
+                sound: true,
+            },
+            factory: || Box::new(Invaders::new()),
+            sniff: Some(|rom| rom.len() == ROM_SIZE && rom[..4] == [0x00, 0x00, 0x00, 0xC3]),
+        }
+    }
+
+    pub fn cpu(&self) -> &I8080 {
+        &self.cpu
+    }
+
+    fn init_memory() -> MappedBus {
+        let mut memory = MappedBus::new(OpenBus::Error);
+        memory.map_rom(ROM_REGION, 0, vec![0u8; ROM_SIZE]);
+        memory.map_ram(RAM_REGION, RAM_START, RAM_SIZE);
+        memory.map_mirror("ram mirror", RAM_START + RAM_SIZE, ADDRESS_SPACE - RAM_START - RAM_SIZE, RAM_START, RAM_SIZE);
+        memory
+    }
+
+    pub fn load_rom(&mut self, file_name: &str) {
+        let rom = match utils::load_rom(file_name) {
+            Ok(rom) => rom,
+            Err(error) => {
+                log::error!(file = file_name; "Cannot load ROM: {}", error);
+                return;
+            }
+        };
+        let load_res = match self.memory.ram_mut(ROM_REGION) {
+            Some(storage) => storage.write_block(0, rom),
+            None => Ok(()),
+        };
+        match load_res {
+            Err(error) => log::error!(file = file_name; "Cannot write ROM to memory: {}", error),
+            _ => self.active = true,
+        }
+    }
+
+    /// Raises the beam interrupts at the middle and at the end of the frame, adds their cycles to `cycles`.
+    /// Returns whether the frame ended
+    fn update_beam(&mut self, cycles: &mut u32) -> Result<bool, Box<dyn Msg>> {
+        self.frame_cycles += *cycles as u64;
+        if !self.mid_screen_done && self.frame_cycles >= CYCLES_PER_FRAME / 2 {
+            self.mid_screen_done = true;
+            let taken = self.cpu.interrupt(&mut self.memory, RST_MID_SCREEN)?;
+            self.frame_cycles += taken as u64;
+            *cycles += taken;
+        }
+        if self.frame_cycles < CYCLES_PER_FRAME {
+            return Ok(false);
+        }
+        self.frame_cycles -= CYCLES_PER_FRAME;
+        self.mid_screen_done = false;
+        self.render();
+        let taken = self.cpu.interrupt(&mut self.memory, RST_VBLANK)?;
+        self.frame_cycles += taken as u64;
+        *cycles += taken;
+        Ok(true)
+    }
+
+    /// Framebuffer bytes hold 8 pixels of a column from the bottom up, LSB first
+    fn render(&mut self) {
+        let video = match self.memory.ram(RAM_REGION) {
+            Some(ram) => &ram.data()[VIDEO_OFFSET..],
+            None => return,
+        };
+        for (i, byte) in video.iter().enumerate() {
+            let x = i / 32;
+            let y_base = (i % 32) * 8;
+            for bit in 0..8 {
+                let y = HEIGHT - 1 - (y_base + bit);
+                let pixel = if byte >> bit & 1 != 0 { Invaders::overlay(x, y) } else { 0 };
+                self.video_memory.write_pixel(y * WIDTH + x, pixel);
+            }
+        }
+    }
+
+    /// Red band for the UFO, green for the bases, the player and the lives left
+    fn overlay(x: usize, y: usize) -> u8 {
+        match y {
+            32..=63 => RED,
+            184..=239 => GREEN,
+            240..=255 if (16..134).contains(&x) => GREEN,
+            _ => WHITE,
+        }
+    }
+
+    /// CPU, RAM, the port latches and the beam position; the ROM is loaded again from the file
+    fn write_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(RAM_SIZE + 64);
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+        self.cpu.save(&mut state);
+        self.hardware.save(&mut state);
+        if let Some(ram) = self.memory.ram(RAM_REGION) {
+            state.extend_from_slice(ram.data());
+        }
+        state.extend_from_slice(&self.frame_cycles.to_be_bytes());
+        state.extend_from_slice(&[self.mid_screen_done as u8, self.active as u8]);
+        state
+    }
+
+    fn read_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        let expected = STATE_MAGIC.len() + 1 + cpu::STATE_SIZE + HARDWARE_STATE_SIZE + RAM_SIZE + 10;
+        if state.len() != expected || &state[..4] != STATE_MAGIC || state[4] != STATE_VERSION {
+            let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::InvalidFormat.into())
+                .add_param(state.len().to_string());
+            return Err(Box::new(err));
+        }
+        let (cpu, rest) = state[5..].split_at(cpu::STATE_SIZE);
+        let (hardware, rest) = rest.split_at(HARDWARE_STATE_SIZE);
+        let (ram, rest) = rest.split_at(RAM_SIZE);
+        if let Some(storage) = self.memory.ram_mut(RAM_REGION) {
+            storage.load(ram)?;
+        }
+        self.cpu.load(cpu);
+        self.hardware.load(hardware);
+        self.frame_cycles = u64::from_be_bytes([rest[0], rest[1], rest[2], rest[3], rest[4], rest[5], rest[6], rest[7]]);
+        self.mid_screen_done = rest[8] != 0;
+        self.active = rest[9] != 0;
+        self.render();
+        Ok(())
+    }
+}
+
+impl Emulator for Invaders {
+    fn video_buffer(&self) -> &[u8] {
+        self.video_memory.video()
+    }
+
+    fn palette(&self) -> Palette {
+        Palette::new("Overlay", vec![0x000000FF, 0xFFFFFFFF, 0x20FF20FF, 0xFF2020FF])
+    }
+
+    fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
+        if !self.active {
+            return Ok(CycleResult::default());
+        }
+        let mut cycles = self.cpu.step(&mut self.memory, &mut self.hardware)?;
+        let video_buff_changed = self.update_beam(&mut cycles)?;
+        self.cycle_count += cycles as u128;
+        Ok(CycleResult {
+            video_buff_changed,
+            total_cycle_count: self.cycle_count,
+            last_cycle_count: cycles as u128,
+        })
+    }
+
+    /// The interrupts follow the beam in `cycle`, the frame end only mixes the sounds triggered during it
+    fn end_frame(&mut self) {
+        for sound in self.hardware.triggers.drain(..) {
+            self.sound.trigger(sound);
+        }
+        self.sound.render_frame(self.hardware.port3 & 0x01 != 0);
+    }
+
+    fn process_input(&mut self, key: u32, pressed: bool) {
+        let (port, bit) = match KEY_BITS.get(key as usize) {
+            Some(key_bit) => *key_bit,
+            None => return,
+        };
+        let latch = if port == 1 { &mut self.hardware.port1 } else { &mut self.hardware.port2 };
+        if pressed {
+            *latch |= 1 << bit;
+        } else {
+            *latch &= !(1 << bit);
+        }
+    }
+
+    fn load_rom(&mut self, file_name: &String) {
+        self.load_rom(file_name);
+    }
+
+    fn resolution(&self) -> [u32; 2] {
+        [WIDTH as u32, HEIGHT as u32]
+    }
+
+    fn cycles_in_sec(&self) -> u64 {
+        CLOCK
+    }
+
+    fn sound_active(&self) -> bool {
+        self.sound.is_playing()
+    }
+
+    fn audio_samples(&self) -> Option<&[i16]> {
+        self.sound.samples()
+    }
+
+    fn reset(&mut self) {
+        self.cpu = I8080::new();
+        self.hardware = Hardware::new();
+        self.sound.clear();
+        self.video_memory.clear();
+        self.frame_cycles = 0;
+        self.mid_screen_done = false;
+    }
+
+    fn power_cycle(&mut self) {
+        self.memory = Invaders::init_memory();
+        self.reset();
+        self.cycle_count = 0;
+        self.active = false;
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, Box<dyn Msg>> {
+        Ok(self.write_state())
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        self.read_state(state)
+    }
+}
+
+pub enum InvadersKeys {
+    Coin = 0,
+    P1Start = 1,
+    P2Start = 2,
+    P1Fire = 3,
+    P1Left = 4,
+    P1Right = 5,
+    P2Fire = 6,
+    P2Left = 7,
+    P2Right = 8,
+    Tilt = 9,
+}
+
+#[cfg(test)]
+mod invaders_tests {
+
+    use super::*;
+    use crate::common::bus::Bus;
+
+    fn with_program(program: &[u8]) -> Invaders {
+        let mut invaders = Invaders::new();
+        let mut rom = program.to_vec();
+        rom.resize(ROM_SIZE, 0);
+        invaders.memory.ram_mut(ROM_REGION).unwrap().write_block(0, rom).unwrap();
+        invaders.active = true;
+        invaders
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut hardware = Hardware::new();
+        hardware.output(4, 0xAB);
+        hardware.output(4, 0xCD);
+        hardware.output(2, 0);
+        assert_eq!(hardware.input(3), 0xCD);
+        hardware.output(2, 4);
+        assert_eq!(hardware.input(3), 0xDA);
+        hardware.output(3, 0x02);
+        hardware.output(3, 0x03);
+        assert_eq!(hardware.triggers, vec![Sound::Shot, Sound::Ufo]);
+    }
+
+    #[test]
+    fn test_sound_triggers() {
+        let mut invaders = with_program(&[]);
+        invaders.end_frame();
+        assert_eq!(invaders.audio_samples(), None);
+        invaders.hardware.output(3, 0x02);
+        invaders.end_frame();
+        assert!(invaders.hardware.triggers.is_empty());
+        assert!(invaders.sound_active());
+        assert!(invaders.audio_samples().unwrap().iter().any(|s| *s != 0));
+    }
+
+    #[test]
+    fn test_beam_interrupts() {
+        // LXI SP,0x2400; EI; JMP 4 ... 0x08: MVI A,1; STA 0x2400; EI; RET ... 0x10: EI; RET
+        let mut program = vec![0x31, 0x00, 0x24, 0xFB, 0xC3, 0x04, 0x00, 0x00];
+        program.extend_from_slice(&[0x3E, 0x01, 0x32, 0x00, 0x24, 0xFB, 0xC9, 0x00]);
+        program.extend_from_slice(&[0xFB, 0xC9]);
+        let mut invaders = with_program(&program);
+        let mut cycles = 0;
+        let mut frames = 0;
+        while cycles < CYCLES_PER_FRAME {
+            let res = invaders.cycle().unwrap();
+            cycles += res.last_cycle_count as u64;
+            frames += res.video_buff_changed as u32;
+        }
+        assert_eq!(frames, 1);
+        assert_eq!(invaders.memory.peek(0x2400), Some(0x01));
+        // the first framebuffer byte is the bottom of the leftmost column
+        invaders.render();
+        assert_eq!(invaders.video_buffer()[(HEIGHT - 1) * WIDTH], WHITE);
+        assert_eq!(invaders.memory.peek(0x6400), Some(0x01));
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut invaders = with_program(&[0x3E, 0x42, 0xC3, 0x02, 0x00]);
+        invaders.process_input(InvadersKeys::Coin as u32, true);
+        for _ in 0..10 {
+            invaders.cycle().unwrap();
+        }
+        let state = invaders.save_state().unwrap();
+        let mut restored = with_program(&[]);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.cpu().a, 0x42);
+        assert_eq!(restored.hardware.input(1), 0x09);
+        assert!(restored.load_state(&state[1..]).is_err());
+    }
+}
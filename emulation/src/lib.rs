@@ -1,2 +1,8 @@
 pub mod common;
-pub mod chip8;
\ No newline at end of file
+pub mod chip8;
+pub mod i8080;
+pub mod invaders;
+pub mod gb;
+pub mod mos6502;
+pub mod nes;
+pub mod atari2600;
\ No newline at end of file
@@ -0,0 +1,853 @@
+use crate::common::bus::Bus;
+use crate::common::message::*;
+
+pub const CARRY: u8 = 0x01;
+pub const ZERO: u8 = 0x02;
+pub const IRQ_DISABLE: u8 = 0x04;
+pub const DECIMAL: u8 = 0x08;
+pub const BREAK: u8 = 0x10;
+/// Always reads as set
+pub const UNUSED: u8 = 0x20;
+pub const OVERFLOW: u8 = 0x40;
+pub const NEGATIVE: u8 = 0x80;
+
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+const STACK_PAGE: u16 = 0x0100;
+const INTERRUPT_CYCLES: u32 = 7;
+
+/// Chips built around the 6502 core
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    /// MOS 6502 and 6507, with decimal mode
+    Nmos,
+    /// NES CPU, the D flag is kept but ADC and SBC stay binary
+    Ricoh2A03,
+}
+
+/// Instructions, the unofficial ones follow the nestest names
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Adc, And, Asl, Bcc, Bcs, Beq, Bit, Bmi, Bne, Bpl, Brk, Bvc, Bvs, Clc,
+    Cld, Cli, Clv, Cmp, Cpx, Cpy, Dec, Dex, Dey, Eor, Inc, Inx, Iny, Jmp,
+    Jsr, Lda, Ldx, Ldy, Lsr, Nop, Ora, Pha, Php, Pla, Plp, Rol, Ror, Rti,
+    Rts, Sbc, Sec, Sed, Sei, Sta, Stx, Sty, Tax, Tay, Tsx, Txa, Txs, Tya,
+    Lax, Sax, Dcp, Isb, Slo, Rla, Sre, Rra, Anc, Alr, Arr, Axs,
+    /// Jams and the unstable opcodes
+    Unknown,
+}
+
+impl Op {
+    /// Read instructions take a cycle more when indexing crosses a page
+    fn page_penalty(&self) -> bool {
+        matches!(
+            self,
+            Op::Adc | Op::And | Op::Cmp | Op::Eor | Op::Lda | Op::Ldx | Op::Ldy | Op::Ora | Op::Sbc | Op::Lax | Op::Nop
+        )
+    }
+
+    fn unofficial(&self, opcode: u8) -> bool {
+        match self {
+            Op::Nop => opcode != 0xEA,
+            Op::Sbc => opcode == 0xEB,
+            Op::Lax | Op::Sax | Op::Dcp | Op::Isb | Op::Slo | Op::Rla | Op::Sre | Op::Rra => true,
+            Op::Anc | Op::Alr | Op::Arr | Op::Axs | Op::Unknown => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Imp, Acc, Imm, Zp, Zpx, Zpy, Abs, Abx, Aby, Ind, Izx, Izy, Rel,
+}
+
+impl Mode {
+    fn operand_len(&self) -> u16 {
+        match self {
+            Mode::Imp | Mode::Acc => 0,
+            Mode::Abs | Mode::Abx | Mode::Aby | Mode::Ind => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Instruction, addressing mode and base clock cycles of each opcode
+const OPCODES: [(Op, Mode, u8); 256] = [
+    (Op::Brk, Mode::Imp, 7), (Op::Ora, Mode::Izx, 6), (Op::Unknown, Mode::Imp, 2), (Op::Slo, Mode::Izx, 8), // 0x00
+    (Op::Nop, Mode::Zp, 3), (Op::Ora, Mode::Zp, 3), (Op::Asl, Mode::Zp, 5), (Op::Slo, Mode::Zp, 5), // 0x04
+    (Op::Php, Mode::Imp, 3), (Op::Ora, Mode::Imm, 2), (Op::Asl, Mode::Acc, 2), (Op::Anc, Mode::Imm, 2), // 0x08
+    (Op::Nop, Mode::Abs, 4), (Op::Ora, Mode::Abs, 4), (Op::Asl, Mode::Abs, 6), (Op::Slo, Mode::Abs, 6), // 0x0C
+    (Op::Bpl, Mode::Rel, 2), (Op::Ora, Mode::Izy, 5), (Op::Unknown, Mode::Imp, 2), (Op::Slo, Mode::Izy, 8), // 0x10
+    (Op::Nop, Mode::Zpx, 4), (Op::Ora, Mode::Zpx, 4), (Op::Asl, Mode::Zpx, 6), (Op::Slo, Mode::Zpx, 6), // 0x14
+    (Op::Clc, Mode::Imp, 2), (Op::Ora, Mode::Aby, 4), (Op::Nop, Mode::Imp, 2), (Op::Slo, Mode::Aby, 7), // 0x18
+    (Op::Nop, Mode::Abx, 4), (Op::Ora, Mode::Abx, 4), (Op::Asl, Mode::Abx, 7), (Op::Slo, Mode::Abx, 7), // 0x1C
+    (Op::Jsr, Mode::Abs, 6), (Op::And, Mode::Izx, 6), (Op::Unknown, Mode::Imp, 2), (Op::Rla, Mode::Izx, 8), // 0x20
+    (Op::Bit, Mode::Zp, 3), (Op::And, Mode::Zp, 3), (Op::Rol, Mode::Zp, 5), (Op::Rla, Mode::Zp, 5), // 0x24
+    (Op::Plp, Mode::Imp, 4), (Op::And, Mode::Imm, 2), (Op::Rol, Mode::Acc, 2), (Op::Anc, Mode::Imm, 2), // 0x28
+    (Op::Bit, Mode::Abs, 4), (Op::And, Mode::Abs, 4), (Op::Rol, Mode::Abs, 6), (Op::Rla, Mode::Abs, 6), // 0x2C
+    (Op::Bmi, Mode::Rel, 2), (Op::And, Mode::Izy, 5), (Op::Unknown, Mode::Imp, 2), (Op::Rla, Mode::Izy, 8), // 0x30
+    (Op::Nop, Mode::Zpx, 4), (Op::And, Mode::Zpx, 4), (Op::Rol, Mode::Zpx, 6), (Op::Rla, Mode::Zpx, 6), // 0x34
+    (Op::Sec, Mode::Imp, 2), (Op::And, Mode::Aby, 4), (Op::Nop, Mode::Imp, 2), (Op::Rla, Mode::Aby, 7), // 0x38
+    (Op::Nop, Mode::Abx, 4), (Op::And, Mode::Abx, 4), (Op::Rol, Mode::Abx, 7), (Op::Rla, Mode::Abx, 7), // 0x3C
+    (Op::Rti, Mode::Imp, 6), (Op::Eor, Mode::Izx, 6), (Op::Unknown, Mode::Imp, 2), (Op::Sre, Mode::Izx, 8), // 0x40
+    (Op::Nop, Mode::Zp, 3), (Op::Eor, Mode::Zp, 3), (Op::Lsr, Mode::Zp, 5), (Op::Sre, Mode::Zp, 5), // 0x44
+    (Op::Pha, Mode::Imp, 3), (Op::Eor, Mode::Imm, 2), (Op::Lsr, Mode::Acc, 2), (Op::Alr, Mode::Imm, 2), // 0x48
+    (Op::Jmp, Mode::Abs, 3), (Op::Eor, Mode::Abs, 4), (Op::Lsr, Mode::Abs, 6), (Op::Sre, Mode::Abs, 6), // 0x4C
+    (Op::Bvc, Mode::Rel, 2), (Op::Eor, Mode::Izy, 5), (Op::Unknown, Mode::Imp, 2), (Op::Sre, Mode::Izy, 8), // 0x50
+    (Op::Nop, Mode::Zpx, 4), (Op::Eor, Mode::Zpx, 4), (Op::Lsr, Mode::Zpx, 6), (Op::Sre, Mode::Zpx, 6), // 0x54
+    (Op::Cli, Mode::Imp, 2), (Op::Eor, Mode::Aby, 4), (Op::Nop, Mode::Imp, 2), (Op::Sre, Mode::Aby, 7), // 0x58
+    (Op::Nop, Mode::Abx, 4), (Op::Eor, Mode::Abx, 4), (Op::Lsr, Mode::Abx, 7), (Op::Sre, Mode::Abx, 7), // 0x5C
+    (Op::Rts, Mode::Imp, 6), (Op::Adc, Mode::Izx, 6), (Op::Unknown, Mode::Imp, 2), (Op::Rra, Mode::Izx, 8), // 0x60
+    (Op::Nop, Mode::Zp, 3), (Op::Adc, Mode::Zp, 3), (Op::Ror, Mode::Zp, 5), (Op::Rra, Mode::Zp, 5), // 0x64
+    (Op::Pla, Mode::Imp, 4), (Op::Adc, Mode::Imm, 2), (Op::Ror, Mode::Acc, 2), (Op::Arr, Mode::Imm, 2), // 0x68
+    (Op::Jmp, Mode::Ind, 5), (Op::Adc, Mode::Abs, 4), (Op::Ror, Mode::Abs, 6), (Op::Rra, Mode::Abs, 6), // 0x6C
+    (Op::Bvs, Mode::Rel, 2), (Op::Adc, Mode::Izy, 5), (Op::Unknown, Mode::Imp, 2), (Op::Rra, Mode::Izy, 8), // 0x70
+    (Op::Nop, Mode::Zpx, 4), (Op::Adc, Mode::Zpx, 4), (Op::Ror, Mode::Zpx, 6), (Op::Rra, Mode::Zpx, 6), // 0x74
+    (Op::Sei, Mode::Imp, 2), (Op::Adc, Mode::Aby, 4), (Op::Nop, Mode::Imp, 2), (Op::Rra, Mode::Aby, 7), // 0x78
+    (Op::Nop, Mode::Abx, 4), (Op::Adc, Mode::Abx, 4), (Op::Ror, Mode::Abx, 7), (Op::Rra, Mode::Abx, 7), // 0x7C
+    (Op::Nop, Mode::Imm, 2), (Op::Sta, Mode::Izx, 6), (Op::Nop, Mode::Imm, 2), (Op::Sax, Mode::Izx, 6), // 0x80
+    (Op::Sty, Mode::Zp, 3), (Op::Sta, Mode::Zp, 3), (Op::Stx, Mode::Zp, 3), (Op::Sax, Mode::Zp, 3), // 0x84
+    (Op::Dey, Mode::Imp, 2), (Op::Nop, Mode::Imm, 2), (Op::Txa, Mode::Imp, 2), (Op::Unknown, Mode::Imp, 2), // 0x88
+    (Op::Sty, Mode::Abs, 4), (Op::Sta, Mode::Abs, 4), (Op::Stx, Mode::Abs, 4), (Op::Sax, Mode::Abs, 4), // 0x8C
+    (Op::Bcc, Mode::Rel, 2), (Op::Sta, Mode::Izy, 6), (Op::Unknown, Mode::Imp, 2), (Op::Unknown, Mode::Imp, 2), // 0x90
+    (Op::Sty, Mode::Zpx, 4), (Op::Sta, Mode::Zpx, 4), (Op::Stx, Mode::Zpy, 4), (Op::Sax, Mode::Zpy, 4), // 0x94
+    (Op::Tya, Mode::Imp, 2), (Op::Sta, Mode::Aby, 5), (Op::Txs, Mode::Imp, 2), (Op::Unknown, Mode::Imp, 2), // 0x98
+    (Op::Unknown, Mode::Imp, 2), (Op::Sta, Mode::Abx, 5), (Op::Unknown, Mode::Imp, 2), (Op::Unknown, Mode::Imp, 2), // 0x9C
+    (Op::Ldy, Mode::Imm, 2), (Op::Lda, Mode::Izx, 6), (Op::Ldx, Mode::Imm, 2), (Op::Lax, Mode::Izx, 6), // 0xA0
+    (Op::Ldy, Mode::Zp, 3), (Op::Lda, Mode::Zp, 3), (Op::Ldx, Mode::Zp, 3), (Op::Lax, Mode::Zp, 3), // 0xA4
+    (Op::Tay, Mode::Imp, 2), (Op::Lda, Mode::Imm, 2), (Op::Tax, Mode::Imp, 2), (Op::Unknown, Mode::Imp, 2), // 0xA8
+    (Op::Ldy, Mode::Abs, 4), (Op::Lda, Mode::Abs, 4), (Op::Ldx, Mode::Abs, 4), (Op::Lax, Mode::Abs, 4), // 0xAC
+    (Op::Bcs, Mode::Rel, 2), (Op::Lda, Mode::Izy, 5), (Op::Unknown, Mode::Imp, 2), (Op::Lax, Mode::Izy, 5), // 0xB0
+    (Op::Ldy, Mode::Zpx, 4), (Op::Lda, Mode::Zpx, 4), (Op::Ldx, Mode::Zpy, 4), (Op::Lax, Mode::Zpy, 4), // 0xB4
+    (Op::Clv, Mode::Imp, 2), (Op::Lda, Mode::Aby, 4), (Op::Tsx, Mode::Imp, 2), (Op::Unknown, Mode::Imp, 2), // 0xB8
+    (Op::Ldy, Mode::Abx, 4), (Op::Lda, Mode::Abx, 4), (Op::Ldx, Mode::Aby, 4), (Op::Lax, Mode::Aby, 4), // 0xBC
+    (Op::Cpy, Mode::Imm, 2), (Op::Cmp, Mode::Izx, 6), (Op::Nop, Mode::Imm, 2), (Op::Dcp, Mode::Izx, 8), // 0xC0
+    (Op::Cpy, Mode::Zp, 3), (Op::Cmp, Mode::Zp, 3), (Op::Dec, Mode::Zp, 5), (Op::Dcp, Mode::Zp, 5), // 0xC4
+    (Op::Iny, Mode::Imp, 2), (Op::Cmp, Mode::Imm, 2), (Op::Dex, Mode::Imp, 2), (Op::Axs, Mode::Imm, 2), // 0xC8
+    (Op::Cpy, Mode::Abs, 4), (Op::Cmp, Mode::Abs, 4), (Op::Dec, Mode::Abs, 6), (Op::Dcp, Mode::Abs, 6), // 0xCC
+    (Op::Bne, Mode::Rel, 2), (Op::Cmp, Mode::Izy, 5), (Op::Unknown, Mode::Imp, 2), (Op::Dcp, Mode::Izy, 8), // 0xD0
+    (Op::Nop, Mode::Zpx, 4), (Op::Cmp, Mode::Zpx, 4), (Op::Dec, Mode::Zpx, 6), (Op::Dcp, Mode::Zpx, 6), // 0xD4
+    (Op::Cld, Mode::Imp, 2), (Op::Cmp, Mode::Aby, 4), (Op::Nop, Mode::Imp, 2), (Op::Dcp, Mode::Aby, 7), // 0xD8
+    (Op::Nop, Mode::Abx, 4), (Op::Cmp, Mode::Abx, 4), (Op::Dec, Mode::Abx, 7), (Op::Dcp, Mode::Abx, 7), // 0xDC
+    (Op::Cpx, Mode::Imm, 2), (Op::Sbc, Mode::Izx, 6), (Op::Nop, Mode::Imm, 2), (Op::Isb, Mode::Izx, 8), // 0xE0
+    (Op::Cpx, Mode::Zp, 3), (Op::Sbc, Mode::Zp, 3), (Op::Inc, Mode::Zp, 5), (Op::Isb, Mode::Zp, 5), // 0xE4
+    (Op::Inx, Mode::Imp, 2), (Op::Sbc, Mode::Imm, 2), (Op::Nop, Mode::Imp, 2), (Op::Sbc, Mode::Imm, 2), // 0xE8
+    (Op::Cpx, Mode::Abs, 4), (Op::Sbc, Mode::Abs, 4), (Op::Inc, Mode::Abs, 6), (Op::Isb, Mode::Abs, 6), // 0xEC
+    (Op::Beq, Mode::Rel, 2), (Op::Sbc, Mode::Izy, 5), (Op::Unknown, Mode::Imp, 2), (Op::Isb, Mode::Izy, 8), // 0xF0
+    (Op::Nop, Mode::Zpx, 4), (Op::Sbc, Mode::Zpx, 4), (Op::Inc, Mode::Zpx, 6), (Op::Isb, Mode::Zpx, 6), // 0xF4
+    (Op::Sed, Mode::Imp, 2), (Op::Sbc, Mode::Aby, 4), (Op::Nop, Mode::Imp, 2), (Op::Isb, Mode::Aby, 7), // 0xF8
+    (Op::Nop, Mode::Abx, 4), (Op::Sbc, Mode::Abx, 4), (Op::Inc, Mode::Abx, 7), (Op::Isb, Mode::Abx, 7), // 0xFC
+];
+
+/// Effective address of the operand
+struct Operand {
+    addr: u16,
+    page_crossed: bool,
+}
+
+/// MOS 6502 family, registers are public for the machines, debuggers and tests
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mos6502 {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub p: u8,
+    /// Clock cycles since power on, the reset sequence and stalls included
+    pub cycles: u64,
+    variant: Variant,
+    nmi_pending: bool,
+    irq_line: bool,
+    opcode: u8,
+    /// Address of the instruction being executed, for fault reports
+    instr_pc: u16,
+}
+
+impl Mos6502 {
+    /// Power-on state, `reset` fetches the start address
+    pub fn new(variant: Variant) -> Self {
+        Self {
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0,
+            pc: 0,
+            p: UNUSED,
+            cycles: 0,
+            variant,
+            nmi_pending: false,
+            irq_line: false,
+            opcode: 0,
+            instr_pc: 0,
+        }
+    }
+
+    /// The reset sequence: three dummy pushes, interrupts off and a jump through the reset vector
+    pub fn reset(&mut self, bus: &mut dyn Bus) -> Result<(), Box<dyn Msg>> {
+        self.sp = self.sp.wrapping_sub(3);
+        self.p |= IRQ_DISABLE;
+        self.pc = self.read_word(bus, RESET_VECTOR)?;
+        self.nmi_pending = false;
+        self.cycles += INTERRUPT_CYCLES as u64;
+        Ok(())
+    }
+
+    /// Edge triggered, taken before the next instruction
+    pub fn nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    /// Level of the IRQ line, the devices of the machine ORed together
+    pub fn set_irq(&mut self, active: bool) {
+        self.irq_line = active;
+    }
+
+    /// Executes one instruction or enters an interrupt, returns the clock cycles it took
+    pub fn step(&mut self, bus: &mut dyn Bus) -> Result<u32, Box<dyn Msg>> {
+        self.instr_pc = self.pc;
+        let res = if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(bus, NMI_VECTOR)
+        } else if self.irq_line && self.p & IRQ_DISABLE == 0 {
+            self.interrupt(bus, IRQ_VECTOR)
+        } else {
+            self.execute(bus)
+        };
+        match res {
+            Ok(cycles) => self.cycles += cycles as u64,
+            // leave PC on the faulting instruction for the debugger
+            Err(_) => self.pc = self.instr_pc,
+        }
+        res
+    }
+
+    /// The next instruction in the nestest log layout, e.g.
+    /// `C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7`
+    pub fn trace(&self, bus: &dyn Bus) -> String {
+        self.trace_with(bus, "")
+    }
+
+    /// `fields` go before `CYC`, like the `PPU:  0, 21 ` position of the nestest log
+    pub fn trace_with(&self, bus: &dyn Bus, fields: &str) -> String {
+        let opcode = bus.peek(self.pc as usize).unwrap_or(0);
+        let (op, mode, _) = OPCODES[opcode as usize];
+        let operands: Vec<u8> = (1..=mode.operand_len())
+            .map(|i| bus.peek(self.pc.wrapping_add(i) as usize).unwrap_or(0))
+            .collect();
+        let bytes: Vec<String> = std::iter::once(opcode).chain(operands.iter().copied()).map(|b| format!("{:02X}", b)).collect();
+        let word = match operands.as_slice() {
+            [low, high] => u16::from_le_bytes([*low, *high]),
+            [low] => *low as u16,
+            _ => 0,
+        };
+        let argument = match mode {
+            Mode::Imp => String::new(),
+            Mode::Acc => String::from("A"),
+            Mode::Imm => format!("#${:02X}", word),
+            Mode::Zp => format!("${:02X}", word),
+            Mode::Zpx => format!("${:02X},X", word),
+            Mode::Zpy => format!("${:02X},Y", word),
+            Mode::Abs => format!("${:04X}", word),
+            Mode::Abx => format!("${:04X},X", word),
+            Mode::Aby => format!("${:04X},Y", word),
+            Mode::Ind => format!("(${:04X})", word),
+            Mode::Izx => format!("(${:02X},X)", word),
+            Mode::Izy => format!("(${:02X}),Y", word),
+            Mode::Rel => format!("${:04X}", self.pc.wrapping_add(2).wrapping_add(word as i8 as u16)),
+        };
+        let mnemonic = match op {
+            Op::Unknown => String::from("???"),
+            _ => format!("{:?}", op).to_uppercase(),
+        };
+        let marker = if op.unofficial(opcode) { '*' } else { ' ' };
+        format!(
+            "{:04X}  {:<9}{}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} {}CYC:{}",
+            self.pc,
+            bytes.join(" "),
+            marker,
+            format!("{} {}", mnemonic, argument).trim_end(),
+            self.a,
+            self.x,
+            self.y,
+            self.p,
+            self.sp,
+            fields,
+            self.cycles
+        )
+    }
+
+    /// Pushes PC and P and jumps through `vector`
+    fn interrupt(&mut self, bus: &mut dyn Bus, vector: u16) -> Result<u32, Box<dyn Msg>> {
+        self.push_word(bus, self.pc)?;
+        self.push(bus, (self.p & !BREAK) | UNUSED)?;
+        self.p |= IRQ_DISABLE;
+        self.pc = self.read_word(bus, vector)?;
+        Ok(INTERRUPT_CYCLES)
+    }
+
+    fn execute(&mut self, bus: &mut dyn Bus) -> Result<u32, Box<dyn Msg>> {
+        self.opcode = self.fetch(bus)?;
+        let (op, mode, base_cycles) = OPCODES[self.opcode as usize];
+        let operand = self.operand(bus, mode)?;
+        let mut cycles = base_cycles as u32;
+        if operand.page_crossed && op.page_penalty() {
+            cycles += 1;
+        }
+        let addr = operand.addr;
+        match op {
+            Op::Adc => {
+                let value = self.read(bus, addr)?;
+                self.adc(value);
+            }
+            Op::Sbc => {
+                let value = self.read(bus, addr)?;
+                self.sbc(value);
+            }
+            Op::And => {
+                self.a &= self.read(bus, addr)?;
+                self.set_zn(self.a);
+            }
+            Op::Ora => {
+                self.a |= self.read(bus, addr)?;
+                self.set_zn(self.a);
+            }
+            Op::Eor => {
+                self.a ^= self.read(bus, addr)?;
+                self.set_zn(self.a);
+            }
+            Op::Asl => {
+                self.modify(bus, mode, addr, Mos6502::asl)?;
+            }
+            Op::Lsr => {
+                self.modify(bus, mode, addr, Mos6502::lsr)?;
+            }
+            Op::Rol => {
+                self.modify(bus, mode, addr, Mos6502::rol)?;
+            }
+            Op::Ror => {
+                self.modify(bus, mode, addr, Mos6502::ror)?;
+            }
+            Op::Inc => {
+                self.modify(bus, mode, addr, |cpu, value| cpu.increment(value, 1))?;
+            }
+            Op::Dec => {
+                self.modify(bus, mode, addr, |cpu, value| cpu.increment(value, 0xFF))?;
+            }
+            Op::Bit => {
+                let value = self.read(bus, addr)?;
+                self.set_flag(ZERO, self.a & value == 0);
+                self.p = (self.p & !(NEGATIVE | OVERFLOW)) | (value & (NEGATIVE | OVERFLOW));
+            }
+            Op::Bpl => cycles += self.branch(addr, self.p & NEGATIVE == 0),
+            Op::Bmi => cycles += self.branch(addr, self.p & NEGATIVE != 0),
+            Op::Bvc => cycles += self.branch(addr, self.p & OVERFLOW == 0),
+            Op::Bvs => cycles += self.branch(addr, self.p & OVERFLOW != 0),
+            Op::Bcc => cycles += self.branch(addr, self.p & CARRY == 0),
+            Op::Bcs => cycles += self.branch(addr, self.p & CARRY != 0),
+            Op::Bne => cycles += self.branch(addr, self.p & ZERO == 0),
+            Op::Beq => cycles += self.branch(addr, self.p & ZERO != 0),
+            Op::Brk => {
+                // the byte after BRK is padding
+                self.push_word(bus, self.pc.wrapping_add(1))?;
+                self.push(bus, self.p | BREAK | UNUSED)?;
+                self.p |= IRQ_DISABLE;
+                self.pc = self.read_word(bus, IRQ_VECTOR)?;
+            }
+            Op::Clc => self.p &= !CARRY,
+            Op::Cld => self.p &= !DECIMAL,
+            Op::Cli => self.p &= !IRQ_DISABLE,
+            Op::Clv => self.p &= !OVERFLOW,
+            Op::Sec => self.p |= CARRY,
+            Op::Sed => self.p |= DECIMAL,
+            Op::Sei => self.p |= IRQ_DISABLE,
+            Op::Cmp => {
+                let value = self.read(bus, addr)?;
+                self.compare(self.a, value);
+            }
+            Op::Cpx => {
+                let value = self.read(bus, addr)?;
+                self.compare(self.x, value);
+            }
+            Op::Cpy => {
+                let value = self.read(bus, addr)?;
+                self.compare(self.y, value);
+            }
+            Op::Dex => self.x = self.increment(self.x, 0xFF),
+            Op::Dey => self.y = self.increment(self.y, 0xFF),
+            Op::Inx => self.x = self.increment(self.x, 1),
+            Op::Iny => self.y = self.increment(self.y, 1),
+            Op::Jmp => self.pc = addr,
+            Op::Jsr => {
+                self.push_word(bus, self.pc.wrapping_sub(1))?;
+                self.pc = addr;
+            }
+            Op::Rts => self.pc = self.pull_word(bus)?.wrapping_add(1),
+            Op::Rti => {
+                let p = self.pull(bus)?;
+                self.p = (p & !BREAK) | UNUSED;
+                self.pc = self.pull_word(bus)?;
+            }
+            Op::Lda => {
+                self.a = self.read(bus, addr)?;
+                self.set_zn(self.a);
+            }
+            Op::Ldx => {
+                self.x = self.read(bus, addr)?;
+                self.set_zn(self.x);
+            }
+            Op::Ldy => {
+                self.y = self.read(bus, addr)?;
+                self.set_zn(self.y);
+            }
+            Op::Lax => {
+                self.a = self.read(bus, addr)?;
+                self.x = self.a;
+                self.set_zn(self.a);
+            }
+            Op::Nop => {
+                // the operand is still read, registers of the machine may react
+                if mode != Mode::Imp {
+                    self.read(bus, addr)?;
+                }
+            }
+            Op::Pha => self.push(bus, self.a)?,
+            Op::Php => self.push(bus, self.p | BREAK | UNUSED)?,
+            Op::Pla => {
+                self.a = self.pull(bus)?;
+                self.set_zn(self.a);
+            }
+            Op::Plp => {
+                let p = self.pull(bus)?;
+                self.p = (p & !BREAK) | UNUSED;
+            }
+            Op::Sta => self.write(bus, addr, self.a)?,
+            Op::Stx => self.write(bus, addr, self.x)?,
+            Op::Sty => self.write(bus, addr, self.y)?,
+            Op::Sax => self.write(bus, addr, self.a & self.x)?,
+            Op::Tax => {
+                self.x = self.a;
+                self.set_zn(self.x);
+            }
+            Op::Tay => {
+                self.y = self.a;
+                self.set_zn(self.y);
+            }
+            Op::Tsx => {
+                self.x = self.sp;
+                self.set_zn(self.x);
+            }
+            Op::Txa => {
+                self.a = self.x;
+                self.set_zn(self.a);
+            }
+            Op::Txs => self.sp = self.x,
+            Op::Tya => {
+                self.a = self.y;
+                self.set_zn(self.a);
+            }
+            Op::Dcp => {
+                let value = self.modify(bus, mode, addr, |cpu, value| cpu.increment(value, 0xFF))?;
+                self.compare(self.a, value);
+            }
+            Op::Isb => {
+                let value = self.modify(bus, mode, addr, |cpu, value| cpu.increment(value, 1))?;
+                self.sbc(value);
+            }
+            Op::Slo => {
+                self.a |= self.modify(bus, mode, addr, Mos6502::asl)?;
+                self.set_zn(self.a);
+            }
+            Op::Rla => {
+                self.a &= self.modify(bus, mode, addr, Mos6502::rol)?;
+                self.set_zn(self.a);
+            }
+            Op::Sre => {
+                self.a ^= self.modify(bus, mode, addr, Mos6502::lsr)?;
+                self.set_zn(self.a);
+            }
+            Op::Rra => {
+                let value = self.modify(bus, mode, addr, Mos6502::ror)?;
+                self.adc(value);
+            }
+            Op::Anc => {
+                self.a &= self.read(bus, addr)?;
+                self.set_zn(self.a);
+                self.set_flag(CARRY, self.a & 0x80 != 0);
+            }
+            Op::Alr => {
+                self.a &= self.read(bus, addr)?;
+                self.a = self.lsr(self.a);
+            }
+            Op::Arr => {
+                self.a &= self.read(bus, addr)?;
+                self.a = (self.a >> 1) | (self.p & CARRY) << 7;
+                self.set_zn(self.a);
+                self.set_flag(CARRY, self.a & 0x40 != 0);
+                self.set_flag(OVERFLOW, ((self.a >> 6) ^ (self.a >> 5)) & 0x01 != 0);
+            }
+            Op::Axs => {
+                let value = self.read(bus, addr)?;
+                let and = self.a & self.x;
+                self.x = and.wrapping_sub(value);
+                self.set_flag(CARRY, and >= value);
+                self.set_zn(self.x);
+            }
+            Op::Unknown => return Err(self.fault(ErrorMsgId::UnknownInstruction)),
+        }
+        Ok(cycles)
+    }
+
+    fn operand(&mut self, bus: &mut dyn Bus, mode: Mode) -> Result<Operand, Box<dyn Msg>> {
+        let mut page_crossed = false;
+        let addr = match mode {
+            Mode::Imp | Mode::Acc => 0,
+            Mode::Imm => {
+                let addr = self.pc;
+                self.pc = self.pc.wrapping_add(1);
+                addr
+            }
+            Mode::Zp => self.fetch(bus)? as u16,
+            Mode::Zpx => self.fetch(bus)?.wrapping_add(self.x) as u16,
+            Mode::Zpy => self.fetch(bus)?.wrapping_add(self.y) as u16,
+            Mode::Abs => self.fetch_word(bus)?,
+            Mode::Abx | Mode::Aby => {
+                let base = self.fetch_word(bus)?;
+                let index = if mode == Mode::Abx { self.x } else { self.y };
+                let addr = base.wrapping_add(index as u16);
+                page_crossed = base & 0xFF00 != addr & 0xFF00;
+                addr
+            }
+            Mode::Ind => {
+                // the high byte is read without carrying into the page
+                let pointer = self.fetch_word(bus)?;
+                let low = self.read(bus, pointer)?;
+                let high = self.read(bus, (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF))?;
+                u16::from_le_bytes([low, high])
+            }
+            Mode::Izx => {
+                let pointer = self.fetch(bus)?.wrapping_add(self.x);
+                self.read_zero_page_word(bus, pointer)?
+            }
+            Mode::Izy => {
+                let pointer = self.fetch(bus)?;
+                let base = self.read_zero_page_word(bus, pointer)?;
+                let addr = base.wrapping_add(self.y as u16);
+                page_crossed = base & 0xFF00 != addr & 0xFF00;
+                addr
+            }
+            Mode::Rel => {
+                let offset = self.fetch(bus)? as i8;
+                self.pc.wrapping_add(offset as u16)
+            }
+        };
+        Ok(Operand { addr, page_crossed })
+    }
+
+    /// Read-modify-write on A or memory, returns the new value
+    fn modify(
+        &mut self,
+        bus: &mut dyn Bus,
+        mode: Mode,
+        addr: u16,
+        f: impl FnOnce(&mut Self, u8) -> u8,
+    ) -> Result<u8, Box<dyn Msg>> {
+        if mode == Mode::Acc {
+            self.a = f(self, self.a);
+            return Ok(self.a);
+        }
+        let value = self.read(bus, addr)?;
+        let value = f(self, value);
+        self.write(bus, addr, value)?;
+        Ok(value)
+    }
+
+    /// Taken branches cost a cycle, one more when landing on another page
+    fn branch(&mut self, target: u16, condition: bool) -> u32 {
+        if !condition {
+            return 0;
+        }
+        let cycles = if target & 0xFF00 != self.pc & 0xFF00 { 2 } else { 1 };
+        self.pc = target;
+        cycles
+    }
+
+    fn adc(&mut self, value: u8) {
+        if self.p & DECIMAL != 0 && self.variant == Variant::Nmos {
+            self.adc_decimal(value);
+            return;
+        }
+        let sum = self.a as u16 + value as u16 + (self.p & CARRY) as u16;
+        let result = sum as u8;
+        self.set_flag(CARRY, sum > 0xFF);
+        self.set_flag(OVERFLOW, (self.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.a = result;
+        self.set_zn(result);
+    }
+
+    fn sbc(&mut self, value: u8) {
+        if self.p & DECIMAL != 0 && self.variant == Variant::Nmos {
+            self.sbc_decimal(value);
+            return;
+        }
+        self.adc(!value);
+    }
+
+    /// NMOS behaviour: Z follows the binary sum, N and V the sum before the high nibble is adjusted
+    fn adc_decimal(&mut self, value: u8) {
+        let carry = (self.p & CARRY) as u16;
+        let (a, value) = (self.a as u16, value as u16);
+        let mut low = (a & 0x0F) + (value & 0x0F) + carry;
+        if low > 0x09 {
+            low += 0x06;
+        }
+        let mut high = (a >> 4) + (value >> 4) + (low > 0x0F) as u16;
+        self.set_flag(ZERO, (a + value + carry) & 0xFF == 0);
+        self.set_flag(NEGATIVE, high & 0x08 != 0);
+        self.set_flag(OVERFLOW, ((high << 4) ^ a) & !(a ^ value) & 0x80 != 0);
+        if high > 0x09 {
+            high += 0x06;
+        }
+        self.set_flag(CARRY, high > 0x0F);
+        self.a = ((high << 4) | (low & 0x0F)) as u8;
+    }
+
+    /// NMOS behaviour: the flags follow the binary difference
+    fn sbc_decimal(&mut self, value: u8) {
+        let borrow = 1 - (self.p & CARRY) as i16;
+        let (a, operand) = (self.a as i16, value as i16);
+        let mut low = (a & 0x0F) - (operand & 0x0F) - borrow;
+        let mut high = (a >> 4) - (operand >> 4);
+        if low < 0 {
+            low -= 0x06;
+            high -= 1;
+        }
+        if high < 0 {
+            high -= 0x06;
+        }
+        let result = ((high << 4) | (low & 0x0F)) as u8;
+        let binary = self.a as i16 - value as i16 - borrow;
+        self.set_flag(CARRY, binary >= 0);
+        self.set_flag(OVERFLOW, (self.a ^ value) & (self.a ^ binary as u8) & 0x80 != 0);
+        self.set_zn(binary as u8);
+        self.a = result;
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(CARRY, register >= value);
+        self.set_zn(register.wrapping_sub(value));
+    }
+
+    fn increment(&mut self, value: u8, delta: u8) -> u8 {
+        let result = value.wrapping_add(delta);
+        self.set_zn(result);
+        result
+    }
+
+    fn asl(&mut self, value: u8) -> u8 {
+        self.set_flag(CARRY, value & 0x80 != 0);
+        let result = value << 1;
+        self.set_zn(result);
+        result
+    }
+
+    fn lsr(&mut self, value: u8) -> u8 {
+        self.set_flag(CARRY, value & 0x01 != 0);
+        let result = value >> 1;
+        self.set_zn(result);
+        result
+    }
+
+    fn rol(&mut self, value: u8) -> u8 {
+        let result = (value << 1) | (self.p & CARRY);
+        self.set_flag(CARRY, value & 0x80 != 0);
+        self.set_zn(result);
+        result
+    }
+
+    fn ror(&mut self, value: u8) -> u8 {
+        let result = (value >> 1) | (self.p & CARRY) << 7;
+        self.set_flag(CARRY, value & 0x01 != 0);
+        self.set_zn(result);
+        result
+    }
+
+    fn set_zn(&mut self, value: u8) {
+        self.set_flag(ZERO, value == 0);
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn fetch(&mut self, bus: &mut dyn Bus) -> Result<u8, Box<dyn Msg>> {
+        let value = self.read(bus, self.pc)?;
+        self.pc = self.pc.wrapping_add(1);
+        Ok(value)
+    }
+
+    fn fetch_word(&mut self, bus: &mut dyn Bus) -> Result<u16, Box<dyn Msg>> {
+        let low = self.fetch(bus)?;
+        let high = self.fetch(bus)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn read_word(&self, bus: &mut dyn Bus, addr: u16) -> Result<u16, Box<dyn Msg>> {
+        let low = self.read(bus, addr)?;
+        let high = self.read(bus, addr.wrapping_add(1))?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    /// Pointers in the zero page wrap around within it
+    fn read_zero_page_word(&self, bus: &mut dyn Bus, pointer: u8) -> Result<u16, Box<dyn Msg>> {
+        let low = self.read(bus, pointer as u16)?;
+        let high = self.read(bus, pointer.wrapping_add(1) as u16)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn push(&mut self, bus: &mut dyn Bus, value: u8) -> Result<(), Box<dyn Msg>> {
+        self.write(bus, STACK_PAGE | self.sp as u16, value)?;
+        self.sp = self.sp.wrapping_sub(1);
+        Ok(())
+    }
+
+    fn push_word(&mut self, bus: &mut dyn Bus, value: u16) -> Result<(), Box<dyn Msg>> {
+        let [high, low] = value.to_be_bytes();
+        self.push(bus, high)?;
+        self.push(bus, low)
+    }
+
+    fn pull(&mut self, bus: &mut dyn Bus) -> Result<u8, Box<dyn Msg>> {
+        self.sp = self.sp.wrapping_add(1);
+        self.read(bus, STACK_PAGE | self.sp as u16)
+    }
+
+    fn pull_word(&mut self, bus: &mut dyn Bus) -> Result<u16, Box<dyn Msg>> {
+        let low = self.pull(bus)?;
+        let high = self.pull(bus)?;
+        Ok(u16::from_le_bytes([low, high]))
+    }
+
+    fn read(&self, bus: &mut dyn Bus, addr: u16) -> Result<u8, Box<dyn Msg>> {
+        bus.read_byte(addr as usize).map_err(|_| self.memory_fault(addr))
+    }
+
+    fn write(&self, bus: &mut dyn Bus, addr: u16, value: u8) -> Result<(), Box<dyn Msg>> {
+        bus.write_byte(addr as usize, value).map_err(|_| self.memory_fault(addr))
+    }
+
+    fn fault(&self, msg_id: ErrorMsgId) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(ErrorTopicId::GuestFault.into(), msg_id.into())
+            .add_param(format!("{:#06X}", self.instr_pc))
+            .add_param(format!("{:#04X}", self.opcode));
+        Box::new(err)
+    }
+
+    /// Guest program error, reported with the address and the opcode of the faulting instruction
+    fn memory_fault(&self, addr: u16) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(ErrorTopicId::GuestFault.into(), ErrorMsgId::MemoryFault.into())
+            .add_param(format!("{:#06X}", self.instr_pc))
+            .add_param(format!("{:#04X}", self.opcode))
+            .add_param(format!("{:#06X}", addr));
+        Box::new(err)
+    }
+}
+
+#[cfg(test)]
+mod mos6502_tests {
+
+    use super::*;
+    use crate::common::bus::{MappedBus, OpenBus};
+
+    /// `program` at 0x0200, the reset vector points to it
+    fn run(variant: Variant, program: &[u8], steps: usize) -> (Mos6502, MappedBus, u32) {
+        let mut bus = MappedBus::new(OpenBus::Error);
+        bus.map_ram("ram", 0, 0x10000);
+        for (i, byte) in program.iter().enumerate() {
+            bus.write_byte(0x200 + i, *byte).unwrap();
+        }
+        bus.write_byte(RESET_VECTOR as usize + 1, 0x02).unwrap();
+        let mut cpu = Mos6502::new(variant);
+        cpu.reset(&mut bus).unwrap();
+        let mut cycles = 0;
+        for _ in 0..steps {
+            cycles += cpu.step(&mut bus).unwrap();
+        }
+        (cpu, bus, cycles)
+    }
+
+    #[test]
+    fn test_arithmetic_and_decimal() {
+        // CLC; LDA #$50; ADC #$50
+        let (cpu, _, cycles) = run(Variant::Nmos, &[0x18, 0xA9, 0x50, 0x69, 0x50], 3);
+        assert_eq!(cpu.a, 0xA0);
+        assert_eq!(cpu.p & (OVERFLOW | NEGATIVE | CARRY), OVERFLOW | NEGATIVE);
+        assert_eq!(cycles, 6);
+
+        // SED; CLC; LDA #$19; ADC #$28; SEC; SBC #$09
+        let program = [0xF8, 0x18, 0xA9, 0x19, 0x69, 0x28, 0x38, 0xE9, 0x09];
+        let (cpu, _, _) = run(Variant::Nmos, &program, 5);
+        assert_eq!(cpu.a, 0x47);
+        let (cpu, _, _) = run(Variant::Nmos, &program, 7);
+        assert_eq!(cpu.a, 0x38);
+        assert_eq!(cpu.p & CARRY, CARRY);
+        // the 2A03 ignores the D flag
+        let (cpu, _, _) = run(Variant::Ricoh2A03, &program, 5);
+        assert_eq!(cpu.a, 0x41);
+    }
+
+    #[test]
+    fn test_addressing_and_stack() {
+        // LDX #$01; LDA #$34; STA $02FF,X; JSR $0210; BRK(unused)
+        // 0x0210: INC $0300; LDY $0300; RTS
+        let mut program = vec![0xA2, 0x01, 0xA9, 0x34, 0x9D, 0xFF, 0x02, 0x20, 0x10, 0x02];
+        program.resize(0x10, 0xEA);
+        program.extend_from_slice(&[0xEE, 0x00, 0x03, 0xAC, 0x00, 0x03, 0x60]);
+        let (cpu, bus, cycles) = run(Variant::Ricoh2A03, &program, 7);
+        assert_eq!(bus.peek(0x300), Some(0x35));
+        assert_eq!(cpu.y, 0x35);
+        assert_eq!(cpu.pc, 0x020A);
+        assert_eq!(cpu.sp, 0xFD);
+        // STA abs,X always takes 5, no page penalty
+        assert_eq!(cycles, 2 + 2 + 5 + 6 + 6 + 4 + 6);
+        assert_eq!(cpu.cycles, 7 + cycles as u64);
+    }
+
+    #[test]
+    fn test_interrupts_and_trace() {
+        // CLI; NOP; NOP
+        let mut bus = MappedBus::new(OpenBus::Error);
+        bus.map_ram("ram", 0, 0x10000);
+        for (i, byte) in [0x58, 0xEA, 0xEA].iter().enumerate() {
+            bus.write_byte(0x8000 + i, *byte).unwrap();
+        }
+        bus.write_byte(0xFFFD, 0x80).unwrap();
+        bus.write_byte(0xFFFF, 0x90).unwrap();
+        bus.write_byte(0xFFFB, 0xA0).unwrap();
+        let mut cpu = Mos6502::new(Variant::Ricoh2A03);
+        cpu.reset(&mut bus).unwrap();
+        assert_eq!(
+            cpu.trace(&bus),
+            "8000  58        CLI                             A:00 X:00 Y:00 P:24 SP:FD CYC:7"
+        );
+        cpu.set_irq(true);
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.step(&mut bus).unwrap(), INTERRUPT_CYCLES);
+        assert_eq!(cpu.pc, 0x9000);
+        assert_eq!(bus.peek(0x01FB), Some(UNUSED));
+        cpu.nmi();
+        cpu.step(&mut bus).unwrap();
+        assert_eq!(cpu.pc, 0xA000);
+
+        bus.write_byte(0xA000, 0x02).unwrap();
+        assert!(cpu.step(&mut bus).is_err());
+        assert_eq!(cpu.pc, 0xA000);
+    }
+}
@@ -0,0 +1 @@
+pub mod cpu;
\ No newline at end of file
@@ -0,0 +1,700 @@
+use crate::common::emulator::{FRAME_RATE, SAMPLE_RATE};
+
+/// Length counter loads, indexed by the upper 5 bits of the fourth channel register
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16,
+    28, 32, 30,
+];
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+/// NTSC periods in APU cycles
+const NOISE_PERIODS: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+/// NTSC periods in CPU cycles
+const DMC_PERIODS: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
+/// Frame counter steps in CPU cycles
+const QUARTER_FRAMES: [u32; 4] = [7457, 14913, 22371, 29829];
+const FOUR_STEP_LENGTH: u32 = 29830;
+const FIVE_STEP_LAST: u32 = 37281;
+const FIVE_STEP_LENGTH: u32 = 37282;
+/// Mixer outputs kept for resampling, a bit more than the CPU cycles of a frame
+const MAX_FRAME_OUTPUTS: usize = 40000;
+const VOLUME: f32 = 20000.0;
+/// Pole of the high-pass filter that removes the DC offset of the mixer, as the console's output stage does
+const HIGH_PASS: f32 = 0.996;
+
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looped: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.looped = value & 0x20 != 0;
+        self.constant = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looped {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pulse {
+    /// Pulse 1 negates its sweep in one's complement
+    ones_complement: bool,
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 0x07;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value & 0x07) as u16) << 8;
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            self.period.saturating_sub(change + self.ones_complement as u16)
+        } else {
+            self.period + change
+        }
+    }
+
+    fn muted(&self) -> bool {
+        self.period < 8 || self.target_period() > 0x07FF
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looped && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.muted() || DUTY_TABLE[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Triangle {
+    enabled: bool,
+    /// Also halts the length counter
+    control: bool,
+    linear_load: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+    length: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.linear_load = value & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value & 0x07) as u16) << 8;
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocked by the CPU clock, twice as fast as the other channels
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length > 0 && self.linear_counter > 0 {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_load;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+}
+
+struct Noise {
+    enabled: bool,
+    /// Short mode taps bit 6 for a 93-step sequence
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16,
+    length: u8,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Self {
+            enabled: false,
+            short_mode: false,
+            period: NOISE_PERIODS[0],
+            timer: 0,
+            shift: 1,
+            length: 0,
+            envelope: Envelope::default(),
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.envelope.write(value),
+            1 => {}
+            2 => {
+                self.short_mode = value & 0x80 != 0;
+                self.period = NOISE_PERIODS[(value & 0x0F) as usize];
+            }
+            _ => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | feedback << 14;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_length(&mut self) {
+        if !self.envelope.looped && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+/// Delta modulation channel, plays 1-bit samples fetched from the cartridge
+struct Dmc {
+    irq_enabled: bool,
+    looped: bool,
+    period: u16,
+    timer: u16,
+    level: u8,
+    sample_addr: u16,
+    sample_length: u16,
+    current_addr: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+    irq: bool,
+}
+
+impl Dmc {
+    fn new() -> Self {
+        Self {
+            irq_enabled: false,
+            looped: false,
+            period: DMC_PERIODS[0],
+            timer: 0,
+            level: 0,
+            sample_addr: 0xC000,
+            sample_length: 1,
+            current_addr: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+            irq: false,
+        }
+    }
+
+    fn write(&mut self, register: u16, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looped = value & 0x40 != 0;
+                self.period = DMC_PERIODS[(value & 0x0F) as usize];
+            }
+            1 => self.level = value & 0x7F,
+            2 => self.sample_addr = 0xC000 | (value as u16) << 6,
+            _ => self.sample_length = (value as u16) << 4 | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_addr = self.sample_addr;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period - 1;
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(sample) => {
+                    self.silence = false;
+                    self.shift = sample;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn fill(&mut self, value: u8) {
+        self.buffer = Some(value);
+        self.current_addr = if self.current_addr == 0xFFFF { 0x8000 } else { self.current_addr + 1 };
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looped {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+}
+
+/// 2A03 sound: two pulses, triangle, noise, DMC and the frame counter
+pub struct Apu {
+    pulse1: Pulse,
+    pulse2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+    /// CPU cycles into the frame counter sequence
+    frame_cycle: u32,
+    odd_cycle: bool,
+    /// `output` sampled on every CPU cycle of the frame
+    frame_outputs: Vec<f32>,
+    /// Last input and output of the high-pass filter
+    filter: (f32, f32),
+    samples: Vec<i16>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            pulse1: Pulse {
+                ones_complement: true,
+                ..Default::default()
+            },
+            pulse2: Pulse::default(),
+            triangle: Triangle::default(),
+            noise: Noise::new(),
+            dmc: Dmc::new(),
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            frame_cycle: 0,
+            odd_cycle: false,
+            frame_outputs: Vec::with_capacity(MAX_FRAME_OUTPUTS),
+            filter: (0.0, 0.0),
+            samples: Vec::new(),
+        }
+    }
+
+    /// CPU write of 0x4000..=0x4013, 0x4015 or 0x4017
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse1.write(addr & 0x03, value),
+            0x4004..=0x4007 => self.pulse2.write(addr & 0x03, value),
+            0x4008..=0x400B => self.triangle.write(addr & 0x03, value),
+            0x400C..=0x400F => self.noise.write(addr & 0x03, value),
+            0x4010..=0x4013 => self.dmc.write(addr & 0x03, value),
+            0x4015 => {
+                self.pulse1.enabled = value & 0x01 != 0;
+                self.pulse2.enabled = value & 0x02 != 0;
+                self.triangle.enabled = value & 0x04 != 0;
+                self.noise.enabled = value & 0x08 != 0;
+                if !self.pulse1.enabled {
+                    self.pulse1.length = 0;
+                }
+                if !self.pulse2.enabled {
+                    self.pulse2.length = 0;
+                }
+                if !self.triangle.enabled {
+                    self.triangle.length = 0;
+                }
+                if !self.noise.enabled {
+                    self.noise.length = 0;
+                }
+                if value & 0x10 == 0 {
+                    self.dmc.bytes_remaining = 0;
+                } else if self.dmc.bytes_remaining == 0 {
+                    self.dmc.restart();
+                }
+                self.dmc.irq = false;
+            }
+            0x4017 => {
+                self.five_step = value & 0x80 != 0;
+                self.irq_inhibit = value & 0x40 != 0;
+                if self.irq_inhibit {
+                    self.frame_irq = false;
+                }
+                self.frame_cycle = 0;
+                if self.five_step {
+                    self.quarter_frame();
+                    self.half_frame();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// 0x4015, reading clears the frame interrupt
+    pub fn read_status(&mut self) -> u8 {
+        let value = (self.pulse1.length > 0) as u8
+            | ((self.pulse2.length > 0) as u8) << 1
+            | ((self.triangle.length > 0) as u8) << 2
+            | ((self.noise.length > 0) as u8) << 3
+            | ((self.dmc.bytes_remaining > 0) as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq as u8) << 7;
+        self.frame_irq = false;
+        value
+    }
+
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// Address the DMC wants to read, the machine answers with `dmc_fill` and stalls the CPU
+    pub fn dmc_request(&self) -> Option<u16> {
+        if self.dmc.buffer.is_none() && self.dmc.bytes_remaining > 0 {
+            Some(self.dmc.current_addr)
+        } else {
+            None
+        }
+    }
+
+    pub fn dmc_fill(&mut self, value: u8) {
+        self.dmc.fill(value);
+    }
+
+    /// Runs `cycles` CPU cycles
+    pub fn tick(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.triangle.clock_timer();
+            self.dmc.clock_timer();
+            if self.odd_cycle {
+                self.pulse1.clock_timer();
+                self.pulse2.clock_timer();
+                self.noise.clock_timer();
+            }
+            self.odd_cycle = !self.odd_cycle;
+            self.clock_frame_counter();
+            if self.frame_outputs.len() < MAX_FRAME_OUTPUTS {
+                self.frame_outputs.push(self.output());
+            }
+        }
+    }
+
+    /// Resamples the outputs of the frame to `SAMPLE_RATE`, averaging the CPU cycles behind each sample
+    pub fn end_frame(&mut self) {
+        self.samples.clear();
+        if self.frame_outputs.is_empty() {
+            return;
+        }
+        let count = (SAMPLE_RATE as u64 / FRAME_RATE) as usize;
+        let step = self.frame_outputs.len() as f32 / count as f32;
+        for i in 0..count {
+            let start = (i as f32 * step) as usize;
+            let end = (((i + 1) as f32 * step) as usize).clamp(start + 1, self.frame_outputs.len());
+            let window = &self.frame_outputs[start..end];
+            let value = window.iter().sum::<f32>() / window.len() as f32;
+            let (last_in, last_out) = self.filter;
+            let filtered = HIGH_PASS * (last_out + value - last_in);
+            self.filter = (value, filtered);
+            self.samples.push((filtered * VOLUME).clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+        }
+        self.frame_outputs.clear();
+    }
+
+    /// Samples of the last frame, `None` before the APU ran
+    pub fn samples(&self) -> Option<&[i16]> {
+        match self.samples.is_empty() {
+            true => None,
+            false => Some(&self.samples),
+        }
+    }
+
+    /// Mixed output 0.0..=1.0, the linear approximation of the 2A03 DAC
+    pub fn output(&self) -> f32 {
+        let pulses = (self.pulse1.output() + self.pulse2.output()) as f32;
+        0.00752 * pulses
+            + 0.00851 * self.triangle.output() as f32
+            + 0.00494 * self.noise.output() as f32
+            + 0.00335 * self.dmc.level as f32
+    }
+
+    /// Whether any channel is producing a tone
+    pub fn active(&self) -> bool {
+        let pulse = |pulse: &Pulse| pulse.length > 0 && !pulse.muted() && pulse.envelope.output() > 0;
+        pulse(&self.pulse1)
+            || pulse(&self.pulse2)
+            || (self.triangle.length > 0 && self.triangle.linear_counter > 0 && self.triangle.period > 1)
+            || (self.noise.length > 0 && self.noise.envelope.output() > 0)
+            || self.dmc.bytes_remaining > 0
+    }
+
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let last = if self.five_step { FIVE_STEP_LAST } else { QUARTER_FRAMES[3] };
+        match self.frame_cycle {
+            cycle if cycle == QUARTER_FRAMES[0] || cycle == QUARTER_FRAMES[2] => self.quarter_frame(),
+            cycle if cycle == QUARTER_FRAMES[1] => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            cycle if cycle == last => {
+                self.quarter_frame();
+                self.half_frame();
+                if !self.five_step && !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+            }
+            _ => {}
+        }
+        let length = if self.five_step { FIVE_STEP_LENGTH } else { FOUR_STEP_LENGTH };
+        if self.frame_cycle >= length {
+            self.frame_cycle = 0;
+        }
+    }
+
+    /// Envelopes and the triangle linear counter
+    fn quarter_frame(&mut self) {
+        self.pulse1.envelope.clock();
+        self.pulse2.envelope.clock();
+        self.noise.envelope.clock();
+        self.triangle.clock_linear();
+    }
+
+    /// Length counters and sweeps
+    fn half_frame(&mut self) {
+        self.pulse1.clock_length();
+        self.pulse2.clock_length();
+        self.triangle.clock_length();
+        self.noise.clock_length();
+        self.pulse1.clock_sweep();
+        self.pulse2.clock_sweep();
+    }
+}
+
+#[cfg(test)]
+mod apu_tests {
+
+    use super::*;
+
+    #[test]
+    fn test_length_and_frame_irq() {
+        let mut apu = Apu::new();
+        apu.write(0x4015, 0x01);
+        // constant volume 15, period 0x100, length index 1 (254)
+        apu.write(0x4000, 0x9F);
+        apu.write(0x4002, 0x00);
+        apu.write(0x4003, 0x09);
+        assert!(apu.active());
+        assert_eq!(apu.read_status() & 0x01, 0x01);
+
+        apu.tick(FOUR_STEP_LENGTH);
+        assert!(apu.irq());
+        assert_eq!(apu.pulse1.length, 252);
+        assert_eq!(apu.read_status() & 0x40, 0x40);
+        assert!(!apu.irq());
+
+        apu.write(0x4015, 0x00);
+        assert!(!apu.active());
+        assert!(apu.output() < 0.2);
+    }
+
+    #[test]
+    fn test_frame_samples() {
+        let mut apu = Apu::new();
+        apu.end_frame();
+        assert_eq!(apu.samples(), None);
+        apu.write(0x4015, 0x01);
+        // 50% duty, constant volume 15, period 0xFD (about 440Hz)
+        apu.write(0x4000, 0xBF);
+        apu.write(0x4002, 0xFD);
+        apu.write(0x4003, 0x08);
+        apu.tick(FOUR_STEP_LENGTH);
+        apu.end_frame();
+        let samples = apu.samples().unwrap();
+        assert_eq!(samples.len(), (SAMPLE_RATE as u64 / FRAME_RATE) as usize);
+        // the square wave swings around zero once the filter settles
+        let tail = &samples[samples.len() / 2..];
+        assert!(*tail.iter().max().unwrap() > 500);
+        assert!(*tail.iter().min().unwrap() < -500);
+    }
+
+    #[test]
+    fn test_dmc_fetch() {
+        let mut apu = Apu::new();
+        apu.write(0x4010, 0x8F);
+        apu.write(0x4012, 0x01);
+        apu.write(0x4013, 0x00);
+        apu.write(0x4015, 0x10);
+        assert_eq!(apu.dmc_request(), Some(0xC040));
+        apu.dmc_fill(0xFF);
+        assert_eq!(apu.dmc_request(), None);
+        assert!(apu.irq());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::common::message::*;
+use crate::common::ram::Ram;
+use crate::nes::mappers::{self, Mapper};
+
+pub const MAGIC: [u8; 4] = [b'N', b'E', b'S', 0x1A];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+/// The trainer is loaded at 0x7000
+const TRAINER_OFFSET: usize = 0x1000;
+const PRG_UNIT: usize = 0x4000;
+const CHR_UNIT: usize = 0x2000;
+const PRG_RAM_DEFAULT: usize = 0x2000;
+const PRG_RAM_START: u16 = 0x6000;
+
+/// Layout of the four nametables in the 2K of the console, or 4K on the cartridge
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleLow,
+    SingleHigh,
+    FourScreen,
+}
+
+impl Mirroring {
+    /// Offset of `addr` (0x2000..=0x3EFF) in the nametable memory
+    pub fn nametable_offset(&self, addr: u16) -> usize {
+        let addr = addr as usize & 0x0FFF;
+        let (table, offset) = (addr / 0x400, addr % 0x400);
+        let page = match self {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleLow => 0,
+            Mirroring::SingleHigh => 1,
+            Mirroring::FourScreen => table,
+        };
+        page * 0x400 + offset
+    }
+}
+
+/// iNES or NES 2.0 image with the mapper chosen by its header
+pub struct Cartridge {
+    mapper_id: u16,
+    prg: Vec<u8>,
+    /// CHR ROM, or CHR RAM when the image has none
+    chr: Vec<u8>,
+    chr_writable: bool,
+    prg_ram: Ram,
+    mirroring: Mirroring,
+    battery: bool,
+    mapper: Box<dyn Mapper>,
+}
+
+impl Cartridge {
+    pub fn from_ines(data: &[u8]) -> Result<Self, Box<dyn Msg>> {
+        let invalid = || {
+            let err = ErrorMsg::new(ErrorTopicId::Cartridge.into(), ErrorMsgId::InvalidFormat.into())
+                .add_param(data.len().to_string());
+            Box::new(err) as Box<dyn Msg>
+        };
+        if data.len() < HEADER_SIZE || data[..4] != MAGIC {
+            return Err(invalid());
+        }
+        let (flags6, flags7) = (data[6], data[7]);
+        let nes2 = flags7 & 0x0C == 0x08;
+        let mut mapper_id = (flags6 >> 4) as u16;
+        let (prg_size, chr_size, prg_ram_size, chr_ram_size);
+        if nes2 {
+            mapper_id |= (flags7 & 0xF0) as u16 | ((data[8] & 0x0F) as u16) << 8;
+            prg_size = rom_size(data[4], data[9] & 0x0F, PRG_UNIT);
+            chr_size = rom_size(data[5], data[9] >> 4, CHR_UNIT);
+            prg_ram_size = ram_size(data[10] & 0x0F) + ram_size(data[10] >> 4);
+            chr_ram_size = ram_size(data[11] & 0x0F) + ram_size(data[11] >> 4);
+        } else {
+            // old dumps carry text like "DiskDude!" from byte 7 on, their upper mapper nibble is junk
+            if data[12..HEADER_SIZE].iter().all(|b| *b == 0) {
+                mapper_id |= (flags7 & 0xF0) as u16;
+            }
+            prg_size = data[4] as usize * PRG_UNIT;
+            chr_size = data[5] as usize * CHR_UNIT;
+            prg_ram_size = (data[8].max(1)) as usize * PRG_RAM_DEFAULT;
+            chr_ram_size = if chr_size == 0 { CHR_UNIT } else { 0 };
+        }
+
+        let trainer = flags6 & 0x04 != 0;
+        let prg_start = HEADER_SIZE + if trainer { TRAINER_SIZE } else { 0 };
+        let chr_start = prg_start + prg_size;
+        if prg_size == 0 || data.len() < chr_start + chr_size {
+            return Err(invalid());
+        }
+        let mapper = match mappers::create(mapper_id, prg_size, chr_size.max(chr_ram_size)) {
+            Some(mapper) => mapper,
+            None => {
+                let err = ErrorMsg::new(ErrorTopicId::Cartridge.into(), ErrorMsgId::NotSupported.into())
+                    .add_param(format!("iNES {}", mapper_id));
+                return Err(Box::new(err));
+            }
+        };
+
+        let mut prg_ram = Ram::new(prg_ram_size.max(if trainer { PRG_RAM_DEFAULT } else { 0 }));
+        if trainer {
+            prg_ram.write_block(TRAINER_OFFSET, data[HEADER_SIZE..prg_start].to_vec())?;
+        }
+        let (chr, chr_writable) = if chr_size == 0 {
+            (vec![0u8; chr_ram_size.max(CHR_UNIT)], true)
+        } else {
+            (data[chr_start..chr_start + chr_size].to_vec(), false)
+        };
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        Ok(Self {
+            mapper_id,
+            prg: data[prg_start..chr_start].to_vec(),
+            chr,
+            chr_writable,
+            prg_ram,
+            mirroring,
+            battery: flags6 & 0x02 != 0,
+            mapper,
+        })
+    }
+
+    pub fn mapper_id(&self) -> u16 {
+        self.mapper_id
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    /// CPU read of 0x4020..=0xFFFF, `None` where nothing drives the bus
+    pub fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x8000..=0xFFFF => Some(self.prg[self.mapper.prg_offset(addr) % self.prg.len()]),
+            0x6000..=0x7FFF if self.mapper.prg_ram_enabled() => {
+                self.prg_ram.read_byte((addr - PRG_RAM_START) as usize).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// CPU write of 0x4020..=0xFFFF, the ROM area holds the mapper registers
+    pub fn write(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x8000..=0xFFFF => self.mapper.write(addr, value),
+            0x6000..=0x7FFF if self.mapper.prg_ram_enabled() => {
+                let _ = self.prg_ram.write_byte((addr - PRG_RAM_START) as usize, value);
+            }
+            _ => {}
+        }
+    }
+
+    /// PPU read of the pattern tables, 0x0000..=0x1FFF
+    pub fn read_chr(&self, addr: u16) -> u8 {
+        self.chr[self.mapper.chr_offset(addr) % self.chr.len()]
+    }
+
+    pub fn write_chr(&mut self, addr: u16, value: u8) {
+        if self.chr_writable {
+            let offset = self.mapper.chr_offset(addr) % self.chr.len();
+            self.chr[offset] = value;
+        }
+    }
+
+    /// Four-screen boards wire the nametables themselves, otherwise the mapper may switch them
+    pub fn mirroring(&self) -> Mirroring {
+        match self.mirroring {
+            Mirroring::FourScreen => Mirroring::FourScreen,
+            header => self.mapper.mirroring().unwrap_or(header),
+        }
+    }
+
+    /// Called by the PPU once per rendered scanline
+    pub fn scanline(&mut self) {
+        self.mapper.scanline();
+    }
+
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+}
+
+/// NES 2.0 sizes are `MSB:LSB` units, or `2^E * (M * 2 + 1)` bytes when the MSB nibble is 0xF
+fn rom_size(lsb: u8, msb: u8, unit: usize) -> usize {
+    if msb == 0x0F {
+        (1usize << (lsb >> 2)) * ((lsb & 0x03) as usize * 2 + 1)
+    } else {
+        ((msb as usize) << 8 | lsb as usize) * unit
+    }
+}
+
+/// NES 2.0 RAM sizes are shift counts of 64 bytes
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        0
+    } else {
+        64 << shift
+    }
+}
+
+#[cfg(test)]
+mod cartridge_tests {
+
+    use super::*;
+
+    fn image(mapper: u8, prg_units: u8, chr_units: u8, flags6: u8) -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_SIZE];
+        data[..4].copy_from_slice(&MAGIC);
+        data[4] = prg_units;
+        data[5] = chr_units;
+        data[6] = (mapper << 4) | flags6;
+        data[7] = mapper & 0xF0;
+        for unit in 0..prg_units {
+            data.extend(std::iter::repeat_n(unit, PRG_UNIT));
+        }
+        for unit in 0..chr_units {
+            data.extend(std::iter::repeat_n(0x80 | unit, CHR_UNIT));
+        }
+        data
+    }
+
+    #[test]
+    fn test_header() {
+        let cart = Cartridge::from_ines(&image(0, 1, 1, 0x01)).unwrap();
+        assert_eq!(cart.mirroring(), Mirroring::Vertical);
+        // 16K are mirrored at 0xC000
+        assert_eq!(cart.read(0xC000), Some(0));
+        assert_eq!(cart.read(0x6000), Some(0));
+        assert_eq!(cart.read(0x5000), None);
+
+        // NES 2.0 with 8K of CHR RAM and no PRG RAM
+        let mut data = image(2, 1, 0, 0);
+        data[7] |= 0x08;
+        data[11] = 0x07;
+        let cart = Cartridge::from_ines(&data).unwrap();
+        assert_eq!(cart.mapper_id(), 2);
+        assert_eq!(cart.read(0x6000), None);
+        assert!(cart.chr_writable);
+        assert_eq!(cart.chr.len(), 0x2000);
+
+        assert!(Cartridge::from_ines(&data[..HEADER_SIZE + 100]).is_err());
+        assert!(Cartridge::from_ines(&image(5, 1, 1, 0)).is_err());
+        assert_eq!(Mirroring::Horizontal.nametable_offset(0x2C05), 0x405);
+        assert_eq!(Mirroring::Vertical.nametable_offset(0x2805), 0x005);
+    }
+}
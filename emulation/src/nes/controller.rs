@@ -0,0 +1,50 @@
+/// Standard controller, a shift register read one button at a time
+pub struct Controller {
+    /// Pressed buttons as bits in `NesKeys` order
+    buttons: u8,
+    shift: u8,
+    /// While set the shift register keeps reloading, reads return A
+    strobe: bool,
+}
+
+impl Default for Controller {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self {
+            buttons: 0,
+            shift: 0,
+            strobe: false,
+        }
+    }
+
+    /// 0x4016 write, shared by both ports
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    /// Bit 0 of 0x4016 or 0x4017, ones once all 8 buttons were read
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & 0x01;
+        }
+        let bit = self.shift & 0x01;
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+
+    pub fn set_button(&mut self, button: u32, pressed: bool) {
+        if pressed {
+            self.buttons |= 1 << button;
+        } else {
+            self.buttons &= !(1 << button);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+use crate::nes::cartridge::Mirroring;
+
+const PRG_BANK_16K: usize = 0x4000;
+const PRG_BANK_8K: usize = 0x2000;
+const CHR_BANK_8K: usize = 0x2000;
+const CHR_BANK_4K: usize = 0x1000;
+const CHR_BANK_1K: usize = 0x0400;
+
+/// Bank switching logic of a board, the `Cartridge` owns the memories
+pub trait Mapper: Send {
+    /// Offset in the PRG ROM of a CPU address 0x8000..=0xFFFF, wrapped by the caller
+    fn prg_offset(&self, addr: u16) -> usize;
+    /// Offset in the CHR memory of a PPU address 0x0000..=0x1FFF, wrapped by the caller
+    fn chr_offset(&self, addr: u16) -> usize;
+    /// CPU write to 0x8000..=0xFFFF
+    fn write(&mut self, addr: u16, value: u8);
+    /// `None` keeps the layout of the header
+    fn mirroring(&self) -> Option<Mirroring> {
+        None
+    }
+    fn prg_ram_enabled(&self) -> bool {
+        true
+    }
+    /// Rendered scanline, MMC3 counts them through the A12 line of the PPU
+    fn scanline(&mut self) {}
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+/// Mapper of an iNES number, `None` when it is not implemented
+pub fn create(id: u16, prg_size: usize, chr_size: usize) -> Option<Box<dyn Mapper>> {
+    let mapper: Box<dyn Mapper> = match id {
+        0 => Box::new(Nrom),
+        1 => Box::new(Mmc1::new(prg_size / PRG_BANK_16K)),
+        2 => Box::new(Uxrom::new(prg_size / PRG_BANK_16K)),
+        3 => Box::new(Cnrom { bank: 0 }),
+        4 => Box::new(Mmc3::new(prg_size / PRG_BANK_8K, chr_size)),
+        _ => return None,
+    };
+    Some(mapper)
+}
+
+/// Mapper 0, no registers: 16K or 32K of PRG and 8K of CHR
+pub struct Nrom;
+
+impl Mapper for Nrom {
+    fn prg_offset(&self, addr: u16) -> usize {
+        (addr - 0x8000) as usize
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn write(&mut self, _addr: u16, _value: u8) {}
+}
+
+/// Mapper 1, registers are loaded bit by bit through a 5-bit shift register
+pub struct Mmc1 {
+    prg_banks: usize,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    prg_bank: u8,
+}
+
+impl Mmc1 {
+    pub fn new(prg_banks: usize) -> Self {
+        Self {
+            prg_banks,
+            shift: 0,
+            shift_count: 0,
+            // PRG mode 3 at power on, the last bank is fixed at 0xC000
+            control: 0x0C,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    /// 512K boards (SUROM) select the PRG half with a CHR register bit
+    fn prg_outer(&self) -> usize {
+        if self.prg_banks > 16 {
+            (self.chr_bank0 & 0x10) as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = self.prg_outer() | (self.prg_bank & 0x0F) as usize;
+        let offset = addr as usize & 0x3FFF;
+        let bank = match ((self.control >> 2) & 0x03, addr >= 0xC000) {
+            (0 | 1, high) => (bank & !1) | high as usize,
+            (2, false) => self.prg_outer(),
+            (2, true) => bank,
+            (_, false) => bank,
+            (_, true) => self.prg_outer() | (self.prg_banks.clamp(1, 16) - 1),
+        };
+        bank * PRG_BANK_16K + offset
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let addr = addr as usize;
+        if self.control & 0x10 == 0 {
+            (self.chr_bank0 & 0x1E) as usize * CHR_BANK_4K + addr
+        } else if addr < CHR_BANK_4K {
+            self.chr_bank0 as usize * CHR_BANK_4K + addr
+        } else {
+            self.chr_bank1 as usize * CHR_BANK_4K + (addr - CHR_BANK_4K)
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        if value & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+            return;
+        }
+        self.shift |= (value & 0x01) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count < 5 {
+            return;
+        }
+        match (addr >> 13) & 0x03 {
+            0 => self.control = self.shift,
+            1 => self.chr_bank0 = self.shift,
+            2 => self.chr_bank1 = self.shift,
+            _ => self.prg_bank = self.shift,
+        }
+        self.shift = 0;
+        self.shift_count = 0;
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 0x03 {
+            0 => Mirroring::SingleLow,
+            1 => Mirroring::SingleHigh,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        })
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0
+    }
+}
+
+/// Mapper 2, a switchable 16K bank at 0x8000 and the last one fixed at 0xC000
+pub struct Uxrom {
+    prg_banks: usize,
+    bank: u8,
+}
+
+impl Uxrom {
+    pub fn new(prg_banks: usize) -> Self {
+        Self { prg_banks, bank: 0 }
+    }
+}
+
+impl Mapper for Uxrom {
+    fn prg_offset(&self, addr: u16) -> usize {
+        let bank = if addr < 0xC000 { self.bank as usize } else { self.prg_banks - 1 };
+        bank * PRG_BANK_16K + (addr as usize & 0x3FFF)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn write(&mut self, _addr: u16, value: u8) {
+        self.bank = value;
+    }
+}
+
+/// Mapper 3, fixed PRG and a switchable 8K CHR bank
+pub struct Cnrom {
+    bank: u8,
+}
+
+impl Mapper for Cnrom {
+    fn prg_offset(&self, addr: u16) -> usize {
+        (addr - 0x8000) as usize
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        self.bank as usize * CHR_BANK_8K + addr as usize
+    }
+
+    fn write(&mut self, _addr: u16, value: u8) {
+        self.bank = value;
+    }
+}
+
+/// Mapper 4, 8K PRG and 1K/2K CHR banks with a scanline counter raising IRQs
+pub struct Mmc3 {
+    prg_banks: usize,
+    /// CHR RAM boards have 8K, the banks wrap around it
+    chr_size: usize,
+    select: u8,
+    registers: [u8; 8],
+    mirroring: Mirroring,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl Mmc3 {
+    pub fn new(prg_banks: usize, chr_size: usize) -> Self {
+        Self {
+            prg_banks,
+            chr_size,
+            select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: Mirroring::Vertical,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn prg_offset(&self, addr: u16) -> usize {
+        let second_last = self.prg_banks.saturating_sub(2);
+        let (r6, r7) = ((self.registers[6] & 0x3F) as usize, (self.registers[7] & 0x3F) as usize);
+        let banks = if self.select & 0x40 == 0 {
+            [r6, r7, second_last, self.prg_banks - 1]
+        } else {
+            [second_last, r7, r6, self.prg_banks - 1]
+        };
+        let slot = (addr as usize - 0x8000) / PRG_BANK_8K;
+        banks[slot] * PRG_BANK_8K + (addr as usize & 0x1FFF)
+    }
+
+    fn chr_offset(&self, addr: u16) -> usize {
+        let mut slot = addr as usize / CHR_BANK_1K;
+        // with the inversion bit the 2K banks move to 0x1000
+        if self.select & 0x80 != 0 {
+            slot ^= 4;
+        }
+        let bank = match slot {
+            0 => self.registers[0] & 0xFE,
+            1 => self.registers[0] | 0x01,
+            2 => self.registers[1] & 0xFE,
+            3 => self.registers[1] | 0x01,
+            _ => self.registers[slot - 2],
+        };
+        (bank as usize * CHR_BANK_1K + (addr as usize & 0x3FF)) % self.chr_size.max(1)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        match addr & 0xE001 {
+            0x8000 => self.select = value,
+            0x8001 => self.registers[(self.select & 0x07) as usize] = value,
+            0xA000 => {
+                self.mirroring = if value & 0x01 == 0 { Mirroring::Vertical } else { Mirroring::Horizontal };
+            }
+            // PRG RAM protection, left out like most emulators do for MMC6 compatibility
+            0xA001 => {}
+            0xC000 => self.irq_latch = value,
+            0xC001 => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            0xE000 => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(self.mirroring)
+    }
+
+    fn scanline(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+#[cfg(test)]
+mod mappers_tests {
+
+    use super::*;
+
+    fn serial_write(mapper: &mut Mmc1, addr: u16, value: u8) {
+        for bit in 0..5 {
+            mapper.write(addr, (value >> bit) & 0x01);
+        }
+    }
+
+    #[test]
+    fn test_mmc1() {
+        let mut mmc1 = Mmc1::new(8);
+        assert_eq!(mmc1.prg_offset(0xC000), 7 * PRG_BANK_16K);
+        serial_write(&mut mmc1, 0xE000, 0x03);
+        assert_eq!(mmc1.prg_offset(0x8001), 3 * PRG_BANK_16K + 1);
+        // 32K mode ignores the low bit of the bank
+        serial_write(&mut mmc1, 0x8000, 0x02);
+        assert_eq!(mmc1.prg_offset(0xC000), 3 * PRG_BANK_16K);
+        assert_eq!(mmc1.mirroring(), Some(Mirroring::Vertical));
+        mmc1.write(0x8000, 0x80);
+        assert_eq!(mmc1.prg_offset(0xC000), 7 * PRG_BANK_16K);
+    }
+
+    #[test]
+    fn test_mmc3_banks_and_irq() {
+        let mut mmc3 = Mmc3::new(16, 0x20000);
+        mmc3.write(0x8000, 0x06);
+        mmc3.write(0x8001, 0x03);
+        assert_eq!(mmc3.prg_offset(0x8000), 3 * PRG_BANK_8K);
+        assert_eq!(mmc3.prg_offset(0xC000), 14 * PRG_BANK_8K);
+        mmc3.write(0x8000, 0x40);
+        assert_eq!(mmc3.prg_offset(0xC000), 3 * PRG_BANK_8K);
+        assert_eq!(mmc3.prg_offset(0xE000), 15 * PRG_BANK_8K);
+        mmc3.write(0x8000, 0x82);
+        mmc3.write(0x8001, 0x09);
+        assert_eq!(mmc3.chr_offset(0x0000), 9 * CHR_BANK_1K);
+
+        mmc3.write(0xC000, 2);
+        mmc3.write(0xC001, 0);
+        mmc3.write(0xE001, 0);
+        mmc3.scanline();
+        mmc3.scanline();
+        assert!(!mmc3.irq());
+        mmc3.scanline();
+        assert!(mmc3.irq());
+        mmc3.write(0xE000, 0);
+        assert!(!mmc3.irq());
+    }
+}
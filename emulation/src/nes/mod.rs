@@ -0,0 +1,6 @@
+pub mod system;
+pub mod cartridge;
+pub mod mappers;
+pub mod ppu;
+pub mod apu;
+pub mod controller;
\ No newline at end of file
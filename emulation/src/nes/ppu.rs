@@ -0,0 +1,480 @@
+use crate::common::ram::{AddressMode, Ram};
+use crate::common::vram::Vram;
+use crate::nes::cartridge::Cartridge;
+
+pub const WIDTH: usize = 256;
+pub const HEIGHT: usize = 240;
+const DOTS_PER_LINE: u16 = 341;
+const LINES_PER_FRAME: u16 = 262;
+const VBLANK_LINE: u16 = 241;
+const PRE_RENDER_LINE: u16 = 261;
+const NAMETABLES_SIZE: usize = 0x1000;
+const OAM_SIZE: usize = 0x100;
+const SPRITES_PER_LINE: usize = 8;
+
+const CTRL_INCREMENT_32: u8 = 0x04;
+const CTRL_SPRITE_TABLE: u8 = 0x08;
+const CTRL_BACKGROUND_TABLE: u8 = 0x10;
+const CTRL_SPRITE_TALL: u8 = 0x20;
+const CTRL_NMI: u8 = 0x80;
+const MASK_GREYSCALE: u8 = 0x01;
+const MASK_BACKGROUND_LEFT: u8 = 0x02;
+const MASK_SPRITES_LEFT: u8 = 0x04;
+const MASK_BACKGROUND: u8 = 0x08;
+const MASK_SPRITES: u8 = 0x10;
+const STATUS_OVERFLOW: u8 = 0x20;
+const STATUS_SPRITE_ZERO: u8 = 0x40;
+const STATUS_VBLANK: u8 = 0x80;
+
+/// Sprite pixel of a line, `color` 0 is transparent
+#[derive(Clone, Copy, Default)]
+struct SpritePixel {
+    color: u8,
+    behind: bool,
+    zero: bool,
+}
+
+/// 2C02 rendering whole scanlines, the loopy `v`/`t` registers give mid-frame scrolling
+pub struct Ppu {
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    /// Current VRAM address `yyy NN YYYYY XXXXX`
+    v: u16,
+    /// Temporary VRAM address, the top-left corner of the screen
+    t: u16,
+    fine_x: u8,
+    /// First or second write of PPUSCROLL and PPUADDR
+    w: bool,
+    read_buffer: u8,
+    /// Last value on the data bus, read back from write-only registers
+    latch: u8,
+    nametables: Ram,
+    palette: [u8; 32],
+    oam: Ram,
+    scanline: u16,
+    dot: u16,
+    odd_frame: bool,
+    nmi: bool,
+    /// Master palette indices 0..=63
+    framebuffer: Vram,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            read_buffer: 0,
+            latch: 0,
+            nametables: Ram::new(NAMETABLES_SIZE),
+            palette: [0; 32],
+            oam: Ram::with_mode(OAM_SIZE, AddressMode::Wrap),
+            scanline: 0,
+            dot: 0,
+            odd_frame: false,
+            nmi: false,
+            framebuffer: Vram::new(WIDTH, HEIGHT),
+        }
+    }
+
+    /// The reset line clears the control registers, memories are kept
+    pub fn reset(&mut self) {
+        self.ctrl = 0;
+        self.mask = 0;
+        self.w = false;
+        self.read_buffer = 0;
+        self.odd_frame = false;
+    }
+
+    pub fn framebuffer(&self) -> &Vram {
+        &self.framebuffer
+    }
+
+    /// Scanline and dot about to be rendered
+    pub fn position(&self) -> (u16, u16) {
+        (self.scanline, self.dot)
+    }
+
+    /// Whether vblank raised an NMI since the last call
+    pub fn take_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi)
+    }
+
+    /// Runs `dots` PPU cycles, three per CPU cycle. Returns whether a frame was completed
+    pub fn tick(&mut self, dots: u32, cart: &mut Cartridge) -> bool {
+        let mut frame_done = false;
+        for _ in 0..dots {
+            frame_done |= self.step(cart);
+        }
+        frame_done
+    }
+
+    fn step(&mut self, cart: &mut Cartridge) -> bool {
+        let rendering = self.rendering();
+        let mut frame_done = false;
+        match (self.scanline, self.dot) {
+            (0..=239, 256) => {
+                self.render_line(cart);
+                if rendering {
+                    self.increment_y();
+                }
+            }
+            (0..=239 | PRE_RENDER_LINE, 257) if rendering => self.v = (self.v & !0x041F) | (self.t & 0x041F),
+            (0..=239 | PRE_RENDER_LINE, 260) if rendering => cart.scanline(),
+            (PRE_RENDER_LINE, 280) if rendering => self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0),
+            (VBLANK_LINE, 1) => {
+                self.status |= STATUS_VBLANK;
+                self.nmi |= self.ctrl & CTRL_NMI != 0;
+                frame_done = true;
+            }
+            (PRE_RENDER_LINE, 1) => self.status &= !(STATUS_VBLANK | STATUS_SPRITE_ZERO | STATUS_OVERFLOW),
+            _ => {}
+        }
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == LINES_PER_FRAME {
+                self.scanline = 0;
+                self.odd_frame = !self.odd_frame;
+                // odd frames are a dot shorter while rendering
+                if self.odd_frame && rendering {
+                    self.dot = 1;
+                }
+            }
+        }
+        frame_done
+    }
+
+    /// CPU read of 0x2000..=0x3FFF
+    pub fn read_register(&mut self, addr: u16, cart: &Cartridge) -> u8 {
+        let value = match addr & 0x07 {
+            2 => {
+                let value = (self.status & 0xE0) | (self.latch & 0x1F);
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+                value
+            }
+            4 => self.oam.read_byte(self.oam_addr as usize).unwrap_or(0),
+            7 => {
+                let addr = self.v & 0x3FFF;
+                let value = if addr >= 0x3F00 {
+                    // palette reads are direct, the buffer gets the nametable below
+                    self.read_buffer = self.read_memory(addr - 0x1000, cart);
+                    (self.read_memory(addr, cart) & 0x3F) | (self.latch & 0xC0)
+                } else {
+                    let fetched = self.read_memory(addr, cart);
+                    std::mem::replace(&mut self.read_buffer, fetched)
+                };
+                self.increment_v();
+                value
+            }
+            _ => return self.latch,
+        };
+        self.latch = value;
+        value
+    }
+
+    /// CPU write of 0x2000..=0x3FFF
+    pub fn write_register(&mut self, addr: u16, value: u8, cart: &mut Cartridge) {
+        self.latch = value;
+        match addr & 0x07 {
+            0 => {
+                // enabling NMI during vblank raises it at once
+                if self.ctrl & CTRL_NMI == 0 && value & CTRL_NMI != 0 && self.status & STATUS_VBLANK != 0 {
+                    self.nmi = true;
+                }
+                self.ctrl = value;
+                self.t = (self.t & !0x0C00) | ((value & 0x03) as u16) << 10;
+            }
+            1 => self.mask = value,
+            3 => self.oam_addr = value,
+            4 => self.write_oam(value),
+            5 => {
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (value >> 3) as u16;
+                    self.fine_x = value & 0x07;
+                } else {
+                    self.t = (self.t & !0x73E0) | ((value & 0x07) as u16) << 12 | ((value & 0xF8) as u16) << 2;
+                }
+                self.w = !self.w;
+            }
+            6 => {
+                if !self.w {
+                    self.t = (self.t & 0x00FF) | ((value & 0x3F) as u16) << 8;
+                } else {
+                    self.t = (self.t & 0xFF00) | value as u16;
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            7 => {
+                self.write_memory(self.v & 0x3FFF, value, cart);
+                self.increment_v();
+            }
+            _ => {}
+        }
+    }
+
+    /// OAMDATA write, also used by the sprite DMA
+    pub fn write_oam(&mut self, value: u8) {
+        let _ = self.oam.write_byte(self.oam_addr as usize, value);
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    fn rendering(&self) -> bool {
+        self.mask & (MASK_BACKGROUND | MASK_SPRITES) != 0
+    }
+
+    fn increment_v(&mut self) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 { 32 } else { 1 };
+        self.v = self.v.wrapping_add(step) & 0x7FFF;
+    }
+
+    /// Next pixel row, coarse Y wraps to the nametable below after row 29
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | coarse_y << 5;
+    }
+
+    fn read_memory(&self, addr: u16, cart: &Cartridge) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => cart.read_chr(addr),
+            0x2000..=0x3EFF => self.read_nametable(addr, cart),
+            _ => self.palette[palette_index(addr)],
+        }
+    }
+
+    fn write_memory(&mut self, addr: u16, value: u8, cart: &mut Cartridge) {
+        match addr {
+            0x0000..=0x1FFF => cart.write_chr(addr, value),
+            0x2000..=0x3EFF => {
+                let _ = self.nametables.write_byte(cart.mirroring().nametable_offset(addr), value);
+            }
+            _ => self.palette[palette_index(addr)] = value & 0x3F,
+        }
+    }
+
+    fn read_nametable(&self, addr: u16, cart: &Cartridge) -> u8 {
+        self.nametables.read_byte(cart.mirroring().nametable_offset(addr)).unwrap_or(0)
+    }
+
+    fn render_line(&mut self, cart: &Cartridge) {
+        let mut background = [0u8; WIDTH];
+        let mut sprites = [SpritePixel::default(); WIDTH];
+        if self.mask & MASK_BACKGROUND != 0 {
+            self.render_background(cart, &mut background);
+        }
+        if self.mask & MASK_SPRITES != 0 {
+            self.render_sprites(cart, &mut sprites);
+        }
+        let colour_mask = if self.mask & MASK_GREYSCALE != 0 { 0x30 } else { 0x3F };
+        let row = self.scanline as usize * WIDTH;
+        for x in 0..WIDTH {
+            let left = x < 8;
+            let bg = if left && self.mask & MASK_BACKGROUND_LEFT == 0 { 0 } else { background[x] };
+            let sprite = if left && self.mask & MASK_SPRITES_LEFT == 0 { SpritePixel::default() } else { sprites[x] };
+            let opaque_bg = bg & 0x03 != 0;
+            if sprite.zero && opaque_bg && x != 255 {
+                self.status |= STATUS_SPRITE_ZERO;
+            }
+            let index = if sprite.color != 0 && (!opaque_bg || !sprite.behind) {
+                sprite.color
+            } else if opaque_bg {
+                bg
+            } else {
+                0
+            };
+            self.framebuffer.write_pixel(row + x, self.palette[palette_index(index as u16)] & colour_mask);
+        }
+    }
+
+    /// Palette indices 0..=15 of the line, walking the tiles from `v` across the nametables
+    fn render_background(&self, cart: &Cartridge, line: &mut [u8; WIDTH]) {
+        let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 { 0x1000 } else { 0 };
+        let fine_y = (self.v >> 12) & 0x07;
+        let mut v = self.v;
+        let mut x = -(self.fine_x as i32);
+        while x < WIDTH as i32 {
+            let tile = self.read_nametable(0x2000 | (v & 0x0FFF), cart) as u16;
+            let attribute = self.read_nametable(0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07), cart);
+            let shift = ((v >> 4) & 0x04) | (v & 0x02);
+            let palette = ((attribute >> shift) & 0x03) << 2;
+            let addr = table + tile * 16 + fine_y;
+            let (low, high) = (cart.read_chr(addr), cart.read_chr(addr + 8));
+            for bit in 0..8 {
+                let screen_x = x + bit;
+                if (0..WIDTH as i32).contains(&screen_x) {
+                    let color = ((low >> (7 - bit)) & 0x01) | ((high >> (7 - bit)) & 0x01) << 1;
+                    line[screen_x as usize] = if color == 0 { 0 } else { palette | color };
+                }
+            }
+            x += 8;
+            // coarse X wraps to the nametable on the right
+            if v & 0x001F == 31 {
+                v = (v & !0x001F) ^ 0x0400;
+            } else {
+                v += 1;
+            }
+        }
+    }
+
+    /// The first 8 sprites of the line in OAM order, lower indices on top
+    fn render_sprites(&mut self, cart: &Cartridge, line: &mut [SpritePixel; WIDTH]) {
+        let height = if self.ctrl & CTRL_SPRITE_TALL != 0 { 16 } else { 8 };
+        let oam = self.oam.data();
+        let mut count = 0;
+        for (index, sprite) in oam.chunks_exact(4).enumerate() {
+            // sprites show one line below their Y
+            let mut row = self.scanline as i32 - sprite[0] as i32 - 1;
+            if !(0..height).contains(&row) {
+                continue;
+            }
+            if count == SPRITES_PER_LINE {
+                self.status |= STATUS_OVERFLOW;
+                break;
+            }
+            count += 1;
+            let (mut tile, attributes, left) = (sprite[1] as u16, sprite[2], sprite[3] as usize);
+            if attributes & 0x80 != 0 {
+                row = height - 1 - row;
+            }
+            let table = if height == 16 {
+                let table = (tile & 0x01) * 0x1000;
+                tile &= 0xFE;
+                if row >= 8 {
+                    tile += 1;
+                    row -= 8;
+                }
+                table
+            } else if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0
+            };
+            let addr = table + tile * 16 + row as u16;
+            let (low, high) = (cart.read_chr(addr), cart.read_chr(addr + 8));
+            for bit in 0..8 {
+                let x = left + bit;
+                if x >= WIDTH {
+                    break;
+                }
+                let shift = if attributes & 0x40 != 0 { bit } else { 7 - bit };
+                let color = ((low >> shift) & 0x01) | ((high >> shift) & 0x01) << 1;
+                if color == 0 || line[x].color != 0 {
+                    continue;
+                }
+                line[x] = SpritePixel {
+                    color: 0x10 | (attributes & 0x03) << 2 | color,
+                    behind: attributes & 0x20 != 0,
+                    zero: index == 0,
+                };
+            }
+        }
+    }
+}
+
+/// 0x3F10/14/18/1C mirror the backdrop entries 0x3F00/04/08/0C
+fn palette_index(addr: u16) -> usize {
+    let index = addr as usize & 0x1F;
+    if index & 0x13 == 0x10 {
+        index & 0x0F
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod ppu_tests {
+
+    use super::*;
+
+    fn cartridge() -> Cartridge {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 0x4000, 0);
+        // tile 1: opaque with colour 1
+        let mut chr = vec![0u8; 0x2000];
+        chr[0x10..0x18].fill(0xFF);
+        data.extend(chr);
+        Cartridge::from_ines(&data).unwrap()
+    }
+
+    #[test]
+    fn test_registers() {
+        let mut cart = cartridge();
+        let mut ppu = Ppu::new();
+        ppu.write_register(0x2006, 0x3F, &mut cart);
+        ppu.write_register(0x2006, 0x10, &mut cart);
+        ppu.write_register(0x2007, 0x2A, &mut cart);
+        assert_eq!(ppu.palette[0], 0x2A);
+
+        ppu.write_register(0x2006, 0x24, &mut cart);
+        ppu.write_register(0x2006, 0x00, &mut cart);
+        ppu.write_register(0x2007, 0x55, &mut cart);
+        ppu.write_register(0x2006, 0x24, &mut cart);
+        ppu.write_register(0x2006, 0x00, &mut cart);
+        // reads are buffered
+        ppu.read_register(0x2007, &cart);
+        assert_eq!(ppu.read_register(0x2007, &cart), 0x55);
+
+        ppu.write_register(0x2005, 0x7D, &mut cart);
+        ppu.write_register(0x2005, 0x5E, &mut cart);
+        // coarse X 15 and fine Y 6 replace the bits of the 0x2400 address, the nametable stays
+        assert_eq!(ppu.t, 0x656F);
+        assert_eq!(ppu.fine_x, 0x05);
+    }
+
+    #[test]
+    fn test_frame_and_sprite_zero() {
+        let mut cart = cartridge();
+        let mut ppu = Ppu::new();
+        ppu.palette[1] = 0x16;
+        ppu.palette[0x11] = 0x2A;
+        // tile 1 at the top-left, sprite 0 over it on line 1
+        ppu.write_register(0x2006, 0x20, &mut cart);
+        ppu.write_register(0x2006, 0x00, &mut cart);
+        ppu.write_register(0x2007, 0x01, &mut cart);
+        for value in [0x00, 0x01, 0x00, 0x04] {
+            ppu.write_oam(value);
+        }
+        ppu.write_register(0x2006, 0x00, &mut cart);
+        ppu.write_register(0x2006, 0x00, &mut cart);
+        ppu.write_register(0x2000, CTRL_NMI, &mut cart);
+        ppu.write_register(0x2001, 0x1E, &mut cart);
+
+        let frame_dots = DOTS_PER_LINE as u32 * VBLANK_LINE as u32 + 2;
+        assert!(ppu.tick(frame_dots, &mut cart));
+        assert!(ppu.take_nmi());
+        assert_eq!(ppu.framebuffer().read_pixel(0), 0x16);
+        assert_eq!(ppu.framebuffer().read_pixel(WIDTH + 8), 0x2A);
+        assert_eq!(ppu.read_register(0x2002, &cart) & 0xC0, STATUS_VBLANK | STATUS_SPRITE_ZERO);
+        assert_eq!(ppu.read_register(0x2002, &cart) & STATUS_VBLANK, 0);
+    }
+}
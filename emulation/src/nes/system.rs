@@ -0,0 +1,430 @@
+use crate::common::bus::Bus;
+use crate::common::emulator::*;
+use crate::common::message::*;
+use crate::common::palette::Palette;
+use crate::common::ram::{AddressMode, Ram};
+use crate::common::registry::{Capabilities, SystemInfo};
+use crate::common::utils;
+use crate::mos6502::cpu::{Mos6502, Variant};
+use crate::nes::apu::Apu;
+use crate::nes::cartridge::{self, Cartridge};
+use crate::nes::controller::Controller;
+use crate::nes::ppu::{self, Ppu};
+
+/// NTSC CPU clock
+const CLOCK: u64 = 1_789_773;
+const RAM_SIZE: usize = 0x800;
+const PPU_DOTS_PER_CYCLE: u32 = 3;
+/// Plus one when the DMA starts on an odd cycle
+const OAM_DMA_CYCLES: u32 = 513;
+const OAM_DMA_LENGTH: u16 = 0x100;
+const DMC_DMA_CYCLES: u32 = 4;
+/// Buttons of one controller, player 2 keys start at this offset
+pub const PLAYER_2: u32 = 8;
+
+/// 2C02 colours of the 64 palette indices
+const MASTER_PALETTE: [u32; 64] = [
+    0x545454FF, 0x001E74FF, 0x081090FF, 0x300088FF, 0x440064FF, 0x5C0030FF, 0x540400FF, 0x3C1800FF,
+    0x202A00FF, 0x083A00FF, 0x004000FF, 0x003C00FF, 0x00323CFF, 0x000000FF, 0x000000FF, 0x000000FF,
+    0x989698FF, 0x084CC4FF, 0x3032ECFF, 0x5C1EE4FF, 0x8814B0FF, 0xA01464FF, 0x982220FF, 0x783C00FF,
+    0x545A00FF, 0x287200FF, 0x087C00FF, 0x007628FF, 0x006678FF, 0x000000FF, 0x000000FF, 0x000000FF,
+    0xECEEECFF, 0x4C9AECFF, 0x787CECFF, 0xB062ECFF, 0xE454ECFF, 0xEC58B4FF, 0xEC6A64FF, 0xD48820FF,
+    0xA0AA00FF, 0x74C400FF, 0x4CD020FF, 0x38CC6CFF, 0x38B4CCFF, 0x3C3C3CFF, 0x000000FF, 0x000000FF,
+    0xECEEECFF, 0xA8CCECFF, 0xBCBCECFF, 0xD4B2ECFF, 0xECAEECFF, 0xECAED4FF, 0xECB4B0FF, 0xE4C490FF,
+    0xCCD278FF, 0xB4DE78FF, 0xA8E290FF, 0x98E2B4FF, 0xA0D6E4FF, 0xA0A2A0FF, 0x000000FF, 0x000000FF,
+];
+
+/// Everything the CPU sees on its bus
+struct NesBus {
+    ram: Ram,
+    ppu: Ppu,
+    apu: Apu,
+    cartridge: Option<Cartridge>,
+    controllers: [Controller; 2],
+    /// Page written to 0x4014, copied to the OAM after the instruction
+    dma_page: Option<u8>,
+    /// Last value on the data bus, returned where nothing answers
+    open_bus: u8,
+}
+
+impl NesBus {
+    fn new(cartridge: Option<Cartridge>) -> Self {
+        Self {
+            ram: Ram::with_mode(RAM_SIZE, AddressMode::Wrap),
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            cartridge,
+            controllers: [Controller::new(), Controller::new()],
+            dma_page: None,
+            open_bus: 0,
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let value = match addr {
+            // 2K mirrored up to 0x1FFF
+            0x0000..=0x1FFF => self.ram.read_byte(addr as usize).unwrap_or(0),
+            0x2000..=0x3FFF => match self.cartridge.as_ref() {
+                Some(cartridge) => self.ppu.read_register(addr, cartridge),
+                None => self.open_bus,
+            },
+            0x4015 => self.apu.read_status(),
+            0x4016 | 0x4017 => self.controllers[(addr & 0x01) as usize].read() | (self.open_bus & 0xE0),
+            0x4020..=0xFFFF => self.cartridge.as_ref().and_then(|c| c.read(addr)).unwrap_or(self.open_bus),
+            _ => self.open_bus,
+        };
+        self.open_bus = value;
+        value
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.open_bus = value;
+        match addr {
+            0x0000..=0x1FFF => {
+                let _ = self.ram.write_byte(addr as usize, value);
+            }
+            0x2000..=0x3FFF => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    self.ppu.write_register(addr, value, cartridge);
+                }
+            }
+            0x4014 => self.dma_page = Some(value),
+            0x4016 => {
+                for controller in self.controllers.iter_mut() {
+                    controller.write(value);
+                }
+            }
+            0x4000..=0x4017 => self.apu.write(addr, value),
+            0x4020..=0xFFFF => {
+                if let Some(cartridge) = self.cartridge.as_mut() {
+                    cartridge.write(addr, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Copies the page to the OAM through OAMDATA
+    fn oam_dma(&mut self, page: u8) {
+        let start = (page as u16) << 8;
+        for offset in 0..OAM_DMA_LENGTH {
+            let value = self.read(start + offset);
+            self.ppu.write_oam(value);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.apu.irq() || self.cartridge.as_ref().is_some_and(|c| c.irq())
+    }
+
+    /// Runs the PPU and the APU for `cycles` CPU cycles, returns whether a frame was completed
+    fn tick(&mut self, cycles: u32) -> bool {
+        self.apu.tick(cycles);
+        match self.cartridge.as_mut() {
+            Some(cartridge) => self.ppu.tick(cycles * PPU_DOTS_PER_CYCLE, cartridge),
+            None => false,
+        }
+    }
+}
+
+impl Bus for NesBus {
+    fn read_byte(&mut self, addr: usize) -> Result<u8, Box<dyn Msg>> {
+        match u16::try_from(addr) {
+            Ok(addr) => Ok(self.read(addr)),
+            Err(_) => Err(NesBus::out_of_bounds(ErrorTopicId::RamRead, addr)),
+        }
+    }
+
+    fn write_byte(&mut self, addr: usize, value: u8) -> Result<(), Box<dyn Msg>> {
+        match u16::try_from(addr) {
+            Ok(addr) => {
+                self.write(addr, value);
+                Ok(())
+            }
+            Err(_) => Err(NesBus::out_of_bounds(ErrorTopicId::RamWrite, addr)),
+        }
+    }
+
+    /// Memory only, reading the registers has side effects
+    fn peek(&self, addr: usize) -> Option<u8> {
+        match addr {
+            0x0000..=0x1FFF => self.ram.read_byte(addr).ok(),
+            0x4020..=0xFFFF => self.cartridge.as_ref()?.read(addr as u16),
+            _ => None,
+        }
+    }
+}
+
+impl NesBus {
+    fn out_of_bounds(topic: ErrorTopicId, addr: usize) -> Box<dyn Msg> {
+        let err = ErrorMsg::new(topic.into(), ErrorMsgId::OutOfBounds.into())
+            .add_param(addr.to_string())
+            .add_param(String::from("65536"))
+            .add_param(String::from("1"));
+        Box::new(err)
+    }
+}
+
+/// NTSC NES / Famicom
+pub struct Nes {
+    cpu: Mos6502,
+    bus: NesBus,
+    cycle_count: u128,
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Nes {
+    pub fn new() -> Self {
+        Nes {
+            cpu: Mos6502::new(Variant::Ricoh2A03),
+            bus: NesBus::new(None),
+            cycle_count: 0,
+        }
+    }
+
+    /// Recognised by the iNES magic
+    pub fn system_info() -> SystemInfo {
+        SystemInfo {
+            name: "NES",
+            extensions: &["nes"],
+            capabilities: Capabilities {
+                save_states: false,
+                reset: true,
+                sound: true,
+            },
+            factory: || Box::new(Nes::new()),
+            sniff: Some(|rom| rom.starts_with(&cartridge::MAGIC)),
+        }
+    }
+
+    pub fn cpu(&self) -> &Mos6502 {
+        &self.cpu
+    }
+
+    pub fn load_rom(&mut self, file_name: &str) {
+        let rom = match utils::load_rom(file_name) {
+            Ok(rom) => rom,
+            Err(error) => {
+                log::error!(file = file_name; "Cannot load ROM: {}", error);
+                return;
+            }
+        };
+        match Cartridge::from_ines(&rom) {
+            Ok(cartridge) => {
+                log::debug!(mapper = cartridge.mapper_id(), battery = cartridge.has_battery(); "Cartridge inserted");
+                self.insert(cartridge);
+            }
+            Err(err) => log::error!(file = file_name; "{}", err),
+        }
+    }
+
+    /// Powers the console on with `cartridge`
+    fn insert(&mut self, cartridge: Cartridge) {
+        self.cpu = Mos6502::new(Variant::Ricoh2A03);
+        self.bus = NesBus::new(Some(cartridge));
+        self.cycle_count = 0;
+        self.reset_cpu();
+    }
+
+    /// The PPU and APU keep running during the reset sequence
+    fn reset_cpu(&mut self) {
+        let start = self.cpu.cycles;
+        if let Err(err) = self.cpu.reset(&mut self.bus) {
+            log::error!("{}", err);
+        }
+        let cycles = (self.cpu.cycles - start) as u32;
+        self.bus.tick(cycles);
+        self.cycle_count += cycles as u128;
+    }
+}
+
+impl Emulator for Nes {
+    fn video_buffer(&self) -> &[u8] {
+        self.bus.ppu.framebuffer().video()
+    }
+
+    fn palette(&self) -> Palette {
+        Palette::new("2C02", MASTER_PALETTE.to_vec())
+    }
+
+    /// One instruction, then the PPU and APU catch up
+    fn cycle(&mut self) -> Result<CycleResult, Box<dyn Msg>> {
+        if self.bus.cartridge.is_none() {
+            return Ok(CycleResult::default());
+        }
+        self.cpu.set_irq(self.bus.irq());
+        let mut cycles = self.cpu.step(&mut self.bus)?;
+        let mut stall = 0;
+        if let Some(page) = self.bus.dma_page.take() {
+            stall += OAM_DMA_CYCLES + (self.cpu.cycles & 1) as u32;
+            self.bus.oam_dma(page);
+        }
+        if let Some(addr) = self.bus.apu.dmc_request() {
+            let value = self.bus.read(addr);
+            self.bus.apu.dmc_fill(value);
+            stall += DMC_DMA_CYCLES;
+        }
+        self.cpu.cycles += stall as u64;
+        cycles += stall;
+
+        let video_buff_changed = self.bus.tick(cycles);
+        if self.bus.ppu.take_nmi() {
+            self.cpu.nmi();
+        }
+        self.cycle_count += cycles as u128;
+        Ok(CycleResult {
+            video_buff_changed,
+            total_cycle_count: self.cycle_count,
+            last_cycle_count: cycles as u128,
+        })
+    }
+
+    /// The PPU follows the clock in `cycle`, the frame end only resamples the APU output
+    fn end_frame(&mut self) {
+        self.bus.apu.end_frame();
+    }
+
+    fn process_input(&mut self, key: u32, pressed: bool) {
+        match key {
+            0..=7 => self.bus.controllers[0].set_button(key, pressed),
+            8..=15 => self.bus.controllers[1].set_button(key - PLAYER_2, pressed),
+            _ => {}
+        }
+    }
+
+    fn load_rom(&mut self, file_name: &String) {
+        self.load_rom(file_name);
+    }
+
+    fn resolution(&self) -> [u32; 2] {
+        [ppu::WIDTH as u32, ppu::HEIGHT as u32]
+    }
+
+    fn cycles_in_sec(&self) -> u64 {
+        CLOCK
+    }
+
+    fn sound_active(&self) -> bool {
+        self.bus.apu.active()
+    }
+
+    fn audio_samples(&self) -> Option<&[i16]> {
+        self.bus.apu.samples()
+    }
+
+    /// The reset button: CPU, PPU control registers and sound channels, memories are kept
+    fn reset(&mut self) {
+        if self.bus.cartridge.is_none() {
+            return;
+        }
+        self.bus.apu.write(0x4015, 0);
+        self.bus.ppu.reset();
+        self.reset_cpu();
+    }
+
+    fn power_cycle(&mut self) {
+        self.cpu = Mos6502::new(Variant::Ricoh2A03);
+        self.bus = NesBus::new(None);
+        self.cycle_count = 0;
+    }
+
+    fn save_state(&self) -> Result<Vec<u8>, Box<dyn Msg>> {
+        let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::NotSupported.into());
+        Err(Box::new(err))
+    }
+
+    fn load_state(&mut self, _state: &[u8]) -> Result<(), Box<dyn Msg>> {
+        let err = ErrorMsg::new(ErrorTopicId::SaveState.into(), ErrorMsgId::NotSupported.into());
+        Err(Box::new(err))
+    }
+
+    /// nestest log layout
+    fn trace(&self) -> Option<String> {
+        let (scanline, dot) = self.bus.ppu.position();
+        Some(self.cpu.trace_with(&self.bus, &format!("PPU:{:>3},{:>3} ", scanline, dot)))
+    }
+
+    fn set_pc(&mut self, pc: u32) -> Result<(), Box<dyn Msg>> {
+        self.cpu.pc = pc as u16;
+        Ok(())
+    }
+}
+
+/// Shift register order of the controller, add `PLAYER_2` for the second one
+pub enum NesKeys {
+    A = 0,
+    B = 1,
+    Select = 2,
+    Start = 3,
+    Up = 4,
+    Down = 5,
+    Left = 6,
+    Right = 7,
+}
+
+#[cfg(test)]
+mod nes_tests {
+
+    use super::*;
+
+    /// NROM image, `program` at 0xC000 where all vectors point
+    fn with_program(program: &[u8]) -> Nes {
+        let mut data = vec![0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xEAu8; 0x4000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0xC0, 0x00, 0xC0, 0x00, 0xC0]);
+        data.extend(prg);
+        data.extend(vec![0u8; 0x2000]);
+        let mut nes = Nes::new();
+        nes.insert(Cartridge::from_ines(&data).unwrap());
+        nes
+    }
+
+    #[test]
+    fn test_trace_and_controller() {
+        // LDA #$01; STA $4016; LSR A; STA $4016; LDA $4016; LDX $4016; JMP $C00F
+        let program = [
+            0xA9, 0x01, 0x8D, 0x16, 0x40, 0x4A, 0x8D, 0x16, 0x40, 0xAD, 0x16, 0x40, 0xAE, 0x16, 0x40, 0x4C, 0x0F, 0xC0,
+        ];
+        let mut nes = with_program(&program);
+        assert_eq!(
+            nes.trace().unwrap(),
+            "C000  A9 01     LDA #$01                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+        nes.process_input(NesKeys::B as u32, true);
+        for _ in 0..6 {
+            nes.cycle().unwrap();
+        }
+        // A first, then B
+        assert_eq!(nes.cpu().a & 0x01, 0);
+        assert_eq!(nes.cpu().x & 0x01, 1);
+        assert_eq!(nes.cpu().cycles, 7 + 2 + 4 + 2 + 4 + 4 + 4);
+    }
+
+    #[test]
+    fn test_vblank_nmi_and_dma() {
+        // LDA #$80; STA $2000; LDA #$02; STA $4014; JMP $C00A
+        let program = [0xA9, 0x80, 0x8D, 0x00, 0x20, 0xA9, 0x02, 0x8D, 0x14, 0x40, 0x4C, 0x0A, 0xC0];
+        let mut nes = with_program(&program);
+        let mut frame_cycles = 0;
+        let mut dma_cycles = 0;
+        loop {
+            let res = nes.cycle().unwrap();
+            dma_cycles = dma_cycles.max(res.last_cycle_count);
+            frame_cycles += res.last_cycle_count;
+            if res.video_buff_changed {
+                break;
+            }
+        }
+        assert!(dma_cycles >= (OAM_DMA_CYCLES + 4) as u128);
+        // vblank starts 241 lines into the frame
+        assert!((27_380..27_400).contains(&frame_cycles));
+        nes.cycle().unwrap();
+        assert_eq!(nes.cpu().pc, 0xC000);
+    }
+}
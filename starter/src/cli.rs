@@ -0,0 +1,83 @@
+use crate::logger;
+use emulation::common::filter::Filter;
+
+const DEFAULT_ROM: &str = "D:/Projects/rusty-emul/chip8-roms/games/Airplane.ch8";
+
+pub struct Args {
+    pub rom: String,
+    pub headless: bool,
+    pub frames: u32,
+    pub scale: u32,
+    pub screenshot: Option<String>,
+    pub golden: Option<String>,
+    pub record: Option<String>,
+    pub filter: Filter,
+    /// Logger spec, e.g. `warn,emulation::chip8=debug`
+    pub log_level: String,
+    pub log_file: Option<String>,
+    /// Reference trace log the emulator is stepped against, e.g. nestest.log
+    pub trace_log: Option<String>,
+    /// Serial output that makes the run pass, e.g. `Passed` for the Blargg test ROMs
+    pub expect_serial: Option<String>,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            rom: String::from(DEFAULT_ROM),
+            headless: false,
+            frames: 60,
+            scale: 1,
+            screenshot: None,
+            golden: None,
+            record: None,
+            filter: Filter::Nearest,
+            log_level: String::from(logger::DEFAULT_LEVEL),
+            log_file: None,
+            trace_log: None,
+            expect_serial: None,
+        }
+    }
+}
+
+pub const USAGE: &str = "Usage: starter [ROM] [--headless] [--frames N] [--scale N] [--screenshot FILE] [--golden FILE] [--filter NAME] [--record FILE.gif|FILE.y4m|FILE.avi] [--log-level SPEC] [--log-file FILE] [--trace-log FILE] [--expect-serial TEXT]
+  --record: GIF has no sound; Y4M has no audio track either, its sound is written to FILE.wav next to it;
+  AVI carries the video and the sound in one file";
+
+impl Args {
+    pub fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut result = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => result.headless = true,
+                "--frames" => result.frames = Args::number(&arg, args.next())?,
+                "--scale" => result.scale = Args::number(&arg, args.next())?,
+                "--screenshot" => result.screenshot = Some(Args::value(&arg, args.next())?),
+                "--golden" => result.golden = Some(Args::value(&arg, args.next())?),
+                "--record" => result.record = Some(Args::value(&arg, args.next())?),
+                "--log-level" => result.log_level = Args::value(&arg, args.next())?,
+                "--log-file" => result.log_file = Some(Args::value(&arg, args.next())?),
+                "--trace-log" => result.trace_log = Some(Args::value(&arg, args.next())?),
+                "--expect-serial" => result.expect_serial = Some(Args::value(&arg, args.next())?),
+                "--filter" => {
+                    let name = Args::value(&arg, args.next())?;
+                    result.filter = Filter::from_name(&name).ok_or_else(|| format!("Unknown filter {}", name))?;
+                }
+                _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+                _ => result.rom = arg,
+            }
+        }
+        Ok(result)
+    }
+
+    fn value(name: &str, value: Option<String>) -> Result<String, String> {
+        value.ok_or_else(|| format!("Missing value for {}", name))
+    }
+
+    fn number(name: &str, value: Option<String>) -> Result<u32, String> {
+        let value = Args::value(name, value)?;
+        value
+            .parse()
+            .map_err(|_| format!("Invalid value for {}: {}", name, value))
+    }
+}
@@ -0,0 +1,214 @@
+use crate::cli::Args;
+use emulation::common::emulator::EmulMgr;
+use emulation::common::message::Msg;
+use emulation::common::record::{FrameSound, RecordFormat, Recorder};
+use emulation::common::screenshot::RgbaImage;
+
+/// Test ROMs print this on the serial port when a test fails
+const SERIAL_FAILED: &str = "Failed";
+
+/// Runs the emulator without a window, returns the process exit code
+pub fn run(args: &Args, emul: &mut EmulMgr) -> i32 {
+    if let Some(file_name) = &args.trace_log {
+        return compare_trace(emul, file_name);
+    }
+    let mut recorder = match &args.record {
+        Some(file_name) => match start_recording(args, emul, file_name) {
+            Ok(recorder) => Some(recorder),
+            Err(err) => {
+                log::error!("{}", err);
+                return 1;
+            }
+        },
+        None => None,
+    };
+    for frame in 0..args.frames {
+        if let Err(err) = emul.run_frame() {
+            log::error!(frame = frame; "{}", err);
+            return 1;
+        }
+        if let Some(rec) = recorder.as_mut() {
+            let pushed = emul
+                .video_buffer()
+                .and_then(|buffer| rec.push_frame(buffer, FrameSound::new(emul.sound_active(), emul.audio_samples())));
+            if let Err(err) = pushed {
+                log::error!("{}", err);
+                return 1;
+            }
+        }
+        if args.expect_serial.as_ref().is_some_and(|expected| serial_finished(emul, expected)) {
+            break;
+        }
+    }
+    if let Some(rec) = recorder.take() {
+        if let Some(audio_file) = rec.audio_file() {
+            log::info!(file = audio_file.as_str(); "Y4M has no audio track, the sound is written to a WAV file");
+        }
+        if let Err(err) = rec.finish() {
+            log::error!("{}", err);
+            return 1;
+        }
+    }
+
+    let image = match emul
+        .palette()
+        .and_then(|palette| RgbaImage::from_emulator(emul, &palette, args.scale, args.filter))
+    {
+        Ok(image) => image,
+        Err(err) => {
+            log::error!("{}", err);
+            return 1;
+        }
+    };
+    if let Some(file_name) = &args.screenshot {
+        if let Err(err) = image.save_png(file_name) {
+            log::error!("{}", err);
+            return 1;
+        }
+    }
+    if let Some(expected) = &args.expect_serial {
+        let code = check_serial(emul, expected, args.frames);
+        if code != 0 {
+            return code;
+        }
+    }
+    if let Some(golden) = &args.golden {
+        return compare_golden(&image, golden);
+    }
+    0
+}
+
+/// The test ROM printed the expected text or a failure
+fn serial_finished(emul: &EmulMgr, expected: &str) -> bool {
+    emul.serial_output()
+        .is_some_and(|output| output.contains(expected) || output.contains(SERIAL_FAILED))
+}
+
+/// A failure report or running out of frames before the expected text fails the run
+fn check_serial(emul: &EmulMgr, expected: &str, frames: u32) -> i32 {
+    let output = match emul.serial_output() {
+        Some(output) => output,
+        None => {
+            log::error!("The running system has no serial output");
+            return 1;
+        }
+    };
+    if output.contains(SERIAL_FAILED) {
+        log::error!(output = output; "Test ROM reported a failure");
+        1
+    } else if output.contains(expected) {
+        log::info!(expected = expected; "Serial output matches");
+        0
+    } else {
+        log::error!(expected = expected, frames = frames, output = output; "Expected serial output not seen in time");
+        1
+    }
+}
+
+fn start_recording(args: &Args, emul: &EmulMgr, file_name: &str) -> Result<Recorder, String> {
+    let format = RecordFormat::from_file_name(file_name)
+        .ok_or_else(|| format!("Unsupported recording format: {}", file_name))?;
+    let to_string = |err: Box<dyn Msg>| err.to_string();
+    let palette = emul.palette().map_err(to_string)?;
+    let resolution = emul.resolution().map_err(to_string)?;
+    Recorder::start(file_name, format, resolution, &palette, args.scale).map_err(to_string)
+}
+
+fn compare_golden(image: &RgbaImage, golden: &str) -> i32 {
+    match RgbaImage::load_png(golden) {
+        Ok(expected) if expected == *image => 0,
+        Ok(_) => {
+            let actual = format!("{}.actual.png", golden.trim_end_matches(".png"));
+            log::error!(golden = golden, actual = actual.as_str(); "Framebuffer differs from the golden image");
+            if let Err(err) = image.save_png(&actual) {
+                log::error!("{}", err);
+            }
+            1
+        }
+        Err(err) => {
+            log::error!("{}", err);
+            1
+        }
+    }
+}
+
+/// Steps one instruction per line of the reference log, starting at the address of its first line
+fn compare_trace(emul: &mut EmulMgr, file_name: &str) -> i32 {
+    let reference = match std::fs::read_to_string(file_name) {
+        Ok(reference) => reference,
+        Err(err) => {
+            log::error!(file = file_name; "Cannot read trace log: {}", err);
+            return 1;
+        }
+    };
+    let lines: Vec<&str> = reference.lines().filter(|line| !line.trim().is_empty()).collect();
+    let start = lines
+        .first()
+        .and_then(|line| line.split_whitespace().next())
+        .and_then(|pc| u32::from_str_radix(pc, 16).ok());
+    let start = match start {
+        Some(start) => start,
+        None => {
+            log::error!(file = file_name; "Trace log does not start with an address");
+            return 1;
+        }
+    };
+    if let Err(err) = emul.set_pc(start) {
+        log::error!("{}", err);
+        return 1;
+    }
+    for (index, expected) in lines.iter().enumerate() {
+        let actual = match emul.trace() {
+            Some(actual) => actual,
+            None => {
+                log::error!("The running system has no trace output");
+                return 1;
+            }
+        };
+        if !trace_matches(&actual, expected) {
+            log::error!(line = index + 1, expected = *expected, actual = actual.as_str(); "Trace differs from the reference log");
+            return 1;
+        }
+        if let Err(err) = emul.step() {
+            log::error!(line = index + 1; "{}", err);
+            return 1;
+        }
+    }
+    log::info!(lines = lines.len(); "Trace matches the reference log");
+    0
+}
+
+/// The address, the opcode bytes and every `NAME:value` field of the reference must be in the emulator
+/// line with the same value, the emulator may add fields the reference has not
+fn trace_matches(actual: &str, expected: &str) -> bool {
+    if trace_bytes(actual) != trace_bytes(expected) {
+        return false;
+    }
+    let actual_fields = trace_fields(actual);
+    trace_fields(expected)
+        .iter()
+        .all(|field| actual_fields.contains(field))
+}
+
+/// Address and up to three opcode bytes at the start of the line
+fn trace_bytes(line: &str) -> Vec<&str> {
+    let mut columns = line.split_whitespace();
+    let address = columns.next().into_iter();
+    let bytes = columns
+        .take(3)
+        .take_while(|column| column.len() == 2 && column.chars().all(|c| c.is_ascii_hexdigit()));
+    address.chain(bytes).collect()
+}
+
+/// `NAME:value` fields, the value runs up to the next field so `PPU:  0, 21` is `("PPU", "0,21")`
+fn trace_fields(line: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for column in line.split_whitespace() {
+        match (column.split_once(':'), fields.last_mut()) {
+            (Some((name, value)), _) => fields.push((String::from(name), String::from(value))),
+            (None, Some((_, value))) => value.push_str(column),
+            (None, None) => {}
+        }
+    }
+    fields
+}
@@ -0,0 +1,98 @@
+use log::kv::{self, Key, Value, VisitSource};
+use log::{LevelFilter, Log, Metadata, Record};
+
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+pub const DEFAULT_LEVEL: &str = "info";
+
+/// Writes records to stderr and optionally to a file, with levels per module path.
+/// The spec is a comma separated list of `level` and `module=level`, e.g. `warn,emulation::chip8=debug`
+pub struct Logger {
+    default: LevelFilter,
+    /// Longest module prefix first
+    modules: Vec<(String, LevelFilter)>,
+    file: Option<Mutex<File>>,
+}
+
+impl Logger {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let mut logger = Logger {
+            default: LevelFilter::Info,
+            modules: Vec::new(),
+            file: None,
+        };
+        for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('=') {
+                Some((module, level)) => logger.modules.push((String::from(module), Logger::level(level)?)),
+                None => logger.default = Logger::level(part)?,
+            }
+        }
+        logger.modules.sort_by_key(|m| std::cmp::Reverse(m.0.len()));
+        Ok(logger)
+    }
+
+    pub fn set_file(&mut self, file_name: &str) -> Result<(), String> {
+        let file = File::create(file_name).map_err(|e| format!("Cannot create log file {}: {}", file_name, e))?;
+        self.file = Some(Mutex::new(file));
+        Ok(())
+    }
+
+    pub fn install(self) -> Result<(), String> {
+        let max_level = self.modules.iter().map(|m| m.1).chain([self.default]).max().unwrap_or(LevelFilter::Off);
+        log::set_boxed_logger(Box::new(self)).map_err(|e| e.to_string())?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+
+    fn level(name: &str) -> Result<LevelFilter, String> {
+        LevelFilter::from_str(name.trim()).map_err(|_| format!("Unknown log level {}", name))
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| target == module || target.starts_with(&format!("{}::", module)))
+            .map_or(self.default, |m| m.1)
+    }
+}
+
+/// Appends the structured key-values as ` key=value`
+struct KeyValues<'a>(&'a mut String);
+
+impl<'kvs, 'a> VisitSource<'kvs> for KeyValues<'a> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.push_str(&format!(" {}={}", key, value));
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut line = format!("[{:<5} {}] {}", record.level(), record.target(), record.args());
+        let _ = record.key_values().visit(&mut KeyValues(&mut line));
+        eprintln!("{}", line);
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
@@ -1,12 +1,39 @@
 use emulation::common::emulator::EmulMgr;
-use emulation::chip8::chip8::Chip8;
+
+mod cli;
+mod headless;
+mod logger;
 
 fn main() {
-    let mut chip = Chip8::new();
-    chip.load_rom(&String::from("D:/Projects/rusty-emul/chip8-roms/games/Airplane.ch8"));
-    
+    let args = match cli::Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{}\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(err) = init_logger(&args) {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    }
+
     let mut emul = EmulMgr::default();
-    emul.set_emulator(Box::new(chip));
+    if let Err(err) = emul.open(&args.rom) {
+        log::error!(file = args.rom.as_str(); "Cannot open ROM: {}", err);
+        std::process::exit(1);
+    }
 
+    if args.headless {
+        std::process::exit(headless::run(&args, &mut emul));
+    }
     ui::show(emul);
-}
\ No newline at end of file
+}
+
+fn init_logger(args: &cli::Args) -> Result<(), String> {
+    let mut logger = logger::Logger::parse(&args.log_level)?;
+    if let Some(file_name) = &args.log_file {
+        logger.set_file(file_name)?;
+    }
+    logger.install()
+}
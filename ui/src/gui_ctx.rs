@@ -2,24 +2,129 @@ use json_gettext::JSONGetText;
 use glium::backend::Facade;
 use imgui::{ Ui, Textures };
 use imgui_glium_renderer::Texture;
+use std::collections::HashMap;
+use emulation::common::filter::Filter;
+use emulation::common::message::{self, Msg};
+use emulation::common::palette::Palette;
+use emulation::common::phosphor::DisplayMode;
+use emulation::common::record::RecordFormat;
 
 pub enum GuiMode {
     GAME,
     DEBUG,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    /// Fixed `render_scale`
+    Fixed,
+    /// Largest integer scale that fits the window
+    FitInteger,
+    /// Fills the window keeping the aspect ratio
+    FitFractional,
+}
+
+impl ScaleMode {
+    pub fn all() -> [ScaleMode; 3] {
+        [ScaleMode::Fixed, ScaleMode::FitInteger, ScaleMode::FitFractional]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScaleMode::Fixed => "Fixed scale",
+            ScaleMode::FitInteger => "Fit window (integer)",
+            ScaleMode::FitFractional => "Fit window (fractional)",
+        }
+    }
+}
+
+/// Languages of `langs/*.json`, the first one is the default
+pub const LANGUAGES: [&str; 2] = ["en_US", "ru_RU"];
+
 pub struct UiState {
     pub open_file: bool,
+    /// ROM picked in the open window, sent with the next frame
+    pub load_rom: Option<String>,
+    pub language: String,
+    /// Errors and statuses waiting to be shown to the user
+    pub messages: Vec<Box<dyn Msg>>,
+    pub show_console: bool,
     pub gui_mode: GuiMode,
-    pub render_scale: u32
+    pub render_scale: u32,
+    pub scale_mode: ScaleMode,
+    pub fullscreen: bool,
+    /// Emulation speed multiplier
+    pub speed: f64,
+    /// Fast-forward while held
+    pub turbo: bool,
+    pub frame_advance: bool,
+    pub toggle_pause: bool,
+    pub reset: bool,
+    pub power_cycle: bool,
+    pub save_state: Option<u8>,
+    pub load_state: Option<u8>,
+    /// Executes one instruction while paused
+    pub debug_step: bool,
+    pub filter: Filter,
+    /// Display mode per ROM name
+    pub display_modes: HashMap<String, DisplayMode>,
+    /// User selected palette, the emulator one is used when none
+    pub palette: Option<Palette>,
+    pub show_palette_editor: bool,
+    pub take_screenshot: bool,
+    /// Screenshots at `render_scale` instead of native resolution
+    pub screenshot_scaled: bool,
+    pub screenshot_to_clipboard: bool,
+    pub start_recording: Option<RecordFormat>,
+    pub stop_recording: bool,
 }
 
 impl Default for UiState {
     fn default() -> Self {
         Self {
             open_file: false,
+            load_rom: None,
+            language: String::from(LANGUAGES[0]),
+            messages: Vec::new(),
+            show_console: false,
             gui_mode: GuiMode::GAME,
             render_scale: 10,
+            scale_mode: ScaleMode::FitInteger,
+            fullscreen: false,
+            speed: 1.0,
+            turbo: false,
+            frame_advance: false,
+            toggle_pause: false,
+            reset: false,
+            power_cycle: false,
+            save_state: None,
+            load_state: None,
+            debug_step: false,
+            filter: Filter::Nearest,
+            display_modes: HashMap::new(),
+            palette: None,
+            show_palette_editor: false,
+            take_screenshot: false,
+            screenshot_scaled: false,
+            screenshot_to_clipboard: false,
+            start_recording: None,
+            stop_recording: false,
+        }
+    }
+}
+
+impl UiState {
+    pub fn display_mode(&self, rom_name: &Option<String>) -> DisplayMode {
+        rom_name
+            .as_ref()
+            .and_then(|name| self.display_modes.get(name))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn set_display_mode(&mut self, rom_name: &Option<String>, mode: DisplayMode) {
+        if let Some(name) = rom_name {
+            self.display_modes.insert(name.clone(), mode);
         }
     }
 }
@@ -56,14 +161,30 @@ impl<'a> GuiCtx<'a> {
         self.facade
     }
 
-    pub fn localize(&'a self, text: &str) -> String {
-        let txt = self.local.get_text(text);
-        match txt {
-            Some(t) => String::from(t.as_str().unwrap()),
+    pub fn localize(&self, text: &str) -> String {
+        self.localize_in(&self.state.language, text)
+    }
+
+    pub fn localize_in(&self, language: &str, text: &str) -> String {
+        let txt = self.local.get_text_with_key(language, text);
+        match txt.as_ref().and_then(|t| t.as_str()) {
+            Some(t) => String::from(t),
             _ => String::from(text)
         }
     }
 
+    /// Catalog text of `topic.id` with the message params substituted
+    pub fn localize_msg(&self, msg: &dyn Msg) -> String {
+        let key = format!("{}.{}", msg.topic_id(), msg.msg_id());
+        match self.local.get_text_with_key(&self.state.language, &key) {
+            Some(t) => match t.as_str() {
+                Some(template) => message::format_template(template, msg.params()),
+                None => msg.to_string(),
+            },
+            None => msg.to_string(),
+        }
+    }
+
     pub fn state(&mut self) -> &mut UiState {
         self.state
     }
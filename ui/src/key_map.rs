@@ -0,0 +1,88 @@
+use emulation::atari2600::system::Atari2600Keys;
+use emulation::chip8::chip8::{Chip8Keys, BUTTONS};
+use emulation::chip8::config::BUTTON_NAMES;
+use emulation::common::input::InputMap;
+use emulation::gb::system::GbKeys;
+use emulation::invaders::system::InvadersKeys;
+use emulation::nes::system::NesKeys;
+use glium::glutin::event::VirtualKeyCode;
+
+/// Keyboard layout of a system by its registry name, the F keys and Tab stay hotkeys
+pub fn input_map(system: &str) -> InputMap {
+    let keys: Vec<(VirtualKeyCode, u32)> = match system {
+        // the 4x4 keypad on the left of a QWERTY keyboard, the arrows and buttons
+        // go through the key layout of the ROM
+        "CHIP-8" => vec![
+            (VirtualKeyCode::Key1, Chip8Keys::Num1 as u32),
+            (VirtualKeyCode::Key2, Chip8Keys::Num2 as u32),
+            (VirtualKeyCode::Key3, Chip8Keys::Num3 as u32),
+            (VirtualKeyCode::Key4, Chip8Keys::C as u32),
+            (VirtualKeyCode::Q, Chip8Keys::Num4 as u32),
+            (VirtualKeyCode::W, Chip8Keys::Num5 as u32),
+            (VirtualKeyCode::E, Chip8Keys::Num6 as u32),
+            (VirtualKeyCode::R, Chip8Keys::D as u32),
+            (VirtualKeyCode::A, Chip8Keys::Num7 as u32),
+            (VirtualKeyCode::S, Chip8Keys::Num8 as u32),
+            (VirtualKeyCode::D, Chip8Keys::Num9 as u32),
+            (VirtualKeyCode::F, Chip8Keys::E as u32),
+            (VirtualKeyCode::Z, Chip8Keys::A as u32),
+            (VirtualKeyCode::X, Chip8Keys::Num0 as u32),
+            (VirtualKeyCode::C, Chip8Keys::B as u32),
+            (VirtualKeyCode::V, Chip8Keys::F as u32),
+            (VirtualKeyCode::Up, chip8_button("up")),
+            (VirtualKeyCode::Down, chip8_button("down")),
+            (VirtualKeyCode::Left, chip8_button("left")),
+            (VirtualKeyCode::Right, chip8_button("right")),
+            (VirtualKeyCode::Space, chip8_button("a")),
+            (VirtualKeyCode::Return, chip8_button("b")),
+        ],
+        "Space Invaders" => vec![
+            (VirtualKeyCode::C, InvadersKeys::Coin as u32),
+            (VirtualKeyCode::Key1, InvadersKeys::P1Start as u32),
+            (VirtualKeyCode::Key2, InvadersKeys::P2Start as u32),
+            (VirtualKeyCode::Space, InvadersKeys::P1Fire as u32),
+            (VirtualKeyCode::Left, InvadersKeys::P1Left as u32),
+            (VirtualKeyCode::Right, InvadersKeys::P1Right as u32),
+            (VirtualKeyCode::T, InvadersKeys::Tilt as u32),
+        ],
+        "Game Boy" => vec![
+            (VirtualKeyCode::Right, GbKeys::Right as u32),
+            (VirtualKeyCode::Left, GbKeys::Left as u32),
+            (VirtualKeyCode::Up, GbKeys::Up as u32),
+            (VirtualKeyCode::Down, GbKeys::Down as u32),
+            (VirtualKeyCode::X, GbKeys::A as u32),
+            (VirtualKeyCode::Z, GbKeys::B as u32),
+            (VirtualKeyCode::RShift, GbKeys::Select as u32),
+            (VirtualKeyCode::Return, GbKeys::Start as u32),
+        ],
+        "NES" => vec![
+            (VirtualKeyCode::X, NesKeys::A as u32),
+            (VirtualKeyCode::Z, NesKeys::B as u32),
+            (VirtualKeyCode::RShift, NesKeys::Select as u32),
+            (VirtualKeyCode::Return, NesKeys::Start as u32),
+            (VirtualKeyCode::Up, NesKeys::Up as u32),
+            (VirtualKeyCode::Down, NesKeys::Down as u32),
+            (VirtualKeyCode::Left, NesKeys::Left as u32),
+            (VirtualKeyCode::Right, NesKeys::Right as u32),
+        ],
+        "Atari 2600" => vec![
+            (VirtualKeyCode::Up, Atari2600Keys::Up as u32),
+            (VirtualKeyCode::Down, Atari2600Keys::Down as u32),
+            (VirtualKeyCode::Left, Atari2600Keys::Left as u32),
+            (VirtualKeyCode::Right, Atari2600Keys::Right as u32),
+            (VirtualKeyCode::Space, Atari2600Keys::Fire as u32),
+            (VirtualKeyCode::Return, Atari2600Keys::Reset as u32),
+            (VirtualKeyCode::RShift, Atari2600Keys::Select as u32),
+            (VirtualKeyCode::C, Atari2600Keys::TvType as u32),
+        ],
+        _ => Vec::new(),
+    };
+    let keys: Vec<(u32, u32)> = keys.into_iter().map(|(host, emul)| (host as u32, emul)).collect();
+    InputMap::new(&keys)
+}
+
+/// Logical button by its `BUTTON_NAMES` name
+fn chip8_button(name: &str) -> u32 {
+    let index = BUTTON_NAMES.iter().position(|button| *button == name).unwrap_or_default();
+    BUTTONS + index as u32
+}
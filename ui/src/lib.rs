@@ -1,12 +1,14 @@
 use crate::win::main::MainWindow;
 use emulation::common::emulator::EmulMgr;
 use emulation::common::input::*;
+use emulation::common::message::{ErrorTopicId, InfoMsgId, Msg, StatusMsg};
+use emulation::common::emul_thread::{Command, DebugCommand, EmulThread, Event as EmulEvent};
 use gilrs::{Button, Gilrs};
 use glium::backend::Facade;
 use glium::glutin;
-use glium::glutin::event::{ElementState, Event, WindowEvent};
+use glium::glutin::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
 use glium::glutin::event_loop::{ControlFlow, EventLoop};
-use glium::glutin::window::WindowBuilder;
+use glium::glutin::window::{Fullscreen, WindowBuilder};
 use imgui::sys::igGetMainViewport;
 use json_gettext::JSONGetText;
 
@@ -15,12 +17,12 @@ use imgui::{Context, FontConfig, FontGlyphRanges, FontSource, TextureId, Ui};
 use imgui_glium_renderer::Renderer;
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
 
-use std::time::{ Instant, Duration };
+use std::time::Instant;
 #[macro_use]
 extern crate json_gettext;
 
 mod gui_ctx;
-mod render;
+mod key_map;
 mod ui_error;
 mod win;
 
@@ -34,11 +36,12 @@ pub struct System {
     pub renderer: Renderer,
     pub font_size: f32,
     pub texture_id: Option<TextureId>,
-    pub emul: EmulMgr,
+    pub emul: EmulThread,
+    pub title: String,
 }
 
 impl System {
-    pub fn main_loop<F: FnMut(&mut bool, &EmulMgr, &Ui, &mut GuiCtx) + 'static>(
+    pub fn main_loop<F: FnMut(&mut bool, &EmulThread, &Ui, &mut GuiCtx) + 'static>(
         self,
         mut run_ui: F,
     ) {
@@ -49,6 +52,7 @@ impl System {
             mut platform,
             mut renderer,
             mut emul,
+            title,
             ..
         } = self;
 
@@ -56,21 +60,26 @@ impl System {
         let mut start = std::time::Instant::now();
         let mut frames = 0;
         let mut state = UiState::default();
+        let mut fullscreen = false;
         let loc = init_local();
         let mut gilrs = Gilrs::new().unwrap();
-        let cycles_in_ms = 1.0 / emul.cycles_in_sec().unwrap() as f64 * Duration::from_secs(1).as_millis() as f64;
+        let mut speed = state.speed;
+        let mut turbo = state.turbo;
+        let mut paused = false;
+        let mut input_map = InputMap::default();
+        let mut input_system: Option<&'static str> = None;
 
         event_loop.run(move |event, _, control_flow| {
 
             if let Some(gilrs::Event { id, event, time }) = gilrs.next_event() {
-                println!("{:?}: {:?}", id, event);
+                log::debug!("Gamepad {:?}: {:?}", id, event);
             }
             
             match event {
                 Event::NewEvents(_) => {
                     frames += 1;
                     if start.elapsed().as_secs() >= 1 {
-                        println!(
+                        log::trace!(
                             "FPS: {:.0}",
                             frames as f64 / start.elapsed().as_millis() as f64 * 1000.0
                         );
@@ -82,13 +91,65 @@ impl System {
                     imgui.io_mut().update_delta_time(delta_time);
                     last_frame = now;
 
-                    let cycle_count = (delta_time.as_millis() as f64 / cycles_in_ms).round() as u128;
-                    let mut cnt = 0u128;
-                    while cnt <= cycle_count {
-                        match emul.cycle() {
-                            Ok(res) => cnt += res.last_cycle_count,
-                            Err(_) => {}
-                        }
+                    if state.speed != speed {
+                        speed = state.speed;
+                        emul.send(Command::SetSpeed(speed));
+                    }
+                    if state.turbo != turbo {
+                        turbo = state.turbo;
+                        emul.send(Command::SetTurbo(turbo));
+                    }
+                    if state.frame_advance {
+                        state.frame_advance = false;
+                        emul.send(Command::AdvanceFrame);
+                    }
+                    if state.toggle_pause {
+                        state.toggle_pause = false;
+                        emul.send(Command::SetPause(!emul.is_paused()));
+                    }
+                    if state.reset {
+                        state.reset = false;
+                        emul.send(Command::Reset);
+                    }
+                    if state.power_cycle {
+                        state.power_cycle = false;
+                        emul.send(Command::PowerCycle);
+                    }
+                    if let Some(file_name) = state.load_rom.take() {
+                        emul.send(Command::LoadRom(file_name));
+                    }
+                    if let Some(slot) = state.save_state.take() {
+                        emul.send(Command::SaveState(slot));
+                    }
+                    if let Some(slot) = state.load_state.take() {
+                        emul.send(Command::LoadState(slot));
+                    }
+                    if state.debug_step {
+                        state.debug_step = false;
+                        emul.send(Command::Debug(DebugCommand::Step));
+                    }
+                    while let Some(event) = emul.poll_event() {
+                        let msg: Box<dyn Msg> = match event {
+                            EmulEvent::Error(err) => err,
+                            EmulEvent::StateSaved(file) => Box::new(
+                                StatusMsg::info(ErrorTopicId::SaveState.into(), InfoMsgId::StateSaved.into())
+                                    .add_param(file),
+                            ),
+                            EmulEvent::StateLoaded(file) => Box::new(
+                                StatusMsg::info(ErrorTopicId::SaveState.into(), InfoMsgId::StateLoaded.into())
+                                    .add_param(file),
+                            ),
+                            EmulEvent::RecordingSaved { file_name, audio_file: Some(audio_file) } => Box::new(
+                                StatusMsg::info(ErrorTopicId::Record.into(), InfoMsgId::RecordingSavedWithAudio.into())
+                                    .add_param(file_name)
+                                    .add_param(audio_file),
+                            ),
+                            EmulEvent::RecordingSaved { file_name, audio_file: None } => Box::new(
+                                StatusMsg::info(ErrorTopicId::Record.into(), InfoMsgId::RecordingSaved.into())
+                                    .add_param(file_name),
+                            ),
+                        };
+                        state.messages.push(msg);
                     }
                 }
                 Event::MainEventsCleared => {
@@ -99,6 +160,7 @@ impl System {
                     gl_window.window().request_redraw();
                 }
                 Event::RedrawRequested(_) => {
+                    emul.update();
                     let ui = imgui.frame();
                     let mut run = true;
                     let (work_size, work_pos) = System::get_viewport_size();
@@ -117,6 +179,16 @@ impl System {
                     }
 
                     let gl_window = display.gl_window();
+                    if emul.is_paused() != paused {
+                        paused = emul.is_paused();
+                        let window_title = if paused { format!("{} - Paused", title) } else { title.clone() };
+                        gl_window.window().set_title(&window_title);
+                    }
+                    if state.fullscreen != fullscreen {
+                        fullscreen = state.fullscreen;
+                        let mode = if fullscreen { Some(Fullscreen::Borderless(None)) } else { None };
+                        gl_window.window().set_fullscreen(mode);
+                    }
                     let mut target = display.draw();
                     target.clear_color_srgb(0.177, 0.177, 0.177, 1.0);
                     platform.prepare_render(&ui, gl_window.window());
@@ -131,18 +203,24 @@ impl System {
                     event: WindowEvent::CloseRequested,
                     ..
                 } => *control_flow = ControlFlow::Exit,
-                Event::WindowEvent {
-                    event:
-                        WindowEvent::KeyboardInput {
-                            device_id: _,
-                            input,
-                            is_synthetic: _,
-                        },
-                    ..
-                } => {
-                    
-                }
                 event => {
+                    if let Event::WindowEvent {
+                        event: WindowEvent::KeyboardInput { input, .. },
+                        ..
+                    } = &event
+                    {
+                        System::handle_hotkey(&mut state, input);
+                        let system = emul.frame().system;
+                        if system != input_system {
+                            input_system = system;
+                            input_map = system.map(key_map::input_map).unwrap_or_default();
+                        }
+                        let key = input.virtual_keycode.and_then(|key| input_map.emul_key(key as u32));
+                        if let (Some(key), false) = (key, imgui.io().want_capture_keyboard) {
+                            let pressed = input.state == ElementState::Pressed;
+                            emul.send(Command::Input { key, pressed });
+                        }
+                    }
                     let gl_window = display.gl_window();
                     platform.handle_event(imgui.io_mut(), gl_window.window(), &event);
                 }
@@ -150,6 +228,26 @@ impl System {
         })
     }
 
+    fn handle_hotkey(state: &mut UiState, input: &KeyboardInput) {
+        if input.virtual_keycode == Some(VirtualKeyCode::Tab) {
+            state.turbo = input.state == ElementState::Pressed;
+        }
+        if input.state != ElementState::Pressed {
+            return;
+        }
+        match input.virtual_keycode {
+            Some(VirtualKeyCode::F5) => state.toggle_pause = true,
+            Some(VirtualKeyCode::F6) => state.frame_advance = true,
+            Some(VirtualKeyCode::F7) => state.debug_step = true,
+            Some(VirtualKeyCode::F8) => state.reset = true,
+            Some(VirtualKeyCode::F9) => state.power_cycle = true,
+            Some(VirtualKeyCode::F10) => state.show_console = !state.show_console,
+            Some(VirtualKeyCode::F11) => state.fullscreen = !state.fullscreen,
+            Some(VirtualKeyCode::F12) => state.take_screenshot = true,
+            _ => {}
+        }
+    }
+
     fn get_viewport_size() -> ([f32; 2], [f32; 2]) {
         unsafe {
             let vp = igGetMainViewport();
@@ -220,7 +318,8 @@ fn init(title: &str, em: EmulMgr) -> System {
         renderer,
         font_size,
         texture_id: None,
-        emul: em,
+        emul: EmulThread::spawn(em),
+        title: title.to_owned(),
     }
 }
 
@@ -3,9 +3,11 @@ use strum_macros::IntoStaticStr;
 #[derive(Debug, IntoStaticStr)]
 pub enum UiErrorMsgId {
     NotInitialized,
+    ClipboardUnavailable,
 }
 
 #[derive(Debug, IntoStaticStr)]
 pub enum UiErrorTopicId {
-    SdlRender,
+    Render,
+    Clipboard,
 }
\ No newline at end of file
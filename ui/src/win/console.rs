@@ -0,0 +1,110 @@
+use crate::GuiCtx;
+use super::toast::ToastWindow;
+use emulation::common::message::MsgKind;
+use emulation::common::utils;
+use imgui::{Condition, Ui};
+
+use std::collections::VecDeque;
+
+const MAX_ENTRIES: usize = 1000;
+const ALL_TOPICS: &str = "All topics";
+
+struct LogEntry {
+    time: String,
+    kind: MsgKind,
+    topic: &'static str,
+    text: String,
+}
+
+/// Every message shown to the user, kept for the session and filtered by kind and topic
+pub struct ConsoleWindow {
+    entries: VecDeque<LogEntry>,
+    topics: Vec<&'static str>,
+    show_errors: bool,
+    show_warnings: bool,
+    show_info: bool,
+    /// Index in `topics` plus one, 0 shows all topics
+    topic_filter: usize,
+    scroll_to_bottom: bool,
+}
+
+impl ConsoleWindow {
+    pub fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            topics: Vec::new(),
+            show_errors: true,
+            show_warnings: true,
+            show_info: true,
+            topic_filter: 0,
+            scroll_to_bottom: false,
+        }
+    }
+
+    pub fn push(&mut self, kind: MsgKind, topic: &'static str, text: String) {
+        if self.entries.len() == MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+        if !self.topics.contains(&topic) {
+            self.topics.push(topic);
+        }
+        self.entries.push_back(LogEntry {
+            time: utils::clock(),
+            kind,
+            topic,
+            text,
+        });
+        self.scroll_to_bottom = true;
+    }
+
+    pub fn show_window(&mut self, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        let state = gui_ctx.state();
+        if !state.show_console {
+            return;
+        }
+        ui.window("Log")
+            .opened(&mut state.show_console)
+            .size([520.0, 260.0], Condition::FirstUseEver)
+            .build(|| {
+                ui.checkbox("Errors", &mut self.show_errors);
+                ui.same_line();
+                ui.checkbox("Warnings", &mut self.show_warnings);
+                ui.same_line();
+                ui.checkbox("Info", &mut self.show_info);
+                ui.same_line();
+                let mut topics = vec![ALL_TOPICS];
+                topics.extend(self.topics.iter());
+                ui.set_next_item_width(140.0);
+                ui.combo_simple_string("##topic", &mut self.topic_filter, &topics);
+                ui.same_line();
+                if ui.button("Clear") {
+                    self.entries.clear();
+                }
+                ui.separator();
+                ui.child_window("entries").build(|| {
+                    for entry in self.entries.iter().filter(|e| self.is_visible(e)) {
+                        ui.text_disabled(&entry.time);
+                        ui.same_line();
+                        ui.text_colored(ToastWindow::color(entry.kind), &entry.text);
+                    }
+                    if self.scroll_to_bottom && ui.scroll_y() >= ui.scroll_max_y() {
+                        ui.set_scroll_here_y_with_ratio(1.0);
+                    }
+                    self.scroll_to_bottom = false;
+                });
+            });
+    }
+
+    fn is_visible(&self, entry: &LogEntry) -> bool {
+        let kind_visible = match entry.kind {
+            MsgKind::Error => self.show_errors,
+            MsgKind::Warning => self.show_warnings,
+            MsgKind::Info => self.show_info,
+        };
+        let topic_visible = match self.topic_filter {
+            0 => true,
+            i => self.topics.get(i - 1) == Some(&entry.topic),
+        };
+        kind_visible && topic_visible
+    }
+}
@@ -1,84 +1,147 @@
 use crate::GuiCtx;
-use emulation::common::emulator::EmulMgr;
+use crate::gui_ctx::ScaleMode;
+use emulation::common::emul_thread::{Command, EmulThread};
+use emulation::common::filter::Filter;
 use crate::ui_error::*;
-use emulation::common::message::{ ErrorMsg, Msg };
+use emulation::common::message::{ ErrorMsg, ErrorTopicId, InfoMsgId, Msg, StatusMsg };
+use emulation::common::palette::Palette;
+use emulation::common::phosphor::{DisplayMode, Phosphor};
+use emulation::common::scaler::Scaler;
+use emulation::common::screenshot::{self, RgbaImage};
+use emulation::common::utils;
 use glium::texture::{ClientFormat, RawImage2d};
 use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter, SamplerBehavior};
 use glium::Texture2d;
 use imgui::Ui;
 use imgui::Window;
-use imgui::{Condition, Image, TextureId, WindowFlags};
+use imgui::{Condition, Image, StyleColor, StyleVar, TextureId, WindowFlags};
 use imgui_glium_renderer::Texture;
 
 use std::borrow::Cow;
+use std::path::Path;
 use std::rc::Rc;
 
-use crate::render::SdlRender;
+const MAX_TEXTURE_SCALE: u32 = 16;
 
-pub struct GameWindow<'a> {
+pub struct GameWindow {
     texture_id: Option<TextureId>,
-    sdl_render: Option<SdlRender<'a>>,
+    scaler: Option<Scaler>,
     current_version: u32,
+    /// The resolution may change while a program runs, e.g. the frame height of the Atari 2600
+    current_resolution: [u32; 2],
     current_scale: u32,
+    current_filter: Filter,
+    phosphor: Phosphor,
+    current_frame: u64,
+    current_palette: Option<Palette>,
 }
 
-impl<'a> GameWindow<'a> {
+impl GameWindow {
     pub fn new() -> Self {
         Self {
             texture_id: None,
-            sdl_render: None,
+            scaler: None,
             current_version: 0,
+            current_resolution: [0, 0],
             current_scale: 0,
+            current_filter: Filter::Nearest,
+            phosphor: Phosphor::new(DisplayMode::Normal),
+            current_frame: 0,
+            current_palette: None,
         }
     }
 
     pub fn show_window(
         &mut self,
-        emul: &EmulMgr,
+        emul: &EmulThread,
         ui: &Ui,
         gui_ctx: &mut GuiCtx,
     ) -> Result<(), Box<dyn Msg>> {
-        ui.window("Game")
+        let background = ui.push_style_color(StyleColor::WindowBg, [0.0, 0.0, 0.0, 1.0]);
+        let padding = ui.push_style_var(StyleVar::WindowPadding([0.0, 0.0]));
+        let result = ui.window("Game")
             .flags(WindowFlags::NO_TITLE_BAR | WindowFlags::NO_RESIZE)
             .position(gui_ctx.work_pos(), Condition::Always)
             .size(gui_ctx.work_size(), Condition::Always)
             .build(|| {
-                if self.should_update_render(emul) {
-                    self.create_render(emul, gui_ctx.state().render_scale);
+                let resolution = emul.resolution()?;
+                let state = gui_ctx.state();
+                let (size, scale) = GameWindow::view_size(
+                    state.scale_mode,
+                    state.render_scale,
+                    resolution,
+                    ui.content_region_avail(),
+                );
+                let filter = gui_ctx.state().filter;
+                if self.should_update_scaler(emul, scale, filter) {
+                    self.create_scaler(emul, gui_ctx, scale, filter);
                 }
-                let render = match &self.sdl_render {
-                    Some(r) => r,
-                    None => {
-                        let err = ErrorMsg::new(UiErrorTopicId::SdlRender.into(), UiErrorMsgId::NotInitialized.into());
-                        let result: Result<(), Box<dyn Msg>> = Err(Box::new(err));
-                        return result;
-                    }
+                // a custom palette has nothing to map in true colour or composed frames
+                let system_palette = emul.palette()?;
+                let palette = match &gui_ctx.state().palette {
+                    Some(p) if !system_palette.is_true_color() && !system_palette.is_composed() => p.clone(),
+                    _ => system_palette,
                 };
-                let width = render.scaled_size()[0];
-                let height = render.scaled_size()[1];
-                let pixels = emul.video_buffer()?;
-                self.convert_buffer(gui_ctx, pixels)?;
+                let buffer = emul.video_buffer()?;
+                let mode = gui_ctx.state().display_mode(&emul.rom_name());
+                self.phosphor.set_mode(mode);
+                if self.is_dirty(emul, &palette) {
+                    let colors = self.phosphor.process(buffer, &palette);
+                    self.convert_buffer(gui_ctx, &colors)?;
+                    self.current_frame = emul.frame_version();
+                    self.current_palette = Some(palette.clone());
+                }
                 if let Some(texture_id) = self.texture_id {
-                    Image::new(texture_id, [width as f32, height as f32]).build(ui);
+                    // letterbox: center the image in the free space
+                    let avail = ui.content_region_avail();
+                    let cursor = ui.cursor_pos();
+                    ui.set_cursor_pos([
+                        cursor[0] + ((avail[0] - size[0]) / 2.0).max(0.0),
+                        cursor[1] + ((avail[1] - size[1]) / 2.0).max(0.0),
+                    ]);
+                    Image::new(texture_id, size).build(ui);
+                }
+                if gui_ctx.state().take_screenshot {
+                    gui_ctx.state().take_screenshot = false;
+                    self.save_screenshot(emul, gui_ctx, buffer, &palette)?;
                 }
+                self.update_recording(emul, gui_ctx, &palette);
                 Ok(())
             })
-            .unwrap()
+            .unwrap();
+        padding.pop();
+        background.pop();
+        result
+    }
+
+    /// Displayed image size and the integer scale the texture is rendered at
+    fn view_size(mode: ScaleMode, render_scale: u32, resolution: [u32; 2], avail: [f32; 2]) -> ([f32; 2], u32) {
+        let width = resolution[0].max(1) as f32;
+        let height = resolution[1].max(1) as f32;
+        let fit = (avail[0] / width).min(avail[1] / height).max(0.0);
+        let (scale, texture_scale) = match mode {
+            ScaleMode::Fixed => (render_scale as f32, render_scale),
+            ScaleMode::FitInteger => {
+                let scale = fit.floor().max(1.0);
+                (scale, scale as u32)
+            }
+            ScaleMode::FitFractional => (fit, fit.ceil().max(1.0) as u32),
+        };
+        ([width * scale, height * scale], texture_scale.clamp(1, MAX_TEXTURE_SCALE))
     }
 
-    fn convert_buffer(&mut self, gui_ctx: &mut GuiCtx, buff: Vec<u8>) -> Result<(), Box<dyn Msg>> {
-        let render = match self.sdl_render.as_mut() {
-            Some(r) => r,
+    fn convert_buffer(&mut self, gui_ctx: &mut GuiCtx, colors: &[u32]) -> Result<(), Box<dyn Msg>> {
+        let scaler = match self.scaler.as_mut() {
+            Some(s) => s,
             None => {
-                let err = ErrorMsg::new(UiErrorTopicId::SdlRender.into(), UiErrorMsgId::NotInitialized.into());
+                let err = ErrorMsg::new(UiErrorTopicId::Render.into(), UiErrorMsgId::NotInitialized.into());
                 return Err(Box::new(err));
             }
         };
-        let width = render.scaled_size()[0];
-        let height = render.scaled_size()[1];
-        let pixels = render.get_pixels(buff);
+        let [width, height] = *scaler.scaled_size();
+        let pixels = scaler.scale_colors(colors);
         let raw = RawImage2d {
-            data: Cow::Owned(pixels),
+            data: Cow::Borrowed(pixels),
             width: width as u32,
             height: height as u32,
             format: ClientFormat::U8U8U8U8,
@@ -97,25 +160,69 @@ impl<'a> GameWindow<'a> {
             let gl_texture = Texture2d::new(gui_ctx.facade(), raw);
             match gl_texture {
                 Err(e) => {
-                    let err = ErrorMsg::new(UiErrorTopicId::SdlRender.into(), UiErrorMsgId::NotInitialized.into())
+                    let err = ErrorMsg::new(UiErrorTopicId::Render.into(), UiErrorMsgId::NotInitialized.into())
                         .set_source(Box::new(e));
                     return Err(Box::new(err));
                 }
                 Ok(r) => {
                     self.create_texture(gui_ctx, r);
-                    return Ok(());
                 }
             }
         }
         Ok(())
     }
 
+    fn save_screenshot(
+        &self,
+        emul: &EmulThread,
+        gui_ctx: &mut GuiCtx,
+        buffer: &[u8],
+        palette: &Palette,
+    ) -> Result<(), Box<dyn Msg>> {
+        let state = gui_ctx.state();
+        let scale = if state.screenshot_scaled { state.render_scale } else { 1 };
+        let image = RgbaImage::capture(buffer, emul.resolution()?, palette, scale, state.filter);
+        let file_name = screenshot::file_name(Path::new("."), emul.rom_name().as_deref());
+        image.save_png(&file_name)?;
+        let saved = StatusMsg::info(ErrorTopicId::Screenshot.into(), InfoMsgId::ScreenshotSaved.into())
+            .add_param(file_name);
+        state.messages.push(Box::new(saved));
+        if state.screenshot_to_clipboard {
+            let image_data = arboard::ImageData {
+                width: image.width as usize,
+                height: image.height as usize,
+                bytes: Cow::Borrowed(&image.pixels),
+            };
+            let copy_result = arboard::Clipboard::new().and_then(|mut c| c.set_image(image_data));
+            if let Err(e) = copy_result {
+                let err = ErrorMsg::new(UiErrorTopicId::Clipboard.into(), UiErrorMsgId::ClipboardUnavailable.into())
+                    .set_source(Box::new(e));
+                return Err(Box::new(err));
+            }
+        }
+        Ok(())
+    }
+
+    /// The emulation thread records, it sees every emulated frame
+    fn update_recording(&mut self, emul: &EmulThread, gui_ctx: &mut GuiCtx, palette: &Palette) {
+        let state = gui_ctx.state();
+        if state.stop_recording {
+            state.stop_recording = false;
+            emul.send(Command::StopRecording);
+        }
+        if let Some(format) = state.start_recording.take() {
+            let file_name = utils::capture_file_name(Path::new("."), emul.rom_name().as_deref(), format.extension());
+            let scale = if state.screenshot_scaled { state.render_scale } else { 1 };
+            emul.send(Command::StartRecording { file_name, format, palette: palette.clone(), scale });
+        }
+    }
+
     fn create_texture(&mut self, gui_ctx: &mut GuiCtx, gl_texture: Texture2d) {
         let texture = Texture {
             texture: Rc::new(gl_texture),
             sampler: SamplerBehavior {
-                magnify_filter: MagnifySamplerFilter::Linear,
-                minify_filter: MinifySamplerFilter::Linear,
+                magnify_filter: MagnifySamplerFilter::Nearest,
+                minify_filter: MinifySamplerFilter::Nearest,
                 ..Default::default()
             },
         };
@@ -123,23 +230,39 @@ impl<'a> GameWindow<'a> {
         self.texture_id = Some(texture_id);
     }
 
-    fn should_update_render(&mut self, emul: &EmulMgr) -> bool {
-        match self.sdl_render.as_ref() {
-            Some(render) => {
-                self.current_version != emul.version() || *render.scale() != self.current_scale
+    /// Idle frames skip the conversion and the texture upload
+    fn is_dirty(&self, emul: &EmulThread, palette: &Palette) -> bool {
+        self.texture_id.is_none()
+            || self.current_frame != emul.frame_version()
+            || self.current_palette.as_ref() != Some(palette)
+            || !self.phosphor.is_settled()
+    }
+
+    fn should_update_scaler(&self, emul: &EmulThread, scale: u32, filter: Filter) -> bool {
+        match self.scaler.as_ref() {
+            Some(_) => {
+                self.current_version != emul.version()
+                    || emul.resolution().ok() != Some(self.current_resolution)
+                    || scale != self.current_scale
+                    || filter != self.current_filter
             }
             _ => true,
         }
     }
 
-    fn create_render(&mut self, emul: &EmulMgr, scale: u32) {
-        if let Some(render) = self.sdl_render.take() {
-            drop(render);
+    fn create_scaler(&mut self, emul: &EmulThread, gui_ctx: &mut GuiCtx, scale: u32, filter: Filter) {
+        // the texture has the scaled size, so it goes with the scaler
+        if let Some(texture_id) = self.texture_id.take() {
+            gui_ctx.textures().remove(texture_id);
         }
+        self.scaler = None;
+        self.phosphor.reset();
         if let Ok(resolution) = emul.resolution() {
-            self.sdl_render = Some(SdlRender::new(resolution, scale));
+            self.scaler = Some(Scaler::with_filter(resolution, scale, filter));
             self.current_version = emul.version();
+            self.current_resolution = resolution;
             self.current_scale = scale;
+            self.current_filter = filter;
         }
     }
 }
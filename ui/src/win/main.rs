@@ -1,36 +1,274 @@
 use imgui::Ui;
-use emulation::common::emulator::EmulMgr;
+use emulation::common::emul_thread::EmulThread;
 use crate::GuiCtx;
+use crate::gui_ctx::{ScaleMode, LANGUAGES};
 use imgui::MenuItem;
+use imgui::Condition;
+use emulation::common::filter::Filter;
+use emulation::common::phosphor::{self, DisplayMode};
+use emulation::common::record::RecordFormat;
+use emulation::common::scheduler;
 use super::game::GameWindow;
+use super::palette::PaletteWindow;
+use super::toast::ToastWindow;
+use super::console::ConsoleWindow;
 
-pub struct MainWindow<'a> {
-    rn: GameWindow<'a>,
+const STATE_SLOTS: u8 = 10;
+
+pub struct MainWindow {
+    rn: GameWindow,
+    palette: PaletteWindow,
+    toasts: ToastWindow,
+    console: ConsoleWindow,
+    /// Game window error of the last frame, reported once until it changes
+    last_error: Option<String>,
+    /// Path typed in the open ROM window
+    rom_path: String,
 }
 
-impl<'a> MainWindow<'a> {
+impl MainWindow {
 
     pub fn new() -> Self {
         Self {
             rn: GameWindow::new(),
+            palette: PaletteWindow::new(),
+            toasts: ToastWindow::new(),
+            console: ConsoleWindow::new(),
+            last_error: None,
+            rom_path: String::new(),
         }
     }
     
-    pub fn show(&mut self, emul: &EmulMgr, ui: &Ui, gui_ctx: &mut GuiCtx) {
-        self.rn.show_window(emul, ui, gui_ctx);
-        self.main_menu(ui, gui_ctx);
+    pub fn show(&mut self, emul: &EmulThread, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        match self.rn.show_window(emul, ui, gui_ctx) {
+            Ok(()) => self.last_error = None,
+            Err(err) => {
+                let text = err.to_string();
+                if self.last_error.as_ref() != Some(&text) {
+                    self.last_error = Some(text);
+                    gui_ctx.state().messages.push(err);
+                }
+            }
+        }
+        self.palette.show_window(emul, ui, gui_ctx);
+        self.open_rom_window(ui, gui_ctx);
+        self.main_menu(emul, ui, gui_ctx);
+        self.show_messages(ui, gui_ctx);
+    }
+
+    fn show_messages(&mut self, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        let messages = std::mem::take(&mut gui_ctx.state().messages);
+        for msg in messages {
+            let text = gui_ctx.localize_msg(msg.as_ref());
+            self.console.push(msg.kind(), msg.topic_id(), text.clone());
+            self.toasts.push(msg.kind(), text);
+        }
+        self.console.show_window(ui, gui_ctx);
+        self.toasts.show_window(ui, gui_ctx);
+    }
+
+    /// There is no native file dialog, the path is typed relative to the working directory
+    fn open_rom_window(&mut self, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        let state = gui_ctx.state();
+        if !state.open_file {
+            return;
+        }
+        let mut loaded = false;
+        let rom_path = &mut self.rom_path;
+        ui.window("Open ROM")
+            .opened(&mut state.open_file)
+            .size([400.0, 80.0], Condition::FirstUseEver)
+            .build(|| {
+                let entered = ui.input_text("File", rom_path).enter_returns_true(true).build();
+                if (ui.button("Open") || entered) && !rom_path.is_empty() {
+                    state.load_rom = Some(rom_path.clone());
+                    loaded = true;
+                }
+            });
+        if loaded {
+            state.open_file = false;
+        }
     }
 
-    fn main_menu(&mut self, ui: &Ui, gui_ctx: &GuiCtx)  {
+    /// Slots of the save state files next to the ROM
+    fn state_slots_menu(ui: &Ui) -> Option<u8> {
+        let mut selected = None;
+        for slot in 0..STATE_SLOTS {
+            if ui.menu_item(format!("Slot {}", slot)) {
+                selected = Some(slot);
+            }
+        }
+        selected
+    }
+
+    fn language_menu(&mut self, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        for language in LANGUAGES {
+            let name = gui_ctx.localize_in(language, "Language");
+            let state = gui_ctx.state();
+            if ui.menu_item_config(&name).selected(state.language == language).build() {
+                state.language = String::from(language);
+            }
+        }
+    }
+
+    /// Display mode is kept per ROM
+    fn display_mode_menu(&mut self, emul: &EmulThread, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        let rom_name = emul.rom_name();
+        let state = gui_ctx.state();
+        let mode = state.display_mode(&rom_name);
+        let decay = match mode {
+            DisplayMode::Blend { decay } => decay,
+            _ => phosphor::DEFAULT_DECAY,
+        };
+        let modes = [DisplayMode::Normal, DisplayMode::Blend { decay }, DisplayMode::OrLastTwo];
+        for item in modes {
+            let selected = std::mem::discriminant(&item) == std::mem::discriminant(&mode);
+            if ui.menu_item_config(item.name()).enabled(rom_name.is_some()).selected(selected).build() {
+                state.set_display_mode(&rom_name, item);
+            }
+        }
+        if let DisplayMode::Blend { mut decay } = mode {
+            if ui.slider("Decay", 0.1, 0.95, &mut decay) {
+                state.set_display_mode(&rom_name, DisplayMode::Blend { decay });
+            }
+        }
+    }
+
+    /// Systems are picked by the loaded file, the menu only lists them
+    fn systems_menu(&mut self, emul: &EmulThread, ui: &Ui) {
+        let current = emul.system().map(|s| s.name);
+        for system in emul.systems() {
+            let extensions: Vec<String> = system.extensions.iter().map(|e| format!(".{}", e)).collect();
+            ui.menu_item_config(system.name)
+                .shortcut(extensions.join(" "))
+                .selected(current == Some(system.name))
+                .build();
+        }
+    }
+
+    fn main_menu(&mut self, emul: &EmulThread, ui: &Ui, gui_ctx: &mut GuiCtx)  {
         if let Some(menu_bar) = ui.begin_main_menu_bar() {
             if let Some(menu) = ui.begin_menu("File") {
-                ui.menu_item("Open");
+                let state = gui_ctx.state();
+                if ui.menu_item("Open...") {
+                    state.open_file = true;
+                }
+                ui.separator();
+                if ui.menu_item_config("Screenshot").shortcut("F12").build() {
+                    state.take_screenshot = true;
+                }
+                ui.menu_item_config("Screenshot at render scale")
+                    .build_with_ref(&mut state.screenshot_scaled);
+                ui.menu_item_config("Copy screenshot to clipboard")
+                    .build_with_ref(&mut state.screenshot_to_clipboard);
+                ui.separator();
+                if ui.menu_item_config("Record GIF").enabled(!emul.is_recording()).build() {
+                    state.start_recording = Some(RecordFormat::Gif);
+                }
+                if ui.menu_item_config("Record AVI").enabled(!emul.is_recording()).build() {
+                    state.start_recording = Some(RecordFormat::Avi);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Uncompressed video with the sound in the same file");
+                }
+                if ui.menu_item_config("Record Y4M + WAV").enabled(!emul.is_recording()).build() {
+                    state.start_recording = Some(RecordFormat::Y4m);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Y4M has no audio track, the sound is saved to a WAV file with the same name");
+                }
+                if ui.menu_item_config("Stop recording").enabled(emul.is_recording()).build() {
+                    state.stop_recording = true;
+                }
                 ui.separator();
                 ui.menu_item("Exit");
                 menu.end();
             }
+            if let Some(menu) = ui.begin_menu("Emulation") {
+                let state = gui_ctx.state();
+                if ui.menu_item_config("Pause").shortcut("F5").selected(emul.is_paused()).build() {
+                    state.toggle_pause = true;
+                }
+                if ui.menu_item_config("Reset").shortcut("F8").build() {
+                    state.reset = true;
+                }
+                if ui.menu_item_config("Power cycle").shortcut("F9").build() {
+                    state.power_cycle = true;
+                }
+                ui.separator();
+                if let Some(speed_menu) = ui.begin_menu("Speed") {
+                    for speed in scheduler::SPEEDS {
+                        let label = format!("{}%", (speed * 100.0) as u32);
+                        if ui.menu_item_config(&label).selected(state.speed == speed).build() {
+                            state.speed = speed;
+                        }
+                    }
+                    speed_menu.end();
+                }
+                ui.menu_item_config("Turbo").shortcut("Tab").build_with_ref(&mut state.turbo);
+                if ui.menu_item_config("Frame advance").shortcut("F6").build() {
+                    state.frame_advance = true;
+                }
+                if ui.menu_item_config("Step instruction").shortcut("F7").enabled(emul.is_paused()).build() {
+                    state.debug_step = true;
+                }
+                ui.separator();
+                let save_states = emul.system().is_some_and(|s| s.capabilities.save_states);
+                if let Some(save_menu) = ui.begin_menu_with_enabled("Save state", save_states) {
+                    state.save_state = MainWindow::state_slots_menu(ui).or(state.save_state);
+                    save_menu.end();
+                }
+                if let Some(load_menu) = ui.begin_menu_with_enabled("Load state", save_states) {
+                    state.load_state = MainWindow::state_slots_menu(ui).or(state.load_state);
+                    load_menu.end();
+                }
+                ui.separator();
+                if let Some(system_menu) = ui.begin_menu("Systems") {
+                    self.systems_menu(emul, ui);
+                    system_menu.end();
+                }
+                menu.end();
+            }
             if let Some(menu) = ui.begin_menu("View") {
-                
+                let state = gui_ctx.state();
+                for mode in ScaleMode::all() {
+                    if ui.menu_item_config(mode.name()).selected(state.scale_mode == mode).build() {
+                        state.scale_mode = mode;
+                    }
+                }
+                if state.scale_mode == ScaleMode::Fixed {
+                    ui.slider("Scale", 1, 16, &mut state.render_scale);
+                }
+                ui.menu_item_config("Fullscreen")
+                    .shortcut("F11")
+                    .build_with_ref(&mut state.fullscreen);
+                ui.separator();
+                if let Some(palette_menu) = ui.begin_menu("Palette") {
+                    PaletteWindow::presets_menu(ui, gui_ctx);
+                    palette_menu.end();
+                }
+                if let Some(filter_menu) = ui.begin_menu("Filter") {
+                    let state = gui_ctx.state();
+                    for filter in Filter::all() {
+                        if ui.menu_item_config(filter.name()).selected(state.filter == filter).build() {
+                            state.filter = filter;
+                        }
+                    }
+                    filter_menu.end();
+                }
+                if let Some(mode_menu) = ui.begin_menu("Display mode") {
+                    self.display_mode_menu(emul, ui, gui_ctx);
+                    mode_menu.end();
+                }
+                ui.separator();
+                ui.menu_item_config("Log console")
+                    .shortcut("F10")
+                    .build_with_ref(&mut gui_ctx.state().show_console);
+                if let Some(language_menu) = ui.begin_menu("Language") {
+                    self.language_menu(ui, gui_ctx);
+                    language_menu.end();
+                }
+                menu.end();
             }
             menu_bar.end();
         }
@@ -1,2 +1,5 @@
 pub mod main;
-pub mod game;
\ No newline at end of file
+pub mod game;
+pub mod palette;
+pub mod toast;
+pub mod console;
\ No newline at end of file
@@ -0,0 +1,78 @@
+use crate::GuiCtx;
+use emulation::common::emul_thread::EmulThread;
+use emulation::common::palette::Palette;
+use imgui::{Condition, Ui};
+
+const COLOR_NAMES: [&str; 4] = ["Background", "Foreground", "Foreground 2", "Blend"];
+
+pub struct PaletteWindow {}
+
+impl PaletteWindow {
+    pub fn new() -> Self {
+        Self {}
+    }
+
+    pub fn show_window(&mut self, emul: &EmulThread, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        let state = gui_ctx.state();
+        if !state.show_palette_editor {
+            return;
+        }
+        if state.palette.is_none() {
+            if let Ok(mut palette) = emul.palette() {
+                palette.set_name("Custom");
+                state.palette = Some(palette);
+            }
+        }
+        ui.window("Palette")
+            .opened(&mut state.show_palette_editor)
+            .size([300.0, 160.0], Condition::FirstUseEver)
+            .build(|| {
+                let palette = match state.palette.as_mut() {
+                    Some(p) => p,
+                    None => return,
+                };
+                ui.text(palette.name());
+                for (i, color) in palette.colors_mut().iter_mut().enumerate() {
+                    let label = match COLOR_NAMES.get(i) {
+                        Some(name) => String::from(*name),
+                        None => format!("Color {}", i),
+                    };
+                    let mut rgba = PaletteWindow::to_float(*color);
+                    if ui.color_edit4(&label, &mut rgba) {
+                        *color = PaletteWindow::from_float(rgba);
+                    }
+                }
+            });
+    }
+
+    pub fn presets_menu(ui: &Ui, gui_ctx: &mut GuiCtx) {
+        let state = gui_ctx.state();
+        let selected_name = state.palette.as_ref().map(|p| String::from(p.name()));
+        if ui
+            .menu_item_config("ROM default")
+            .selected(selected_name.is_none())
+            .build()
+        {
+            state.palette = None;
+        }
+        ui.separator();
+        for preset in Palette::presets() {
+            let selected = selected_name.as_deref() == Some(preset.name());
+            if ui.menu_item_config(preset.name()).selected(selected).build() {
+                state.palette = Some(preset);
+            }
+        }
+        ui.separator();
+        if ui.menu_item("Edit...") {
+            state.show_palette_editor = true;
+        }
+    }
+
+    fn to_float(color: u32) -> [f32; 4] {
+        color.to_be_bytes().map(|c| c as f32 / 255.0)
+    }
+
+    fn from_float(rgba: [f32; 4]) -> u32 {
+        u32::from_be_bytes(rgba.map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+    }
+}
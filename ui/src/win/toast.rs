@@ -0,0 +1,102 @@
+use crate::GuiCtx;
+use emulation::common::message::MsgKind;
+use imgui::{Condition, MouseButton, StyleColor, Ui, WindowFlags};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const MAX_TOASTS: usize = 5;
+const MARGIN: f32 = 10.0;
+const WRAP_WIDTH: f32 = 360.0;
+
+struct Toast {
+    id: u64,
+    kind: MsgKind,
+    text: String,
+    shown: Instant,
+}
+
+/// Notifications stacked in the bottom-right corner, they expire or are dismissed by a click
+pub struct ToastWindow {
+    toasts: VecDeque<Toast>,
+    next_id: u64,
+}
+
+impl ToastWindow {
+    pub fn new() -> Self {
+        Self {
+            toasts: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    pub fn push(&mut self, kind: MsgKind, text: String) {
+        if self.toasts.len() == MAX_TOASTS {
+            self.toasts.pop_front();
+        }
+        self.toasts.push_back(Toast {
+            id: self.next_id,
+            kind,
+            text,
+            shown: Instant::now(),
+        });
+        self.next_id += 1;
+    }
+
+    pub fn show_window(&mut self, ui: &Ui, gui_ctx: &mut GuiCtx) {
+        self.toasts.retain(|t| t.shown.elapsed() < ToastWindow::lifetime(t.kind));
+        let [x, y] = gui_ctx.work_pos();
+        let [width, height] = gui_ctx.work_size();
+        let mut bottom = y + height - MARGIN;
+        let mut dismissed = None;
+        // newest at the bottom
+        for toast in self.toasts.iter().rev() {
+            let color = ToastWindow::color(toast.kind);
+            let border = ui.push_style_color(StyleColor::Border, color);
+            let toast_height = ui
+                .window(format!("##toast{}", toast.id))
+                .flags(
+                    WindowFlags::NO_DECORATION
+                        | WindowFlags::ALWAYS_AUTO_RESIZE
+                        | WindowFlags::NO_MOVE
+                        | WindowFlags::NO_SAVED_SETTINGS
+                        | WindowFlags::NO_FOCUS_ON_APPEARING
+                        | WindowFlags::NO_NAV,
+                )
+                .position([x + width - MARGIN, bottom], Condition::Always)
+                .position_pivot([1.0, 1.0])
+                .bg_alpha(0.85)
+                .build(|| {
+                    let wrap = ui.push_text_wrap_pos_with_pos(WRAP_WIDTH);
+                    ui.text_colored(color, &toast.text);
+                    wrap.pop();
+                    if ui.is_window_hovered() && ui.is_mouse_clicked(MouseButton::Left) {
+                        dismissed = Some(toast.id);
+                    }
+                    ui.window_size()[1]
+                })
+                .unwrap_or(0.0);
+            border.pop();
+            bottom -= toast_height + MARGIN / 2.0;
+        }
+        if let Some(id) = dismissed {
+            self.toasts.retain(|t| t.id != id);
+        }
+    }
+
+    fn lifetime(kind: MsgKind) -> Duration {
+        match kind {
+            MsgKind::Info => Duration::from_secs(4),
+            MsgKind::Warning => Duration::from_secs(6),
+            MsgKind::Error => Duration::from_secs(10),
+        }
+    }
+
+    pub fn color(kind: MsgKind) -> [f32; 4] {
+        match kind {
+            MsgKind::Info => [0.85, 0.85, 0.85, 1.0],
+            MsgKind::Warning => [1.0, 0.8, 0.3, 1.0],
+            MsgKind::Error => [1.0, 0.4, 0.4, 1.0],
+        }
+    }
+}